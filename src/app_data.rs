@@ -0,0 +1,121 @@
+//! Application-specific data ([NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md))
+//!
+//! A kind 30078 note stores arbitrary content for an app under a `d` tag namespace. It is
+//! parameterized replaceable, so relays keep only the newest one for each author and namespace,
+//! which makes it a place to keep device configuration and fetch it back at boot.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{query::Query, Note};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let config = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .create_app_data("esp32-sensor/config", r#"{"interval":60}"#)
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//!
+//! // at boot, ask for the latest version
+//! let pubkey = config.pubkey();
+//! let query = Query::app_data(core::str::from_utf8(&pubkey).unwrap(), "esp32-sensor/config").unwrap();
+//! assert!(query.matches(&config));
+//! let msg = query.serialize_to_relay("config").unwrap();
+//! ```
+
+use heapless::String;
+
+use crate::{errors::Error, query::Query, Note, NoteBuilder, NoteKinds};
+
+/// Kind of an application data note
+pub const APP_DATA_KIND: u32 = 30078;
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 30078 application data note holding `content` under the
+    /// `d` tag `namespace`.
+    /// Errors with `ContentOverflow` if `content` is longer than `CONTENT` or `namespace` is
+    /// longer than `TAG`.
+    pub fn create_app_data(
+        mut self,
+        namespace: &str,
+        content: &str,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let mut data = String::new();
+        data.push_str(content).map_err(|_| Error::ContentOverflow)?;
+        self.note.kind = NoteKinds::ParameterizedReplaceable(APP_DATA_KIND);
+        self.note.content = Some(data);
+        self.identifier(namespace)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// The namespace of an application data note, `None` for other kinds
+    pub fn app_data_namespace(&self) -> Option<&str> {
+        if self.kind.as_u32() != APP_DATA_KIND {
+            return None;
+        }
+        self.identifier()
+    }
+}
+
+impl Query {
+    /// A query for the latest application data note `pubkey` stored under `namespace`.
+    /// Errors with `InvalidPubkey` if `pubkey` isn't hex and with `QueryBuilderOverflow`
+    /// if `namespace` is longer than [`TAG_FILTER_VALUE_SIZE`](crate::query::TAG_FILTER_VALUE_SIZE).
+    pub fn app_data(pubkey: &str, namespace: &str) -> Result<Self, Error> {
+        let mut query = Query::new();
+        query.add_author(pubkey)?;
+        query
+            .kinds
+            .push(NoteKinds::ParameterizedReplaceable(APP_DATA_KIND))
+            .map_err(|_| Error::QueryBuilderOverflow)?;
+        query.add_tag_filter('d', namespace)?;
+        query.limit = Some(1);
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, PRIVKEY, PUBKEY};
+
+    #[test]
+    fn test_app_data() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_app_data("sensor/config", "interval=60")
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.kind(), NoteKinds::from(30078_u32));
+        assert_eq!(note.app_data_namespace(), Some("sensor/config"));
+        assert_eq!(note.content(), Some("interval=60"));
+        assert!(note.verify().is_ok());
+        assert_eq!(text_note().app_data_namespace(), None);
+
+        assert!(Query::app_data(PUBKEY, "sensor/config")
+            .unwrap()
+            .matches(&note));
+        assert!(!Query::app_data(PUBKEY, "sensor/other")
+            .unwrap()
+            .matches(&note));
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Query::app_data(PUBKEY, "sensor/config").unwrap();
+        let msg = query.serialize_to_relay("boot").unwrap();
+        assert_eq!(
+            core::str::from_utf8(&msg).unwrap(),
+            r##"["REQ","boot",{"authors":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"],"#d":["sensor/config"],"kinds":[30078],"limit":1}]"##
+        );
+        assert!(matches!(
+            Query::app_data("xyz", "sensor/config"),
+            Err(Error::InvalidPubkey)
+        ));
+    }
+}
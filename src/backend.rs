@@ -0,0 +1,252 @@
+//! Hash and block cipher backends, so hardware accelerators can replace the software ones
+//!
+//! Note ids and NIP-44 use SHA-256, and NIP-04 uses AES-256. By default these come from the
+//! `sha2` and `aes` crates. Chips such as the ESP32 and STM32 have SHA and AES peripherals, which
+//! can implement these traits and be passed to the `_with` functions, such as
+//! [`NoteBuilder::build_with_hasher`](crate::NoteBuilder::build_with_hasher),
+//! [`Note::verify_id_with`](crate::Note::verify_id_with),
+//! [`NoteBuilder::create_dm_with_cipher`](crate::NoteBuilder::create_dm_with_cipher) and
+//! [`nip44::encrypt_sized_with`](crate::nip44::encrypt_sized_with).
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{backend::Sha256Backend, Note};
+//! // wraps the SHA peripheral on a real device
+//! struct Peripheral(sha2::Sha256);
+//! impl Sha256Backend for Peripheral {
+//!     fn reset(&mut self) {
+//!         self.0.reset()
+//!     }
+//!     fn update(&mut self, data: &[u8]) {
+//!         self.0.update(data)
+//!     }
+//!     fn finish(&mut self) -> [u8; 32] {
+//!         self.0.finish()
+//!     }
+//! }
+//!
+//! let mut sha = Peripheral(Default::default());
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build_with_hasher(1686880020, [0; 32], &mut sha)
+//!     .unwrap();
+//! assert!(note.verify_id_with(&mut sha).is_ok());
+//! ```
+
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use sha2::{Digest, Sha256};
+
+/// SHA-256, fed in pieces
+pub trait Sha256Backend {
+    /// Drops any unfinished hash and starts a new one
+    fn reset(&mut self);
+    /// Hashes `data`
+    fn update(&mut self, data: &[u8]);
+    /// Returns the hash of everything since the last reset, and starts a new one
+    fn finish(&mut self) -> [u8; 32];
+}
+
+impl Sha256Backend for Sha256 {
+    fn reset(&mut self) {
+        Digest::reset(self)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data)
+    }
+
+    fn finish(&mut self) -> [u8; 32] {
+        self.finalize_reset().into()
+    }
+}
+
+impl<H: Sha256Backend + ?Sized> Sha256Backend for &mut H {
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        (**self).update(data)
+    }
+
+    fn finish(&mut self) -> [u8; 32] {
+        (**self).finish()
+    }
+}
+
+/// AES-256 on single 16 byte blocks
+pub trait Aes256Backend {
+    /// Sets the key used by the following blocks
+    fn set_key(&mut self, key: &[u8; 32]);
+    /// Encrypts `block` in place
+    fn encrypt_block(&mut self, block: &mut [u8; 16]);
+    /// Decrypts `block` in place
+    fn decrypt_block(&mut self, block: &mut [u8; 16]);
+}
+
+/// AES-256 from the `aes` crate, the default for NIP-04
+pub struct SoftAes256 {
+    cipher: Aes256,
+}
+
+impl Default for SoftAes256 {
+    /// Starts with an all zero key, call [`Aes256Backend::set_key`] before use
+    fn default() -> Self {
+        SoftAes256 {
+            cipher: Aes256::new(&[0; 32].into()),
+        }
+    }
+}
+
+impl Aes256Backend for SoftAes256 {
+    fn set_key(&mut self, key: &[u8; 32]) {
+        self.cipher = Aes256::new(key.into());
+    }
+
+    fn encrypt_block(&mut self, block: &mut [u8; 16]) {
+        self.cipher.encrypt_block(block.into());
+    }
+
+    fn decrypt_block(&mut self, block: &mut [u8; 16]) {
+        self.cipher.decrypt_block(block.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{nip44, vectors::PRIVKEY, Note};
+
+    const RCVR_PUBKEY: &str = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+
+    /// Counts the blocks and hashes it was used for
+    #[derive(Default)]
+    struct Counting<T> {
+        inner: T,
+        calls: usize,
+    }
+
+    impl<T: Sha256Backend> Sha256Backend for Counting<T> {
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+
+        fn update(&mut self, data: &[u8]) {
+            self.inner.update(data)
+        }
+
+        fn finish(&mut self) -> [u8; 32] {
+            self.calls += 1;
+            self.inner.finish()
+        }
+    }
+
+    impl<T: Aes256Backend> Aes256Backend for Counting<T> {
+        fn set_key(&mut self, key: &[u8; 32]) {
+            self.inner.set_key(key)
+        }
+
+        fn encrypt_block(&mut self, block: &mut [u8; 16]) {
+            self.calls += 1;
+            self.inner.encrypt_block(block)
+        }
+
+        fn decrypt_block(&mut self, block: &mut [u8; 16]) {
+            self.calls += 1;
+            self.inner.decrypt_block(block)
+        }
+    }
+
+    #[test]
+    fn test_sha256() {
+        let mut sha = Sha256::new();
+        let hasher: &mut dyn Sha256Backend = &mut sha;
+        hasher.update(b"ab");
+        hasher.reset();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        let first = hasher.finish();
+        assert_eq!(first, <[u8; 32]>::from(Sha256::digest(b"abc")));
+        // finishing starts a new hash
+        hasher.update(b"abc");
+        assert_eq!(hasher.finish(), first);
+    }
+
+    #[test]
+    fn test_aes256() {
+        // FIPS-197 appendix C.3
+        let mut key = [0_u8; 32];
+        key.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let mut block = [0_u8; 16];
+        block
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = (i as u8) * 0x11);
+        let plain = block;
+        let mut aes = SoftAes256::default();
+        aes.set_key(&key);
+        aes.encrypt_block(&mut block);
+        assert_eq!(
+            block,
+            [
+                0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49,
+                0x60, 0x89
+            ]
+        );
+        aes.decrypt_block(&mut block);
+        assert_eq!(block, plain);
+    }
+
+    #[test]
+    fn test_used_by_notes() {
+        let mut sha: Counting<Sha256> = Counting::default();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_with_hasher(1686880020, [0; 32], &mut sha)
+            .unwrap();
+        assert_eq!(sha.calls, 1);
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note, expected);
+        assert!(note.verify_id_with(&mut sha).is_ok());
+        assert_eq!(sha.calls, 2);
+
+        let mut aes: Counting<SoftAes256> = Counting::default();
+        let dm = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm_with_cipher("hello", RCVR_PUBKEY, [1; 16], &mut aes)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(aes.calls, 1);
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm("hello", RCVR_PUBKEY, [1; 16])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(dm, expected);
+        assert_eq!(dm.read_dm_with_cipher(PRIVKEY, &mut aes).unwrap(), "hello");
+        assert_eq!(aes.calls, 2);
+
+        let conversation_key = [7; 32];
+        let mut sha: Counting<Sha256> = Counting::default();
+        let payload: crate::String<200> =
+            nip44::encrypt_sized_with(&mut sha, &conversation_key, "hello", [1; 32]).unwrap();
+        assert!(sha.calls > 0);
+        assert_eq!(
+            nip44::encrypt_sized(&conversation_key, "hello", [1; 32]),
+            Ok(payload.clone())
+        );
+        assert_eq!(
+            nip44::decrypt_sized_with::<_, 200>(&mut sha, &conversation_key, &payload).unwrap(),
+            "hello"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Reconnection policy for relay connections
+//!
+//! [`Backoff`] computes exponentially growing delays with jitter, and [`ConnectionSupervisor`]
+//! uses it to decide when a dropped connection should be retried. Neither does any IO or keeps
+//! a clock: the caller passes the current unix time and a random number from its own RNG.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::backoff::{Backoff, ConnectionState, ConnectionSupervisor};
+//! // start at 2s, never wait longer than 5 minutes
+//! let mut supervisor = ConnectionSupervisor::new(Backoff::new(2, 300));
+//! let now = 1686880020;
+//! if supervisor.should_connect(now) {
+//!     // open the websocket...
+//!     let connected = false;
+//!     if connected {
+//!         supervisor.on_connected();
+//!     } else {
+//!         // pass a value from the device's RNG for jitter
+//!         supervisor.on_disconnected(now, 0x1234_5678);
+//!     }
+//! }
+//! assert_eq!(supervisor.state(), ConnectionState::Disconnected);
+//! assert!(!supervisor.should_connect(now));
+//! ```
+
+/// Exponential backoff with jitter
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: u32,
+    max: u32,
+    attempt: u8,
+}
+
+impl Backoff {
+    /// Creates a backoff starting at `base` seconds and capped at `max` seconds
+    pub const fn new(base: u32, max: u32) -> Self {
+        Backoff {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Number of delays handed out since the last reset
+    pub fn attempt(&self) -> u8 {
+        self.attempt
+    }
+
+    /// Returns the delay in seconds before the next attempt, and doubles the one after it.
+    /// The delay is picked between half and all of the current interval using `random`,
+    /// so devices that lost a relay at the same time don't all reconnect at once.
+    pub fn next_delay(&mut self, random: u32) -> u32 {
+        let interval = self
+            .base
+            .saturating_mul(1 << self.attempt.min(31))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = interval / 2;
+        half + random % (interval - half + 1)
+    }
+
+    /// Starts again from `base`, call after a successful connection
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// State of a supervised connection
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectionState {
+    /// Not connected, waiting for the next attempt
+    Disconnected,
+    /// A connection attempt is in progress
+    Connecting,
+    /// Connected to the relay
+    Connected,
+}
+
+/// Decides when to (re)connect to a relay
+#[derive(Debug, Clone)]
+pub struct ConnectionSupervisor {
+    backoff: Backoff,
+    state: ConnectionState,
+    retry_at: u64,
+}
+
+impl ConnectionSupervisor {
+    /// Creates a disconnected supervisor that may connect right away
+    pub const fn new(backoff: Backoff) -> Self {
+        ConnectionSupervisor {
+            backoff,
+            state: ConnectionState::Disconnected,
+            retry_at: 0,
+        }
+    }
+
+    /// Current state of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Unix time of the next connection attempt
+    pub fn retry_at(&self) -> u64 {
+        self.retry_at
+    }
+
+    /// Returns true if a connection attempt should be started at `now`, and moves to `Connecting`
+    pub fn should_connect(&mut self, now: u64) -> bool {
+        if self.state == ConnectionState::Disconnected && now >= self.retry_at {
+            self.state = ConnectionState::Connecting;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records a successful connection and resets the backoff
+    pub fn on_connected(&mut self) {
+        self.state = ConnectionState::Connected;
+        self.backoff.reset();
+    }
+
+    /// Records a failed attempt or a dropped connection and schedules the next attempt.
+    /// - `random` is any value from the caller's RNG, used for jitter
+    pub fn on_disconnected(&mut self, now: u64, random: u32) {
+        self.state = ConnectionState::Disconnected;
+        self.retry_at = now.saturating_add(self.backoff.next_delay(random).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_bounds() {
+        let mut backoff = Backoff::new(2, 20);
+        // with no jitter the delay is half the interval, with full jitter all of it
+        assert_eq!(backoff.next_delay(0), 1);
+        assert_eq!(backoff.next_delay(2), 4);
+        let delays = [
+            backoff.next_delay(7),
+            backoff.next_delay(7),
+            backoff.next_delay(7),
+        ];
+        assert!(delays.iter().all(|d| (4..=20).contains(d)));
+        // capped at max
+        assert!((10..=20).contains(&backoff.next_delay(123)));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(0), 1);
+    }
+
+    #[test]
+    fn test_supervisor() {
+        let mut supervisor = ConnectionSupervisor::new(Backoff::new(10, 100));
+        assert!(supervisor.should_connect(0));
+        assert!(!supervisor.should_connect(0));
+        supervisor.on_disconnected(1000, 0);
+        assert_eq!(supervisor.retry_at(), 1005);
+        assert!(!supervisor.should_connect(1004));
+        assert!(supervisor.should_connect(1005));
+        supervisor.on_connected();
+        assert_eq!(supervisor.state(), ConnectionState::Connected);
+        supervisor.on_disconnected(2000, 0);
+        // backoff was reset by the connection
+        assert_eq!(supervisor.retry_at(), 2005);
+    }
+}
@@ -0,0 +1,356 @@
+//! Compact binary encoding of notes for storage
+//!
+//! Ids, pubkeys and signatures are stored as raw bytes rather than hex, and no JSON
+//! punctuation is kept, so an encoded note takes roughly half the space of its JSON form
+//! and decodes without any parsing.
+//!
+//! Layout (all integers little endian):
+//!
+//! | field      | size                                           |
+//! |------------|------------------------------------------------|
+//! | version    | 1 byte, currently `4`                          |
+//! | id         | 32 bytes                                       |
+//! | pubkey     | 32 bytes                                       |
+//! | sig        | 64 bytes                                       |
+//! | created_at | varint                                         |
+//! | kind       | varint                                         |
+//! | content    | varint length followed by the UTF-8 bytes      |
+//! | tags       | 1 byte count, each tag described below         |
+//!
+//! Varints are LEB128: 7 bits per byte, lowest first, with the top bit set on every byte but the
+//! last. The created_at is a u64 and takes up to 10 bytes. Each tag is a 1 byte count of its
+//! strings (the name followed by the values, none for the empty tag `[]`), then each string as a
+//! 1 byte length followed by the UTF-8 bytes.
+//!
+//! Notes stored with any other version are rejected.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, Vec};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut stored: Vec<u8, 300> = Vec::new();
+//! note.encode_binary(&mut stored).unwrap();
+//! assert_eq!(Note::decode_binary(&stored).unwrap(), note);
+//!
+//! // or straight into a flash page
+//! let mut page = [0xFF_u8; 256];
+//! let len = note.to_binary(&mut page).unwrap();
+//! assert_eq!(Note::from_binary(&page[..len]).unwrap(), note);
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    buffer::{OutBuf, SliceBuf},
+    errors::Error,
+    tag::{Tag, MAX_TAG_VALUES, TAG_NAME_SIZE, TAG_VALUE_SIZE},
+    Note,
+};
+
+/// Version byte written at the start of every encoded note
+pub const BINARY_VERSION: u8 = 4;
+
+/// Largest possible encoded tag
+const MAX_BINARY_TAG_SIZE: usize = 1 + 1 + TAG_NAME_SIZE + MAX_TAG_VALUES * (1 + TAG_VALUE_SIZE);
+
+/// Largest possible varint of a u32
+const MAX_VARINT_SIZE: usize = 5;
+/// Largest possible varint of a u64
+const MAX_VARINT64_SIZE: usize = 10;
+
+/// Largest possible encoded note
+pub const MAX_BINARY_SIZE: usize = 1
+    + 32
+    + 32
+    + 64
+    + MAX_VARINT64_SIZE
+    + MAX_VARINT_SIZE
+    + varint_size(crate::NOTE_SIZE as u64)
+    + crate::NOTE_SIZE
+    + 1
+    + crate::MAX_TAGS * MAX_BINARY_TAG_SIZE;
+
+/// Number of bytes `value` takes as a varint
+const fn varint_size(mut value: u64) -> usize {
+    let mut size = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        size += 1;
+    }
+    size
+}
+
+/// Writes `value` as a varint
+fn push_varint<B: OutBuf>(mut value: u64, out: &mut B) -> Result<(), Error> {
+    while value >= 0x80 {
+        out.push_byte(value as u8 | 0x80)?;
+        value >>= 7;
+    }
+    out.push_byte(value as u8)
+}
+
+/// Reads fixed size fields from the front of a byte slice
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::MalformedContent);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        self.take(N)?
+            .try_into()
+            .map_err(|_| Error::MalformedContent)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Errors with `MalformedContent` if the varint doesn't fit in a u32
+    fn varint(&mut self) -> Result<u32, Error> {
+        u32::try_from(self.varint64()?).map_err(|_| Error::MalformedContent)
+    }
+
+    /// Errors with `MalformedContent` if the varint doesn't fit in a u64
+    fn varint64(&mut self) -> Result<u64, Error> {
+        let mut value: u64 = 0;
+        for shift in (0..MAX_VARINT64_SIZE as u32).map(|i| i * 7) {
+            let byte = self.u8()?;
+            let bits = u64::from(byte & 0x7F);
+            if shift > 0 && bits.leading_zeros() < shift {
+                return Err(Error::MalformedContent);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::MalformedContent)
+    }
+
+    fn str(&mut self, len: usize) -> Result<&'a str, Error> {
+        core::str::from_utf8(self.take(len)?).map_err(|_| Error::MalformedContent)
+    }
+}
+
+/// Copies `value` into a fixed capacity string
+fn to_string<const N: usize>(value: &str) -> Result<String<N>, Error> {
+    let mut output = String::new();
+    output.push_str(value).map_err(|_| Error::ContentOverflow)?;
+    Ok(output)
+}
+
+impl Note {
+    /// Writes the note in the compact binary layout described in the [module docs](crate::binary).
+    /// At most [`MAX_BINARY_SIZE`] bytes are written.
+    pub fn encode_binary<B: OutBuf>(&self, out: &mut B) -> Result<(), Error> {
+        out.push_byte(BINARY_VERSION)?;
+        out.push_slice(&self.id)?;
+        out.push_slice(&self.pubkey)?;
+        out.push_slice(&self.sig)?;
+        push_varint(self.created_at, out)?;
+        push_varint(self.kind.as_u32().into(), out)?;
+        let content = self.content.as_ref().map(|c| c.as_str()).unwrap_or("");
+        push_varint(content.len() as u64, out)?;
+        out.push_slice(content.as_bytes())?;
+        out.push_byte(self.tags.len() as u8)?;
+        for tag in self.tags.iter() {
+            if tag.is_empty() {
+                out.push_byte(0)?;
+                continue;
+            }
+            out.push_byte(1 + tag.values().count() as u8)?;
+            for part in core::iter::once(tag.name()).chain(tag.values()) {
+                out.push_byte(part.len() as u8)?;
+                out.push_slice(part.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [`Note::encode_binary`], writing to the start of `buf` and returning the number
+    /// of bytes written.
+    /// Errors with `ContentOverflow` if the note doesn't fit in `buf`.
+    pub fn to_binary(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut out = SliceBuf::new(buf);
+        self.encode_binary(&mut out)?;
+        Ok(out.len())
+    }
+
+    /// Same as [`Note::decode_binary`], the counterpart of [`Note::to_binary`]
+    pub fn from_binary(data: &[u8]) -> Result<Note, Error> {
+        Note::decode_binary(data)
+    }
+
+    /// Reads a note written by [`Note::encode_binary`].
+    /// The signature is not checked, call [`Note::validate_signature`] if the storage is not trusted.
+    /// Errors with `MalformedContent` if the data is cut short or written with another version.
+    pub fn decode_binary(data: &[u8]) -> Result<Note, Error> {
+        let mut reader = Reader { data };
+        if reader.u8()? != BINARY_VERSION {
+            return Err(Error::MalformedContent);
+        }
+        let id = reader.array()?;
+        let pubkey = reader.array()?;
+        let sig = reader.array()?;
+        let created_at = reader.varint64()?;
+        let kind = reader.varint()?;
+        let content_len = reader.varint()? as usize;
+
+        let content = reader.str(content_len)?;
+        let content = if content.is_empty() {
+            None
+        } else {
+            Some(to_string(content)?)
+        };
+
+        let tag_count = reader.u8()?;
+        let mut tags = Vec::new();
+        for _ in 0..tag_count {
+            let part_count = reader.u8()?;
+            if part_count == 0 {
+                tags.push(Tag::empty()).map_err(|_| Error::TooManyTags)?;
+                continue;
+            }
+            let name_len = reader.u8()? as usize;
+            let mut tag = Tag::new(reader.str(name_len)?)?;
+            for _ in 1..part_count {
+                let value_len = reader.u8()? as usize;
+                tag.push_value(reader.str(value_len)?)?;
+            }
+            tags.push(tag).map_err(|_| Error::TooManyTags)?;
+        }
+
+        Ok(Note {
+            id,
+            pubkey,
+            created_at,
+            kind: kind.into(),
+            tags,
+            content,
+            sig,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+
+    #[test]
+    fn test_round_trip() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .add_tag(Tag::try_from(["l", "bitcoin"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut stored: Vec<u8, MAX_BINARY_SIZE> = Vec::new();
+        note.encode_binary(&mut stored).unwrap();
+        // 1 + 32 + 32 + 64 + 5 + 1 + (1 + 7) + (1 + 1 + (1 + 1) + (1 + 7))
+        assert_eq!(stored.len(), 155);
+        let decoded = Note::decode_binary(&stored).unwrap();
+        assert_eq!(decoded, note);
+        assert!(decoded.validate_signature().is_ok());
+
+        let mut page = [0_u8; MAX_BINARY_SIZE];
+        assert_eq!(note.to_binary(&mut page), Ok(155));
+        assert_eq!(&page[..155], stored.as_slice());
+        assert_eq!(Note::from_binary(&page[..155]), Ok(note.clone()));
+        assert_eq!(
+            note.to_binary(&mut page[..154]),
+            Err(Error::ContentOverflow)
+        );
+    }
+
+    #[test]
+    fn test_empty_tag() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tags([Tag::empty(), Tag::try_from(["t", "esp"]).unwrap()])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut stored: Vec<u8, MAX_BINARY_SIZE> = Vec::new();
+        note.encode_binary(&mut stored).unwrap();
+        assert_eq!(Note::decode_binary(&stored), Ok(note));
+    }
+
+    #[test]
+    fn test_varint() {
+        for (value, size) in [(0, 1), (127, 1), (128, 2), (30078, 3), (u32::MAX, 5)] {
+            let mut out: Vec<u8, MAX_VARINT_SIZE> = Vec::new();
+            push_varint(value.into(), &mut out).unwrap();
+            assert_eq!(out.len(), size);
+            assert_eq!(varint_size(value.into()), size);
+            assert_eq!(Reader { data: &out }.varint(), Ok(value));
+        }
+        assert_eq!(
+            Reader {
+                data: &[0xFF, 0xFF, 0xFF, 0xFF, 0x10]
+            }
+            .varint(),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            Reader { data: &[0x80; 6] }.varint(),
+            Err(Error::MalformedContent)
+        );
+
+        for (value, size) in [(1 << 32, 5), (1 << 35, 6), (u64::MAX, MAX_VARINT64_SIZE)] {
+            let mut out: Vec<u8, MAX_VARINT64_SIZE> = Vec::new();
+            push_varint(value, &mut out).unwrap();
+            assert_eq!(out.len(), size);
+            assert_eq!(varint_size(value), size);
+            assert_eq!(Reader { data: &out }.varint64(), Ok(value));
+        }
+        let mut too_big = [0xFF_u8; MAX_VARINT64_SIZE];
+        too_big[MAX_VARINT64_SIZE - 1] = 0x02;
+        assert_eq!(
+            Reader { data: &too_big }.varint64(),
+            Err(Error::MalformedContent)
+        );
+    }
+
+    #[test]
+    fn test_other_versions() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut stored: Vec<u8, MAX_BINARY_SIZE> = Vec::new();
+        note.encode_binary(&mut stored).unwrap();
+        for version in [0, BINARY_VERSION - 1, BINARY_VERSION + 1] {
+            stored[0] = version;
+            assert_eq!(Note::decode_binary(&stored), Err(Error::MalformedContent));
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut stored: Vec<u8, MAX_BINARY_SIZE> = Vec::new();
+        note.encode_binary(&mut stored).unwrap();
+        assert_eq!(
+            Note::decode_binary(&stored[..stored.len() - 1]),
+            Err(Error::MalformedContent)
+        );
+    }
+}
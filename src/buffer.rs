@@ -0,0 +1,375 @@
+//! Output buffers used by the serializers
+//!
+//! Every serializer in this crate writes through the [`OutBuf`] trait, so the destination
+//! can be a `heapless::Vec` or a plain byte slice (for example a static buffer or one placed
+//! by the linker).
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::buffer::{OutBuf, SliceBuf, StoredBuf};
+//! let mut storage = [0_u8; 16];
+//! let mut buf = SliceBuf::new(&mut storage);
+//! buf.push_slice(b"hello").unwrap();
+//! assert_eq!(buf.as_slice(), b"hello");
+//! ```
+//!
+//! # Streaming
+//! [`StreamBuf`] and [`FmtBuf`] pass each piece on as soon as it is serialized instead of
+//! keeping it, so a large event can go straight into a socket without the whole JSON ever
+//! being held in RAM.
+//!
+//! ```
+//! use nostr_nostd::{buffer::StreamBuf, query::Query};
+//! let mut sent = 0;
+//! let mut socket = StreamBuf::new(|bytes: &[u8]| {
+//!     // hand `bytes` to the TCP stack here
+//!     sent += bytes.len();
+//!     Ok(())
+//! });
+//! Query::new().serialize_to_buf("sub_1", &mut socket).unwrap();
+//! assert_eq!(sent, r#"["REQ","sub_1",{}]"#.len());
+//! ```
+//!
+//! # Scratch buffers
+//! Encrypting or decrypting a DM needs around 1.5KB of working memory, which is too much for
+//! tasks with small stacks. The `_in` variants
+//! ([`NoteBuilder::create_dm_in`](crate::NoteBuilder::create_dm_in),
+//! [`Note::read_dm_in`](crate::Note::read_dm_in)) take that memory as a `scratch` slice instead.
+//! The [proof of work](crate::pow) op keeps the hashed form of the note in one, up to
+//! [`MAX_NOTE_HASH_SIZE`] bytes.
+//! With the `static-pool` feature, [`StaticPool`] can hand out a `'static` scratch buffer once at start up.
+
+use heapless::Vec;
+
+use crate::errors::Error;
+use crate::{nip04::DM_SCRATCH_SIZE, MAX_NOTE_HASH_SIZE};
+
+/// Size of a scratch buffer large enough for any of the `_in` functions, and for the hashed
+/// form of any default sized note
+pub const SCRATCH_SIZE: usize = if MAX_NOTE_HASH_SIZE > DM_SCRATCH_SIZE {
+    MAX_NOTE_HASH_SIZE
+} else {
+    DM_SCRATCH_SIZE
+};
+
+/// A growable byte buffer that serializers can write into
+pub trait OutBuf {
+    /// Appends `data` to the end of the buffer.
+    /// Errors with `ContentOverflow` if the buffer does not have enough room left,
+    /// in which case nothing is written.
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Number of bytes written so far
+    fn len(&self) -> usize;
+
+    /// True if nothing has been written yet
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a single byte
+    fn push_byte(&mut self, byte: u8) -> Result<(), Error> {
+        self.push_slice(&[byte])
+    }
+}
+
+/// An [`OutBuf`] that keeps what is written to it, unlike [`StreamBuf`] and [`FmtBuf`]
+pub trait StoredBuf: OutBuf {
+    /// The bytes written so far
+    fn as_slice(&self) -> &[u8];
+}
+
+impl<const N: usize> OutBuf for Vec<u8, N> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(data)
+            .map_err(|_| Error::ContentOverflow)
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+}
+
+impl<const N: usize> StoredBuf for Vec<u8, N> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl OutBuf for alloc::vec::Vec<u8> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl StoredBuf for alloc::vec::Vec<u8> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Wraps a `&mut [u8]` and keeps track of how much of it has been written
+pub struct SliceBuf<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceBuf<'a> {
+    /// Creates an empty buffer backed by `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        SliceBuf { buf, len: 0 }
+    }
+
+    /// Total number of bytes the buffer can hold
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Discards everything written so far
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Consumes the wrapper, returning the written part of the underlying slice
+    pub fn into_slice(self) -> &'a mut [u8] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for SliceBuf<'a> {
+    fn from(buf: &'a mut [u8]) -> Self {
+        SliceBuf::new(buf)
+    }
+}
+
+impl OutBuf for SliceBuf<'_> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(Error::ContentOverflow);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl StoredBuf for SliceBuf<'_> {
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<B: OutBuf + ?Sized> OutBuf for &mut B {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        (**self).push_slice(data)
+    }
+
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+}
+
+impl<B: StoredBuf + ?Sized> StoredBuf for &mut B {
+    fn as_slice(&self) -> &[u8] {
+        (**self).as_slice()
+    }
+}
+
+/// Passes everything written on to `F` as it arrives, such as a socket's write function.
+/// Nothing is kept, so it isn't a [`StoredBuf`], but [`OutBuf::len`] counts the
+/// bytes passed on. Errors returned by `F` stop the serializer.
+pub struct StreamBuf<F> {
+    write: F,
+    len: usize,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> StreamBuf<F> {
+    /// Creates a stream that hands each piece to `write`
+    pub fn new(write: F) -> Self {
+        StreamBuf { write, len: 0 }
+    }
+
+    /// Consumes the stream, returning `write`
+    pub fn into_inner(self) -> F {
+        self.write
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> OutBuf for StreamBuf<F> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        (self.write)(data)?;
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Passes everything written on to a [`core::fmt::Write`], such as a `heapless::String` or a
+/// UART driver. Like [`StreamBuf`], nothing is kept.
+/// Errors with `ContentOverflow` if the writer fails.
+pub struct FmtBuf<W> {
+    writer: W,
+    len: usize,
+}
+
+impl<W: core::fmt::Write> FmtBuf<W> {
+    /// Creates a stream into `writer`
+    pub fn new(writer: W) -> Self {
+        FmtBuf { writer, len: 0 }
+    }
+
+    /// Consumes the stream, returning `writer`
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: core::fmt::Write> OutBuf for FmtBuf<W> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        // serializers only split their output between characters
+        let data = core::str::from_utf8(data).map_err(|_| Error::EncodeError)?;
+        self.writer
+            .write_str(data)
+            .map_err(|_| Error::ContentOverflow)?;
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A statically allocated buffer that can be taken exactly once.
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "static-pool")]
+/// # {
+/// use nostr_nostd::buffer::{StaticPool, SCRATCH_SIZE};
+/// static POOL: StaticPool<SCRATCH_SIZE> = StaticPool::new();
+/// let scratch: &'static mut [u8; SCRATCH_SIZE] = POOL.take().unwrap();
+/// assert!(POOL.take().is_none());
+/// # }
+/// ```
+#[cfg(feature = "static-pool")]
+pub struct StaticPool<const N: usize> {
+    taken: core::sync::atomic::AtomicBool,
+    buf: core::cell::UnsafeCell<[u8; N]>,
+}
+
+// Safety: the buffer is only reachable through `take`, which hands it out at most once
+#[cfg(feature = "static-pool")]
+unsafe impl<const N: usize> Sync for StaticPool<N> {}
+
+#[cfg(feature = "static-pool")]
+impl<const N: usize> StaticPool<N> {
+    /// Creates a zeroed pool, usable in a `static`
+    pub const fn new() -> Self {
+        StaticPool {
+            taken: core::sync::atomic::AtomicBool::new(false),
+            buf: core::cell::UnsafeCell::new([0; N]),
+        }
+    }
+
+    /// Returns the buffer the first time it is called and `None` afterwards
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(&'static self) -> Option<&'static mut [u8; N]> {
+        if self.taken.swap(true, core::sync::atomic::Ordering::AcqRel) {
+            None
+        } else {
+            // Safety: `taken` guarantees this is the only reference ever created
+            Some(unsafe { &mut *self.buf.get() })
+        }
+    }
+}
+
+#[cfg(feature = "static-pool")]
+impl<const N: usize> Default for StaticPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vec_buf() {
+        let mut buf: Vec<u8, 4> = Vec::new();
+        buf.push_slice(b"ab").unwrap();
+        buf.push_byte(b'c').unwrap();
+        assert_eq!(StoredBuf::as_slice(&buf), b"abc");
+        assert_eq!(buf.push_slice(b"de"), Err(Error::ContentOverflow));
+        assert_eq!(OutBuf::len(&buf), 3);
+    }
+
+    #[test]
+    fn test_slice_buf() {
+        let mut storage = [0_u8; 4];
+        let mut buf = SliceBuf::new(&mut storage);
+        buf.push_slice(b"abcd").unwrap();
+        assert_eq!(buf.push_byte(b'e'), Err(Error::ContentOverflow));
+        assert_eq!(buf.as_slice(), b"abcd");
+        buf.clear();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_stream() {
+        let note = crate::vectors::text_note();
+        let mut whole: Vec<u8, 1000> = Vec::new();
+        note.serialize_to_buf(crate::ClientMsgKinds::Event, &mut whole)
+            .unwrap();
+
+        let mut streamed: Vec<u8, 1000> = Vec::new();
+        let mut largest = 0;
+        let mut stream = StreamBuf::new(|bytes: &[u8]| {
+            largest = largest.max(bytes.len());
+            streamed.push_slice(bytes)
+        });
+        note.serialize_to_buf(crate::ClientMsgKinds::Event, &mut stream)
+            .unwrap();
+        assert_eq!(stream.len(), whole.len());
+        assert_eq!(streamed, whole);
+        assert!(largest <= 128);
+
+        let mut stream = StreamBuf::new(|_: &[u8]| Err(Error::QueueFull));
+        assert_eq!(
+            note.serialize_to_buf(crate::ClientMsgKinds::Event, &mut stream),
+            Err(Error::QueueFull)
+        );
+    }
+
+    #[test]
+    fn test_fmt() {
+        let note = crate::vectors::text_note();
+        let mut out = FmtBuf::new(heapless::String::<1000>::new());
+        note.serialize_to_buf(crate::ClientMsgKinds::Event, &mut out)
+            .unwrap();
+        let json = out.into_inner();
+        assert!(json.starts_with(r#"["EVENT",{"content":"#));
+
+        let mut out = FmtBuf::new(heapless::String::<10>::new());
+        assert_eq!(
+            note.serialize_to_buf(crate::ClientMsgKinds::Event, &mut out),
+            Err(Error::ContentOverflow)
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! Transport-agnostic client for a single relay
+//!
+//! [`Client`] ties together the pieces a device needs to talk to a relay: it answers `AUTH`
+//! challenges, keeps published notes in an [`Outbox`] until the relay confirms them with `OK`,
+//! and tracks subscriptions through their `EOSE` and `CLOSED` messages. It does no networking,
+//! the caller feeds it every frame received with [`Client::receive`] and sends whatever
+//! [`Client::poll_transmit`] writes out.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     client::{Client, ClientEvent},
+//!     keys::{AlignedType, Keys, CONTEXT_SIZE},
+//!     outbox::Outbox,
+//!     query::Query,
+//!     NoteBuffer,
+//! };
+//! let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+//! let keys = Keys::new(
+//!     "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3",
+//!     &mut context,
+//! )
+//! .unwrap();
+//! let mut client: Client<4, 8> =
+//!     Client::new(keys, "wss://relay.example.com", Outbox::new(30, 5)).unwrap();
+//! let mut frame = NoteBuffer::new();
+//! client.subscribe(&Query::new(), &mut frame).unwrap();
+//! // send frame to the relay
+//!
+//! let now = 1686880020;
+//! let event = client.receive(br#"["AUTH","challenge_me"]"#).unwrap();
+//! assert_eq!(event, ClientEvent::AuthRequested);
+//! frame.clear();
+//! while client.poll_transmit(now, [0; 32], &mut frame).unwrap() {
+//!     // send frame to the relay
+//!     frame.clear();
+//! }
+//! // notes the relay never answered are dropped after their last attempt
+//! while let Some(event) = client.poll_expired(now) {
+//!     assert!(matches!(event, ClientEvent::GaveUp(_)));
+//! }
+//! ```
+
+use heapless::String;
+
+use crate::{
+    buffer::OutBuf,
+    errors::Error,
+    hex::HexId,
+    keys::Keys,
+    outbox::{OkOutcome, Outbox},
+    pool::RELAY_URL_SIZE,
+    query::Query,
+    relay_responses::{AuthMessage, NoticeMessage, RelayMessage},
+    seen::SeenCache,
+    subscription_manager::{Received, SubscriptionManager},
+    ClientMsgKinds, Note,
+};
+
+/// Progress of authenticating to the relay
+#[derive(Debug, PartialEq)]
+pub enum AuthState {
+    /// The relay hasn't asked for authentication
+    None,
+    /// A challenge arrived and the reply hasn't been sent yet
+    Challenged(AuthMessage),
+    /// The reply with this event id was sent, waiting for the relay's `OK`
+    Sent(HexId),
+    /// The relay accepted the reply
+    Authenticated,
+    /// The relay rejected the reply, with its reason
+    Failed(String<180>),
+}
+
+/// What a frame received from the relay meant
+// an Event holds a whole note, there is no heap to box it on
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum ClientEvent {
+    /// An event arrived on the subscription in `slot`
+    Event { slot: usize, note: Note },
+    /// An event already seen arrived on the subscription in `slot`, see
+    /// [`Client::receive_unseen`]
+    Duplicate { slot: usize },
+    /// The subscription in `slot` has received every stored event
+    Eose { slot: usize },
+    /// The relay ended the subscription in `slot`, its reason is in the subscription's state
+    Closed { slot: usize },
+    /// The relay counted `count` events for the subscription in `slot`
+    Count { slot: usize, count: u16 },
+    /// The relay asked for authentication, the reply is waiting in [`Client::poll_transmit`]
+    AuthRequested,
+    /// The relay accepted the authentication reply
+    Authenticated,
+    /// The relay rejected the authentication reply
+    AuthFailed,
+    /// A published note was accepted
+    Published(HexId),
+    /// A published note was rejected and will be retried
+    WillRetry(HexId),
+    /// A published note was rejected for good, or ran out of attempts, and was dropped
+    GaveUp(HexId),
+    /// A human-readable message from the relay
+    Notice(NoticeMessage),
+    /// The message was about a subscription or note this client doesn't know
+    Ignored,
+}
+
+/// Client state for one relay connection, with up to `SUBS` subscriptions and `OUTBOX` notes
+/// waiting to be confirmed
+pub struct Client<'buf, const SUBS: usize, const OUTBOX: usize> {
+    keys: Keys<'buf>,
+    relay_url: String<RELAY_URL_SIZE>,
+    auth: AuthState,
+    subscriptions: SubscriptionManager<SUBS>,
+    outbox: Outbox<OUTBOX>,
+}
+
+impl<'buf, const SUBS: usize, const OUTBOX: usize> Client<'buf, SUBS, OUTBOX> {
+    /// Creates a client signing with `keys` for the relay at `relay_url`, which is named in
+    /// authentication replies.
+    /// Errors with `ContentOverflow` if `relay_url` is longer than [`RELAY_URL_SIZE`].
+    pub fn new(keys: Keys<'buf>, relay_url: &str, outbox: Outbox<OUTBOX>) -> Result<Self, Error> {
+        let mut url = String::new();
+        url.push_str(relay_url)
+            .map_err(|_| Error::ContentOverflow)?;
+        Ok(Client {
+            keys,
+            relay_url: url,
+            auth: AuthState::None,
+            subscriptions: SubscriptionManager::new(),
+            outbox,
+        })
+    }
+
+    /// Keys the client signs with
+    pub fn keys(&self) -> &Keys<'buf> {
+        &self.keys
+    }
+
+    /// Where authentication to the relay stands
+    pub fn auth_state(&self) -> &AuthState {
+        &self.auth
+    }
+
+    /// Subscriptions held by the client
+    pub fn subscriptions(&self) -> &SubscriptionManager<SUBS> {
+        &self.subscriptions
+    }
+
+    /// Notes waiting to be confirmed by the relay
+    pub fn outbox(&self) -> &Outbox<OUTBOX> {
+        &self.outbox
+    }
+
+    /// Queues `note` to be sent by [`Client::poll_transmit`] until the relay accepts it.
+    /// Errors with `QueueFull` if `OUTBOX` notes are already waiting. Notes the relay never
+    /// answered are only dropped by [`Client::poll_expired`].
+    pub fn publish(&mut self, note: Note) -> Result<(), Error> {
+        self.outbox.push(note)
+    }
+
+    /// Starts a subscription, writing its `REQ` into `out` and returning its slot.
+    /// On error `out` may end in an unfinished `REQ`, see [`SubscriptionManager::subscribe`].
+    pub fn subscribe<B: OutBuf>(&mut self, query: &Query, out: &mut B) -> Result<usize, Error> {
+        self.subscriptions.subscribe(query, out)
+    }
+
+    /// Ends the subscription in `slot`, writing its `CLOSE` into `out` if the relay still has it open
+    pub fn unsubscribe<B: OutBuf>(&mut self, slot: usize, out: &mut B) -> Result<(), Error> {
+        self.subscriptions.close(slot, out)
+    }
+
+    /// Forgets state tied to the connection, call when it drops.
+    /// Subscriptions are released, since the relay drops them too, and queued notes are kept.
+    pub fn on_disconnected(&mut self) {
+        self.auth = AuthState::None;
+        self.subscriptions.clear();
+    }
+
+    /// Handles a frame received from the relay.
+    /// Errors if the frame isn't a relay message this crate can parse.
+    pub fn receive(&mut self, frame: &[u8]) -> Result<ClientEvent, Error> {
+        let msg = RelayMessage::try_from(frame)?;
+        let slot = self.subscriptions.handle(&msg);
+        self.handle(msg, slot)
+    }
+
+    /// Updates the client's state with a message parsed from a received frame, already
+    /// recorded against the subscription in `slot`
+    fn handle(&mut self, msg: RelayMessage, slot: Option<usize>) -> Result<ClientEvent, Error> {
+        Ok(match msg {
+            RelayMessage::Auth(auth) => {
+                self.auth = AuthState::Challenged(auth);
+                ClientEvent::AuthRequested
+            }
+            RelayMessage::Ok(ok) => {
+                if let AuthState::Sent(id) = &self.auth {
+                    if *id == ok.event_id {
+                        return Ok(if ok.accepted {
+                            self.auth = AuthState::Authenticated;
+                            ClientEvent::Authenticated
+                        } else {
+                            self.auth = AuthState::Failed(ok.info);
+                            ClientEvent::AuthFailed
+                        });
+                    }
+                }
+                let id = ok.event_id;
+                match self.outbox.handle_ok(&ok) {
+                    Some(OkOutcome::Delivered) => ClientEvent::Published(id),
+                    Some(OkOutcome::WillRetry) => ClientEvent::WillRetry(id),
+                    Some(OkOutcome::GaveUp) => ClientEvent::GaveUp(id),
+                    None => ClientEvent::Ignored,
+                }
+            }
+            RelayMessage::Notice(notice) => ClientEvent::Notice(notice),
+            RelayMessage::Event(event) => match slot {
+                Some(slot) => ClientEvent::Event {
+                    slot,
+                    note: event.note,
+                },
+                None => ClientEvent::Ignored,
+            },
+            RelayMessage::Eose(_) => {
+                slot.map_or(ClientEvent::Ignored, |slot| ClientEvent::Eose { slot })
+            }
+            RelayMessage::Closed(_) => {
+                slot.map_or(ClientEvent::Ignored, |slot| ClientEvent::Closed { slot })
+            }
+            RelayMessage::Count(count) => {
+                slot.map_or(ClientEvent::Ignored, |slot| ClientEvent::Count {
+                    slot,
+                    count: count.count,
+                })
+            }
+        })
+    }
+
+    /// Same as [`Client::receive`], but an `EVENT` whose id is in `seen` is reported as
+    /// [`ClientEvent::Duplicate`] without copying the note out or checking its signature. Ids of
+    /// events that pass the checks are added to `seen`, which can be shared with the clients of
+    /// other relays subscribed to the same filters.
+    pub fn receive_unseen<const N: usize>(
+        &mut self,
+        frame: &[u8],
+        seen: &mut SeenCache<N>,
+    ) -> Result<ClientEvent, Error> {
+        match self.subscriptions.handle_unseen(frame, seen)? {
+            Received::Duplicate { slot } => Ok(ClientEvent::Duplicate { slot }),
+            Received::Message { slot, msg } => self.handle(msg, slot),
+        }
+    }
+
+    /// Writes the next frame to send to the relay into `out`, returning false if there is
+    /// nothing to send. An authentication reply goes first, signed with `aux_rand`, then notes
+    /// due at unix time `now`.
+    pub fn poll_transmit<B: OutBuf>(
+        &mut self,
+        now: u64,
+        aux_rand: [u8; 32],
+        out: &mut B,
+    ) -> Result<bool, Error> {
+        if let AuthState::Challenged(auth) = &self.auth {
+            let reply = Note::builder_with_keys(&self.keys)
+                .create_auth(auth, &self.relay_url)?
+                .build_with_keys(&self.keys, now, aux_rand)?;
+            reply.serialize_to_buf(ClientMsgKinds::Auth, out)?;
+            self.auth = AuthState::Sent(reply.hex_id());
+            return Ok(true);
+        }
+        match self.outbox.next_due(now) {
+            Some(note) => {
+                note.serialize_to_buf(ClientMsgKinds::Event, out)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Drops a published note that used up its attempts without the relay answering, once the
+    /// retry delay at unix time `now` has passed, and reports it as [`ClientEvent::GaveUp`].
+    /// Call it regularly, along with [`Client::poll_transmit`], to free its outbox slot.
+    pub fn poll_expired(&mut self, now: u64) -> Option<ClientEvent> {
+        self.outbox
+            .poll_expired(now)
+            .map(|note| ClientEvent::GaveUp(note.hex_id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::{
+        keys::{AlignedType, CONTEXT_SIZE},
+        nip42::verify_auth_event,
+        subscription_manager::SubscriptionState,
+        vectors::PRIVKEY,
+        NoteBuffer,
+    };
+    const RELAY: &str = "wss://relay.example.com";
+
+    fn ok_frame(id: &[u8; 64], accepted: bool, info: &str) -> Vec<u8, 256> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(br#"["OK",""#).unwrap();
+        frame.extend_from_slice(id).unwrap();
+        let accepted: &[u8] = if accepted { b"true" } else { b"false" };
+        frame.extend_from_slice(b"\",").unwrap();
+        frame.extend_from_slice(accepted).unwrap();
+        frame.extend_from_slice(b",\"").unwrap();
+        frame.extend_from_slice(info.as_bytes()).unwrap();
+        frame.extend_from_slice(b"\"]").unwrap();
+        frame
+    }
+
+    #[test]
+    fn test_auth_and_publish() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let mut client: Client<2, 2> = Client::new(keys, RELAY, Outbox::new(10, 3)).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let note_id = note.hex_id();
+        client.publish(note).unwrap();
+
+        let mut frame = NoteBuffer::new();
+        assert!(client.poll_transmit(100, [0; 32], &mut frame).unwrap());
+        assert!(frame.starts_with(br#"["EVENT","#));
+        let rejected = ok_frame(&note_id.to_hex(), false, "auth-required: sign in first");
+        assert_eq!(
+            client.receive(&rejected),
+            Ok(ClientEvent::WillRetry(note_id))
+        );
+
+        assert_eq!(
+            client.receive(br#"["AUTH","challenge_me"]"#),
+            Ok(ClientEvent::AuthRequested)
+        );
+        frame.clear();
+        assert!(client.poll_transmit(105, [0; 32], &mut frame).unwrap());
+        let json = core::str::from_utf8(&frame[r#"["AUTH","#.len()..frame.len() - 1]).unwrap();
+        let auth = Note::try_from(json).unwrap();
+        assert_eq!(verify_auth_event(&auth, "challenge_me", RELAY, 105), Ok(()));
+        assert_eq!(client.auth_state(), &AuthState::Sent(auth.hex_id()));
+        assert_eq!(
+            client.receive(&ok_frame(&auth.id(), true, "")),
+            Ok(ClientEvent::Authenticated)
+        );
+
+        frame.clear();
+        assert!(!client.poll_transmit(105, [0; 32], &mut frame).unwrap());
+        assert!(client.poll_transmit(110, [0; 32], &mut frame).unwrap());
+        assert_eq!(
+            client.receive(&ok_frame(&note_id.to_hex(), true, "")),
+            Ok(ClientEvent::Published(note_id))
+        );
+        assert!(client.outbox().is_empty());
+    }
+
+    #[test]
+    fn test_unanswered_publish() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let mut client: Client<1, 1> = Client::new(keys, RELAY, Outbox::new(10, 2)).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let note_id = note.hex_id();
+        client.publish(note.clone()).unwrap();
+        let mut frame = NoteBuffer::new();
+        assert!(client.poll_transmit(100, [0; 32], &mut frame).unwrap());
+        frame.clear();
+        assert!(client.poll_transmit(110, [0; 32], &mut frame).unwrap());
+        assert_eq!(client.publish(note.clone()), Err(Error::QueueFull));
+        assert_eq!(client.poll_expired(129), None);
+        assert_eq!(client.poll_expired(130), Some(ClientEvent::GaveUp(note_id)));
+        assert!(client.publish(note).is_ok());
+    }
+
+    #[test]
+    fn test_receive_unseen() {
+        const EVENT: &[u8] = br#"["EVENT","sub_0",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+        let mut seen: SeenCache<8> = SeenCache::new();
+        let mut contexts = [[AlignedType::zeroed(); CONTEXT_SIZE]; 2];
+        let [first, second] = &mut contexts;
+        let mut clients: [Client<1, 1>; 2] = [
+            Client::new(
+                Keys::new(PRIVKEY, first).unwrap(),
+                RELAY,
+                Outbox::new(10, 3),
+            )
+            .unwrap(),
+            Client::new(
+                Keys::new(PRIVKEY, second).unwrap(),
+                RELAY,
+                Outbox::new(10, 3),
+            )
+            .unwrap(),
+        ];
+        let mut frame: Vec<u8, 128> = Vec::new();
+        for client in clients.iter_mut() {
+            frame.clear();
+            client.subscribe(&Query::new(), &mut frame).unwrap();
+        }
+
+        assert!(matches!(
+            clients[0].receive_unseen(EVENT, &mut seen),
+            Ok(ClientEvent::Event { slot: 0, .. })
+        ));
+        assert_eq!(
+            clients[1].receive_unseen(EVENT, &mut seen),
+            Ok(ClientEvent::Duplicate { slot: 0 })
+        );
+        assert_eq!(
+            clients[0].receive_unseen(EVENT, &mut seen),
+            Ok(ClientEvent::Duplicate { slot: 0 })
+        );
+        assert_eq!(clients[1].subscriptions().get(0).unwrap().events(), 0);
+        assert_eq!(seen.len(), 1);
+
+        // a copy with a bad signature isn't remembered, so the real one still gets through
+        let mut forged: Vec<u8, 1024> = Vec::from_slice(EVENT).unwrap();
+        let sig = forged.len() - 20;
+        forged[sig] = if forged[sig] == b'0' { b'1' } else { b'0' };
+        seen.clear();
+        assert!(clients[1].receive_unseen(&forged, &mut seen).is_err());
+        assert!(seen.is_empty());
+        assert!(matches!(
+            clients[1].receive_unseen(EVENT, &mut seen),
+            Ok(ClientEvent::Event { slot: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let mut client: Client<2, 2> = Client::new(keys, RELAY, Outbox::new(10, 3)).unwrap();
+        let mut frame: Vec<u8, 128> = Vec::new();
+        let slot = client.subscribe(&Query::new(), &mut frame).unwrap();
+        assert_eq!(frame, br#"["REQ","sub_0",{}]"#);
+        assert_eq!(
+            client.receive(br#"["EOSE","sub_0"]"#),
+            Ok(ClientEvent::Eose { slot })
+        );
+        assert_eq!(
+            client.receive(br#"["COUNT","sub_0",{"count":3}]"#),
+            Ok(ClientEvent::Count { slot, count: 3 })
+        );
+        assert_eq!(
+            client.receive(br#"["CLOSED","sub_0","error: shutting down"]"#),
+            Ok(ClientEvent::Closed { slot })
+        );
+        assert_eq!(
+            client.subscriptions().get(slot).unwrap().state(),
+            &SubscriptionState::Closed("error: shutting down".into())
+        );
+        assert_eq!(
+            client.receive(br#"["EOSE","sub_9"]"#),
+            Ok(ClientEvent::Ignored)
+        );
+        assert_eq!(
+            client.receive(br#"["NOTICE","hi"]"#),
+            Ok(ClientEvent::Notice(NoticeMessage {
+                message: "hi".into()
+            }))
+        );
+        client.on_disconnected();
+        assert!(client.subscriptions().is_empty());
+        assert_eq!(
+            client.receive(&ok_frame(&[b'0'; 64], true, "")),
+            Ok(ClientEvent::Ignored)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Handle messages from clients, for devices acting as a relay
+//!
+//! A small relay, such as a node on a mesh network, reads the same four messages a client
+//! writes with [`Note::serialize_to_relay`] and [`Query::serialize_to_relay`]. Notes are checked
+//! with [`Note::verify`] as they are parsed, so a relay never stores or forwards a forged one.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{client_messages::ClientMessage, query::Query};
+//! let mut query = Query::new();
+//! query
+//!     .add_author("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
+//!     .unwrap();
+//! let msg = query.serialize_to_relay("sub_1").unwrap();
+//!
+//! // on the relay
+//! let msg: ClientMessage = core::str::from_utf8(&msg).unwrap().try_into().unwrap();
+//! match msg {
+//!     ClientMessage::Req(subscription_id, filters) => {
+//!         assert_eq!(subscription_id, "sub_1");
+//!         assert_eq!(filters.filters.len(), 1);
+//!     }
+//!     ClientMessage::Event(_) => panic!("store and broadcast the note"),
+//!     ClientMessage::Close(_) => panic!("end the subscription"),
+//!     ClientMessage::Auth(_) => panic!("check the challenge"),
+//! }
+//! ```
+
+use heapless::String;
+
+use crate::{
+    errors::Error,
+    query::{MultiQuery, Query},
+    relay_responses::Tokens,
+    utils::unescape_json,
+    ClientMsgKinds, Note,
+};
+
+/// Most filters a parsed `REQ` holds by default
+pub const MAX_REQ_FILTERS: usize = 3;
+
+/// Any message a client sends, parsed along with its payload in one call
+// a note or several queries are held inline, there is no heap to box them on
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum ClientMessage<const FILTERS: usize = MAX_REQ_FILTERS> {
+    /// A note to store and send on to subscribers
+    Event(Note),
+    /// A subscription id and the filters of the subscription
+    Req(String<64>, MultiQuery<FILTERS>),
+    /// The id of a subscription to end
+    Close(String<64>),
+    /// A signed response to an `AUTH` challenge
+    Auth(Note),
+}
+
+impl TryFrom<&str> for ClientMsgKinds {
+    type Error = Error;
+
+    /// The type of a client message.
+    /// Errors with `InvalidType` if it isn't one a client sends.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let label = Tokens::new(value.as_bytes())
+            .and_then(|mut tokens| tokens.string())
+            .map_err(|_| Error::InvalidType)?;
+        match label {
+            "EVENT" => Ok(Self::Event),
+            "REQ" => Ok(Self::Req),
+            "CLOSE" => Ok(Self::Close),
+            "AUTH" => Ok(Self::Auth),
+            _ => Err(Error::InvalidType),
+        }
+    }
+}
+
+/// Unescapes a subscription id, which has to be 1 to 64 characters
+fn subscription_id(escaped: &str) -> Result<String<64>, Error> {
+    let id: String<64> = unescape_json(escaped)?;
+    if id.is_empty() {
+        return Err(Error::MalformedContent);
+    }
+    Ok(id)
+}
+
+impl<const FILTERS: usize> TryFrom<&str> for ClientMessage<FILTERS> {
+    type Error = Error;
+
+    /// Parses a client message, allowing whitespace between elements.
+    /// Errors with `InvalidType` for unknown message types, `Malformed` with the offset of the
+    /// element that couldn't be read or of anything after the closing bracket,
+    /// `MalformedContent` for an empty subscription id or a `REQ` without filters,
+    /// `QueryBuilderOverflow` if a `REQ` has more than `FILTERS` filters or a filter doesn't fit
+    /// in a [`Query`], and like [`Note::from_json`] for invalid notes.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let kind = ClientMsgKinds::try_from(value)?;
+        let mut tokens = Tokens::new(value.as_bytes())?;
+        tokens.string()?;
+        let msg = match kind {
+            ClientMsgKinds::Event | ClientMsgKinds::Auth => {
+                let json = tokens.raw()?;
+                let note =
+                    Note::from_json_bytes(json).map_err(|e| e.offset_by(tokens.offset_of(json)))?;
+                if kind == ClientMsgKinds::Event {
+                    ClientMessage::Event(note)
+                } else {
+                    ClientMessage::Auth(note)
+                }
+            }
+            ClientMsgKinds::Close => ClientMessage::Close(subscription_id(tokens.string()?)?),
+            ClientMsgKinds::Req => {
+                let id = subscription_id(tokens.string()?)?;
+                let mut filters = MultiQuery::new();
+                while !tokens.at_end() {
+                    let filter = tokens.raw()?;
+                    filters.push(Query::try_from(tokens.str_in(value, filter)?)?)?;
+                }
+                if filters.filters.is_empty() {
+                    return Err(Error::MalformedContent);
+                }
+                ClientMessage::Req(id, filters)
+            }
+        };
+        tokens.end()?;
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        errors::Field,
+        vectors::{text_note, PUBKEY},
+        NoteKinds,
+    };
+
+    #[test]
+    fn test_event_and_auth() {
+        let note = text_note();
+        let msg = note.serialize_to_relay(ClientMsgKinds::Event).unwrap();
+        let msg = core::str::from_utf8(&msg).unwrap();
+        assert_eq!(
+            ClientMessage::<1>::try_from(msg),
+            Ok(ClientMessage::Event(text_note()))
+        );
+
+        let msg = text_note()
+            .serialize_to_relay(ClientMsgKinds::Auth)
+            .unwrap();
+        let msg = core::str::from_utf8(&msg).unwrap();
+        assert_eq!(
+            ClientMessage::<1>::try_from(msg),
+            Ok(ClientMessage::Auth(text_note()))
+        );
+
+        // a forged note is refused
+        let mut forged = text_note();
+        forged.sig[0] ^= 1;
+        let msg = forged.serialize_to_relay(ClientMsgKinds::Event).unwrap();
+        let msg = core::str::from_utf8(&msg).unwrap();
+        assert_eq!(
+            ClientMessage::<1>::try_from(msg),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_req() {
+        let msg = r##"["REQ", "sub_1", {"authors": ["098ef66b"], "kinds": [1, 30078], "#t": ["esp", "iot"], "search": "ignored", "since": 1686880000, "limit": 10}, {"#p": ["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"]}]"##;
+        let ClientMessage::Req(id, filters) = ClientMessage::<2>::try_from(msg).unwrap() else {
+            panic!("expected a REQ");
+        };
+        assert_eq!(id, "sub_1");
+        let first = &filters.filters[0];
+        assert_eq!(first.authors.len(), 1);
+        assert_eq!(first.kinds[1], NoteKinds::ParameterizedReplaceable(30078));
+        assert_eq!(first.tag_filter('t').unwrap().values().count(), 2);
+        assert_eq!(
+            (first.since, first.until, first.limit),
+            (Some(1686880000), None, Some(10))
+        );
+        assert!(!first.matches(&text_note()));
+        assert_eq!(&filters.filters[1].ref_pks[0].to_hex(), PUBKEY.as_bytes());
+        assert!(!filters.filters[1].matches(&text_note()));
+
+        assert_eq!(
+            ClientMessage::<1>::try_from(msg),
+            Err(Error::QueryBuilderOverflow)
+        );
+    }
+
+    #[test]
+    fn test_query_round_trip() {
+        let mut query = Query::new();
+        query.add_author(PUBKEY).unwrap();
+        query.add_id("b515da91").unwrap();
+        query.kinds.push(NoteKinds::ShortNote).unwrap();
+        query.add_tag_filter('d', "a \"quoted\" name").unwrap();
+        query.until = Some(1686880020);
+        let msg = query.clone().serialize_to_relay("round").unwrap();
+        let parsed = ClientMessage::<1>::try_from(core::str::from_utf8(&msg).unwrap()).unwrap();
+        let mut expected = MultiQuery::new();
+        expected.push(query).unwrap();
+        assert_eq!(parsed, ClientMessage::Req("round".into(), expected));
+    }
+
+    #[test]
+    fn test_close_and_errors() {
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["CLOSE", "sub_1"]"#),
+            Ok(ClientMessage::Close("sub_1".into()))
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["CLOSE", ""]"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["CLOSE", "sub_1", "extra"]"#),
+            Err(Error::Malformed {
+                field: Field::Element,
+                offset: 17
+            })
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["CLOSE","x"]garbage"#),
+            Err(Error::Malformed {
+                field: Field::Element,
+                offset: 13
+            })
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["REQ", "sub_1"]"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["REQ", "sub_1", {"ids": [12]}]"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r##"["REQ", "sub_1", {"#e": ["abc"]}]"##),
+            Err(Error::EventNotValid)
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from(r#"["EOSE", "sub_1"]"#),
+            Err(Error::InvalidType)
+        );
+        assert_eq!(
+            ClientMessage::<1>::try_from("not json"),
+            Err(Error::InvalidType)
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Sanity checks for `created_at`, in the spirit of [NIP-22](https://github.com/nostr-protocol/nips/blob/master/22.md)
+//!
+//! Devices without a real time clock start counting from 1970 or from whenever they were last
+//! flashed, so a note built before the clock is set gets a wildly wrong timestamp that relays
+//! reject. [`Note::check_created_at`] checks a note against a window around `now`, and
+//! [`NoteBuilder::created_at_window`] makes building fail instead of signing a bogus timestamp.
+//!
+//! Without an RTC or NTP, [`TrustedClock`] estimates the time from the newest event seen on a
+//! subscription the device trusts, such as its own relay, and the device's uptime.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{clock::TrustedClock, errors::Error, Note};
+//! let mut clock = TrustedClock::new();
+//! assert_eq!(clock.now(10), None);
+//! // an event created at 1686880020 arrived 10 seconds after boot
+//! clock.observe(1686880020, 10);
+//! let now = clock.now(70).unwrap();
+//! assert_eq!(now, 1686880080);
+//!
+//! let builder = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .created_at_window(now, 3600, 60);
+//! // the clock of the device was never set
+//! assert!(matches!(builder.build(70, [0; 32]), Err(Error::CreatedAtOutOfRange)));
+//! ```
+
+use crate::{errors::Error, Note, NoteBuilder};
+
+/// Earliest and latest `created_at` around `now`
+pub(crate) fn window(now: u64, max_past: u32, max_future: u32) -> (u64, u64) {
+    (
+        now.saturating_sub(max_past.into()),
+        now.saturating_add(max_future.into()),
+    )
+}
+
+/// Errors with `CreatedAtOutOfRange` if `created_at` is outside of `window`
+pub(crate) fn check_window(created_at: u64, window: Option<(u64, u64)>) -> Result<(), Error> {
+    match window {
+        Some((earliest, latest)) if !(earliest..=latest).contains(&created_at) => {
+            Err(Error::CreatedAtOutOfRange)
+        }
+        _ => Ok(()),
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Checks `created_at` is at most `max_past` seconds before and `max_future` seconds after
+    /// `now`.
+    /// Errors with `CreatedAtOutOfRange` if it isn't.
+    pub fn check_created_at(&self, now: u64, max_past: u32, max_future: u32) -> Result<(), Error> {
+        check_window(self.created_at, Some(window(now, max_past, max_future)))
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Makes building fail with `CreatedAtOutOfRange` if `created_at` is more than `max_past`
+    /// seconds before or `max_future` seconds after `now`, such as when the device's clock
+    /// hasn't been set yet
+    pub fn created_at_window(mut self, now: u64, max_past: u32, max_future: u32) -> Self {
+        self.created_at_window = Some(window(now, max_past, max_future));
+        self
+    }
+
+    /// Sets `created_at` after checking it against the window, if any
+    pub(crate) fn set_created_at(&mut self, created_at: u64) -> Result<(), Error> {
+        check_window(created_at, self.created_at_window)?;
+        self.note.created_at = created_at;
+        Ok(())
+    }
+}
+
+/// Estimates the current unix time from events seen on a trusted subscription and the
+/// device's uptime, for devices without a real time clock
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrustedClock {
+    /// `created_at` of the event giving the latest estimate and the uptime it was seen at
+    newest: Option<(u64, u64)>,
+}
+
+impl TrustedClock {
+    /// Starts without an estimate
+    pub const fn new() -> Self {
+        TrustedClock { newest: None }
+    }
+
+    /// Records an event with `created_at` seen `uptime` seconds after boot.
+    /// Events are always seen after they were created, so the one giving the latest estimate is
+    /// kept and older events are ignored.
+    pub fn observe(&mut self, created_at: u64, uptime: u64) {
+        let later = match self.now(uptime) {
+            Some(now) => created_at > now,
+            None => true,
+        };
+        if later {
+            self.newest = Some((created_at, uptime));
+        }
+    }
+
+    /// Same as [`TrustedClock::observe`], taking the `created_at` of `note`
+    pub fn observe_note<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &mut self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+        uptime: u64,
+    ) {
+        self.observe(note.created_at, uptime)
+    }
+
+    /// Estimated unix time `uptime` seconds after boot, `None` until an event has been seen
+    pub fn now(&self, uptime: u64) -> Option<u64> {
+        self.newest
+            .map(|(created_at, seen)| created_at.saturating_add(uptime.saturating_sub(seen)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+    const NOW: u64 = 1686880020;
+
+    fn note(created_at: u64) -> Note {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(created_at, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_check_created_at() {
+        assert_eq!(note(NOW).check_created_at(NOW, 3600, 60), Ok(()));
+        assert_eq!(note(NOW - 3600).check_created_at(NOW, 3600, 60), Ok(()));
+        assert_eq!(note(NOW + 60).check_created_at(NOW, 3600, 60), Ok(()));
+        assert_eq!(
+            note(NOW - 3601).check_created_at(NOW, 3600, 60),
+            Err(Error::CreatedAtOutOfRange)
+        );
+        assert_eq!(
+            note(NOW + 61).check_created_at(NOW, 3600, 60),
+            Err(Error::CreatedAtOutOfRange)
+        );
+        assert_eq!(note(0).check_created_at(10, u32::MAX, 0), Ok(()));
+        assert_eq!(
+            note(u64::MAX).check_created_at(u64::MAX, 0, u32::MAX),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_builder_window() {
+        let builder = || {
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .content("esptest".into())
+                .created_at_window(NOW, 3600, 60)
+        };
+        assert_eq!(builder().build(NOW, [0; 32]), Ok(note(NOW)));
+        assert_eq!(
+            builder().build(12, [0; 32]),
+            Err(Error::CreatedAtOutOfRange)
+        );
+        assert!(builder()
+            .add_tag(crate::Tag::try_from(["t", "esp32"]).unwrap())
+            .unwrap()
+            .build_unsigned(NOW + 61)
+            .is_err());
+        let template = builder().into_template().unwrap();
+        assert_eq!(
+            template.sign(12, "esptest", [0; 32]),
+            Err(Error::CreatedAtOutOfRange)
+        );
+        assert_eq!(template.sign(NOW, "esptest", [0; 32]), Ok(note(NOW)));
+    }
+
+    #[test]
+    fn test_trusted_clock() {
+        let mut clock = TrustedClock::new();
+        assert_eq!(clock.now(100), None);
+        clock.observe_note(&note(NOW), 100);
+        assert_eq!(clock.now(100), Some(NOW));
+        assert_eq!(clock.now(160), Some(NOW + 60));
+        // an older event seen later doesn't move the clock back
+        clock.observe(NOW - 10, 150);
+        assert_eq!(clock.now(160), Some(NOW + 60));
+        // a newer one moves it forward
+        clock.observe(NOW + 100, 150);
+        assert_eq!(clock.now(160), Some(NOW + 110));
+        // uptime from before the event doesn't go back in time
+        assert_eq!(clock.now(0), Some(NOW + 100));
+    }
+}
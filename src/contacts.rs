@@ -0,0 +1,309 @@
+//! Contact lists ([NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md))
+//!
+//! A kind 3 note lists the users its author follows as `p` tags, each with an optional relay
+//! hint and petname. A [`ContactList`] holds up to `N` contacts, and a builder only takes a list
+//! that fits its `NTAGS`, so a list too long for the note is a compilation error.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{contacts::{Contact, ContactList}, Note, NoteBuilder};
+//! let mut follows: ContactList<8> = ContactList::new();
+//! follows
+//!     .follow(
+//!         Contact::new("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
+//!             .unwrap()
+//!             .with_relay("wss://relay.damus.io")
+//!             .unwrap()
+//!             .with_petname("esp")
+//!             .unwrap(),
+//!     )
+//!     .unwrap();
+//! let note: Note<400, 100, 8> =
+//!     NoteBuilder::<400, 100, 8>::new("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!         .unwrap()
+//!         .contact_list(&follows)
+//!         .unwrap()
+//!         .build(1686880020, [0; 32])
+//!         .unwrap();
+//!
+//! let parsed: ContactList<8> = ContactList::try_from(&note).unwrap();
+//! let contact = parsed.iter().next().unwrap();
+//! assert_eq!(contact.relay.as_deref(), Some("wss://relay.damus.io"));
+//! assert_eq!(contact.petname.as_deref(), Some("esp"));
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    errors::Error,
+    pool::RELAY_URL_SIZE,
+    tag::Tag,
+    utils::{from_hex, to_hex},
+    Note, NoteBuilder, NoteKinds, MAX_TAGS,
+};
+
+/// Kind of a contact list note
+pub const CONTACT_LIST_KIND: u32 = 3;
+/// Longest petname kept for a contact
+pub const PETNAME_SIZE: usize = 32;
+/// Contacts a [`ContactList`] holds by default, as many as a default sized note has tags
+pub const MAX_CONTACTS: usize = MAX_TAGS;
+
+/// Copies `value` into a string of up to `N` bytes, `None` if it doesn't fit
+fn bounded<const N: usize>(value: &str) -> Option<String<N>> {
+    let mut out = String::new();
+    out.push_str(value).ok()?;
+    Some(out)
+}
+
+/// One `p` tag of a contact list
+#[derive(Debug, PartialEq, Clone)]
+pub struct Contact {
+    /// X-only pubkey of the followed user
+    pub pubkey: [u8; 32],
+    pub relay: Option<String<RELAY_URL_SIZE>>,
+    pub petname: Option<String<PETNAME_SIZE>>,
+}
+
+impl Contact {
+    /// A contact for the hex encoded `pubkey`, with no relay or petname.
+    /// Errors with `InvalidPubkey` if it isn't 64 hex characters.
+    pub fn new(pubkey: &str) -> Result<Self, Error> {
+        Ok(Contact {
+            pubkey: from_hex(pubkey.as_bytes()).map_err(|_| Error::InvalidPubkey)?,
+            relay: None,
+            petname: None,
+        })
+    }
+
+    /// Sets the relay the contact can be found on.
+    /// Errors with `ContentOverflow` if it is longer than [`RELAY_URL_SIZE`].
+    pub fn with_relay(mut self, relay: &str) -> Result<Self, Error> {
+        self.relay = Some(bounded(relay).ok_or(Error::ContentOverflow)?);
+        Ok(self)
+    }
+
+    /// Sets the name the contact is shown as.
+    /// Errors with `ContentOverflow` if it is longer than [`PETNAME_SIZE`].
+    pub fn with_petname(mut self, petname: &str) -> Result<Self, Error> {
+        self.petname = Some(bounded(petname).ok_or(Error::ContentOverflow)?);
+        Ok(self)
+    }
+
+    /// The `["p", <pubkey>, <relay>, <petname>]` tag, leaving out empty trailing values
+    fn to_tag<const TAG: usize, const VALUES: usize>(&self) -> Result<Tag<TAG, VALUES>, Error> {
+        let pubkey: [u8; 64] = to_hex(&self.pubkey);
+        let pubkey = core::str::from_utf8(&pubkey).map_err(|_| Error::EncodeError)?;
+        let mut tag = Tag::new("p")?.with_value(pubkey)?;
+        if self.relay.is_some() || self.petname.is_some() {
+            tag.push_value(self.relay.as_deref().unwrap_or_default())?;
+        }
+        if let Some(petname) = &self.petname {
+            tag.push_value(petname)?;
+        }
+        Ok(tag)
+    }
+}
+
+/// Users followed, in the order they were added
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ContactList<const N: usize = MAX_CONTACTS> {
+    contacts: Vec<Contact, N>,
+}
+
+impl<const N: usize> ContactList<N> {
+    /// An empty list
+    pub const fn new() -> Self {
+        ContactList {
+            contacts: Vec::new(),
+        }
+    }
+
+    /// Adds `contact`, replacing the entry for the same pubkey if there is one.
+    /// Errors with `TooManyTags` if the list already holds `N` contacts.
+    pub fn follow(&mut self, contact: Contact) -> Result<(), Error> {
+        match self
+            .contacts
+            .iter_mut()
+            .find(|existing| existing.pubkey == contact.pubkey)
+        {
+            Some(existing) => *existing = contact,
+            None => self
+                .contacts
+                .push(contact)
+                .map_err(|_| Error::TooManyTags)?,
+        }
+        Ok(())
+    }
+
+    /// Removes the contact for `pubkey`, returns false if it wasn't followed
+    pub fn unfollow(&mut self, pubkey: &[u8; 32]) -> bool {
+        let Some(pos) = self
+            .contacts
+            .iter()
+            .position(|contact| contact.pubkey == *pubkey)
+        else {
+            return false;
+        };
+        self.contacts.remove(pos);
+        true
+    }
+
+    /// True if `pubkey` is followed
+    pub fn contains(&self, pubkey: &[u8; 32]) -> bool {
+        self.contacts
+            .iter()
+            .any(|contact| contact.pubkey == *pubkey)
+    }
+
+    /// Iterates over the contacts
+    pub fn iter(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.iter()
+    }
+
+    /// Number of contacts
+    pub fn len(&self) -> usize {
+        self.contacts.len()
+    }
+
+    /// True if nobody is followed
+    pub fn is_empty(&self) -> bool {
+        self.contacts.is_empty()
+    }
+}
+
+impl<
+        const N: usize,
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    > TryFrom<&Note<CONTENT, TAG, NTAGS, VALUES>> for ContactList<N>
+{
+    type Error = Error;
+
+    /// Parses the `p` tags of a kind 3 note.
+    /// Errors with `InvalidType` for other kinds, `InvalidPubkey` if a tag doesn't hold a hex
+    /// pubkey and `TooManyTags` if the note follows more than `N` users.
+    /// Relay hints and petnames that don't fit are dropped.
+    fn try_from(note: &Note<CONTENT, TAG, NTAGS, VALUES>) -> Result<Self, Self::Error> {
+        if note.kind.as_u32() != CONTACT_LIST_KIND {
+            return Err(Error::InvalidType);
+        }
+        let mut list = ContactList::new();
+        for tag in note.tags.iter().filter(|tag| tag.name() == "p") {
+            let mut contact = Contact::new(tag.value(0).unwrap_or_default())?;
+            contact.relay = tag
+                .value(1)
+                .filter(|relay| !relay.is_empty())
+                .and_then(bounded);
+            contact.petname = tag
+                .value(2)
+                .filter(|petname| !petname.is_empty())
+                .and_then(bounded);
+            list.follow(contact)?;
+        }
+        Ok(list)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 3 contact list with a `p` tag for each contact.
+    /// Replaces any tags added before, and a list that could hold more than `NTAGS`
+    /// contacts is a compilation error.
+    /// Errors with `ContentOverflow` if a relay or petname is longer than `TAG`.
+    pub fn contact_list<const N: usize>(
+        mut self,
+        contacts: &ContactList<N>,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        const { assert!(N <= NTAGS, "contact list is longer than NTAGS") };
+        self.note.tags.clear();
+        for contact in contacts.iter() {
+            // the assert above leaves room for every contact
+            let _ = self.note.tags.push(contact.to_tag()?);
+        }
+        self.note.kind = NoteKinds::ContactList;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+    const THEIRS: &str = "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+    const OTHER: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+
+    #[test]
+    fn test_round_trip() {
+        let mut list: ContactList = ContactList::new();
+        list.follow(Contact::new(THEIRS).unwrap().with_petname("bob").unwrap())
+            .unwrap();
+        list.follow(Contact::new(OTHER).unwrap()).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .contact_list(&list)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.kind(), NoteKinds::ContactList);
+        // an empty relay keeps the petname in its place
+        assert_eq!(
+            note.get_tag("p").unwrap()[0].as_slice(),
+            [THEIRS, "", "bob"]
+        );
+        assert_eq!(note.get_tag("p").unwrap()[1].as_slice(), [OTHER]);
+        assert!(note.verify().is_ok());
+
+        let parsed: ContactList = ContactList::try_from(&note).unwrap();
+        assert_eq!(parsed, list);
+        assert_eq!(parsed.iter().next().unwrap().relay, None);
+    }
+
+    #[test]
+    fn test_follow_and_unfollow() {
+        let mut list: ContactList<1> = ContactList::new();
+        let theirs = Contact::new(THEIRS).unwrap();
+        list.follow(theirs.clone()).unwrap();
+        // following again updates the entry
+        list.follow(theirs.clone().with_relay("wss://a.example").unwrap())
+            .unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list.iter().next().unwrap().relay.is_some());
+        assert_eq!(
+            list.follow(Contact::new(OTHER).unwrap()),
+            Err(Error::TooManyTags)
+        );
+        assert!(list.contains(&theirs.pubkey));
+        assert!(list.unfollow(&theirs.pubkey));
+        assert!(!list.unfollow(&theirs.pubkey));
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Contact::new("abc"), Err(Error::InvalidPubkey));
+        assert_eq!(
+            Contact::new(THEIRS).unwrap().with_petname(THEIRS),
+            Err(Error::ContentOverflow)
+        );
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(ContactList::<5>::try_from(&note), Err(Error::InvalidType));
+
+        let mut list: ContactList<2> = ContactList::new();
+        list.follow(Contact::new(THEIRS).unwrap()).unwrap();
+        list.follow(Contact::new(OTHER).unwrap()).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .contact_list(&list)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(ContactList::<1>::try_from(&note), Err(Error::TooManyTags));
+    }
+}
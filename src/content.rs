@@ -0,0 +1,101 @@
+//! Formatting content straight into a note
+//!
+//! Sensor readings are usually formatted with `write!`, which would otherwise need a temporary
+//! buffer copied into [`NoteBuilder::content`]. [`NoteBuilder::content_writer`] returns a
+//! [`ContentWriter`] that formats into the note's own content buffer instead.
+//!
+//! # Example
+//! ```
+//! use core::fmt::Write;
+//! use nostr_nostd::{errors::Error, Note, NoteBuilder};
+//! let mut builder = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap();
+//! write!(builder.content_writer(), "temperature: {:.1}C", 21.53).unwrap();
+//! let note = builder.build(1686880020, [0; 32]).unwrap();
+//! assert_eq!(note.content().unwrap(), "temperature: 21.5C");
+//!
+//! // content longer than CONTENT fails the build
+//! let mut builder = NoteBuilder::<8>::new("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap();
+//! let _ = write!(builder.content_writer(), "temperature: {:.1}C", 21.53);
+//! assert!(matches!(builder.build(1686880020, [0; 32]), Err(Error::ContentOverflow)));
+//! ```
+
+use core::fmt;
+
+use heapless::String;
+
+use crate::{errors::Error, NoteBuilder};
+
+/// Appends formatted text to the content of a [`NoteBuilder`], see [`NoteBuilder::content_writer`]
+pub struct ContentWriter<'a, const CONTENT: usize> {
+    content: &'a mut String<CONTENT>,
+    overflow: &'a mut bool,
+}
+
+impl<const CONTENT: usize> fmt::Write for ContentWriter<'_, CONTENT> {
+    /// Errors if `s` doesn't fit in the rest of the buffer, which also makes building fail
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.content.push_str(s).map_err(|_| {
+            *self.overflow = true;
+            fmt::Error
+        })
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Returns a [`core::fmt::Write`] that appends to the content set so far, so readings can be
+    /// formatted with `write!` without a temporary buffer.
+    /// Text that doesn't fit in `CONTENT` makes building fail with `ContentOverflow`, until the
+    /// content is replaced with [`NoteBuilder::content`].
+    pub fn content_writer(&mut self) -> ContentWriter<'_, CONTENT> {
+        ContentWriter {
+            content: self.note.content.get_or_insert_with(String::new),
+            overflow: &mut self.content_overflow,
+        }
+    }
+
+    /// Errors with `ContentOverflow` if the content writer ran out of room
+    pub(crate) fn check_content(&self) -> Result<(), Error> {
+        if self.content_overflow {
+            return Err(Error::ContentOverflow);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_content_writer() {
+        let mut builder = NoteBuilder::<16>::new(PRIVKEY).unwrap();
+        builder = builder.content("t=".into());
+        write!(builder.content_writer(), "{}", 21).unwrap();
+        write!(builder.content_writer(), ",h={}", 40).unwrap();
+        let note = builder.build(1686880020, [0; 32]).unwrap();
+        assert_eq!(note.content(), Some("t=21,h=40"));
+        assert!(note.verify_id().is_ok());
+
+        let mut builder = NoteBuilder::<16>::new(PRIVKEY).unwrap();
+        assert!(write!(builder.content_writer(), "{:>20}", 1).is_err());
+        assert_eq!(
+            builder.build(1686880020, [0; 32]),
+            Err(Error::ContentOverflow)
+        );
+
+        // replacing the content clears the overflow
+        let mut builder = NoteBuilder::<16>::new(PRIVKEY).unwrap();
+        let _ = write!(builder.content_writer(), "{:>20}", 1);
+        let note = builder
+            .content("21".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.content(), Some("21"));
+    }
+}
@@ -0,0 +1,210 @@
+//! Addressing replaceable events by coordinate
+//!
+//! Parameterized replaceable notes, kinds 30000-39999, are told apart by their `d` tag, and
+//! every version of one shares the coordinate `<kind>:<pubkey>:<d>`. An `a` tag holding the
+//! coordinate points at whatever the latest version is, rather than one id.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{coordinate::EventCoordinate, tag::Tag, Note, NoteKinds};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let article = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .set_kind(NoteKinds::ParameterizedReplaceable(30023))
+//!     .identifier("build-log")
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! assert_eq!(article.identifier(), Some("build-log"));
+//!
+//! let coordinate = article.coordinate().unwrap().unwrap();
+//! let a_tag: Tag = coordinate.to_tag().unwrap();
+//! let parsed = EventCoordinate::try_from(a_tag.value(0).unwrap()).unwrap();
+//! assert_eq!(parsed, coordinate);
+//! assert!(parsed.matches(&article));
+//! ```
+
+use heapless::String;
+
+use crate::{
+    buffer::{OutBuf, SliceBuf},
+    errors::Error,
+    tag::{Tag, TAG_VALUE_SIZE},
+    utils::{from_hex, to_hex},
+    Note, NoteBuilder, NoteKinds,
+};
+
+/// Longest `<kind>:<pubkey>:` in front of the `d` value
+const COORDINATE_PREFIX_SIZE: usize = 10 + 1 + 64 + 1;
+
+/// The `<kind>:<pubkey>:<d>` address shared by every version of a replaceable note
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventCoordinate {
+    pub kind: NoteKinds,
+    /// X-only pubkey of the author
+    pub pubkey: [u8; 32],
+    /// `d` tag of the note, empty for replaceable kinds that don't have one
+    pub d: String<TAG_VALUE_SIZE>,
+}
+
+impl EventCoordinate {
+    /// Writes the coordinate as `<kind>:<pubkey>:<d>`
+    pub fn write_to<B: OutBuf>(&self, out: &mut B) -> Result<(), Error> {
+        out.push_slice(self.kind.serialize().as_bytes())?;
+        out.push_byte(b':')?;
+        out.push_slice(&to_hex::<32, 64>(&self.pubkey))?;
+        out.push_byte(b':')?;
+        out.push_slice(self.d.as_bytes())
+    }
+
+    /// The `["a", <coordinate>]` tag pointing at the note.
+    /// Errors with `ContentOverflow` if the coordinate is longer than `TAG`.
+    pub fn to_tag<const TAG: usize, const VALUES: usize>(&self) -> Result<Tag<TAG, VALUES>, Error> {
+        let mut buf = [0_u8; COORDINATE_PREFIX_SIZE + TAG_VALUE_SIZE];
+        let mut out = SliceBuf::new(&mut buf);
+        self.write_to(&mut out)?;
+        let coordinate = core::str::from_utf8(out.into_slice()).map_err(|_| Error::EncodeError)?;
+        Tag::try_from(["a", coordinate])
+    }
+
+    /// True if `note` is a version of the note at this coordinate
+    pub fn matches<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> bool {
+        note.coordinate().ok().flatten().as_ref() == Some(self)
+    }
+}
+
+impl TryFrom<&str> for EventCoordinate {
+    type Error = Error;
+
+    /// Parses `<kind>:<pubkey>:<d>`, the `d` value may itself contain `:`.
+    /// Errors with `MalformedContent` if a part is missing or invalid, and with
+    /// `ContentOverflow` if `d` is longer than [`TAG_VALUE_SIZE`].
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut parts = value.splitn(3, ':');
+        let kind: u32 = parts
+            .next()
+            .and_then(|kind| kind.parse().ok())
+            .ok_or(Error::MalformedContent)?;
+        let pubkey = from_hex(parts.next().ok_or(Error::MalformedContent)?.as_bytes())?;
+        let mut d = String::new();
+        d.push_str(parts.next().ok_or(Error::MalformedContent)?)
+            .map_err(|_| Error::ContentOverflow)?;
+        Ok(EventCoordinate {
+            kind: kind.into(),
+            pubkey,
+            d,
+        })
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Adds the `d` tag that tells apart parameterized replaceable notes of the same kind.
+    /// Errors with `ContentOverflow` if `d` is longer than `TAG`.
+    pub fn identifier(self, d: &str) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.add_tag(Tag::try_from(["d", d])?)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Value of the first `d` tag, if any
+    pub fn identifier(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.name() == "d")
+            .and_then(|tag| tag.value(0))
+    }
+
+    /// Coordinate of a replaceable or parameterized replaceable note, `None` for other kinds.
+    /// Errors with `ContentOverflow` if the `d` tag is longer than [`TAG_VALUE_SIZE`].
+    pub fn coordinate(&self) -> Result<Option<EventCoordinate>, Error> {
+        if !self.kind.is_replaceable() && !self.kind.is_parameterized_replaceable() {
+            return Ok(None);
+        }
+        let mut d = String::new();
+        if self.kind.is_parameterized_replaceable() {
+            d.push_str(self.identifier().unwrap_or_default())
+                .map_err(|_| Error::ContentOverflow)?;
+        }
+        Ok(Some(EventCoordinate {
+            kind: self.kind,
+            pubkey: self.pubkey,
+            d,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, PRIVKEY};
+    const COORDINATE: &str =
+        "30023:098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf:log:2023";
+
+    #[test]
+    fn test_round_trip() {
+        let coordinate = EventCoordinate::try_from(COORDINATE).unwrap();
+        assert_eq!(coordinate.kind, NoteKinds::ParameterizedReplaceable(30023));
+        assert_eq!(coordinate.d, "log:2023");
+        let tag: Tag = coordinate.to_tag().unwrap();
+        assert_eq!(tag.value(0), Some(COORDINATE));
+
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(coordinate.kind)
+            .identifier("log:2023")
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.coordinate(), Ok(Some(coordinate.clone())));
+        assert!(coordinate.matches(&note));
+        assert_eq!(text_note().coordinate(), Ok(None));
+        assert_eq!(text_note().identifier(), None);
+    }
+
+    #[test]
+    fn test_replaceable_without_d() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::Replaceable(10002))
+            .identifier("ignored")
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let coordinate = note.coordinate().unwrap().unwrap();
+        assert_eq!(coordinate.d, "");
+        assert!(EventCoordinate::try_from(
+            "10002:098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf:"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            EventCoordinate::try_from("30023:abc:d"),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            EventCoordinate::try_from("kind:abc:d"),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            EventCoordinate::try_from(
+                "30023:098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"
+            ),
+            Err(Error::MalformedContent)
+        );
+    }
+}
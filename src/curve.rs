@@ -0,0 +1,607 @@
+//! Curve operations behind signing, verification and ECDH
+//!
+//! Everything the crate does on secp256k1 goes through [`CurveBackend`]. The default backend
+//! wraps the `secp256k1` crate, bindings to bitcoin-core's C library. Enabling the `k256` feature
+//! swaps in RustCrypto's pure Rust `k256` instead, for targets where building the C library is
+//! hard. Both give the same keys, signatures and shared secrets, and the public API is the same
+//! whichever is used. If both features are enabled `k256` is used.
+
+use crate::errors::Error;
+
+#[cfg(not(any(feature = "secp256k1", feature = "k256")))]
+compile_error!("one of the `secp256k1` or `k256` features must be enabled");
+
+#[cfg(not(feature = "k256"))]
+pub use secp256k1::ffi::types::AlignedType;
+
+/// Element of the buffers contexts are set up in.
+/// `k256` needs no context, so this takes no space.
+#[cfg(feature = "k256")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlignedType(());
+
+#[cfg(feature = "k256")]
+impl AlignedType {
+    /// An item of a context buffer
+    pub const fn zeroed() -> Self {
+        AlignedType(())
+    }
+}
+
+/// Backend used by the crate
+#[cfg(not(feature = "k256"))]
+pub(crate) type Curve = Secp256k1Backend;
+/// Backend used by the crate
+#[cfg(feature = "k256")]
+pub(crate) type Curve = K256Backend;
+
+pub(crate) type Context<'buf> = <Curve as CurveBackend>::Context<'buf>;
+pub(crate) type VerifyContext<'buf> = <Curve as CurveBackend>::VerifyContext<'buf>;
+pub(crate) type KeyPair = <Curve as CurveBackend>::KeyPair;
+pub(crate) type PublicKey = <Curve as CurveBackend>::PublicKey;
+
+/// Items needed by [`CurveBackend::context`]
+pub(crate) const CONTEXT_ITEMS: usize = 64;
+/// Items needed by [`CurveBackend::verify_context`]
+pub(crate) const VERIFY_CONTEXT_ITEMS: usize = 16;
+
+/// BIP-340 Schnorr signatures and ECDH on secp256k1
+pub(crate) trait CurveBackend {
+    /// Context for signing, set up in a buffer the caller owns
+    type Context<'buf>;
+    /// Context that can only verify
+    type VerifyContext<'buf>;
+    /// A secret key along with its x-only pubkey
+    type KeyPair: Clone;
+    /// A parsed x-only pubkey
+    type PublicKey: Copy;
+
+    /// Errors with `Secp256k1Error` if `buf` holds less than [`CONTEXT_ITEMS`]
+    fn context(buf: &mut [AlignedType]) -> Result<Self::Context<'_>, Error>;
+
+    /// Errors with `Secp256k1Error` if `buf` holds less than [`VERIFY_CONTEXT_ITEMS`]
+    fn verify_context(buf: &mut [AlignedType]) -> Result<Self::VerifyContext<'_>, Error>;
+
+    /// Errors with `InvalidPrivkey` if `secret` is zero or not below the curve order
+    fn keypair(ctx: &Self::Context<'_>, secret: &[u8; 32]) -> Result<Self::KeyPair, Error>;
+
+    /// The secret key as given to [`CurveBackend::keypair`]
+    fn secret_bytes(keypair: &Self::KeyPair) -> [u8; 32];
+
+    /// X-only pubkey
+    fn x_only(keypair: &Self::KeyPair) -> [u8; 32];
+
+    /// SEC1 compressed pubkey, as hashed by BIP-32
+    fn compressed_pubkey(keypair: &Self::KeyPair) -> [u8; 33];
+
+    /// Errors with `InvalidPubkey` if `pubkey` is not the x coordinate of a point on the curve
+    fn parse_pubkey(pubkey: &[u8; 32]) -> Result<Self::PublicKey, Error>;
+
+    /// Signs the 32 byte `digest`
+    fn sign(
+        ctx: &Self::Context<'_>,
+        keypair: &Self::KeyPair,
+        digest: &[u8; 32],
+        aux_rnd: &[u8; 32],
+    ) -> Result<[u8; 64], Error>;
+
+    /// Errors with `InvalidSignature` if `sig` is not a signature of `digest` by `pubkey`
+    fn verify(
+        ctx: &Self::VerifyContext<'_>,
+        pubkey: &Self::PublicKey,
+        digest: &[u8; 32],
+        sig: &[u8; 64],
+    ) -> Result<(), Error>;
+
+    /// X coordinate of `secret` times the even point of `pubkey`.
+    /// Errors with `InvalidPrivkey` if `secret` is not a valid secret key.
+    fn shared_x(secret: &[u8; 32], pubkey: &Self::PublicKey) -> Result<[u8; 32], Error>;
+
+    /// `secret + tweak` modulo the curve order, as in BIP-32 child key derivation.
+    /// Errors with `KeyDerivationError` if `tweak` is out of range or the sum is zero.
+    fn add_tweak(secret: &[u8; 32], tweak: &[u8; 32]) -> Result<[u8; 32], Error>;
+}
+
+/// Parses a hex secret key, errors with `InvalidPrivkey` if it isn't 64 hex characters
+pub(crate) fn parse_secret(privkey: &str) -> Result<[u8; 32], Error> {
+    let mut secret = [0_u8; 32];
+    match base16ct::mixed::decode(privkey, &mut secret) {
+        Ok(decoded) if decoded.len() == 32 => Ok(secret),
+        _ => Err(Error::InvalidPrivkey),
+    }
+}
+
+/// Parses a hex secret key into a keypair, with a context set up just for the call
+pub(crate) fn keypair_from_hex(privkey: &str) -> Result<KeyPair, Error> {
+    let mut buf = [AlignedType::zeroed(); CONTEXT_ITEMS];
+    let ctx = Curve::context(&mut buf)?;
+    Curve::keypair(&ctx, &parse_secret(privkey)?)
+}
+
+/// Signs `digest`, with a context set up just for the call
+pub(crate) fn sign(
+    keypair: &KeyPair,
+    digest: &[u8; 32],
+    aux_rnd: &[u8; 32],
+) -> Result<[u8; 64], Error> {
+    let mut buf = [AlignedType::zeroed(); CONTEXT_ITEMS];
+    let ctx = Curve::context(&mut buf)?;
+    Curve::sign(&ctx, keypair, digest, aux_rnd)
+}
+
+/// Checks a signature, with a context set up just for the call
+pub(crate) fn verify(pubkey: &PublicKey, digest: &[u8; 32], sig: &[u8; 64]) -> Result<(), Error> {
+    let mut buf = [AlignedType::zeroed(); VERIFY_CONTEXT_ITEMS];
+    let ctx = Curve::verify_context(&mut buf)?;
+    Curve::verify(&ctx, pubkey, digest, sig)
+}
+
+/// Bindings to bitcoin-core's libsecp256k1
+#[cfg(not(feature = "k256"))]
+pub(crate) struct Secp256k1Backend;
+
+#[cfg(not(feature = "k256"))]
+impl CurveBackend for Secp256k1Backend {
+    type Context<'buf> = secp256k1::Secp256k1<secp256k1::AllPreallocated<'buf>>;
+    type VerifyContext<'buf> = secp256k1::Secp256k1<secp256k1::VerifyOnlyPreallocated<'buf>>;
+    type KeyPair = secp256k1::KeyPair;
+    type PublicKey = secp256k1::XOnlyPublicKey;
+
+    fn context(buf: &mut [AlignedType]) -> Result<Self::Context<'_>, Error> {
+        secp256k1::Secp256k1::preallocated_new(buf).map_err(|_| Error::Secp256k1Error)
+    }
+
+    fn verify_context(buf: &mut [AlignedType]) -> Result<Self::VerifyContext<'_>, Error> {
+        secp256k1::Secp256k1::preallocated_verification_only(buf).map_err(|_| Error::Secp256k1Error)
+    }
+
+    fn keypair(ctx: &Self::Context<'_>, secret: &[u8; 32]) -> Result<Self::KeyPair, Error> {
+        secp256k1::KeyPair::from_seckey_slice(ctx, secret).map_err(|_| Error::InvalidPrivkey)
+    }
+
+    fn secret_bytes(keypair: &Self::KeyPair) -> [u8; 32] {
+        keypair.secret_bytes()
+    }
+
+    fn x_only(keypair: &Self::KeyPair) -> [u8; 32] {
+        keypair.x_only_public_key().0.serialize()
+    }
+
+    fn compressed_pubkey(keypair: &Self::KeyPair) -> [u8; 33] {
+        keypair.public_key().serialize()
+    }
+
+    fn parse_pubkey(pubkey: &[u8; 32]) -> Result<Self::PublicKey, Error> {
+        secp256k1::XOnlyPublicKey::from_slice(pubkey).map_err(|_| Error::InvalidPubkey)
+    }
+
+    fn sign(
+        ctx: &Self::Context<'_>,
+        keypair: &Self::KeyPair,
+        digest: &[u8; 32],
+        aux_rnd: &[u8; 32],
+    ) -> Result<[u8; 64], Error> {
+        let message =
+            secp256k1::Message::from_slice(digest).map_err(|_| Error::InternalSigningError)?;
+        let sig = ctx.sign_schnorr_with_aux_rand(&message, keypair, aux_rnd);
+        Ok(*sig.as_ref())
+    }
+
+    fn verify(
+        ctx: &Self::VerifyContext<'_>,
+        pubkey: &Self::PublicKey,
+        digest: &[u8; 32],
+        sig: &[u8; 64],
+    ) -> Result<(), Error> {
+        let message = secp256k1::Message::from_slice(digest).map_err(|_| Error::EventNotValid)?;
+        let sig =
+            secp256k1::schnorr::Signature::from_slice(sig).map_err(|_| Error::InvalidSignature)?;
+        ctx.verify_schnorr(&sig, &message, pubkey)
+            .map_err(|_| Error::InvalidSignature)
+    }
+
+    fn shared_x(secret: &[u8; 32], pubkey: &Self::PublicKey) -> Result<[u8; 32], Error> {
+        let sk = secp256k1::SecretKey::from_slice(secret).map_err(|_| Error::InvalidPrivkey)?;
+        let even = secp256k1::PublicKey::from_x_only_public_key(*pubkey, secp256k1::Parity::Even);
+        let point = secp256k1::ecdh::shared_secret_point(&even, &sk);
+        let mut shared = [0_u8; 32];
+        shared.copy_from_slice(&point[..32]);
+        Ok(shared)
+    }
+
+    fn add_tweak(secret: &[u8; 32], tweak: &[u8; 32]) -> Result<[u8; 32], Error> {
+        let sk = secp256k1::SecretKey::from_slice(secret).map_err(|_| Error::KeyDerivationError)?;
+        let tweak = secp256k1::scalar::Scalar::from_be_bytes(*tweak)
+            .map_err(|_| Error::KeyDerivationError)?;
+        sk.add_tweak(&tweak)
+            .map(|sk| sk.secret_bytes())
+            .map_err(|_| Error::KeyDerivationError)
+    }
+}
+
+/// RustCrypto's pure Rust implementation
+#[cfg(feature = "k256")]
+pub(crate) struct K256Backend;
+
+/// `k256` needs no context, contexts are this empty placeholder
+#[cfg(feature = "k256")]
+pub(crate) struct NoContext;
+
+/// The secret key is kept as given, `SigningKey` negates it when its point has an odd y
+#[cfg(feature = "k256")]
+#[derive(Clone)]
+pub(crate) struct K256KeyPair {
+    secret: k256::NonZeroScalar,
+    signing: k256::schnorr::SigningKey,
+}
+
+#[cfg(feature = "k256")]
+impl CurveBackend for K256Backend {
+    type Context<'buf> = NoContext;
+    type VerifyContext<'buf> = NoContext;
+    type KeyPair = K256KeyPair;
+    type PublicKey = k256::schnorr::VerifyingKey;
+
+    fn context(_buf: &mut [AlignedType]) -> Result<Self::Context<'_>, Error> {
+        Ok(NoContext)
+    }
+
+    fn verify_context(_buf: &mut [AlignedType]) -> Result<Self::VerifyContext<'_>, Error> {
+        Ok(NoContext)
+    }
+
+    fn keypair(_ctx: &Self::Context<'_>, secret: &[u8; 32]) -> Result<Self::KeyPair, Error> {
+        let secret = non_zero_scalar(secret).ok_or(Error::InvalidPrivkey)?;
+        Ok(K256KeyPair {
+            secret,
+            signing: secret.into(),
+        })
+    }
+
+    fn secret_bytes(keypair: &Self::KeyPair) -> [u8; 32] {
+        keypair.secret.to_bytes().into()
+    }
+
+    fn x_only(keypair: &Self::KeyPair) -> [u8; 32] {
+        keypair.signing.verifying_key().to_bytes().into()
+    }
+
+    fn compressed_pubkey(keypair: &Self::KeyPair) -> [u8; 33] {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+        let point = k256::PublicKey::from_secret_scalar(&keypair.secret).to_encoded_point(true);
+        let mut compressed = [0_u8; 33];
+        compressed.copy_from_slice(point.as_bytes());
+        compressed
+    }
+
+    fn parse_pubkey(pubkey: &[u8; 32]) -> Result<Self::PublicKey, Error> {
+        k256::schnorr::VerifyingKey::from_bytes(pubkey).map_err(|_| Error::InvalidPubkey)
+    }
+
+    fn sign(
+        _ctx: &Self::Context<'_>,
+        keypair: &Self::KeyPair,
+        digest: &[u8; 32],
+        aux_rnd: &[u8; 32],
+    ) -> Result<[u8; 64], Error> {
+        keypair
+            .signing
+            .sign_prehash_with_aux_rand(digest, aux_rnd)
+            .map(|sig| sig.to_bytes())
+            .map_err(|_| Error::InternalSigningError)
+    }
+
+    fn verify(
+        _ctx: &Self::VerifyContext<'_>,
+        pubkey: &Self::PublicKey,
+        digest: &[u8; 32],
+        sig: &[u8; 64],
+    ) -> Result<(), Error> {
+        let sig =
+            k256::schnorr::Signature::try_from(&sig[..]).map_err(|_| Error::InvalidSignature)?;
+        pubkey
+            .verify_raw(digest, &sig)
+            .map_err(|_| Error::InvalidSignature)
+    }
+
+    fn shared_x(secret: &[u8; 32], pubkey: &Self::PublicKey) -> Result<[u8; 32], Error> {
+        let secret = non_zero_scalar(secret).ok_or(Error::InvalidPrivkey)?;
+        let shared = k256::ecdh::diffie_hellman(secret, pubkey.as_affine());
+        Ok((*shared.raw_secret_bytes()).into())
+    }
+
+    fn add_tweak(secret: &[u8; 32], tweak: &[u8; 32]) -> Result<[u8; 32], Error> {
+        use k256::elliptic_curve::PrimeField;
+        let secret = non_zero_scalar(secret).ok_or(Error::KeyDerivationError)?;
+        let tweak: Option<k256::Scalar> = k256::Scalar::from_repr((*tweak).into()).into();
+        let sum: Option<k256::NonZeroScalar> =
+            k256::NonZeroScalar::new(*secret + tweak.ok_or(Error::KeyDerivationError)?).into();
+        Ok(sum.ok_or(Error::KeyDerivationError)?.to_bytes().into())
+    }
+}
+
+/// Bits of the scalars a [`WindowedMul`] step adds in
+#[cfg(feature = "k256")]
+const WINDOW_BITS: usize = 2;
+
+/// Multiples of each point a [`WindowedMul`] keeps
+#[cfg(feature = "k256")]
+const WINDOW_TABLE: usize = 1 << WINDOW_BITS;
+
+/// Sum of `scalar * point` for `N` pairs, worked out [`WINDOW_BITS`] bits of the scalars at a
+/// time so a multiplication can be spread over many calls. Each call to [`WindowedMul::step`]
+/// does at most a few point additions. Table lookups are constant time, so the scalars can be
+/// secret.
+#[cfg(feature = "k256")]
+pub(crate) struct WindowedMul<const N: usize> {
+    /// Big endian
+    scalars: [[u8; 32]; N],
+    /// `0 * point` up to `(WINDOW_TABLE - 1) * point` for each point
+    tables: [[k256::ProjectivePoint; WINDOW_TABLE]; N],
+    /// Entries of the tables filled in
+    built: usize,
+    /// Windows of the scalars added in, most significant first
+    window: usize,
+    sum: k256::ProjectivePoint,
+}
+
+#[cfg(feature = "k256")]
+impl<const N: usize> WindowedMul<N> {
+    pub(crate) fn new(terms: [(k256::ProjectivePoint, k256::Scalar); N]) -> Self {
+        let identity = k256::ProjectivePoint::IDENTITY;
+        let mut tables = [[identity; WINDOW_TABLE]; N];
+        for (table, (point, _)) in tables.iter_mut().zip(terms.iter()) {
+            table[1] = *point;
+        }
+        WindowedMul {
+            scalars: terms.map(|(_, scalar)| scalar.to_bytes().into()),
+            tables,
+            built: 2,
+            window: 0,
+            sum: identity,
+        }
+    }
+
+    /// Fills in the next entry of the tables or adds in the next window, returning the sum once
+    /// every window is in
+    pub(crate) fn step(&mut self) -> Option<k256::ProjectivePoint> {
+        use k256::elliptic_curve::subtle::{ConditionallySelectable, ConstantTimeEq};
+        if self.built < WINDOW_TABLE {
+            for table in self.tables.iter_mut() {
+                table[self.built] = table[self.built - 1] + table[1];
+            }
+            self.built += 1;
+            return None;
+        }
+        for _ in 0..WINDOW_BITS {
+            self.sum = self.sum.double();
+        }
+        let bit = self.window * WINDOW_BITS;
+        let shift = 8 - WINDOW_BITS - bit % 8;
+        for (table, scalar) in self.tables.iter().zip(self.scalars.iter()) {
+            let bits = (scalar[bit / 8] >> shift) & (WINDOW_TABLE as u8 - 1);
+            let mut point = k256::ProjectivePoint::IDENTITY;
+            for (i, entry) in (0_u8..).zip(table.iter()) {
+                point.conditional_assign(entry, i.ct_eq(&bits));
+            }
+            self.sum += point;
+        }
+        self.window += 1;
+        (self.window * WINDOW_BITS == 256).then_some(self.sum)
+    }
+}
+
+/// BIP-340 tagged hash of the concatenated `parts`
+#[cfg(feature = "k256")]
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let tag = Sha256::digest(tag);
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    hasher.update(tag);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// `bytes` as a scalar, modulo the curve order
+#[cfg(feature = "k256")]
+fn reduce(bytes: &[u8; 32]) -> k256::Scalar {
+    use k256::elliptic_curve::ops::Reduce;
+    <k256::Scalar as Reduce<k256::U256>>::reduce_bytes(&(*bytes).into())
+}
+
+/// A BIP-340 signature made over many calls, the nonce point being a [`WindowedMul`]
+#[cfg(feature = "k256")]
+pub(crate) struct StepwiseSign {
+    /// Secret key, negated if its point has an odd y
+    secret: k256::Scalar,
+    pubkey: [u8; 32],
+    digest: [u8; 32],
+    nonce: k256::Scalar,
+    mul: WindowedMul<1>,
+}
+
+#[cfg(feature = "k256")]
+impl StepwiseSign {
+    /// Derives the nonce for signing `digest` with `keypair`, the same one
+    /// [`CurveBackend::sign`] would use
+    pub(crate) fn new(
+        keypair: &K256KeyPair,
+        digest: &[u8; 32],
+        aux_rnd: &[u8; 32],
+    ) -> Result<Self, Error> {
+        let secret = *keypair.signing.as_nonzero_scalar().as_ref();
+        let pubkey: [u8; 32] = keypair.signing.verifying_key().to_bytes().into();
+        let mut t = tagged_hash(b"BIP0340/aux", &[aux_rnd]);
+        for (t, d) in t.iter_mut().zip(secret.to_bytes().iter()) {
+            *t ^= d;
+        }
+        let nonce = reduce(&tagged_hash(b"BIP0340/nonce", &[&t, &pubkey, digest]));
+        if bool::from(nonce.is_zero()) {
+            return Err(Error::InternalSigningError);
+        }
+        Ok(StepwiseSign {
+            secret,
+            pubkey,
+            digest: *digest,
+            nonce,
+            mul: WindowedMul::new([(k256::ProjectivePoint::GENERATOR, nonce)]),
+        })
+    }
+
+    /// Does the next step of the nonce point, then returns the signature
+    pub(crate) fn step(&mut self) -> Option<[u8; 64]> {
+        use k256::elliptic_curve::point::AffineCoordinates;
+        let point = self.mul.step()?.to_affine();
+        let nonce = if bool::from(point.y_is_odd()) {
+            -self.nonce
+        } else {
+            self.nonce
+        };
+        let r: [u8; 32] = point.x().into();
+        let e = reduce(&tagged_hash(
+            b"BIP0340/challenge",
+            &[&r, &self.pubkey, &self.digest],
+        ));
+        let mut sig = [0_u8; 64];
+        sig[..32].copy_from_slice(&r);
+        sig[32..].copy_from_slice(&(nonce + e * self.secret).to_bytes());
+        Some(sig)
+    }
+}
+
+/// A BIP-340 signature checked over many calls, `s * G - e * P` being a [`WindowedMul`]
+#[cfg(feature = "k256")]
+pub(crate) struct StepwiseVerify {
+    r: [u8; 32],
+    mul: WindowedMul<2>,
+}
+
+#[cfg(feature = "k256")]
+impl StepwiseVerify {
+    /// Lifts `pubkey` and works out the challenge for `sig` over `digest`.
+    /// Errors with `InvalidPubkey` if `pubkey` is not on the curve and `InvalidSignature` if
+    /// `sig` is out of range.
+    pub(crate) fn new(pubkey: &[u8; 32], digest: &[u8; 32], sig: &[u8; 64]) -> Result<Self, Error> {
+        use k256::elliptic_curve::PrimeField;
+        let point = K256Backend::parse_pubkey(pubkey)?;
+        k256::schnorr::Signature::try_from(&sig[..]).map_err(|_| Error::InvalidSignature)?;
+        let mut r = [0_u8; 32];
+        r.copy_from_slice(&sig[..32]);
+        let mut s = [0_u8; 32];
+        s.copy_from_slice(&sig[32..]);
+        let s: Option<k256::Scalar> = k256::Scalar::from_repr(s.into()).into();
+        let e = reduce(&tagged_hash(b"BIP0340/challenge", &[&r, pubkey, digest]));
+        Ok(StepwiseVerify {
+            r,
+            mul: WindowedMul::new([
+                (
+                    k256::ProjectivePoint::GENERATOR,
+                    s.ok_or(Error::InvalidSignature)?,
+                ),
+                (point.as_affine().into(), -e),
+            ]),
+        })
+    }
+
+    /// Does the next step of `s * G - e * P`, then returns whether it matches the signature.
+    /// Errors with `InvalidSignature` if it doesn't.
+    pub(crate) fn step(&mut self) -> Option<Result<(), Error>> {
+        use k256::elliptic_curve::{group::prime::PrimeCurveAffine, point::AffineCoordinates};
+        let point = self.mul.step()?.to_affine();
+        let x: [u8; 32] = point.x().into();
+        if bool::from(point.is_identity()) || bool::from(point.y_is_odd()) || x != self.r {
+            return Some(Err(Error::InvalidSignature));
+        }
+        Some(Ok(()))
+    }
+}
+
+/// Secret key scalar, `None` if zero or not below the curve order
+#[cfg(feature = "k256")]
+fn non_zero_scalar(secret: &[u8; 32]) -> Option<k256::NonZeroScalar> {
+    k256::NonZeroScalar::try_from(&secret[..]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [
+        0xa5, 0x08, 0x4b, 0x35, 0xa5, 0x8e, 0x3e, 0x1a, 0x26, 0xf5, 0xef, 0xb4, 0x6c, 0xb9, 0xdb,
+        0xad, 0xa7, 0x31, 0x91, 0x52, 0x6a, 0xa6, 0xd1, 0x1b, 0xcc, 0xb5, 0x90, 0xcb, 0xeb, 0x2d,
+        0x8f, 0xa3,
+    ];
+
+    // BIP-340 test vector 0
+    const BIP340_SECRET: &str = "0000000000000000000000000000000000000000000000000000000000000003";
+    const BIP340_PUBKEY: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+    const BIP340_SIG: &str = "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0";
+
+    fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+        let mut bytes = [0_u8; N];
+        base16ct::lower::decode(hex, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_bip340_vector() {
+        let keypair = keypair_from_hex(BIP340_SECRET).unwrap();
+        assert_eq!(Curve::x_only(&keypair), from_hex::<32>(BIP340_PUBKEY));
+        let sig = sign(&keypair, &[0; 32], &[0; 32]).unwrap();
+        assert_eq!(sig, from_hex::<64>(BIP340_SIG));
+
+        let pubkey = Curve::parse_pubkey(&from_hex(BIP340_PUBKEY)).unwrap();
+        assert!(verify(&pubkey, &[0; 32], &sig).is_ok());
+        assert_eq!(
+            verify(&pubkey, &[1; 32], &sig),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_keypair() {
+        let mut buf = [AlignedType::zeroed(); CONTEXT_ITEMS];
+        let ctx = Curve::context(&mut buf).unwrap();
+        let keypair = Curve::keypair(&ctx, &SECRET).unwrap();
+        // kept as given even though this key's point has an odd y
+        assert_eq!(Curve::secret_bytes(&keypair), SECRET);
+        assert_eq!(Curve::compressed_pubkey(&keypair)[0], 0x03);
+        assert_eq!(
+            Curve::compressed_pubkey(&keypair)[1..],
+            Curve::x_only(&keypair)
+        );
+        assert!(Curve::keypair(&ctx, &[0; 32]).is_err());
+        assert!(Curve::keypair(&ctx, &[0xff; 32]).is_err());
+        assert_eq!(parse_secret("zz"), Err(Error::InvalidPrivkey));
+    }
+
+    #[test]
+    fn test_shared_x() {
+        let mut buf = [AlignedType::zeroed(); CONTEXT_ITEMS];
+        let ctx = Curve::context(&mut buf).unwrap();
+        let ours = Curve::keypair(&ctx, &SECRET).unwrap();
+        let theirs = Curve::keypair(&ctx, &[7; 32]).unwrap();
+        let their_pubkey = Curve::parse_pubkey(&Curve::x_only(&theirs)).unwrap();
+        let our_pubkey = Curve::parse_pubkey(&Curve::x_only(&ours)).unwrap();
+        assert_eq!(
+            Curve::shared_x(&SECRET, &their_pubkey).unwrap(),
+            Curve::shared_x(&[7; 32], &our_pubkey).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_add_tweak() {
+        let mut one = [0_u8; 32];
+        one[31] = 1;
+        let mut two = one;
+        two[31] = 2;
+        assert_eq!(Curve::add_tweak(&one, &one).unwrap(), two);
+        assert_eq!(
+            Curve::add_tweak(&one, &[0xff; 32]),
+            Err(Error::KeyDerivationError)
+        );
+    }
+}
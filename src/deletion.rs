@@ -0,0 +1,139 @@
+//! Deletion requests ([NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md))
+//!
+//! A kind 5 note asks relays and clients to drop earlier notes by the same author, listing
+//! their ids in `e` tags. Requests from anyone else are to be ignored, which
+//! [`Note::deletes`] checks.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::Note;
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let note = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("oops".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let id = note.id();
+//! let deletion = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .create_deletion(&[core::str::from_utf8(&id).unwrap()], "posted by mistake")
+//!     .unwrap()
+//!     .build(1686880021, [0; 32])
+//!     .unwrap();
+//! assert!(deletion.deletes(&note));
+//! ```
+
+use heapless::String;
+
+use crate::{errors::Error, tag::Tag, utils::from_hex, Note, NoteBuilder, NoteKinds};
+
+/// Kind of a deletion request
+pub const DELETION_KIND: u32 = 5;
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 5 request to delete the notes with hex ids `ids`, with an
+    /// `e` tag for each. `reason` is shown to users and may be empty.
+    /// Replaces any tags added before.
+    /// Errors with `EventNotValid` if an id isn't 64 hex characters, `TooManyTags` if there are
+    /// more than `NTAGS` ids and `ContentOverflow` if `reason` is longer than `CONTENT`.
+    pub fn create_deletion(
+        mut self,
+        ids: &[&str],
+        reason: &str,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.note.tags.clear();
+        for id in ids {
+            from_hex::<32>(id.as_bytes()).map_err(|_| Error::EventNotValid)?;
+            self.note
+                .tags
+                .push(Tag::try_from(["e", id])?)
+                .map_err(|_| Error::TooManyTags)?;
+        }
+        self.note.content = if reason.is_empty() {
+            None
+        } else {
+            let mut content = String::new();
+            content
+                .push_str(reason)
+                .map_err(|_| Error::ContentOverflow)?;
+            Some(content)
+        };
+        self.note.kind = NoteKinds::Deletion;
+        Ok(self)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Ids of the notes this deletion request targets, nothing if it isn't one.
+    /// `e` tags that don't hold a hex id are skipped.
+    pub fn deleted_ids(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        self.tags
+            .iter()
+            .filter(|_| self.kind.as_u32() == DELETION_KIND)
+            .filter(|tag| tag.name() == "e")
+            .filter_map(|tag| from_hex(tag.value(0)?.as_bytes()).ok())
+    }
+
+    /// True if this is a deletion request for `target` from its author.
+    /// Doesn't check signatures, verify the request first if it came from a relay.
+    pub fn deletes<const C: usize, const T: usize, const N: usize, const V: usize>(
+        &self,
+        target: &Note<C, T, N, V>,
+    ) -> bool {
+        self.pubkey == target.pubkey && self.deleted_ids().any(|id| id == target.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, OTHER_PRIVKEY, PRIVKEY};
+    const OTHER_ID: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+
+    #[test]
+    fn test_deletion() {
+        let target = text_note();
+        let id = target.id();
+        let ids = [OTHER_ID, core::str::from_utf8(&id).unwrap()];
+        let deletion = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_deletion(&ids, "")
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert_eq!(deletion.kind(), NoteKinds::Deletion);
+        assert_eq!(deletion.content(), None);
+        assert_eq!(deletion.deleted_ids().count(), 2);
+        assert!(deletion.deletes(&target));
+        assert!(deletion.verify().is_ok());
+
+        // only the author can delete
+        let forged = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_deletion(&ids, "")
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert!(!forged.deletes(&target));
+        // and only with a deletion request
+        assert_eq!(target.deleted_ids().count(), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let builder = Note::new_builder(PRIVKEY).unwrap();
+        assert!(matches!(
+            builder.create_deletion(&["abc"], ""),
+            Err(Error::EventNotValid)
+        ));
+        let builder = Note::new_builder(PRIVKEY).unwrap();
+        assert!(matches!(
+            builder.create_deletion(&[OTHER_ID; 6], ""),
+            Err(Error::TooManyTags)
+        ));
+    }
+}
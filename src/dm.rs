@@ -0,0 +1,304 @@
+//! Direct message conversations with one peer, deriving the shared key once
+//!
+//! [`Note::read_dm`] and [`NoteBuilder::create_dm`] run ECDH for every message, which is the
+//! slowest part of a DM on a microcontroller. A [`DmSession`] runs it once per peer and keeps
+//! both the NIP-04 key and the [NIP-44](crate::nip44) conversation key, so every following
+//! message only costs the symmetric encryption.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{dm::DmSession, Note};
+//! let sender = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let receiver = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+//! let sender_pubkey = "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+//! let receiver_pubkey = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+//!
+//! let session = DmSession::from_privkey(sender, receiver_pubkey).unwrap();
+//! // iv and nonce should be generated from a random number generator
+//! let note = Note::new_builder(sender)
+//!     .unwrap()
+//!     .create_dm_with_session(&session, "hello from the internet", [1; 16])
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let payload = session.encrypt_v2("and again", [2; 32]).unwrap();
+//!
+//! let session = DmSession::from_privkey(receiver, sender_pubkey).unwrap();
+//! assert_eq!(note.read_dm_with_session(&session).unwrap(), "hello from the internet");
+//! assert_eq!(session.decrypt_v2(&payload).unwrap(), "and again");
+//! ```
+
+use sha2::Sha256;
+
+use crate::{
+    backend::SoftAes256,
+    curve::{self, Curve, CurveBackend},
+    errors::Error,
+    keys::Keys,
+    nip04, nip44,
+    tag::Tag,
+    utils::{from_hex, to_hex},
+    Note, NoteBuilder, NoteKinds, String, MAX_DM_SIZE, NOTE_SIZE,
+};
+
+/// Keys shared with one peer, for both NIP-04 and NIP-44 DMs
+pub struct DmSession {
+    /// Own x-only pubkey
+    own: [u8; 32],
+    /// X-only pubkey of the other side
+    peer: [u8; 32],
+    /// X coordinate of the ECDH point, the NIP-04 AES key
+    shared_key: [u8; 32],
+    /// NIP-44 conversation key
+    conversation_key: [u8; 32],
+}
+
+impl DmSession {
+    /// Derives the keys shared by `keys` and the hex encoded `peer_pubkey`.
+    /// Errors with `InvalidPubkey` if `peer_pubkey` is not a valid pubkey.
+    pub fn new(keys: &Keys, peer_pubkey: &str) -> Result<Self, Error> {
+        DmSession::from_secret(&keys.secret_key(), *keys.pubkey_bytes(), peer_pubkey)
+    }
+
+    /// Same as [`DmSession::new`], parsing `privkey` first.
+    /// Errors with `InvalidPrivkey` if the privkey is invalid.
+    pub fn from_privkey(privkey: &str, peer_pubkey: &str) -> Result<Self, Error> {
+        let keypair = curve::keypair_from_hex(privkey)?;
+        DmSession::from_secret(
+            &Curve::secret_bytes(&keypair),
+            Curve::x_only(&keypair),
+            peer_pubkey,
+        )
+    }
+
+    fn from_secret(secret: &[u8; 32], own: [u8; 32], peer_pubkey: &str) -> Result<Self, Error> {
+        let peer: [u8; 32] = from_hex(peer_pubkey.as_bytes()).map_err(|_| Error::InvalidPubkey)?;
+        let shared_key = nip04::generate_shared_key(secret, &peer)?;
+        let conversation_key =
+            nip44::conversation_key_from_shared(&mut Sha256::default(), &shared_key);
+        Ok(DmSession {
+            own,
+            peer,
+            shared_key,
+            conversation_key,
+        })
+    }
+
+    /// X-only pubkey of the other side of the conversation
+    pub fn peer(&self) -> &[u8; 32] {
+        &self.peer
+    }
+
+    /// Encrypts `text` per NIP-04, `iv` should be generated from a random source.
+    /// Errors with `ContentOverflow` if the encrypted text is longer than [`MAX_DM_SIZE`].
+    pub fn encrypt(&self, text: &str, iv: [u8; 16]) -> Result<String<MAX_DM_SIZE>, Error> {
+        self.encrypt_sized(text, iv)
+    }
+
+    /// Same as [`DmSession::encrypt`], for encrypted texts of up to `N` characters.
+    /// Uses about twice `N` bytes of stack.
+    pub fn encrypt_sized<const N: usize>(
+        &self,
+        text: &str,
+        iv: [u8; 16],
+    ) -> Result<String<N>, Error> {
+        nip04::encrypt_sized(&mut SoftAes256::default(), &self.shared_key, text, iv)
+    }
+
+    /// Decrypts the `content` of a NIP-04 DM
+    pub fn decrypt(&self, content: &str) -> Result<String<MAX_DM_SIZE>, Error> {
+        self.decrypt_sized(content)
+    }
+
+    /// Same as [`DmSession::decrypt`], for contents of up to `N` characters.
+    /// Uses about twice `N` bytes of stack.
+    pub fn decrypt_sized<const N: usize>(&self, content: &str) -> Result<String<N>, Error> {
+        nip04::decrypt_sized(&mut SoftAes256::default(), &self.shared_key, content)
+    }
+
+    /// Encrypts `text` per NIP-44, `nonce` must be random and never reused.
+    /// Errors like [`nip44::encrypt`].
+    pub fn encrypt_v2(&self, text: &str, nonce: [u8; 32]) -> Result<String<NOTE_SIZE>, Error> {
+        nip44::encrypt(&self.conversation_key, text, nonce)
+    }
+
+    /// Same as [`DmSession::encrypt_v2`], for payloads of up to `N` characters.
+    /// Errors like [`nip44::encrypt_sized`].
+    pub fn encrypt_v2_sized<const N: usize>(
+        &self,
+        text: &str,
+        nonce: [u8; 32],
+    ) -> Result<String<N>, Error> {
+        nip44::encrypt_sized(&self.conversation_key, text, nonce)
+    }
+
+    /// Decrypts a NIP-44 payload.
+    /// Errors like [`nip44::decrypt`].
+    pub fn decrypt_v2(&self, payload: &str) -> Result<String<MAX_DM_SIZE>, Error> {
+        nip44::decrypt(&self.conversation_key, payload)
+    }
+
+    /// Same as [`DmSession::decrypt_v2`], for payloads of up to `N` characters.
+    /// Errors like [`nip44::decrypt_sized`].
+    pub fn decrypt_v2_sized<const N: usize>(&self, payload: &str) -> Result<String<N>, Error> {
+        nip44::decrypt_sized(&self.conversation_key, payload)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::create_dm`], encrypting with the key held by `session`
+    pub fn create_dm_with_session(
+        mut self,
+        session: &DmSession,
+        content: &str,
+        iv: [u8; 16],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.note.content = Some(session.encrypt_sized(content, iv)?);
+        self.note.kind = NoteKinds::DM;
+        self.add_peer_tag(session)
+    }
+
+    /// Same as [`NoteBuilder::create_dm_v2`], encrypting with the key held by `session`
+    pub fn create_dm_v2_with_session(
+        mut self,
+        session: &DmSession,
+        content: &str,
+        nonce: [u8; 32],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.note.content = Some(session.encrypt_v2_sized(content, nonce)?);
+        self.note.kind = NoteKinds::DM;
+        self.add_peer_tag(session)
+    }
+
+    fn add_peer_tag(
+        self,
+        session: &DmSession,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let peer: [u8; 64] = to_hex(&session.peer);
+        let peer = core::str::from_utf8(&peer).map_err(|_| Error::InternalError)?;
+        self.add_tag(Tag::try_from(["p", peer])?)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Decrypts a DM sent to or received from the peer of `session`, made with either NIP-04
+    /// or NIP-44.
+    /// Errors with `InvalidPubkey` if the note is not part of the conversation.
+    pub fn read_dm_with_session(&self, session: &DmSession) -> Result<String<CONTENT>, Error> {
+        let tags = |pubkey: &[u8; 32]| {
+            self.tags()
+                .iter()
+                .filter(|tag| tag.name() == "p")
+                .filter_map(|tag| tag.value(0))
+                .any(|value| from_hex::<32>(value.as_bytes()).as_ref() == Ok(pubkey))
+        };
+        let received = self.pubkey == session.peer && tags(&session.own);
+        let sent = self.pubkey == session.own && tags(&session.peer);
+        if !received && !sent {
+            return Err(Error::InvalidPubkey);
+        }
+        let content = self.content().ok_or(Error::MalformedContent)?;
+        if content.contains("?iv=") {
+            session.decrypt_sized(content)
+        } else {
+            session.decrypt_v2_sized(content)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::AlignedType;
+    use crate::vectors::{OTHER_PRIVKEY, OTHER_PUBKEY, PRIVKEY, PUBKEY};
+
+    const DM_SEND: &str = r#"{"content":"lPQ9iBd6abUrDBJbHWaL3qqhqsuAxK0aU80IgsZ2aqE=?iv=O1zZfD9HPiig1yuZEWX7uQ==","created_at":1691117390,"id":"c0be8c32d95f7599ccfe324711ad50890ee08985710997fcda1a1a3840a23d51","kind":4,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"8ee1e83ab037c9e9ff1ac97db88aa045b2f1d9204daa7fee25e5f42274ee8d5f4365b87677c4f27827ca043becc65c1f38f646d05adf3d2c570b66fea57e5918","tags":[["p","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"]]}"#;
+
+    #[test]
+    fn test_matches_read_dm() {
+        let note = Note::try_from(DM_SEND).unwrap();
+        let receiver = DmSession::from_privkey(OTHER_PRIVKEY, PUBKEY).unwrap();
+        let sender = DmSession::from_privkey(PRIVKEY, OTHER_PUBKEY).unwrap();
+        assert_eq!(note.read_dm(PRIVKEY).unwrap(), "hello from the internet");
+        assert_eq!(
+            note.read_dm_with_session(&receiver).unwrap(),
+            "hello from the internet"
+        );
+        assert_eq!(
+            note.read_dm_with_session(&sender).unwrap(),
+            "hello from the internet"
+        );
+
+        let mut buf = [AlignedType::zeroed(); crate::keys::CONTEXT_SIZE];
+        let keys = Keys::new(OTHER_PRIVKEY, &mut buf).unwrap();
+        let from_keys = DmSession::new(&keys, PUBKEY).unwrap();
+        assert_eq!(from_keys.peer(), receiver.peer());
+        assert_eq!(
+            from_keys.decrypt(note.content().unwrap()).unwrap(),
+            "hello from the internet"
+        );
+
+        let stranger = DmSession::from_privkey(OTHER_PRIVKEY, OTHER_PUBKEY).unwrap();
+        assert_eq!(
+            note.read_dm_with_session(&stranger),
+            Err(Error::InvalidPubkey)
+        );
+    }
+
+    #[test]
+    fn test_both_schemes() {
+        let sender = DmSession::from_privkey(PRIVKEY, OTHER_PUBKEY).unwrap();
+        let receiver = DmSession::from_privkey(OTHER_PRIVKEY, PUBKEY).unwrap();
+
+        let v1 = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm_with_session(&sender, "first", [1; 16])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm("first", OTHER_PUBKEY, [1; 16])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(v1.content(), expected.content());
+        assert_eq!(v1.kind(), NoteKinds::DM);
+        assert_eq!(v1.read_dm_with_session(&receiver).unwrap(), "first");
+
+        let v2 = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm_v2_with_session(&sender, "second", [2; 32])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm_v2("second", OTHER_PUBKEY, [2; 32])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(v2, expected);
+        assert_eq!(v2.read_dm_with_session(&receiver).unwrap(), "second");
+        assert_eq!(v2.read_dm_v2(OTHER_PRIVKEY).unwrap(), "second");
+
+        for text in ["one", "two", "three"] {
+            let payload = receiver.encrypt(text, [3; 16]).unwrap();
+            assert_eq!(sender.decrypt(&payload).unwrap(), text);
+            let payload = receiver.encrypt_v2(text, [3; 32]).unwrap();
+            assert_eq!(sender.decrypt_v2(&payload).unwrap(), text);
+        }
+        assert_eq!(
+            DmSession::from_privkey(PRIVKEY, "abc").err(),
+            Some(Error::InvalidPubkey)
+        );
+        assert_eq!(
+            DmSession::from_privkey("abc", OTHER_PUBKEY).err(),
+            Some(Error::InvalidPrivkey)
+        );
+    }
+}
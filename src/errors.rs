@@ -1,6 +1,9 @@
 //! Possible errors thrown by this crate
 
+use core::fmt;
+
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     InvalidPubkey,
     InvalidPrivkey,
@@ -12,12 +15,209 @@ pub enum Error {
     InvalidType,
     TypeNotAccepted,
     MalformedContent,
+    /// Badly formed JSON, `offset` is the byte of the message where `field` starts
+    Malformed {
+        field: Field,
+        offset: usize,
+    },
     ContentOverflow,
     EventNotValid,
-    EventMissingField,
+    EventMissingField(Field),
     TooManyTags,
     InternalError,
     EncodeError,
     Secp256k1Error,
     QueryBuilderOverflow,
+    ScratchTooSmall,
+    RecordEmpty,
+    RecordCorrupted,
+    StoreFull,
+    QueueFull,
+    MessageTooLong,
+    KeyDerivationError,
+    InvalidContent(ContentRule),
+    InvalidAuth(AuthRule),
+    InvalidMac,
+    MissingPrivkey,
+    SignerError,
+    RngError,
+    CreatedAtOutOfRange,
+    InvalidPadding,
+    InvalidSubscriptionId,
+}
+
+/// Field of an event that was required but not found
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Field {
+    Id,
+    Pubkey,
+    CreatedAt,
+    Kind,
+    Tags,
+    Content,
+    Sig,
+    /// A tag the kind of the event requires, such as the `bolt11` tag of a zap receipt
+    Tag,
+    /// An element of a message array, such as the subscription id of an `EOSE`
+    Element,
+}
+
+/// Content rule broken by a note, see [`validate_content`](crate::validation::validate_content)
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ContentRule {
+    /// Kind 0 content must be a JSON object
+    MetadataNotObject,
+    /// Kind 3 content must be empty or a JSON object of relays
+    ContactListNotObject,
+    /// Kind 4 content must be `<base64>?iv=<base64>`
+    DmMissingIv,
+    /// Kind 10002 content must be empty
+    RelayListNotEmpty,
+}
+
+/// Check failed by an incoming auth event, see [`verify_auth_event`](crate::nip42::verify_auth_event)
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthRule {
+    /// The event isn't kind 22242
+    WrongKind,
+    /// The `challenge` tag is missing or isn't the challenge that was sent
+    ChallengeMismatch,
+    /// The `relay` tag is missing or names another relay
+    RelayMismatch,
+    /// `created_at` is too far from the current time
+    Expired,
+}
+
+impl Error {
+    /// Moves the offset of a `Malformed` error by `by` bytes, for errors found in part of a
+    /// larger message
+    pub(crate) fn offset_by(self, by: usize) -> Self {
+        match self {
+            Error::Malformed { field, offset } => Error::Malformed {
+                field,
+                offset: offset + by,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidPubkey => f.write_str("invalid pubkey"),
+            Error::InvalidPrivkey => f.write_str("invalid private key"),
+            Error::InternalPubkeyError => f.write_str("could not derive the pubkey"),
+            Error::InternalSigningError => f.write_str("could not sign"),
+            Error::InvalidSignature => f.write_str("invalid signature"),
+            Error::TagNameTooLong => f.write_str("tag name too long"),
+            Error::UnknownKind => f.write_str("unknown kind"),
+            Error::InvalidType => f.write_str("unexpected message or event type"),
+            Error::TypeNotAccepted => f.write_str("message type not accepted"),
+            Error::MalformedContent => f.write_str("malformed content"),
+            Error::Malformed { field, offset } => write!(f, "malformed {field} at byte {offset}"),
+            Error::ContentOverflow => f.write_str("content does not fit in its buffer"),
+            Error::EventNotValid => {
+                f.write_str("event id is malformed or does not match its contents")
+            }
+            Error::EventMissingField(field) => write!(f, "event is missing its {field}"),
+            Error::TooManyTags => f.write_str("too many tags"),
+            Error::InternalError => f.write_str("internal error"),
+            Error::EncodeError => f.write_str("could not encode"),
+            Error::Secp256k1Error => f.write_str("secp256k1 error"),
+            Error::QueryBuilderOverflow => f.write_str("query is full"),
+            Error::ScratchTooSmall => f.write_str("scratch buffer too small"),
+            Error::RecordEmpty => f.write_str("record is empty"),
+            Error::RecordCorrupted => f.write_str("record is corrupted"),
+            Error::StoreFull => f.write_str("store is full"),
+            Error::QueueFull => f.write_str("queue is full"),
+            Error::MessageTooLong => f.write_str("message too long"),
+            Error::KeyDerivationError => f.write_str("could not derive the key"),
+            Error::InvalidContent(rule) => write!(f, "invalid content: {rule}"),
+            Error::InvalidAuth(rule) => write!(f, "invalid auth event: {rule}"),
+            Error::InvalidMac => f.write_str("invalid MAC"),
+            Error::MissingPrivkey => f.write_str("missing private key"),
+            Error::SignerError => f.write_str("signer error"),
+            Error::RngError => f.write_str("random number generator failed"),
+            Error::CreatedAtOutOfRange => {
+                f.write_str("created_at is too far from the current time")
+            }
+            Error::InvalidPadding => f.write_str("invalid padding"),
+            Error::InvalidSubscriptionId => f.write_str("invalid subscription id"),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Field::Id => "id",
+            Field::Pubkey => "pubkey",
+            Field::CreatedAt => "created_at",
+            Field::Kind => "kind",
+            Field::Tags => "tags",
+            Field::Content => "content",
+            Field::Sig => "sig",
+            Field::Tag => "required tag",
+            Field::Element => "array element",
+        })
+    }
+}
+
+impl fmt::Display for ContentRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContentRule::MetadataNotObject => "kind 0 content must be a JSON object",
+            ContentRule::ContactListNotObject => "kind 3 content must be empty or a JSON object",
+            ContentRule::DmMissingIv => "kind 4 content must be <base64>?iv=<base64>",
+            ContentRule::RelayListNotEmpty => "kind 10002 content must be empty",
+        })
+    }
+}
+
+impl fmt::Display for AuthRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthRule::WrongKind => "not kind 22242",
+            AuthRule::ChallengeMismatch => "challenge does not match",
+            AuthRule::RelayMismatch => "relay does not match",
+            AuthRule::Expired => "created_at too far from now",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::String;
+
+    fn display(error: Error) -> String<64> {
+        let mut out = String::new();
+        write!(out, "{error}").unwrap();
+        out
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(display(Error::StoreFull), "store is full");
+        assert_eq!(
+            display(Error::EventMissingField(Field::Sig)),
+            "event is missing its sig"
+        );
+        assert_eq!(
+            display(Error::Malformed {
+                field: Field::Kind,
+                offset: 120
+            }),
+            "malformed kind at byte 120"
+        );
+        assert_eq!(
+            display(Error::InvalidAuth(AuthRule::Expired)),
+            "invalid auth event: created_at too far from now"
+        );
+    }
 }
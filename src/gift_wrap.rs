@@ -0,0 +1,337 @@
+//! Sealed and gift wrapped notes ([NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md))
+//!
+//! A rumor, an [`UnsignedNote`], is hidden in two layers. The seal, kind 13, holds the rumor
+//! encrypted with NIP-44 to the receiver and is signed by its author, so the receiver knows who
+//! wrote it without being able to show it to anyone as signed. The gift wrap, kind 1059, holds the
+//! seal encrypted again and is signed by a throwaway key, so relays only learn who it is for.
+//!
+//! Both layers should have their `created_at` moved up to two days into the past so it doesn't
+//! give away when the rumor was written, see [`randomize_created_at`]. The crate has no random
+//! number generator, so keys, nonces and offsets are passed in.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     gift_wrap::{randomize_created_at, GIFT_WRAP_SIZE, SEAL_SIZE},
+//!     unsigned::UnsignedNote,
+//!     Note, NoteKinds,
+//! };
+//! let sender = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let receiver = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+//! let receiver_pubkey = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+//! // should be a fresh random key for every gift wrap
+//! let ephemeral = "0000000000000000000000000000000000000000000000000000000000000001";
+//!
+//! let now = 1686880020;
+//! let rumor = Note::new_builder(sender)
+//!     .unwrap()
+//!     .set_kind(NoteKinds::Custom(14))
+//!     .content("meet at noon".into())
+//!     .build_unsigned(now)
+//!     .unwrap();
+//! // nonces, aux_rnd and offsets should come from a random number generator
+//! let seal: Note<SEAL_SIZE> = rumor
+//!     .seal(sender, receiver_pubkey, randomize_created_at(now, 1000), [1; 32], [0; 32])
+//!     .unwrap();
+//! let wrap: Note<GIFT_WRAP_SIZE> = seal
+//!     .gift_wrap(ephemeral, receiver_pubkey, randomize_created_at(now, 2000), [2; 32], [0; 32])
+//!     .unwrap();
+//!
+//! // on the receiver's side
+//! let seal: Note<SEAL_SIZE> = wrap.unwrap_gift(receiver).unwrap();
+//! let opened: UnsignedNote = seal.unseal(receiver).unwrap();
+//! assert_eq!(opened, rumor);
+//! ```
+
+use heapless::String;
+
+use crate::{
+    curve::{self, Curve, CurveBackend},
+    errors::Error,
+    nip44::{conversation_key, decrypt_sized, encrypt_sized},
+    tag::Tag,
+    unsigned::UnsignedNote,
+    utils::from_hex,
+    Note, NoteBuilder, NoteKinds,
+};
+
+/// Kind of a seal
+pub const SEAL_KIND: u32 = 13;
+/// Kind of a gift wrap
+pub const GIFT_WRAP_KIND: u32 = 1059;
+/// Most `created_at` is moved into the past, two days
+pub const MAX_CREATED_AT_OFFSET: u32 = 2 * 24 * 60 * 60;
+/// Content size of a seal that fits a rumor of a few hundred bytes
+pub const SEAL_SIZE: usize = 1024;
+/// Content size of a gift wrap that fits any seal of [`SEAL_SIZE`]
+pub const GIFT_WRAP_SIZE: usize = 2304;
+
+/// Moves `now` up to two days into the past, by `random` seconds modulo
+/// [`MAX_CREATED_AT_OFFSET`]. `random` should come from a random number generator.
+pub fn randomize_created_at(now: u64, random: u32) -> u64 {
+    now.saturating_sub((random % MAX_CREATED_AT_OFFSET).into())
+}
+
+/// X-only pubkey of `receiver`, checked to be on the curve
+fn parse_receiver(receiver: &str) -> Result<[u8; 32], Error> {
+    let pubkey = from_hex(receiver.as_bytes()).map_err(|_| Error::InvalidPubkey)?;
+    Curve::parse_pubkey(&pubkey)?;
+    Ok(pubkey)
+}
+
+/// Note of `kind` holding `plaintext` encrypted by the key of `privkey` to `receiver`
+fn encrypted_note<const CONTENT: usize>(
+    privkey: &str,
+    receiver: &[u8; 32],
+    plaintext: &str,
+    kind: NoteKinds,
+    nonce: [u8; 32],
+) -> Result<NoteBuilder<CONTENT>, Error> {
+    let mut builder = NoteBuilder::<CONTENT>::new(privkey)?;
+    let keypair = builder.keypair.as_ref().ok_or(Error::MissingPrivkey)?;
+    let key = conversation_key(&Curve::secret_bytes(keypair), receiver)?;
+    builder.note.content = Some(encrypt_sized(&key, plaintext, nonce)?);
+    builder.note.kind = kind;
+    Ok(builder)
+}
+
+/// Decrypts the content of `note`, encrypted to the key of `privkey` by the note's author
+fn decrypt_content<
+    const CONTENT: usize,
+    const TAG: usize,
+    const NTAGS: usize,
+    const VALUES: usize,
+>(
+    note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    privkey: &str,
+) -> Result<String<CONTENT>, Error> {
+    let key = conversation_key(&curve::parse_secret(privkey)?, &note.pubkey)?;
+    decrypt_sized(&key, note.content().ok_or(Error::MalformedContent)?)
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    UnsignedNote<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Encrypts the rumor to the hex pubkey `receiver` in a kind 13 seal signed by `sender`,
+    /// who has to be the rumor's author.
+    /// `nonce` and `aux_rnd` should come from a random source.
+    /// Errors with `InvalidPrivkey` if `sender` didn't write the rumor, `InvalidPubkey` if
+    /// `receiver` isn't a valid pubkey and `ContentOverflow` if the encrypted rumor is longer
+    /// than `SEAL`.
+    pub fn seal<const SEAL: usize>(
+        &self,
+        sender: &str,
+        receiver: &str,
+        created_at: u64,
+        nonce: [u8; 32],
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<SEAL>, Error> {
+        let receiver = parse_receiver(receiver)?;
+        // the rumor is shorter than its payload
+        let mut json = [0_u8; SEAL];
+        let len = self.json_into(&mut json)?;
+        let rumor = core::str::from_utf8(&json[..len]).map_err(|_| Error::EncodeError)?;
+        let builder = encrypted_note(sender, &receiver, rumor, NoteKinds::Seal, nonce)?;
+        if builder.note.pubkey() != self.pubkey() {
+            return Err(Error::InvalidPrivkey);
+        }
+        builder.build(created_at, aux_rnd)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Encrypts this seal to the hex pubkey `receiver` in a kind 1059 gift wrap signed by
+    /// `ephemeral`, a private key used for this gift wrap only. The wrap gets a `p` tag with
+    /// `receiver` so relays can deliver it.
+    /// `nonce` and `aux_rnd` should come from a random source.
+    /// Errors with `InvalidType` if this isn't a seal, `InvalidPubkey` if `receiver` isn't a valid
+    /// pubkey and `ContentOverflow` if the encrypted seal is longer than `WRAP`.
+    pub fn gift_wrap<const WRAP: usize>(
+        &self,
+        ephemeral: &str,
+        receiver: &str,
+        created_at: u64,
+        nonce: [u8; 32],
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<WRAP>, Error> {
+        if self.kind.as_u32() != SEAL_KIND {
+            return Err(Error::InvalidType);
+        }
+        let pubkey = parse_receiver(receiver)?;
+        // the seal is shorter than its payload
+        let mut json = [0_u8; WRAP];
+        let len = self.json_into(&mut json)?;
+        let seal = core::str::from_utf8(&json[..len]).map_err(|_| Error::EncodeError)?;
+        encrypted_note(ephemeral, &pubkey, seal, NoteKinds::GiftWrap, nonce)?
+            .add_tag(Tag::try_from(["p", receiver])?)?
+            .build(created_at, aux_rnd)
+    }
+
+    /// Decrypts this gift wrap with the receiver's `privkey` and returns the seal inside, after
+    /// checking its signature.
+    /// Errors with `InvalidType` if this isn't a gift wrap or it doesn't hold a seal, `InvalidMac`
+    /// if it was wrapped for someone else and `ContentOverflow` if the seal is longer than `SEAL`.
+    pub fn unwrap_gift<const SEAL: usize>(&self, privkey: &str) -> Result<Note<SEAL>, Error> {
+        if self.kind.as_u32() != GIFT_WRAP_KIND {
+            return Err(Error::InvalidType);
+        }
+        let json = decrypt_content(self, privkey)?;
+        let seal = Note::<SEAL>::from_json(&json)?;
+        if seal.kind.as_u32() != SEAL_KIND {
+            return Err(Error::InvalidType);
+        }
+        Ok(seal)
+    }
+
+    /// Decrypts this seal with the receiver's `privkey` and returns the rumor inside, after
+    /// checking its id and that it was written by whoever signed the seal.
+    /// Doesn't check the seal's signature, [`Note::unwrap_gift`] already does.
+    /// Errors with `InvalidType` if this isn't a seal, `InvalidMac` if it was sealed for someone
+    /// else and `EventNotValid` if the rumor is forged.
+    pub fn unseal<const C: usize, const T: usize, const N: usize, const V: usize>(
+        &self,
+        privkey: &str,
+    ) -> Result<UnsignedNote<C, T, N, V>, Error> {
+        if self.kind.as_u32() != SEAL_KIND {
+            return Err(Error::InvalidType);
+        }
+        let json = decrypt_content(self, privkey)?;
+        let rumor = UnsignedNote::from_json(&json)?;
+        if rumor.pubkey() != self.pubkey() {
+            return Err(Error::EventNotValid);
+        }
+        Ok(rumor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, OTHER_PRIVKEY, OTHER_PUBKEY, PRIVKEY};
+    const EPHEMERAL: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+    const NOW: u64 = 1686880020;
+
+    fn rumor(content: &str) -> UnsignedNote {
+        let mut text = String::new();
+        text.push_str(content).unwrap();
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::Custom(14))
+            .content(text)
+            .add_tag(Tag::try_from(["p", OTHER_PUBKEY]).unwrap())
+            .unwrap()
+            .build_unsigned(NOW)
+            .unwrap()
+    }
+
+    fn seal(rumor: &UnsignedNote) -> Note<SEAL_SIZE> {
+        rumor
+            .seal(PRIVKEY, OTHER_PUBKEY, NOW - 10, [1; 32], [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rumor = rumor("meet at noon");
+        let seal = seal(&rumor);
+        assert_eq!(seal.kind(), NoteKinds::Seal);
+        assert_eq!(seal.pubkey(), rumor.pubkey());
+        assert!(seal.tags().is_empty());
+        assert!(seal.verify().is_ok());
+
+        let wrap: Note<GIFT_WRAP_SIZE> = seal
+            .gift_wrap(EPHEMERAL, OTHER_PUBKEY, NOW - 20, [2; 32], [0; 32])
+            .unwrap();
+        assert_eq!(wrap.kind(), NoteKinds::GiftWrap);
+        assert_ne!(wrap.pubkey(), rumor.pubkey());
+        assert_eq!(wrap.get_tag("p").unwrap()[0][0], OTHER_PUBKEY);
+        assert!(wrap.verify().is_ok());
+
+        let opened: Note<SEAL_SIZE> = wrap.unwrap_gift(OTHER_PRIVKEY).unwrap();
+        assert_eq!(opened, seal);
+        assert_eq!(opened.unseal(OTHER_PRIVKEY), Ok(rumor));
+
+        // only the receiver can open either layer
+        assert_eq!(
+            wrap.unwrap_gift::<SEAL_SIZE>(PRIVKEY),
+            Err(Error::InvalidMac)
+        );
+        assert_eq!(
+            seal.unseal::<400, 100, 5, 4>(EPHEMERAL),
+            Err(Error::InvalidMac)
+        );
+    }
+
+    #[test]
+    fn test_largest_seal_fits() {
+        // the longest rumor a seal holds
+        let content = [b'a'; 400];
+        let mut sealed = None;
+        for len in (1..=content.len()).rev() {
+            let text = core::str::from_utf8(&content[..len]).unwrap();
+            if let Ok(seal) =
+                rumor(text).seal::<SEAL_SIZE>(PRIVKEY, OTHER_PUBKEY, NOW, [1; 32], [0; 32])
+            {
+                sealed = Some(seal);
+                break;
+            }
+        }
+        let seal = sealed.unwrap();
+        assert!(seal
+            .gift_wrap::<GIFT_WRAP_SIZE>(EPHEMERAL, OTHER_PUBKEY, NOW, [2; 32], [0; 32])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        let rumor = rumor("hi");
+        assert_eq!(
+            rumor.seal::<SEAL_SIZE>(OTHER_PRIVKEY, OTHER_PUBKEY, NOW, [1; 32], [0; 32]),
+            Err(Error::InvalidPrivkey)
+        );
+        assert_eq!(
+            rumor.seal::<SEAL_SIZE>(PRIVKEY, "abc", NOW, [1; 32], [0; 32]),
+            Err(Error::InvalidPubkey)
+        );
+        assert_eq!(
+            rumor.seal::<200>(PRIVKEY, OTHER_PUBKEY, NOW, [1; 32], [0; 32]),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(
+            text_note().gift_wrap::<GIFT_WRAP_SIZE>(EPHEMERAL, OTHER_PUBKEY, NOW, [2; 32], [0; 32]),
+            Err(Error::InvalidType)
+        );
+        assert_eq!(
+            text_note().unwrap_gift::<SEAL_SIZE>(OTHER_PRIVKEY),
+            Err(Error::InvalidType)
+        );
+
+        // a seal signed by someone other than the rumor's author
+        let mut json = [0_u8; 400];
+        let len = rumor.json_into(&mut json).unwrap();
+        let forged: Note<SEAL_SIZE> = encrypted_note(
+            OTHER_PRIVKEY,
+            &parse_receiver(OTHER_PUBKEY).unwrap(),
+            core::str::from_utf8(&json[..len]).unwrap(),
+            NoteKinds::Seal,
+            [1; 32],
+        )
+        .unwrap()
+        .build(NOW, [0; 32])
+        .unwrap();
+        assert_eq!(
+            forged.unseal::<400, 100, 5, 4>(OTHER_PRIVKEY),
+            Err(Error::EventNotValid)
+        );
+    }
+
+    #[test]
+    fn test_randomize_created_at() {
+        assert_eq!(randomize_created_at(NOW, 0), NOW);
+        assert_eq!(randomize_created_at(NOW, 60), NOW - 60);
+        assert!(randomize_created_at(NOW, u32::MAX) > NOW - u64::from(MAX_CREATED_AT_OFFSET));
+        assert_eq!(randomize_created_at(5, 60), 0);
+    }
+}
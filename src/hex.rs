@@ -0,0 +1,123 @@
+//! Validated event ids and pubkeys
+//!
+//! Ids and pubkeys travel as 64 hex characters. [`HexId`] and [`HexPubkey`] check the length
+//! and character set once, when they are made, and are stored decoded. Either case is accepted
+//! and lowercase is always written back, so a query or relay message holding one can't carry
+//! anything else.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{errors::Error, hex::HexPubkey};
+//! let pubkey =
+//!     HexPubkey::try_from("098EF66BCE60DD4CF10B4AE5949D1EC6DD777DDEB4BC49B47F97275A127A63CF").unwrap();
+//! assert_eq!(
+//!     &pubkey.to_hex(),
+//!     b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"
+//! );
+//! assert_eq!(HexPubkey::try_from("098ef66b"), Err(Error::InvalidPubkey));
+//! ```
+
+use core::fmt;
+
+use crate::{errors::Error, utils::to_hex};
+
+/// Decodes 64 hex characters of either case
+fn decode(hex: &[u8]) -> Option<[u8; 32]> {
+    let mut bytes = [0_u8; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    base16ct::mixed::decode(hex, &mut bytes).ok()?;
+    Some(bytes)
+}
+
+macro_rules! hex_key {
+    ($(#[$doc:meta])* $name:ident, $error:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        pub struct $name([u8; 32]);
+
+        impl $name {
+            /// Wraps already decoded bytes
+            pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+                $name(bytes)
+            }
+
+            /// The decoded bytes
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+
+            /// The 64 lowercase hex characters
+            pub fn to_hex(&self) -> [u8; 64] {
+                to_hex(&self.0)
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = Error;
+
+            #[doc = concat!("Errors with `", stringify!($error), "` unless `value` is 64 hex characters, of either case")]
+            fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+                decode(value).map($name).ok_or(Error::$error)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = Error;
+
+            #[doc = concat!("Errors with `", stringify!($error), "` unless `value` is 64 hex characters, of either case")]
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                $name::try_from(value.as_bytes())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                // hex is always ASCII
+                f.write_str(core::str::from_utf8(&self.to_hex()).map_err(|_| fmt::Error)?)
+            }
+        }
+    };
+}
+
+hex_key!(
+    /// Id of an event, written as 64 lowercase hex characters
+    HexId,
+    EventNotValid
+);
+
+hex_key!(
+    /// X-only pubkey, written as 64 lowercase hex characters
+    HexPubkey,
+    InvalidPubkey
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ID: &str = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+
+    #[test]
+    fn test_validation() {
+        let id = HexId::try_from(ID).unwrap();
+        assert_eq!(&id.to_hex(), ID.as_bytes());
+        assert_eq!(id.as_bytes()[0], 0xb5);
+        assert_eq!(HexId::from_bytes(*id.as_bytes()), id);
+        assert_eq!(
+            HexId::try_from("B515DA91AC5DF638FAE0A6E658E03ACC1DDA6152DD2107D02D5702CCFCF927E8"),
+            Ok(id)
+        );
+        assert_eq!(HexId::try_from(&ID[..62]), Err(Error::EventNotValid));
+        assert_eq!(
+            HexId::try_from("g515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"),
+            Err(Error::EventNotValid)
+        );
+        assert_eq!(HexPubkey::try_from(""), Err(Error::InvalidPubkey));
+        assert_eq!(
+            HexPubkey::try_from(ID.as_bytes()).map(|pk| pk.to_hex()),
+            Ok(*b"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8")
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Conversions to and from the [rust-nostr](https://crates.io/crates/nostr) crate
+//!
+//! Enabled by the `interop` feature, which also enables `alloc`. Meant for host side tooling
+//! that uses `nostr` while firmware uses this crate.
+//!
+//! Notes and events are converted through their NIP-01 JSON, so ids and signatures carry over
+//! unchanged and are checked on the way in.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "interop")]
+//! # {
+//! use nostr_nostd::Note;
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let event = nostr::event::Event::try_from(&note).unwrap();
+//! assert_eq!(event.content, "esptest");
+//! assert_eq!(Note::try_from(&event).unwrap(), note);
+//! # }
+//! ```
+
+use alloc::collections::BTreeSet;
+
+use heapless::Vec;
+use nostr::{
+    event::Event,
+    filter::{Filter, SingleLetterTag},
+};
+
+use crate::{
+    errors::Error,
+    query::{HexPrefix, Query},
+    Note, NoteKinds,
+};
+
+impl TryFrom<&Note> for Event {
+    type Error = Error;
+
+    fn try_from(note: &Note) -> Result<Self, Self::Error> {
+        let mut json = alloc::vec::Vec::new();
+        note.write_json(&mut json)?;
+        Event::from_json(json).map_err(|_| Error::MalformedContent)
+    }
+}
+
+impl TryFrom<&Event> for Note {
+    type Error = Error;
+
+    fn try_from(event: &Event) -> Result<Self, Self::Error> {
+        let json = event.try_as_json().map_err(|_| Error::MalformedContent)?;
+        Note::try_from(json.as_str())
+    }
+}
+
+impl TryFrom<&Query> for Filter {
+    type Error = Error;
+
+    fn try_from(query: &Query) -> Result<Self, Self::Error> {
+        let mut json = alloc::vec::Vec::new();
+        query.write_json(&mut json, None)?;
+        Filter::from_json(json).map_err(|_| Error::MalformedContent)
+    }
+}
+
+/// Parses hex strings into a query list of prefixes
+fn to_prefix_list<const N: usize>(
+    values: impl Iterator<Item = alloc::string::String>,
+    parse: fn(&str) -> Result<HexPrefix, Error>,
+) -> Result<Vec<HexPrefix, N>, Error> {
+    let mut list = Vec::new();
+    for value in values {
+        let prefix = parse(value.as_str())?;
+        list.push(prefix).map_err(|_| Error::QueryBuilderOverflow)?;
+    }
+    Ok(list)
+}
+
+/// Values of the `#<letter>` filter, if set
+fn tag_values(filter: &Filter, letter: char) -> Option<&BTreeSet<alloc::string::String>> {
+    SingleLetterTag::from_char(letter)
+        .ok()
+        .and_then(|tag| filter.generic_tags.get(&tag))
+}
+
+impl TryFrom<&Filter> for Query {
+    type Error = Error;
+
+    /// Errors with `QueryBuilderOverflow` if a list is longer than a `Query` holds, and with
+    /// `TypeNotAccepted` for search filters.
+    fn try_from(filter: &Filter) -> Result<Self, Self::Error> {
+        if filter.search.is_some() {
+            return Err(Error::TypeNotAccepted);
+        }
+
+        let mut query = Query::new();
+        if let Some(ids) = &filter.ids {
+            query.ids = to_prefix_list(ids.iter().map(|id| id.to_hex()), HexPrefix::parse_id)?;
+        }
+        if let Some(authors) = &filter.authors {
+            query.authors = to_prefix_list(
+                authors.iter().map(|author| author.to_hex()),
+                HexPrefix::parse_pubkey,
+            )?;
+        }
+        for id in tag_values(filter, 'e').into_iter().flatten() {
+            query.add_ref_event(id)?;
+        }
+        for pubkey in tag_values(filter, 'p').into_iter().flatten() {
+            query.add_ref_pubkey(pubkey)?;
+        }
+        for (tag, values) in &filter.generic_tags {
+            let letter = tag.as_char();
+            if letter == 'e' || letter == 'p' {
+                continue;
+            }
+            for value in values {
+                query.add_tag_filter(letter, value)?;
+            }
+        }
+        if let Some(kinds) = &filter.kinds {
+            for kind in kinds {
+                query
+                    .kinds
+                    .push(NoteKinds::from(u16::from(*kind)))
+                    .map_err(|_| Error::QueryBuilderOverflow)?;
+            }
+        }
+        query.since = filter.since.map(|t| t.as_secs());
+        query.until = filter.until.map(|t| t.as_secs());
+        query.limit = filter
+            .limit
+            .map(|l| u32::try_from(l).map_err(|_| Error::QueryBuilderOverflow))
+            .transpose()?;
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::Tag;
+    use crate::vectors::PRIVKEY;
+
+    #[test]
+    fn test_note_round_trip() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest \"quoted\"\n".into())
+            .add_tag(Tag::try_from(["l", "bitcoin, lightning"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        // rust-nostr recomputes the id, so this also checks the escaping
+        let event = Event::try_from(&note).unwrap();
+        assert!(event.verify().is_ok());
+        assert_eq!(event.kind.as_u16(), 1);
+        assert_eq!(Note::try_from(&event), Ok(note));
+    }
+
+    #[test]
+    fn test_query_round_trip() {
+        let mut query = Query::new();
+        query
+            .add_author("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
+            .unwrap();
+        query
+            .add_ref_pubkey("ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0")
+            .unwrap();
+        query.kinds.push(NoteKinds::DM).unwrap();
+        query.add_tag_filter('t', "esp32").unwrap();
+        query.since = Some(1686880020);
+        query.limit = Some(10);
+
+        let filter = Filter::try_from(&query).unwrap();
+        assert_eq!(filter.limit, Some(10));
+        let back = Query::try_from(&filter).unwrap();
+        assert_eq!(back.authors, query.authors);
+        assert_eq!(back.ref_pks, query.ref_pks);
+        assert_eq!(back.kinds, query.kinds);
+        assert_eq!(back.since, query.since);
+        assert_eq!(back.tag_filters, query.tag_filters);
+
+        let search = Filter::new().search("esp32");
+        assert!(matches!(
+            Query::try_from(&search),
+            Err(Error::TypeNotAccepted)
+        ));
+    }
+}
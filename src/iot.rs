@@ -0,0 +1,403 @@
+//! Sensor telemetry in kind 5732 notes
+//!
+//! [`NoteKinds::IOT`] notes carry free-form content, so devices from different vendors tend to
+//! disagree on its shape. A [`Telemetry`] writes its readings as a flat JSON object, such as
+//! `{"temperature":21.5,"door_open":false}`, tags the note with the sensor's id and adds a
+//! `["unit", <reading>, <unit>]` tag for each reading that has one. [`Note::telemetry`] reads
+//! them back.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{iot::{Telemetry, Value}, Note};
+//! let telemetry: Telemetry = Telemetry::new("greenhouse-1")
+//!     .reading("temperature", Value::Float(21.5), Some("C"))
+//!     .unwrap()
+//!     .reading("door_open", Value::Bool(false), None)
+//!     .unwrap();
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .create_telemetry(&telemetry)
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! assert_eq!(note.content(), Some(r#"{"temperature":21.5,"door_open":false}"#));
+//!
+//! let received: Telemetry = note.telemetry().unwrap();
+//! assert_eq!(received, telemetry);
+//! ```
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+
+use crate::{
+    buffer::FmtBuf,
+    errors::Error,
+    note_ref::{skip_value, split_string},
+    tag::Tag,
+    utils::{unescape_json, write_escaped},
+    Note, NoteBuilder, NoteKinds,
+};
+
+/// Kind of a telemetry note
+pub const IOT_KIND: u32 = 5732;
+/// Most readings a [`Telemetry`] holds by default
+pub const MAX_READINGS: usize = 4;
+
+/// Value of a single reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value<'a> {
+    /// A whole number, such as a counter
+    Int(i64),
+    /// A measurement, such as a temperature
+    Float(f32),
+    /// A switch or contact
+    Bool(bool),
+    /// Free text, such as a status. Read back from a note it is still JSON escaped, see
+    /// [`Reading::text_into`].
+    Text(&'a str),
+}
+
+/// A named reading, with the unit it was measured in, if any
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Reading<'a> {
+    /// Name of the reading, the key in the content
+    pub name: &'a str,
+    /// The value read
+    pub value: Value<'a>,
+    /// Unit such as `C` or `%`, from the reading's `unit` tag
+    pub unit: Option<&'a str>,
+}
+
+impl Reading<'_> {
+    /// Text of a [`Value::Text`] reading with its escapes decoded, `None` for other values.
+    /// Errors with `ContentOverflow` if it is longer than `N`.
+    pub fn text_into<const N: usize>(&self) -> Option<Result<String<N>, Error>> {
+        match self.value {
+            Value::Text(text) => Some(unescape_json(text)),
+            _ => None,
+        }
+    }
+}
+
+/// Readings of one sensor, holding up to `N` of them
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Telemetry<'a, const N: usize = MAX_READINGS> {
+    sensor: &'a str,
+    readings: Vec<Reading<'a>, N>,
+}
+
+impl<'a, const N: usize> Telemetry<'a, N> {
+    /// Starts the readings of the sensor with id `sensor`
+    pub fn new(sensor: &'a str) -> Self {
+        Telemetry {
+            sensor,
+            readings: Vec::new(),
+        }
+    }
+
+    /// Adds a reading named `name`.
+    /// Errors with `ContentOverflow` if it already holds `N` readings.
+    pub fn reading(
+        mut self,
+        name: &'a str,
+        value: Value<'a>,
+        unit: Option<&'a str>,
+    ) -> Result<Self, Error> {
+        self.readings
+            .push(Reading { name, value, unit })
+            .map_err(|_| Error::ContentOverflow)?;
+        Ok(self)
+    }
+
+    /// Id of the sensor
+    pub fn sensor(&self) -> &'a str {
+        self.sensor
+    }
+
+    /// The readings, in the order they were added
+    pub fn readings(&self) -> &[Reading<'a>] {
+        &self.readings
+    }
+
+    /// The reading named `name`
+    pub fn get(&self, name: &str) -> Option<&Reading<'a>> {
+        self.readings.iter().find(|reading| reading.name == name)
+    }
+
+    /// Writes the readings as a JSON object.
+    /// Errors with `EncodeError` if a float isn't finite, as JSON has no way to write it.
+    fn write_json<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        let overflow = |_| Error::ContentOverflow;
+        out.write_char('{').map_err(overflow)?;
+        for (i, reading) in self.readings.iter().enumerate() {
+            if i > 0 {
+                out.write_char(',').map_err(overflow)?;
+            }
+            out.write_char('"').map_err(overflow)?;
+            write_escaped(&mut FmtBuf::new(&mut *out), reading.name)?;
+            out.write_str("\":").map_err(overflow)?;
+            match reading.value {
+                Value::Int(value) => write!(out, "{value}").map_err(overflow)?,
+                Value::Float(value) if !value.is_finite() => return Err(Error::EncodeError),
+                // Debug keeps a `.` or an exponent, so a float is read back as one
+                Value::Float(value) => write!(out, "{value:?}").map_err(overflow)?,
+                Value::Bool(value) => write!(out, "{value}").map_err(overflow)?,
+                Value::Text(text) => {
+                    out.write_char('"').map_err(overflow)?;
+                    write_escaped(&mut FmtBuf::new(&mut *out), text)?;
+                    out.write_char('"').map_err(overflow)?;
+                }
+            }
+        }
+        out.write_char('}').map_err(overflow)
+    }
+}
+
+/// Reads a reading's value off the front of `json`, returning what follows it
+fn split_value(json: &str) -> Result<(Value<'_>, &str), Error> {
+    if json.starts_with('"') {
+        let (text, rest) = split_string(json)?;
+        return Ok((Value::Text(text), rest));
+    }
+    let rest = skip_value(json)?;
+    let raw = json[..json.len() - rest.len()].trim_end();
+    let value = match raw {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ if raw.contains(['.', 'e', 'E']) => {
+            Value::Float(raw.parse().map_err(|_| Error::MalformedContent)?)
+        }
+        _ => Value::Int(raw.parse().map_err(|_| Error::MalformedContent)?),
+    };
+    Ok((value, rest))
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 5732 telemetry note, with the readings as its content, a
+    /// `sensor` tag and a `unit` tag for each reading with a unit.
+    /// Errors with `ContentOverflow` if the readings are longer than `CONTENT` or a tag value
+    /// is longer than `TAG`, `TooManyTags` if the tags don't fit in `NTAGS` and `EncodeError`
+    /// if a float isn't finite.
+    pub fn create_telemetry<const N: usize>(
+        mut self,
+        telemetry: &Telemetry<N>,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let mut content = String::new();
+        telemetry.write_json(&mut content)?;
+        self.note.content = Some(content);
+        self.note.kind = NoteKinds::IOT;
+        self = self.add_tag(Tag::try_from(["sensor", telemetry.sensor])?)?;
+        for reading in telemetry.readings.iter() {
+            if let Some(unit) = reading.unit {
+                self = self.add_tag(Tag::try_from(["unit", reading.name, unit])?)?;
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Reads the readings of a kind 5732 telemetry note. Names and text values are still JSON
+    /// escaped, and the sensor id is empty if the note has no `sensor` tag.
+    /// Errors with `InvalidType` if the note isn't a telemetry note, `MalformedContent` if the
+    /// content isn't a flat JSON object and `ContentOverflow` if it has more than `N` readings.
+    pub fn telemetry<const N: usize>(&self) -> Result<Telemetry<'_, N>, Error> {
+        if self.kind.as_u32() != IOT_KIND {
+            return Err(Error::InvalidType);
+        }
+        let sensor = self
+            .tags
+            .iter()
+            .find(|tag| tag.name() == "sensor")
+            .and_then(|tag| tag.value(0))
+            .unwrap_or_default();
+        let unit = |name: &str| {
+            self.tags
+                .iter()
+                .filter(|tag| tag.name() == "unit")
+                .find(|tag| tag.value(0) == Some(name))
+                .and_then(|tag| tag.value(1))
+        };
+
+        let mut telemetry = Telemetry::new(sensor);
+        let content = self.content().unwrap_or_default().trim();
+        let mut rest = content
+            .strip_prefix('{')
+            .ok_or(Error::MalformedContent)?
+            .trim_start();
+        if let Some(after) = rest.strip_prefix('}') {
+            rest = after;
+        }
+        while !rest.is_empty() {
+            let (name, after) = split_string(rest)?;
+            let after = after
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or(Error::MalformedContent)?;
+            let (value, after) = split_value(after.trim_start())?;
+            telemetry = telemetry.reading(name, value, unit(name))?;
+            let after = after.trim_start();
+            rest = match after.strip_prefix(',') {
+                Some(next) => next.trim_start(),
+                None if after == "}" => "",
+                None => return Err(Error::MalformedContent),
+            };
+        }
+        Ok(telemetry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+
+    fn note_with(content: &str) -> Note {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::IOT)
+            .content(content.into())
+            .build(1686880020, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let telemetry: Telemetry<5> = Telemetry::new("greenhouse-1")
+            .reading("temperature", Value::Float(-3.25), Some("C"))
+            .unwrap()
+            .reading("humidity", Value::Int(40), Some("%"))
+            .unwrap()
+            .reading("door_open", Value::Bool(true), None)
+            .unwrap()
+            .reading("status", Value::Text(r#"low "battery""#), None)
+            .unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_telemetry(&telemetry)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.kind, NoteKinds::IOT);
+        assert_eq!(
+            note.content(),
+            Some(
+                r#"{"temperature":-3.25,"humidity":40,"door_open":true,"status":"low \"battery\""}"#
+            )
+        );
+        assert_eq!(note.tags().len(), 3);
+        assert!(note.verify_id().is_ok());
+
+        let received: Telemetry<5> = note.telemetry().unwrap();
+        assert_eq!(received.sensor(), "greenhouse-1");
+        assert_eq!(received.readings().len(), 4);
+        assert_eq!(received.get("temperature"), telemetry.get("temperature"));
+        assert_eq!(received.get("humidity").unwrap().unit, Some("%"));
+        let status = received.get("status").unwrap();
+        assert_eq!(status.value, Value::Text(r#"low \"battery\""#));
+        assert_eq!(
+            status.text_into::<20>().unwrap().unwrap(),
+            r#"low "battery""#
+        );
+        assert_eq!(received.get("humidity").unwrap().text_into::<20>(), None);
+    }
+
+    #[test]
+    fn test_float_round_trip() {
+        for (value, json) in [
+            (21.0, r#"{"a":21.0}"#),
+            (1e20, r#"{"a":1e20}"#),
+            (-1.5e-7, r#"{"a":-1.5e-7}"#),
+        ] {
+            let telemetry: Telemetry<1> = Telemetry::new("s1")
+                .reading("a", Value::Float(value), None)
+                .unwrap();
+            let note = Note::new_builder(PRIVKEY)
+                .unwrap()
+                .create_telemetry(&telemetry)
+                .unwrap()
+                .build(1686880020, [0; 32])
+                .unwrap();
+            assert_eq!(note.content(), Some(json));
+            let received: Telemetry<1> = note.telemetry().unwrap();
+            assert_eq!(received.get("a").unwrap().value, Value::Float(value));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let note = note_with(r#" { "a" : 1e3 , "b":-7,"c" : false } "#);
+        let telemetry: Telemetry = note.telemetry().unwrap();
+        assert_eq!(telemetry.sensor(), "");
+        assert_eq!(telemetry.get("a").unwrap().value, Value::Float(1000.0));
+        assert_eq!(telemetry.get("b").unwrap().value, Value::Int(-7));
+        assert_eq!(telemetry.get("c").unwrap().value, Value::Bool(false));
+        assert_eq!(
+            note_with("{}").telemetry::<4>().unwrap().readings().len(),
+            0
+        );
+
+        for content in [
+            "",
+            "[1]",
+            r#"{"a":1"#,
+            r#"{"a" 1}"#,
+            r#"{"a":1,}"#,
+            r#"{"a":nope}"#,
+            r#"{"a":{"b":1}}"#,
+            r#"{"a":1} {"b":2}"#,
+        ] {
+            assert_eq!(
+                note_with(content).telemetry::<4>(),
+                Err(Error::MalformedContent),
+                "{content}"
+            );
+        }
+        assert_eq!(
+            note_with(r#"{"a":1,"b":2}"#).telemetry::<1>(),
+            Err(Error::ContentOverflow)
+        );
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content(r#"{"a":1}"#.into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.telemetry::<4>(), Err(Error::InvalidType));
+    }
+
+    #[test]
+    fn test_limits() {
+        let telemetry: Telemetry<1> = Telemetry::new("s1");
+        let telemetry = telemetry.reading("a", Value::Int(1), Some("m")).unwrap();
+        assert_eq!(
+            telemetry.clone().reading("b", Value::Int(2), None),
+            Err(Error::ContentOverflow)
+        );
+        let builder = Note::new_builder(PRIVKEY).unwrap();
+        let nan: Telemetry<1> = Telemetry::new("s1")
+            .reading("a", Value::Float(f32::NAN), None)
+            .unwrap();
+        assert!(matches!(
+            builder.create_telemetry(&nan),
+            Err(Error::EncodeError)
+        ));
+        let builder = crate::NoteBuilder::<6>::new(PRIVKEY).unwrap();
+        assert!(matches!(
+            builder.create_telemetry(&telemetry),
+            Err(Error::ContentOverflow)
+        ));
+        let builder = crate::NoteBuilder::<64, 100, 1>::new(PRIVKEY).unwrap();
+        assert!(matches!(
+            builder.create_telemetry(&telemetry),
+            Err(Error::TooManyTags)
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+//! Keys parsed once and reused
+//!
+//! [`Note::new_builder`], [`Note::read_dm`] and [`Query::get_my_dms`](crate::query::Query::get_my_dms)
+//! each take a hex privkey, so every call sets up a new curve context and parses the key again.
+//! [`Keys`] does both once, keeping the context in a buffer the caller owns, and can then be passed
+//! by reference to the `_with_keys` variants.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{keys::{AlignedType, Keys, CONTEXT_SIZE}, Note};
+//! let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+//! let keys = Keys::new(
+//!     "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3",
+//!     &mut context,
+//! )
+//! .unwrap();
+//! for created_at in [1686880020, 1686880080] {
+//!     let note = Note::builder_with_keys(&keys)
+//!         .content("esptest".into())
+//!         .build_with_keys(&keys, created_at, [0; 32])
+//!         .unwrap();
+//!     assert_eq!(note.pubkey(), keys.pubkey());
+//! }
+//! ```
+
+pub use crate::curve::AlignedType;
+use crate::{
+    backend::SoftAes256,
+    curve::{self, Context, Curve, CurveBackend, KeyPair},
+    errors::Error,
+    utils::to_hex,
+    Note, NoteBuilder, NoteKinds, String, Vec,
+};
+
+/// Number of [`AlignedType`] the context buffer given to [`Keys::new`] should hold
+pub const CONTEXT_SIZE: usize = curve::CONTEXT_ITEMS;
+
+/// A parsed keypair along with the context used to sign with it
+pub struct Keys<'buf> {
+    ctx: Context<'buf>,
+    keypair: KeyPair,
+    /// X-only pubkey
+    pubkey: [u8; 32],
+}
+
+impl<'buf> Keys<'buf> {
+    /// Parses `privkey` and sets up a context in `buf`, which should hold [`CONTEXT_SIZE`] items.
+    /// Errors with `Secp256k1Error` if `buf` is too small and `InvalidPrivkey` if the privkey is invalid.
+    pub fn new(privkey: &str, buf: &'buf mut [AlignedType]) -> Result<Self, Error> {
+        let ctx = Curve::context(buf)?;
+        let keypair = Curve::keypair(&ctx, &curve::parse_secret(privkey)?)?;
+        let pubkey = Curve::x_only(&keypair);
+        Ok(Keys {
+            ctx,
+            keypair,
+            pubkey,
+        })
+    }
+
+    /// Hex encoded pubkey
+    pub fn pubkey(&self) -> [u8; 64] {
+        to_hex(&self.pubkey)
+    }
+
+    /// X-only pubkey
+    pub fn pubkey_bytes(&self) -> &[u8; 32] {
+        &self.pubkey
+    }
+
+    /// Signs the 32 byte `digest`, such as a note id, `aux_rnd` should come from a random source
+    pub fn sign(&self, digest: &[u8; 32], aux_rnd: &[u8; 32]) -> Result<[u8; 64], Error> {
+        Curve::sign(&self.ctx, &self.keypair, digest, aux_rnd)
+    }
+
+    pub(crate) fn secret_key(&self) -> [u8; 32] {
+        Curve::secret_bytes(&self.keypair)
+    }
+
+    /// Signs the already computed id of `note`
+    pub(crate) fn sign_note<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &mut Note<CONTENT, TAG, NTAGS, VALUES>,
+        aux_rnd: &[u8; 32],
+    ) -> Result<(), Error> {
+        note.set_sig_with(&self.ctx, &self.keypair, aux_rnd)
+    }
+}
+
+impl Note {
+    /// Same as [`Note::new_builder`], but takes already parsed keys
+    #[inline]
+    pub fn builder_with_keys(keys: &Keys) -> NoteBuilder {
+        NoteBuilder::from_keys(keys)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`Note::read_dm`], but takes already parsed keys
+    #[inline]
+    pub fn read_dm_with_keys(&self, keys: &Keys) -> Result<String<CONTENT>, Error> {
+        let mut scratch = [[0_u8; CONTENT]; 2];
+        self.read_dm_with_secret(
+            &keys.secret_key(),
+            &mut SoftAes256::default(),
+            scratch.as_flattened_mut(),
+        )
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::new`], but takes already parsed keys
+    #[inline]
+    pub fn from_keys(keys: &Keys) -> Self {
+        NoteBuilder {
+            // only `Copy` with the secp256k1 backend
+            #[allow(clippy::clone_on_copy)]
+            keypair: Some(keys.keypair.clone()),
+            created_at_window: None,
+            content_overflow: false,
+            note: Note {
+                id: [0; 32],
+                pubkey: keys.pubkey,
+                created_at: 0,
+                kind: NoteKinds::ShortNote,
+                tags: Vec::new(),
+                content: None,
+                sig: [0; 64],
+            },
+        }
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::build`], but signs with the context held by `keys`.
+    /// Errors with `InvalidPrivkey` if `keys` is not the key the builder was created with.
+    #[inline]
+    pub fn build_with_keys(
+        mut self,
+        keys: &Keys,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        if keys.pubkey != self.note.pubkey {
+            return Err(Error::InvalidPrivkey);
+        }
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        self.note.set_id()?;
+        keys.sign_note(&mut self.note, &aux_rnd)?;
+        Ok(self.note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::Tag;
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
+
+    #[test]
+    fn test_matches_privkey() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .add_tag(Tag::try_from(["t", "test"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let note = Note::builder_with_keys(&keys)
+            .content("esptest".into())
+            .add_tag(Tag::try_from(["t", "test"]).unwrap())
+            .unwrap()
+            .build_with_keys(&keys, 1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note, expected);
+
+        let dm = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm(
+                "hello",
+                "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0",
+                [1; 16],
+            )
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(dm.read_dm_with_keys(&keys).unwrap(), "hello");
+
+        let dm = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_dm_v2(
+                "hello",
+                "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf",
+                [1; 32],
+            )
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(dm.read_dm_v2_with_keys(&keys).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        assert!(matches!(
+            Keys::new("not a key", &mut context),
+            Err(Error::InvalidPrivkey)
+        ));
+        #[cfg(not(feature = "k256"))]
+        {
+            let mut small = [AlignedType::zeroed(); 1];
+            assert!(matches!(
+                Keys::new(PRIVKEY, &mut small),
+                Err(Error::Secp256k1Error)
+            ));
+        }
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let other = Note::new_builder(OTHER_PRIVKEY).unwrap();
+        assert_eq!(
+            other.build_with_keys(&keys, 1686880020, [0; 32]),
+            Err(Error::InvalidPrivkey)
+        );
+    }
+}
@@ -1,13 +1,17 @@
 #![no_std]
+#![cfg_attr(
+    not(test),
+    deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)
+)]
 //! Implementation of [Nostr](https://nostr.com/) for a #![no_std] environment. It supports note creation and parsing relay responses.
 //! An example project using an esp32 can be seen [here](https://github.com/isaac-asdf/esp32-nostr-client).
 //!
 //! # Example
 //! ```
-//! use nostr_nostd::{Note, String, ClientMsgKinds};
+//! use nostr_nostd::{Note, String, ClientMsgKinds, tag::Tag};
 //! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
 //! let content: String<400> = String::from("Hello, World!");
-//! let tag: String<150> = String::from("relay,wss://relay.example.com/");
+//! let tag = Tag::try_from(["relay", "wss://relay.example.com/"]).unwrap();
 //! // aux_rand should be generated from a random number generator
 //! // required to keep PRIVKEY secure with Schnorr signatures
 //! let aux_rand = [0; 32];
@@ -15,90 +19,293 @@
 //!     .unwrap()
 //!     .content(content)
 //!     .add_tag(tag)
+//!     .unwrap()
 //!     .build(1686880020, aux_rand)
 //!     .unwrap();
-//! let msg = note.serialize_to_relay(ClientMsgKinds::Event);
+//! let msg = note.serialize_to_relay(ClientMsgKinds::Event).unwrap();
 //! ```
 //!
+//! # Features
+//! - `rand_core` (default): `build_with_rng` and `create_dm_with_rng`, taking any `rand_core` CSPRNG instead of raw random bytes
+//! - `alloc`: heap-backed serialization (`serialize_to_vec`) of messages larger than [`MAX_RELAY_MSG_SIZE`]
+//! - `k256`: the pure Rust curve backend instead of `libsecp256k1`, and the `stepwise` module of signing and verification split into small steps
+//! - `static-pool`: [`buffer::StaticPool`] for handing out a `'static` scratch buffer
+//! - `interop`: conversions to and from the [rust-nostr](https://crates.io/crates/nostr) crate's `Event` and `Filter`, implies `alloc`
+//! - `test-vectors`: [`vectors`] module of known-good events, filters and relay messages for downstream test suites
+//! - `defmt`: `defmt::Format` for [`errors::Error`], [`Note`], [`NoteKinds`], [`query::Query`] and the relay messages, to log them over RTT
+//!
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use backend::{Aes256Backend, Sha256Backend, SoftAes256};
+use buffer::{OutBuf, SliceBuf, StreamBuf};
+use curve::{Curve, CurveBackend, KeyPair};
 pub use heapless::{String, Vec};
+use hex::{HexId, HexPubkey};
+#[cfg(feature = "rand_core")]
+pub use rand_core;
+#[cfg(feature = "rand_core")]
+use rand_core::{CryptoRng, RngCore};
 use relay_responses::AuthMessage;
-use secp256k1::{
-    self, ffi::types::AlignedType, schnorr::Signature, KeyPair, Message, XOnlyPublicKey,
-};
 use sha2::{Digest, Sha256};
-use utils::to_decimal_str;
+use tag::{Tag, MAX_TAG_VALUES, TAG_NAME_SIZE, TAG_VALUE_SIZE};
+use utils::{to_decimal_str, to_hex, write_escaped, write_hex, DEC_STRING_SIZE};
 
+pub mod app_data;
+pub mod backend;
+pub mod backoff;
+pub mod binary;
+pub mod buffer;
+pub mod client;
+pub mod client_messages;
+pub mod clock;
+pub mod contacts;
+pub mod content;
+pub mod coordinate;
+mod curve;
+pub mod deletion;
+pub mod dm;
 pub mod errors;
+pub mod gift_wrap;
+pub mod hex;
+#[cfg(feature = "interop")]
+pub mod interop;
+pub mod iot;
+pub mod keys;
+pub mod limits;
 mod nip04;
+pub mod nip06;
+pub mod nip42;
+pub mod nip44;
+pub mod note_ref;
+pub mod outbox;
+pub mod pagination;
 mod parse_json;
+pub mod pool;
+pub mod pow;
 pub mod query;
+pub mod reactions;
+pub mod relay_list;
 pub mod relay_responses;
+pub mod retry;
+pub mod rotation;
+pub mod seen;
+pub mod signer;
+#[cfg(feature = "k256")]
+pub mod stepwise;
+pub mod storage;
+pub mod store;
+pub mod stream;
+pub mod subscription;
+pub mod subscription_manager;
+pub mod tag;
+pub mod template;
+pub mod thread;
+pub mod timeline;
+pub mod unsigned;
 mod utils;
+pub mod validation;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod vectors;
+pub mod verify;
+pub mod zap;
 
-const TAG_SIZE: usize = 150;
 const NOTE_SIZE: usize = 400;
+/// Most tags a note holds by default
+const MAX_TAGS: usize = 5;
 const MAX_DM_SIZE: usize = 400;
 
+/// Longest a tag can serialize to, a full name followed by the most values of the longest length,
+/// with every character escaped to two bytes
+const MAX_TAG_JSON_SIZE: usize =
+    r#"[""]"#.len() + 2 * TAG_NAME_SIZE + MAX_TAG_VALUES * (r#","""#.len() + 2 * TAG_VALUE_SIZE);
+/// Longest JSON object a note can serialize to
+pub const MAX_NOTE_JSON_SIZE: usize = r#"{"content":""#.len()
+    + 2 * NOTE_SIZE // every character escaped
+    + r#"","created_at":"#.len()
+    + 20 // u64::MAX
+    + r#","id":""#.len()
+    + 64
+    + r#"","kind":"#.len()
+    + 10 // u32::MAX
+    + r#","pubkey":""#.len()
+    + 64
+    + r#"","sig":""#.len()
+    + 128
+    + r#"","tags":[]}"#.len()
+    + MAX_TAGS * MAX_TAG_JSON_SIZE
+    + (MAX_TAGS - 1); // commas between the tags
+/// Longest hashed form, `[0,<pubkey>,<created_at>,<kind>,<tags>,<content>]`, a note can
+/// serialize to
+pub const MAX_NOTE_HASH_SIZE: usize = r#"[0,""#.len()
+    + 64
+    + r#"","#.len()
+    + 20 // u64::MAX
+    + ",".len()
+    + 10 // u32::MAX
+    + ",[".len()
+    + MAX_TAGS * MAX_TAG_JSON_SIZE
+    + (MAX_TAGS - 1) // commas between the tags
+    + r#"],""#.len()
+    + 2 * NOTE_SIZE // every character escaped
+    + r#""]"#.len();
+/// Longest `EVENT` or `AUTH` message a note can be sent to a relay in
+pub const MAX_NOTE_WIRE_SIZE: usize = r#"["EVENT",]"#.len() + MAX_NOTE_JSON_SIZE;
+/// Longest `REQ` message a [`query::Query`] can serialize to
+pub const MAX_REQ_SIZE: usize = query::MAX_REQ_SIZE;
+/// Longest relay message this crate can parse, an `EVENT` carrying a note on a 64 character subscription
+pub const MAX_RELAY_MSG_SIZE: usize = r#"["EVENT","",]"#.len() + 64 + MAX_NOTE_JSON_SIZE;
+
+/// Buffer large enough for any note sent to a relay
+pub type NoteBuffer = Vec<u8, MAX_NOTE_WIRE_SIZE>;
+/// Buffer large enough for any relay message this crate parses
+pub type RelayFrame = Vec<u8, MAX_RELAY_MSG_SIZE>;
+
 /// Defined by the [nostr protocol](https://github.com/nostr-protocol/nips/tree/master#event-kinds)
-#[derive(Debug, Copy, Clone, PartialEq)]
+///
+/// Kinds compare by their numeric value, so `NoteKinds::Custom(3)` equals
+/// `NoteKinds::ContactList`. Parsed notes always get the named variant when there is one.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum NoteKinds {
+    /// Profile metadata, 0
+    Metadata,
     /// For most short text based notes
     ShortNote,
+    /// Follow list, 3
+    ContactList,
     /// DM
     DM,
+    /// Deletion request, 5
+    Deletion,
+    /// Repost of a short note, 6
+    Repost,
+    /// Reaction, 7
+    Reaction,
+    /// Seal of a gift wrapped note, 13
+    Seal,
+    /// Repost of any other kind, 16
+    GenericRepost,
+    /// Gift wrap, 1059
+    GiftWrap,
     /// IOT Event,
     IOT,
+    /// Zap request, 9734
+    ZapRequest,
+    /// Zap receipt, 9735
+    ZapReceipt,
+    /// Relay list metadata, 10002
+    RelayList,
     /// Ephemeral event for authentication to relay
     Auth,
     /// Regular Events (must be between 1000 and <=9999)
-    Regular(u16),
+    Regular(u32),
     /// Replacabe event (must be between 10000 and <20000)
-    Replaceable(u16),
+    Replaceable(u32),
     /// Ephemeral event (must be between 20000 and <30000)
-    Ephemeral(u16),
+    Ephemeral(u32),
     /// Parameterized Replacabe event (must be between 30000 and <40000)
-    ParameterizedReplaceable(u16),
+    ParameterizedReplaceable(u32),
     /// Custom
-    Custom(u16),
+    Custom(u32),
 }
 
 impl NoteKinds {
-    pub fn serialize(&self) -> String<10> {
-        // will ignore large bytes when serializing
-        let n: u16 = match self {
+    /// The numeric value of the kind
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            NoteKinds::Metadata => 0,
             NoteKinds::ShortNote => 1,
+            NoteKinds::ContactList => 3,
             NoteKinds::DM => 4,
+            NoteKinds::Deletion => 5,
+            NoteKinds::Repost => 6,
+            NoteKinds::Reaction => 7,
+            NoteKinds::Seal => 13,
+            NoteKinds::GenericRepost => 16,
+            NoteKinds::GiftWrap => 1059,
             NoteKinds::IOT => 5732,
+            NoteKinds::ZapRequest => 9734,
+            NoteKinds::ZapReceipt => 9735,
+            NoteKinds::RelayList => 10002,
             NoteKinds::Auth => 22242,
             NoteKinds::Regular(val) => *val,
             NoteKinds::Replaceable(val) => *val,
             NoteKinds::Ephemeral(val) => *val,
             NoteKinds::ParameterizedReplaceable(val) => *val,
             NoteKinds::Custom(val) => *val,
-        };
+        }
+    }
+
+    pub fn serialize(&self) -> String<DEC_STRING_SIZE> {
+        to_decimal_str(self.as_u32().into())
+    }
+
+    /// True for kinds relays store and keep every note of
+    pub fn is_regular(&self) -> bool {
+        let kind = self.as_u32();
+        kind == 1 || kind == 2 || (4..45).contains(&kind) || (1_000..10_000).contains(&kind)
+    }
+
+    /// True for kinds where relays only keep the latest note for each author
+    pub fn is_replaceable(&self) -> bool {
+        let kind = self.as_u32();
+        kind == 0 || kind == 3 || (10_000..20_000).contains(&kind)
+    }
 
-        to_decimal_str(n as u32)
+    /// True for kinds relays pass on to subscribers without storing
+    pub fn is_ephemeral(&self) -> bool {
+        (20_000..30_000).contains(&self.as_u32())
+    }
+
+    /// True for kinds where relays only keep the latest note for each author and `d` tag
+    pub fn is_parameterized_replaceable(&self) -> bool {
+        (30_000..40_000).contains(&self.as_u32())
+    }
+}
+
+impl PartialEq for NoteKinds {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u32() == other.as_u32()
     }
 }
 
 impl From<u16> for NoteKinds {
     fn from(value: u16) -> Self {
+        u32::from(value).into()
+    }
+}
+
+impl From<u32> for NoteKinds {
+    fn from(value: u32) -> Self {
         match value {
+            0 => NoteKinds::Metadata,
             1 => NoteKinds::ShortNote,
+            3 => NoteKinds::ContactList,
             4 => NoteKinds::DM,
+            5 => NoteKinds::Deletion,
+            6 => NoteKinds::Repost,
+            7 => NoteKinds::Reaction,
+            13 => NoteKinds::Seal,
+            16 => NoteKinds::GenericRepost,
+            1059 => NoteKinds::GiftWrap,
             5732 => NoteKinds::IOT,
+            9734 => NoteKinds::ZapRequest,
+            9735 => NoteKinds::ZapReceipt,
+            10002 => NoteKinds::RelayList,
             22242 => NoteKinds::Auth,
-            x if (1_000..10_000).contains(&x) => NoteKinds::Regular(x as u16),
-            x if (10_000..20_000).contains(&x) => NoteKinds::Replaceable(x as u16),
-            x if (20_000..30_000).contains(&x) => NoteKinds::Ephemeral(x as u16),
-            x if (30_000..40_000).contains(&x) => NoteKinds::ParameterizedReplaceable(x as u16),
+            x if (1_000..10_000).contains(&x) => NoteKinds::Regular(x),
+            x if (10_000..20_000).contains(&x) => NoteKinds::Replaceable(x),
+            x if (20_000..30_000).contains(&x) => NoteKinds::Ephemeral(x),
+            x if (30_000..40_000).contains(&x) => NoteKinds::ParameterizedReplaceable(x),
             x => NoteKinds::Custom(x),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ClientMsgKinds {
     Event,
     Req,
@@ -107,124 +314,98 @@ pub enum ClientMsgKinds {
 }
 
 /// Representation of Nostr Note
-#[derive(Debug, PartialEq)]
-pub struct Note {
+///
+/// The defaults fit most notes. Devices that need smaller or larger buffers can pick their own
+/// sizes at compile time: `CONTENT` is the longest content in bytes, `TAG` the longest tag value,
+/// `NTAGS` the most tags a note can hold and `VALUES` the most values each tag holds after its
+/// name.
+/// ```
+/// use nostr_nostd::{Note, NoteBuilder, tag::Tag};
+/// // 64 bytes of content and up to 2 tags of 32 byte values
+/// type SensorNote = Note<64, 32, 2>;
+/// let note = NoteBuilder::<64, 32, 2>::new(
+///     "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3",
+/// )
+/// .unwrap()
+/// .content("21.5".into())
+/// .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+/// .unwrap()
+/// .build(1686880020, [0; 32])
+/// .unwrap();
+/// let mut buf = [0_u8; 512];
+/// let len = note.serialize_into(nostr_nostd::ClientMsgKinds::Event, &mut buf).unwrap();
+/// let json = core::str::from_utf8(&buf[r#"["EVENT","#.len()..len - 1]).unwrap();
+/// assert_eq!(SensorNote::from_json(json), Ok(note));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Note<
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
     /// ID of note
-    id: [u8; 64],
+    id: [u8; 32],
     /// Derived from privkey, refers to note creator
-    pubkey: [u8; 64],
+    pubkey: [u8; 32],
     /// Unix timestamp
-    created_at: u32,
+    created_at: u64,
     /// Default to kind 1
     kind: NoteKinds,
-    tags: Vec<String<TAG_SIZE>, 5>,
-    content: Option<String<NOTE_SIZE>>,
-    sig: [u8; 128],
-}
-
-/// Impl for tags which can had an additional tag added.
-/// ie, not implemented for FiveTags but implemented for all others
-pub trait AddTag {
-    type Next: TagCount;
-    // Add a generic parameter for the return type, bounded by TagCount
-    fn next(self) -> Self::Next;
-}
-/// Number of tags added
-pub trait TagCount {}
-/// No tags have been added
-pub struct ZeroTags;
-/// One tag has been added
-pub struct OneTag;
-/// Two tags have been added
-pub struct TwoTags;
-/// Three tags have been added
-pub struct ThreeTags;
-/// Four tags have been added
-pub struct FourTags;
-/// Five tags have been added
-pub struct FiveTags;
-
-impl TagCount for ZeroTags {}
-impl TagCount for OneTag {}
-impl TagCount for TwoTags {}
-impl TagCount for ThreeTags {}
-impl TagCount for FourTags {}
-impl TagCount for FiveTags {}
-
-impl AddTag for ZeroTags {
-    type Next = OneTag;
-    // Implement the next method to return a new MyType instance
-    #[inline]
-    fn next(self) -> OneTag {
-        OneTag
-    }
-}
-impl AddTag for OneTag {
-    type Next = TwoTags;
-    #[inline]
-    fn next(self) -> TwoTags {
-        TwoTags
-    }
-}
-impl AddTag for TwoTags {
-    type Next = ThreeTags;
-    #[inline]
-    fn next(self) -> ThreeTags {
-        ThreeTags
-    }
-}
-impl AddTag for ThreeTags {
-    type Next = FourTags;
-    #[inline]
-    fn next(self) -> FourTags {
-        FourTags
-    }
-}
-impl AddTag for FourTags {
-    type Next = FiveTags;
-    #[inline]
-    fn next(self) -> FiveTags {
-        FiveTags
-    }
-}
-
-/// Used to track the addition of the time created and the number of tags added
-pub struct BuildStatus<B> {
-    tags: B,
+    tags: Vec<Tag<TAG, VALUES>, NTAGS>,
+    content: Option<String<CONTENT>>,
+    sig: [u8; 64],
 }
 
 /// Used to fill in the fields of a Note.
-pub struct NoteBuilder<B> {
-    keypair: KeyPair,
-    build_status: BuildStatus<B>,
-    note: Note,
+/// Tags can be added up to the `NTAGS` the note holds.
+pub struct NoteBuilder<
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    /// `None` when signing is done by an external [`signer::Signer`]
+    keypair: Option<KeyPair>,
+    /// Earliest and latest `created_at` accepted, see [`NoteBuilder::created_at_window`]
+    created_at_window: Option<(u64, u64)>,
+    /// Set when [`NoteBuilder::content_writer`] ran out of room, fails the build
+    content_overflow: bool,
+    note: Note<CONTENT, TAG, NTAGS, VALUES>,
 }
 
-impl<T, NextAddTag> NoteBuilder<T>
-where
-    T: AddTag<Next = NextAddTag>,
-    NextAddTag: TagCount,
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
 {
-    /// Adds a new tag to the note.
-    /// The maximum number of tags currently allowed is 5.
-    /// Attempts to add too many tags will be a compilation error.
+    /// Returns a NoteBuilder for a note with custom buffer sizes, can error if the privkey is invalid.
+    /// For the default sizes use [`Note::new_builder`].
     #[inline]
-    pub fn add_tag(mut self, tag: String<TAG_SIZE>) -> NoteBuilder<NextAddTag> {
-        let next_tags = self.build_status.tags.next();
-        self.note
-            .tags
-            .push(tag)
-            .expect("AddTag impl error, should be impossible to err here");
-
-        NoteBuilder {
-            build_status: BuildStatus { tags: next_tags },
-            keypair: self.keypair,
-            note: self.note,
-        }
+    pub fn new(privkey: &str) -> Result<Self, errors::Error> {
+        let key_pair = curve::keypair_from_hex(privkey)?;
+        let mut note = Note {
+            id: [0; 32],
+            pubkey: [0; 32],
+            created_at: 0,
+            kind: NoteKinds::ShortNote,
+            tags: Vec::new(),
+            content: None,
+            sig: [0; 64],
+        };
+        // encoded once here rather than on every build
+        note.pubkey = Curve::x_only(&key_pair);
+        Ok(NoteBuilder {
+            keypair: Some(key_pair),
+            created_at_window: None,
+            content_overflow: false,
+            note,
+        })
     }
 }
 
-impl<B> NoteBuilder<B> {
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
     /// Sets the "kind" field of the note
     pub fn set_kind(mut self, kind: NoteKinds) -> Self {
         self.note.kind = kind;
@@ -232,419 +413,547 @@ impl<B> NoteBuilder<B> {
     }
 
     /// Sets the "content" field of Note
-    pub fn content(mut self, content: String<NOTE_SIZE>) -> Self {
+    pub fn content(mut self, content: String<CONTENT>) -> Self {
         self.note.content = Some(content);
+        self.content_overflow = false;
         self
     }
+
+    /// Adds a new tag to the note.
+    /// Errors with `TooManyTags` if the note already holds `NTAGS` tags.
+    #[inline]
+    pub fn add_tag(mut self, tag: Tag<TAG, VALUES>) -> Result<Self, errors::Error> {
+        const { assert!(NTAGS > 0, "NTAGS leaves no room for tags") };
+        self.note
+            .tags
+            .push(tag)
+            .map_err(|_| errors::Error::TooManyTags)?;
+        Ok(self)
+    }
+
+    /// Adds each of `tags` to the note.
+    /// Errors with `TooManyTags` if they don't all fit in `NTAGS`.
+    pub fn add_tags(
+        mut self,
+        tags: impl IntoIterator<Item = Tag<TAG, VALUES>>,
+    ) -> Result<Self, errors::Error> {
+        for tag in tags {
+            self = self.add_tag(tag)?;
+        }
+        Ok(self)
+    }
 }
 
-impl NoteBuilder<ZeroTags> {
+impl NoteBuilder {
     /// Creates an auth note per NIP42
     #[inline]
     pub fn create_auth(
         mut self,
         auth: &AuthMessage,
         relay: &str,
-    ) -> Result<NoteBuilder<TwoTags>, errors::Error> {
+    ) -> Result<NoteBuilder, errors::Error> {
         let mut tags = Vec::new();
-        let mut challenge_string: String<TAG_SIZE> = String::from("challenge,");
-        challenge_string
-            .push_str(&auth.challenge_string)
-            .map_err(|_| errors::Error::ContentOverflow)?;
-        tags.push(challenge_string).expect("impossible");
-        let mut relay_str: String<TAG_SIZE> = String::from("relay,");
-        relay_str
-            .push_str(relay)
-            .map_err(|_| errors::Error::ContentOverflow)?;
-        tags.push(relay_str).expect("impossible");
+        tags.push(Tag::try_from(["challenge", &auth.challenge_string])?)
+            .map_err(|_| errors::Error::TooManyTags)?;
+        tags.push(Tag::try_from(["relay", relay])?)
+            .map_err(|_| errors::Error::TooManyTags)?;
         self.note.tags = tags;
         self.note.kind = NoteKinds::Auth;
-        Ok(NoteBuilder {
-            keypair: self.keypair,
-            note: self.note,
-            build_status: BuildStatus { tags: TwoTags },
-        })
+        Ok(self)
     }
+}
 
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
     /// Sets the "content" field according to NIP04 and adds the tag for receiver pubkey.
-    /// iv should be generated from a random source
+    /// iv should be generated from a random source. The encrypted content can be up to `CONTENT`
+    /// characters, so a builder with a larger `CONTENT` allows longer DMs.
+    /// Errors with `MissingPrivkey` if the builder was created from a [`signer::Signer`].
     #[inline]
     pub fn create_dm(
+        self,
+        content: &str,
+        rcvr_pubkey: &str,
+        iv: [u8; 16],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let mut scratch = [[0_u8; CONTENT]; 2];
+        self.create_dm_in(content, rcvr_pubkey, iv, scratch.as_flattened_mut())
+    }
+
+    /// Same as [`NoteBuilder::create_dm`], but uses `scratch` for the encryption buffers
+    /// instead of the stack.
+    /// `scratch` should be at least twice `CONTENT` bytes, [`buffer::SCRATCH_SIZE`] for the
+    /// default size.
+    #[inline]
+    pub fn create_dm_in(
+        self,
+        content: &str,
+        rcvr_pubkey: &str,
+        iv: [u8; 16],
+        scratch: &mut [u8],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        self.create_dm_with(
+            content,
+            rcvr_pubkey,
+            iv,
+            &mut SoftAes256::default(),
+            scratch,
+        )
+    }
+
+    /// Same as [`NoteBuilder::create_dm`], running AES-256 on `aes`, such as a
+    /// [hardware accelerator](backend)
+    pub fn create_dm_with_cipher<A: Aes256Backend>(
+        self,
+        content: &str,
+        rcvr_pubkey: &str,
+        iv: [u8; 16],
+        aes: &mut A,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let mut scratch = [[0_u8; CONTENT]; 2];
+        self.create_dm_with(content, rcvr_pubkey, iv, aes, scratch.as_flattened_mut())
+    }
+
+    fn create_dm_with<A: Aes256Backend>(
         mut self,
         content: &str,
         rcvr_pubkey: &str,
         iv: [u8; 16],
-    ) -> Result<NoteBuilder<OneTag>, errors::Error> {
+        aes: &mut A,
+        scratch: &mut [u8],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
         let mut msg = [0_u8; 32];
-        base16ct::lower::decode(&rcvr_pubkey, &mut msg)
-            .map_err(|_| errors::Error::InvalidPubkey)?;
-        let pubkey = XOnlyPublicKey::from_slice(&msg).map_err(|_| errors::Error::InvalidPubkey)?;
-        let encrypted = nip04::encrypt(&self.keypair.secret_key(), &pubkey, content, iv)?;
+        base16ct::lower::decode(rcvr_pubkey, &mut msg).map_err(|_| errors::Error::InvalidPubkey)?;
+        let pubkey = Curve::parse_pubkey(&msg)?;
+        let keypair = self.keypair.as_ref().ok_or(errors::Error::MissingPrivkey)?;
+        let key = Curve::shared_x(&Curve::secret_bytes(keypair), &pubkey)?;
+        let encrypted = nip04::encrypt_with(aes, &key, content, iv, scratch)?;
         self.note.content = Some(encrypted);
-        let mut tag = String::from("p,");
-        tag.push_str(rcvr_pubkey).expect("impossible");
-        Ok(self.add_tag(tag))
+        let tag = Tag::try_from(["p", rcvr_pubkey])?;
+        self.add_tag(tag)
+    }
+
+    /// Same as [`NoteBuilder::create_dm`], drawing the IV from `rng`.
+    /// Errors with `RngError` if `rng` fails.
+    #[cfg(feature = "rand_core")]
+    pub fn create_dm_with_rng<R: RngCore + CryptoRng>(
+        self,
+        content: &str,
+        rcvr_pubkey: &str,
+        rng: &mut R,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let mut iv = [0_u8; 16];
+        rng.try_fill_bytes(&mut iv)
+            .map_err(|_| errors::Error::RngError)?;
+        self.create_dm(content, rcvr_pubkey, iv)
     }
 }
 
-impl<A> NoteBuilder<A> {
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
     /// Set the 'created_at' and sign the note.
+    /// Errors with `MissingPrivkey` if the builder was created from a [`signer::Signer`].
     #[inline]
-    pub fn build(mut self, created_at: u32, aux_rnd: [u8; 32]) -> Result<Note, errors::Error> {
-        self.note.created_at = created_at;
-        self.note.set_pubkey(&self.keypair.x_only_public_key().0)?;
+    pub fn build(
+        mut self,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let keypair = self.keypair.take().ok_or(errors::Error::MissingPrivkey)?;
+        self.check_content()?;
+        self.set_created_at(created_at)?;
         self.note.set_id()?;
-        self.note.set_sig(&self.keypair, &aux_rnd)?;
+        self.note.set_sig(&keypair, &aux_rnd)?;
+        Ok(self.note)
+    }
+
+    /// Same as [`NoteBuilder::build`], hashing the id with `hasher`
+    pub fn build_with_hasher<H: Sha256Backend>(
+        mut self,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+        hasher: &mut H,
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let keypair = self.keypair.take().ok_or(errors::Error::MissingPrivkey)?;
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        self.note.id = self.note.compute_id_with(hasher)?;
+        self.note.set_sig(&keypair, &aux_rnd)?;
         Ok(self.note)
     }
+
+    /// Same as [`NoteBuilder::build`], drawing the aux_rand of the signature from `rng`.
+    /// Errors with `RngError` if `rng` fails.
+    #[cfg(feature = "rand_core")]
+    pub fn build_with_rng<R: RngCore + CryptoRng>(
+        self,
+        created_at: u64,
+        rng: &mut R,
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        let mut aux_rnd = [0_u8; 32];
+        rng.try_fill_bytes(&mut aux_rnd)
+            .map_err(|_| errors::Error::RngError)?;
+        self.build(created_at, aux_rnd)
+    }
+
+    /// Same as [`NoteBuilder::build`]. The note is now hashed as it is serialized, so `scratch`
+    /// is no longer used.
+    #[deprecated(note = "hashing no longer needs a scratch buffer, use `build`")]
+    #[inline]
+    pub fn build_in(
+        self,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+        _scratch: &mut [u8],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, errors::Error> {
+        self.build(created_at, aux_rnd)
+    }
 }
 
 impl Note {
     /// Returns a NoteBuilder, can error if the privkey is invalid
     #[inline]
-    pub fn new_builder(privkey: &str) -> Result<NoteBuilder<ZeroTags>, errors::Error> {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf)
-            .map_err(|_| errors::Error::Secp256k1Error)?;
-        let key_pair: KeyPair = KeyPair::from_seckey_str(&sig_obj, privkey)
-            .map_err(|_| errors::Error::InvalidPrivkey)?;
-        Ok(NoteBuilder {
-            build_status: BuildStatus { tags: ZeroTags },
-            keypair: key_pair,
-            note: Note {
-                id: [0; 64],
-                pubkey: [0; 64],
-                created_at: 0,
-                kind: NoteKinds::ShortNote,
-                tags: Vec::new(),
-                content: None,
-                sig: [0; 128],
-            },
-        })
+    pub fn new_builder(privkey: &str) -> Result<NoteBuilder, errors::Error> {
+        NoteBuilder::new(privkey)
+    }
+
+    /// Serializes the note for sending to relay.
+    /// Errors with `ContentOverflow` if escaping the content makes it too long for [`NoteBuffer`].
+    #[inline]
+    pub fn serialize_to_relay(self, msg_type: ClientMsgKinds) -> Result<NoteBuffer, errors::Error> {
+        let mut output = NoteBuffer::new();
+        self.serialize_to_buf(msg_type, &mut output)?;
+        Ok(output)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Hex encoded id of the note
+    pub fn id(&self) -> [u8; 64] {
+        to_hex(&self.id)
+    }
+
+    /// Id of the note
+    pub fn id_bytes(&self) -> &[u8; 32] {
+        &self.id
+    }
+
+    /// Id of the note, for handing to queries and tags
+    pub fn hex_id(&self) -> HexId {
+        HexId::from_bytes(self.id)
+    }
+
+    /// Hex encoded pubkey of the note's author
+    pub fn pubkey(&self) -> [u8; 64] {
+        to_hex(&self.pubkey)
+    }
+
+    /// X-only pubkey of the note's author
+    pub fn pubkey_bytes(&self) -> &[u8; 32] {
+        &self.pubkey
     }
 
-    fn timestamp_bytes(&self) -> String<10> {
+    /// Pubkey of the note's author, for handing to queries and tags
+    pub fn hex_pubkey(&self) -> HexPubkey {
+        HexPubkey::from_bytes(self.pubkey)
+    }
+
+    /// Unix timestamp the note was created at
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Kind of the note
+    pub fn kind(&self) -> NoteKinds {
+        self.kind
+    }
+
+    /// Content of the note, if any
+    pub fn content(&self) -> Option<&str> {
+        self.content.as_ref().map(|content| content.as_str())
+    }
+
+    /// Hex encoded signature of the note
+    pub fn sig(&self) -> [u8; 128] {
+        to_hex(&self.sig)
+    }
+
+    /// Schnorr signature of the note
+    pub fn sig_bytes(&self) -> &[u8; 64] {
+        &self.sig
+    }
+
+    fn timestamp_bytes(&self) -> String<DEC_STRING_SIZE> {
         to_decimal_str(self.created_at)
     }
 
-    fn to_hash_str(&self) -> ([u8; 1536], usize) {
-        let mut hash_str = [0; 1536];
-        let mut count = 0;
-        br#"[0,""#.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
-        self.pubkey.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
-        br#"","#.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
-        self.timestamp_bytes().chars().for_each(|bs| {
-            hash_str[count] = bs as u8;
-            count += 1;
-        });
-        hash_str[count] = 44; // 44 = ,
-        count += 1;
-        self.kind.serialize().chars().for_each(|bs| {
-            hash_str[count] = bs as u8;
-            count += 1;
-        });
-        hash_str[count] = 44; // 44 = ,
-        count += 1;
-        // tags
-        br#"["#.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
-        let mut tags_present = false;
-        self.tags.iter().for_each(|tag| {
-            // add opening [
-            hash_str[count] = 91;
-            count += 1;
-            tag.split(",").for_each(|element| {
-                // add opening "
-                hash_str[count] = 34;
-                count += 1;
-                element.as_bytes().iter().for_each(|bs| {
-                    hash_str[count] = *bs;
-                    count += 1;
-                });
-                // add closing "
-                hash_str[count] = 34;
-                count += 1;
-                // add , separator back in
-                hash_str[count] = 44;
-                count += 1;
-            });
-            // remove last comma
-            count -= 1;
-            // add closing ]
-            hash_str[count] = 93;
-            count += 1;
-
-            // add closing ,
-            hash_str[count] = 44;
-            count += 1;
-            tags_present = true;
-        });
-        if tags_present {
-            // remove last comma
-            count -= 1;
-        }
-        br#"],""#.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
+    #[cfg(test)]
+    fn to_hash_str(&self) -> ([u8; MAX_NOTE_HASH_SIZE], usize) {
+        let mut hash_str = [0; MAX_NOTE_HASH_SIZE];
+        let mut buf = SliceBuf::new(&mut hash_str);
+        self.write_hash_str(&mut buf)
+            .expect("Impossible due to size constraints of content, tags");
+        let count = buf.len();
+        (hash_str, count)
+    }
+
+    /// Writes the serialized form of the note that is hashed to get its id
+    fn write_hash_str<B: OutBuf>(&self, out: &mut B) -> Result<(), errors::Error> {
+        out.push_slice(br#"[0,""#)?;
+        write_hex(out, &self.pubkey)?;
+        out.push_slice(br#"","#)?;
+        out.push_slice(self.timestamp_bytes().as_bytes())?;
+        out.push_byte(b',')?;
+        out.push_slice(self.kind.serialize().as_bytes())?;
+        out.push_byte(b',')?;
+        self.write_tags(out)?;
+        out.push_slice(br#",""#)?;
         if let Some(content) = &self.content {
-            content.as_bytes().iter().for_each(|bs| {
-                hash_str[count] = *bs;
-                count += 1;
-            });
+            write_escaped(out, content)?;
         }
-        br#""]"#.iter().for_each(|bs| {
-            hash_str[count] = *bs;
-            count += 1;
-        });
-        (hash_str, count)
+        out.push_slice(br#""]"#)
     }
 
-    fn set_pubkey(&mut self, pubkey: &XOnlyPublicKey) -> Result<(), errors::Error> {
-        let pubkey = &pubkey.serialize();
-        base16ct::lower::encode(pubkey, &mut self.pubkey)
-            .map_err(|_| errors::Error::EncodeError)?;
-        Ok(())
+    /// Writes the tags as a JSON array of arrays
+    fn write_tags<B: OutBuf>(&self, out: &mut B) -> Result<(), errors::Error> {
+        out.push_byte(b'[')?;
+        for (i, tag) in self.tags.iter().enumerate() {
+            if i > 0 {
+                out.push_byte(b',')?;
+            }
+            tag.write_json(out)?;
+        }
+        out.push_byte(b']')
+    }
+
+    /// Hashes the note's fields into the id they should have, feeding the hasher as they
+    /// are serialized
+    fn compute_id(&self) -> Result<[u8; 32], errors::Error> {
+        self.compute_id_with(&mut Sha256::new())
+    }
+
+    /// Hashes the note's fields into the id they should have with `hasher`, such as a
+    /// [hardware accelerator](backend)
+    pub fn compute_id_with<H: Sha256Backend>(
+        &self,
+        hasher: &mut H,
+    ) -> Result<[u8; 32], errors::Error> {
+        hasher.reset();
+        self.write_hash_str(&mut StreamBuf::new(|bytes: &[u8]| {
+            hasher.update(bytes);
+            Ok(())
+        }))?;
+        Ok(hasher.finish())
     }
 
     fn set_id(&mut self) -> Result<(), errors::Error> {
-        let (remaining, len) = self.to_hash_str();
-        let mut hasher = Sha256::new();
-        hasher.update(&remaining[..len]);
-        let results = hasher.finalize();
-        base16ct::lower::encode(&results, &mut self.id).map_err(|_| errors::Error::EncodeError)?;
+        self.id = self.compute_id()?;
         Ok(())
     }
 
-    fn set_sig(&mut self, key_pair: &KeyPair, aux_rnd: &[u8; 32]) -> Result<(), errors::Error> {
-        // figure out what size we need and why
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf)
-            .map_err(|_| errors::Error::Secp256k1Error)?;
+    /// Checks the id is the hash of the note's fields.
+    /// Errors with `EventNotValid` if it isn't.
+    pub fn verify_id(&self) -> Result<(), errors::Error> {
+        self.verify_id_with(&mut Sha256::new())
+    }
 
-        let mut msg = [0_u8; 32];
-        base16ct::lower::decode(&self.id, &mut msg)
-            .map_err(|_| errors::Error::InternalSigningError)?;
+    /// Same as [`Note::verify_id`], hashing with `hasher`
+    pub fn verify_id_with<H: Sha256Backend>(&self, hasher: &mut H) -> Result<(), errors::Error> {
+        if self.compute_id_with(hasher)? != self.id {
+            return Err(errors::Error::EventNotValid);
+        }
+        Ok(())
+    }
 
-        let message = Message::from_slice(&msg).map_err(|_| errors::Error::InternalSigningError)?;
+    /// Checks both the id, as [`Note::verify_id`], and the signature, as
+    /// [`Note::validate_signature`]
+    pub fn verify(&self) -> Result<(), errors::Error> {
+        self.verify_id()?;
+        self.validate_signature()
+    }
 
-        let sig = sig_obj.sign_schnorr_with_aux_rand(&message, key_pair, aux_rnd);
-        base16ct::lower::encode(sig.as_ref(), &mut self.sig)
-            .map_err(|_| errors::Error::EncodeError)?;
+    fn set_sig(&mut self, key_pair: &KeyPair, aux_rnd: &[u8; 32]) -> Result<(), errors::Error> {
+        self.sig = curve::sign(key_pair, &self.id, aux_rnd)?;
         Ok(())
     }
 
-    /// Validates the events signature
+    /// Same as `set_sig`, using an already created context
+    fn set_sig_with(
+        &mut self,
+        ctx: &curve::Context<'_>,
+        key_pair: &KeyPair,
+        aux_rnd: &[u8; 32],
+    ) -> Result<(), errors::Error> {
+        self.sig = Curve::sign(ctx, key_pair, &self.id, aux_rnd)?;
+        Ok(())
+    }
+
+    /// Validates the events signature.
+    /// Errors with `InvalidPubkey`, `EventNotValid` or `InvalidSignature` if the matching field
+    /// is malformed, and `InvalidSignature` if the signature doesn't match.
     pub fn validate_signature(&self) -> Result<(), errors::Error> {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf)
-            .map_err(|_| errors::Error::Secp256k1Error)?;
+        let pubkey = Curve::parse_pubkey(&self.pubkey)?;
+        self.verify_with(&pubkey)
+    }
 
-        let mut msg = [0_u8; 32];
-        base16ct::lower::decode(&self.id, &mut msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("1");
-
-        let message = Message::from_slice(&msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("2");
-        let mut msg = [0_u8; 64];
-        base16ct::lower::decode(&self.sig, &mut msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("5");
-        let sig = Signature::from_slice(&msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("3");
+    #[cfg(test)]
+    fn to_json(&self) -> Vec<u8, MAX_NOTE_JSON_SIZE> {
+        let mut output = Vec::new();
+        self.write_json(&mut output)
+            .expect("Impossible due to size constraints of content, tags");
+        output
+    }
 
-        let mut msg = [0_u8; 32];
-        base16ct::lower::decode(&self.pubkey, &mut msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("1");
-        let pubkey = XOnlyPublicKey::from_slice(&msg)
-            .map_err(|_| errors::Error::InternalSigningError)
-            .expect("4");
-
-        sig_obj
-            .verify_schnorr(&sig, &message, &pubkey)
-            .map_err(|_| errors::Error::InvalidSignature)
-    }
-
-    fn to_json(&self) -> Vec<u8, 1000> {
-        let mut output: Vec<u8, 1000> = Vec::new();
-        br#"{"content":""#.iter().for_each(|bs| {
-            // handle result?
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
+    /// Writes the note as a bare JSON object, without a message envelope, into `buf`,
+    /// returning the number of bytes written.
+    /// Errors with `ContentOverflow` if `buf` is too small.
+    pub fn json_into(&self, buf: &mut [u8]) -> Result<usize, errors::Error> {
+        let mut out = SliceBuf::new(buf);
+        self.write_json(&mut out)?;
+        Ok(out.len())
+    }
+
+    /// Writes the note as a JSON object
+    fn write_json<B: OutBuf>(&self, out: &mut B) -> Result<(), errors::Error> {
+        self.write_json_fields(out, true)
+    }
+
+    /// Writes the note as a JSON object, leaving out `sig` for unsigned notes
+    fn write_json_fields<B: OutBuf>(
+        &self,
+        out: &mut B,
+        with_sig: bool,
+    ) -> Result<(), errors::Error> {
+        out.push_slice(br#"{"content":""#)?;
         if let Some(content) = &self.content {
-            content.as_bytes().iter().for_each(|bs| {
-                output
-                    .push(*bs)
-                    .expect("Impossible due to size constraints of content, tags");
-            });
+            write_escaped(out, content)?;
         }
-        br#"","created_at":"#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        self.timestamp_bytes().chars().for_each(|bs| {
-            output
-                .push(bs as u8)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        br#","id":""#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        self.id.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        br#"","kind":"#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        self.kind.serialize().chars().for_each(|bs| {
-            output
-                .push(bs as u8)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        br#","pubkey":""#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        self.pubkey.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        br#"","sig":""#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        self.sig.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        br#"","tags":["#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        let mut tags_present = false;
-        self.tags.iter().for_each(|tag| {
-            // add opening [
-            output.push(91).expect("impossible");
-            tag.split(",").for_each(|element| {
-                // add opening "
-                output.push(34).expect("impossible");
-                element.as_bytes().iter().for_each(|bs| {
-                    output.push(*bs).expect("impossible");
-                });
-                // add closing "
-                output.push(34).expect("impossible");
-                // add a comma separator
-                output.push(44).expect("impossible");
-            });
-            // remove last comma
-            output.pop().expect("impossible");
-            // add closing ]
-            output.push(93).expect("impossible");
-            // add a comma separator
-            output.push(44).expect("impossible");
-            tags_present = true;
-        });
-        if tags_present {
-            // remove last comma
-            output.pop().expect("impossible");
+        out.push_slice(br#"","created_at":"#)?;
+        out.push_slice(self.timestamp_bytes().as_bytes())?;
+        out.push_slice(br#","id":""#)?;
+        write_hex(out, &self.id)?;
+        out.push_slice(br#"","kind":"#)?;
+        out.push_slice(self.kind.serialize().as_bytes())?;
+        out.push_slice(br#","pubkey":""#)?;
+        write_hex(out, &self.pubkey)?;
+        if with_sig {
+            out.push_slice(br#"","sig":""#)?;
+            write_hex(out, &self.sig)?;
         }
-        br#"]}"#.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
+        out.push_slice(br#"","tags":"#)?;
+        self.write_tags(out)?;
+        out.push_byte(b'}')
+    }
 
-        output
+    /// Serializes the note for sending to relay into a heap allocated buffer
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn serialize_to_vec(
+        &self,
+        msg_type: ClientMsgKinds,
+    ) -> Result<alloc::vec::Vec<u8>, errors::Error> {
+        let mut output = alloc::vec::Vec::new();
+        self.serialize_to_buf(msg_type, &mut output)?;
+        Ok(output)
+    }
+
+    /// Serializes the note for sending to relay into `buf`, returning the number of bytes written.
+    /// Use `ClientMsgKinds::Auth` for the response to an `AUTH` challenge.
+    /// Errors with `ContentOverflow` if `buf` is too small.
+    #[inline]
+    pub fn serialize_into(
+        &self,
+        msg_type: ClientMsgKinds,
+        buf: &mut [u8],
+    ) -> Result<usize, errors::Error> {
+        let mut out = SliceBuf::new(buf);
+        self.serialize_to_buf(msg_type, &mut out)?;
+        Ok(out.len())
     }
 
-    /// Serializes the note for sending to relay
+    /// Serializes the note for sending to relay into any [`OutBuf`].
+    /// Errors with `ContentOverflow` if `out` runs out of room.
     #[inline]
-    pub fn serialize_to_relay(self, msg_type: ClientMsgKinds) -> Vec<u8, 1000> {
+    pub fn serialize_to_buf<B: OutBuf>(
+        &self,
+        msg_type: ClientMsgKinds,
+        out: &mut B,
+    ) -> Result<(), errors::Error> {
         let wire_lead = match msg_type {
             ClientMsgKinds::Event => r#"["EVENT","#,
             ClientMsgKinds::Req => r#"["REQ","#,
             ClientMsgKinds::Auth => r#"["AUTH","#,
             ClientMsgKinds::Close => r#"["CLOSE","#,
         };
-        let mut output: Vec<u8, 1000> = Vec::new();
-        // fill in output
-        wire_lead.as_bytes().iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        let json = self.to_json();
-        json.iter().for_each(|bs| {
-            output
-                .push(*bs)
-                .expect("Impossible due to size constraints of content, tags");
-        });
-        output
-            .push(93)
-            .expect("Impossible due to size constraints of content, tags");
-        output
+        out.push_slice(wire_lead.as_bytes())?;
+        self.write_json(out)?;
+        out.push_byte(b']')
     }
 
     /// Get associated values with a given tag name.
-    /// Returns up to 5 instances for the searched for label.
+    /// Returns the values of every tag with the searched for label.
+    /// Errors with `TagNameTooLong` if `tag` is longer than any tag name can be.
     #[inline]
-    pub fn get_tag(&self, tag: &str) -> Result<Vec<Vec<&str, 5>, 5>, errors::Error> {
-        let mut search_tag: String<10> = String::from(tag);
-        search_tag
-            .push_str(",")
-            .map_err(|_| errors::Error::TagNameTooLong)?;
+    pub fn get_tag(&self, tag: &str) -> Result<Vec<Vec<&str, VALUES>, NTAGS>, errors::Error> {
+        if tag.len() > TAG_NAME_SIZE {
+            return Err(errors::Error::TagNameTooLong);
+        }
         Ok(self
             .tags
             .iter()
-            .filter(|my_tag| my_tag.starts_with(search_tag.as_str()))
-            // each tag will look like tag_name,val1,val2,etc...
-            .map(|tag| {
-                let mut splits = tag.split(",");
-                // remove tag_name from splits
-                splits.next();
-                splits.collect()
-            })
+            .filter(|my_tag| my_tag.name() == tag)
+            .map(|tag| tag.values().collect())
             .collect())
     }
 
-    /// Decode an encrypted DM
+    /// Tags of the note
+    pub fn tags(&self) -> &[Tag<TAG, VALUES>] {
+        &self.tags
+    }
+
+    /// Decode an encrypted DM, which can be up to `CONTENT` characters
     #[inline]
-    pub fn read_dm(&self, privkey: &str) -> Result<String<MAX_DM_SIZE>, errors::Error> {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf)
-            .map_err(|_| errors::Error::Secp256k1Error)?;
-        let key_pair: KeyPair = KeyPair::from_seckey_str(&sig_obj, privkey)
-            .map_err(|_| errors::Error::InvalidPrivkey)?;
-        let sk = key_pair.secret_key();
+    pub fn read_dm(&self, privkey: &str) -> Result<String<CONTENT>, errors::Error> {
+        let mut scratch = [[0_u8; CONTENT]; 2];
+        self.read_dm_in(privkey, scratch.as_flattened_mut())
+    }
+
+    /// Same as [`Note::read_dm`], but uses `scratch` for the decryption buffers instead of the stack.
+    /// `scratch` should be at least twice `CONTENT` bytes, [`buffer::SCRATCH_SIZE`] for the
+    /// default size.
+    #[inline]
+    pub fn read_dm_in(
+        &self,
+        privkey: &str,
+        scratch: &mut [u8],
+    ) -> Result<String<CONTENT>, errors::Error> {
+        let secret = curve::parse_secret(privkey)?;
+        self.read_dm_with_secret(&secret, &mut SoftAes256::default(), scratch)
+    }
+
+    /// Same as [`Note::read_dm`], running AES-256 on `aes`, such as a
+    /// [hardware accelerator](backend)
+    pub fn read_dm_with_cipher<A: Aes256Backend>(
+        &self,
+        privkey: &str,
+        aes: &mut A,
+    ) -> Result<String<CONTENT>, errors::Error> {
+        let secret = curve::parse_secret(privkey)?;
+        let mut scratch = [[0_u8; CONTENT]; 2];
+        self.read_dm_with_secret(&secret, aes, scratch.as_flattened_mut())
+    }
+
+    /// Decrypts a NIP-04 DM with an already parsed secret key
+    fn read_dm_with_secret<A: Aes256Backend>(
+        &self,
+        secret: &[u8; 32],
+        aes: &mut A,
+        scratch: &mut [u8],
+    ) -> Result<String<CONTENT>, errors::Error> {
         let pk_tag = self.get_tag("p")?;
         let pk_tag = *pk_tag
             .first()
@@ -652,15 +961,16 @@ impl Note {
             .first()
             .ok_or(errors::Error::MalformedContent)?;
         let mut msg = [0_u8; 32];
-        base16ct::lower::decode(&pk_tag, &mut msg).map_err(|_| errors::Error::EncodeError)?;
-        let pk = XOnlyPublicKey::from_slice(&msg).map_err(|_| errors::Error::InvalidPubkey)?;
-        nip04::decrypt(
-            &sk,
-            &pk,
+        base16ct::lower::decode(pk_tag, &mut msg).map_err(|_| errors::Error::EncodeError)?;
+        let pk = Curve::parse_pubkey(&msg)?;
+        nip04::decrypt_with(
+            aes,
+            &Curve::shared_x(secret, &pk)?,
             self.content
                 .as_ref()
                 .ok_or(errors::Error::MalformedContent)?
                 .as_str(),
+            scratch,
         )
     }
 }
@@ -668,33 +978,26 @@ impl Note {
 #[cfg(test)]
 mod tests {
     use super::*;
-    const PRIVKEY: &str = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
-
-    fn get_note() -> Note {
-        Note::new_builder(PRIVKEY)
-            .unwrap()
-            .content("esptest".into())
-            .build(1686880020, [0; 32])
-            .expect("infallible")
-    }
+    use crate::vectors::{text_note, PRIVKEY};
 
     #[test]
     fn test_note_with_tag() {
         let note = Note::new_builder(PRIVKEY)
             .unwrap()
             .content("esptest".into())
-            .add_tag("l,bitcoin".into())
+            .add_tag(Tag::try_from(["l", "bitcoin"]).unwrap())
+            .unwrap()
             .build(1686880020, [0; 32])
             .expect("infallible");
-        let test = note.serialize_to_relay(ClientMsgKinds::Event);
+        let test = note.serialize_to_relay(ClientMsgKinds::Event).unwrap();
         let expected = br#"["EVENT",{"content":"esptest","created_at":1686880020,"id":"f5a693c9a4add3739a4186c0422f925981f75cb1f7a0adfc48852e54973415a6","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"ff68b2c739f6d19df47c5ae5f150895e11876458afcf8bf169636e55c2b6cce1230d0c54ce9869b555b3395018c1efdad5b4c5a4afbc2748e1f8c3a34da787ec","tags":[["l","bitcoin"]]}]"#;
         assert_eq!(test, expected);
     }
 
     #[test]
     fn pubkey_test() {
-        let note = get_note();
-        let pubkey = note.pubkey;
+        let note = text_note();
+        let pubkey = note.pubkey();
         assert_eq!(
             pubkey,
             *b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"
@@ -703,8 +1006,8 @@ mod tests {
 
     #[test]
     fn id_test() {
-        let note = get_note();
-        let id = note.id;
+        let note = text_note();
+        let id = note.id();
         assert_eq!(
             id,
             *b"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"
@@ -713,14 +1016,14 @@ mod tests {
 
     #[test]
     fn timestamp_test() {
-        let note = get_note();
+        let note = text_note();
         let ts = note.timestamp_bytes();
         assert_eq!(ts, String::<10>::from("1686880020"));
     }
 
     #[test]
     fn hashstr_test() {
-        let note = get_note();
+        let note = text_note();
         let hash_correct = br#"[0,"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf",1686880020,1,[],"esptest"]"#;
         let (hashed, len) = note.to_hash_str();
         let hashed = &hashed[..len];
@@ -730,36 +1033,221 @@ mod tests {
     #[test]
     fn json_test() {
         let output =  br#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
-        let note = get_note();
+        let note = text_note();
         let msg = note.to_json();
         assert_eq!(&msg, output);
+
+        let mut buf = [0_u8; 400];
+        let len = note.json_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], output);
+        assert_eq!(
+            note.json_into(&mut buf[..100]),
+            Err(errors::Error::ContentOverflow)
+        );
     }
 
     #[test]
     fn json_sig_invalid() {
-        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]"#;
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
         let note = Note::try_from(json);
         assert!(note.is_ok());
 
-        let json = r#"{"content":"esptest","created_at":1686880020,"id":"c515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]"#;
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"c515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
         let note = Note::try_from(json);
-        assert_eq!(note, Err(errors::Error::InvalidSignature))
+        // the id no longer matches the fields, caught before the signature is checked
+        assert_eq!(note, Err(errors::Error::EventNotValid));
+
+        let mut note = text_note();
+        note.id[0] ^= 0x70;
+        assert_eq!(note.verify_id(), Err(errors::Error::EventNotValid));
+        assert_eq!(
+            note.validate_signature(),
+            Err(errors::Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn json_unverified() {
+        let json = r#"{"content":"esptest!","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        let note: Note = Note::try_from_unverified(json).unwrap();
+        assert_eq!(note.content().unwrap(), "esptest!");
+        assert_eq!(note.validate_signature(), Ok(()));
+        assert_eq!(note.verify(), Err(errors::Error::EventNotValid));
+        assert_eq!(
+            Note::<400, 100, 5>::try_from_unverified("{}"),
+            Err(errors::Error::EventMissingField(errors::Field::Id))
+        );
+    }
+
+    #[test]
+    fn json_keys_inside_values() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content(r#"{"id":"00","kind":7,"tags":[["e"]]} and an \"escaped\" quote \"#.into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        assert_eq!(Note::try_from(json), Ok(note.clone()));
+
+        // unknown fields, one holding the fields of another note
+        let mut with_decoy: String<1200> = String::new();
+        with_decoy
+            .push_str(r#"{"decoy":{"content":"evil","id":"00","kind":7,"tags":[]},"#)
+            .unwrap();
+        with_decoy.push_str(&json[1..json.len() - 1]).unwrap();
+        with_decoy.push_str(r#","relay":"wss://\"a\"}"}"#).unwrap();
+        assert_eq!(Note::try_from(with_decoy.as_str()), Ok(note));
+    }
+
+    #[test]
+    fn json_from_bytes() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .add_tag(Tag::hashtag("esp").unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut json = note.to_json();
+        assert_eq!(Note::try_from(json.as_slice()), Ok(note));
+        let tag = json.windows(5).position(|w| w == br#""esp""#).unwrap();
+        json[tag + 1] = 0xff;
+        assert_eq!(
+            Note::try_from(json.as_slice()),
+            Err(errors::Error::Malformed {
+                field: errors::Field::Tags,
+                offset: json.windows(6).position(|w| w == br#""tags""#).unwrap()
+            })
+        );
+    }
+
+    #[test]
+    fn json_pretty_printed() {
+        let json = "{\n\t\"content\": \"esptest\",\r\n\t\"created_at\":\t1686880020,\n\t\"id\": \"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8\",\n\t\"kind\": 1,\n\t\"pubkey\": \"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf\",\n\t\"sig\": \"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab\",\n\t\"tags\": [\n\t]\n}\n";
+        assert_eq!(Note::try_from(json), Ok(text_note()));
+        // offsets count the whitespace before the key
+        let json = json.replace(": 1,", ": x1,");
+        assert_eq!(
+            Note::try_from(json.as_str()),
+            Err(errors::Error::Malformed {
+                field: errors::Field::Kind,
+                offset: json.find(r#""kind""#).unwrap()
+            })
+        );
+    }
+
+    #[test]
+    fn json_id_mismatch() {
+        // valid signature over the id, but the content was changed
+        let json = r#"{"content":"esptest!","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(Note::try_from(json), Err(errors::Error::EventNotValid));
+        let note = text_note();
+        assert_eq!(note.verify_id(), Ok(()));
+        assert_eq!(note.verify(), Ok(()));
+    }
+
+    #[test]
+    fn json_created_at_past_u32() {
+        let created_at = u64::from(u32::MAX) + 1;
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(created_at, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        assert!(json.contains(r#""created_at":4294967296,"#));
+        let parsed: Note = Note::try_from(json).unwrap();
+        assert_eq!(parsed.created_at(), created_at);
+        assert_eq!(parsed, note);
+        assert_eq!(
+            note_ref::NoteRef::try_from(json).unwrap().created_at(),
+            created_at
+        );
+
+        let mut stored = [0_u8; binary::MAX_BINARY_SIZE];
+        let len = note.to_binary(&mut stored).unwrap();
+        assert_eq!(Note::from_binary(&stored[..len]), Ok(note));
+    }
+
+    #[test]
+    fn malformed_fields_dont_panic() {
+        // ids shorter than 64 characters are rejected by HexId
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(Note::try_from(json), Err(errors::Error::EventNotValid));
+        // longer ones don't fit
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e800","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(Note::try_from(json), Err(errors::Error::EventNotValid));
+        // ids must be hex
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"x515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(
+            Note::try_from_unverified(json),
+            Err::<Note, _>(errors::Error::EventNotValid)
+        );
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098e","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(
+            Note::try_from_unverified(json),
+            Err::<Note, _>(errors::Error::InvalidPubkey)
+        );
+        // keys with no room for their value between them
+        let json = r#"{"content":"id":"kind":1"pubkey":"sig":"created_at":"tags":[]}"#;
+        assert_eq!(
+            Note::try_from_unverified(json),
+            Err::<Note, _>(errors::Error::Malformed {
+                field: errors::Field::Content,
+                offset: 1
+            })
+        );
+        // values are read as their keys are, before checking every field is there
+        let json = r#"{ "content": "esptest", "created_at": 1686880020, "kind": x1, "tags": [] }"#;
+        assert_eq!(
+            Note::try_from_unverified(json),
+            Err::<Note, _>(errors::Error::Malformed {
+                field: errors::Field::Kind,
+                offset: json.find(r#""kind""#).unwrap()
+            })
+        );
+        let json = r#"{ "content": "esptest", "created_at": 1686880020, "kind": x1, "id": "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8", "pubkey": "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf", "sig": "89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab", "tags": [] }"#;
+        assert_eq!(
+            Note::try_from_unverified(json),
+            Err::<Note, _>(errors::Error::Malformed {
+                field: errors::Field::Kind,
+                offset: json.find(r#""kind""#).unwrap()
+            })
+        );
+
+        let mut note = text_note();
+        note.pubkey = [0xff; 32];
+        assert_eq!(note.validate_signature(), Err(errors::Error::InvalidPubkey));
+        let mut note = text_note();
+        note.sig = [0xff; 64];
+        assert_eq!(
+            note.validate_signature(),
+            Err(errors::Error::InvalidSignature)
+        );
     }
 
     #[test]
     fn serialize_to_relay_test() {
         let output =  br#"["EVENT",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
-        let note = get_note();
-        let msg = note.serialize_to_relay(ClientMsgKinds::Event);
+        let note = text_note();
+        let msg = note.serialize_to_relay(ClientMsgKinds::Event).unwrap();
         assert_eq!(&msg, output);
     }
 
     #[test]
     fn test_from_json() {
-        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]"#;
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
         let note = Note::try_from(json).expect("infallible");
-        let expected_note = get_note();
+        let expected_note = text_note();
         assert_eq!(note, expected_note);
+        // ids and pubkeys of either case are written back lowercase
+        let json = r#"{"content":"esptest","created_at":1686880020,"id":"B515DA91AC5DF638FAE0A6E658E03ACC1DDA6152DD2107D02D5702CCFCF927E8","kind":1,"pubkey":"098EF66BCE60DD4CF10B4AE5949D1EC6DD777DDEB4BC49B47F97275A127A63CF","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        let note = Note::try_from(json).unwrap();
+        assert_eq!(note, expected_note);
+        assert_eq!(note.hex_id().to_hex(), note.id());
+        assert_eq!(note.hex_pubkey().as_bytes(), note.pubkey_bytes());
     }
 
     #[test]
@@ -778,34 +1266,160 @@ mod tests {
     #[test]
     fn test_get_tag() {
         let mut tags = Vec::new();
-        tags.push(String::from("p,test_pubkey")).unwrap();
-        let note = Note {
-            id: [0; 64],
-            pubkey: [0; 64],
+        tags.push(Tag::try_from(["p", "test_pubkey"]).unwrap())
+            .unwrap();
+        let note: Note = Note {
+            id: [0; 32],
+            pubkey: [0; 32],
             created_at: 0,
             kind: NoteKinds::DM,
             tags,
             content: None,
-            sig: [0; 128],
+            sig: [0; 64],
         };
         let tags = note.get_tag("p").unwrap();
         let pubkey = tags.first().unwrap().first().unwrap();
         assert_eq!(*pubkey, "test_pubkey");
     }
 
+    #[test]
+    fn test_tags_round_trip() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tags([
+                Tag::try_from(["e", "b515da91", "wss://r.example", "reply", "098ef66b"]).unwrap(),
+                Tag::empty(),
+                Tag::try_from(["t"]).unwrap(),
+                Tag::try_from(["t", r#"a "quoted", [tag]"#, ""]).unwrap(),
+            ])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        assert!(json.contains(
+            r#""tags":[["e","b515da91","wss://r.example","reply","098ef66b"],[],["t"],"#
+        ));
+        // parsing checks the id, which covers every tag as written
+        let parsed: Note = Note::try_from(json).unwrap();
+        assert_eq!(parsed, note);
+        assert_eq!(&parsed.to_json(), &note.to_json());
+
+        const UNSIGNED: &str = r#"{"content":"","created_at":1,"kind":1,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","tags":"#;
+        let parse_tags = |tags: &str| {
+            let mut json: String<600> = String::new();
+            json.push_str(UNSIGNED).unwrap();
+            json.push_str(tags).unwrap();
+            json.push('}').unwrap();
+            Note::<16, 16, 4>::from_unsigned_json(&json).map(|_| ())
+        };
+        let malformed = Err(errors::Error::Malformed {
+            field: errors::Field::Tags,
+            offset: UNSIGNED.len() - r#""tags":"#.len(),
+        });
+        // elements of a tag are strings, and tags are arrays
+        assert_eq!(parse_tags(r#"[["t",1]]"#), malformed);
+        assert_eq!(parse_tags(r#"[["t",["x"]]]"#), malformed);
+        assert_eq!(parse_tags(r#"["t"]"#), malformed);
+        assert_eq!(parse_tags(r#"[["t"]"#), malformed);
+        // a short name with a bad escape is malformed, only a long one is too long
+        assert_eq!(parse_tags(r#"[["\ud800x","1"]]"#), malformed);
+        assert_eq!(parse_tags(r#"[["\uzzzz"]]"#), malformed);
+        assert_eq!(
+            parse_tags(r#"[["abcdefghijklmnopq","1"]]"#),
+            Err(errors::Error::TagNameTooLong)
+        );
+        assert_eq!(
+            parse_tags(r#"[["t","1","2","3","4","5"]]"#),
+            Err(errors::Error::ContentOverflow)
+        );
+        // a note picking a larger VALUES holds it, and still verifies
+        let note = NoteBuilder::<16, 16, 4, 5>::new(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "1", "2", "3", "4", "5"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        let parsed = Note::<16, 16, 4, 5>::from_json(core::str::from_utf8(&json).unwrap());
+        assert_eq!(parsed.unwrap().tags()[0].value(4), Some("5"));
+        let long = NoteBuilder::<16, 16, 2, 8>::new(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "1", "2", "3", "4", "5", "6"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(
+            long.get_tag("t").unwrap()[0],
+            ["1", "2", "3", "4", "5", "6"]
+        );
+        assert_eq!(
+            Note::<16, 16, 4>::from_json(core::str::from_utf8(&json).unwrap()),
+            Err(errors::Error::ContentOverflow)
+        );
+    }
+
+    #[test]
+    fn test_tag_capacity() {
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut builder = NoteBuilder::<64, 8, 8>::new(PRIVKEY).unwrap();
+        for name in names {
+            builder = builder
+                .add_tag(Tag::try_from(["t", name]).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            builder.add_tag(Tag::try_from(["t", "i"]).unwrap()).err(),
+            Some(errors::Error::TooManyTags)
+        );
+
+        let mut builder = NoteBuilder::<64, 8, 8>::new(PRIVKEY).unwrap();
+        for name in names {
+            builder = builder
+                .add_tag(Tag::try_from(["t", name]).unwrap())
+                .unwrap();
+        }
+        let note = builder.build(1686880020, [0; 32]).unwrap();
+        assert_eq!(note.tags().len(), 8);
+        assert!(note.verify_id().is_ok());
+    }
+
+    #[test]
+    fn test_add_tags() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tags([Tag::hashtag("esp32").unwrap(), Tag::hashtag("iot").unwrap()])
+            .unwrap()
+            .add_tags(core::iter::once(Tag::custom("l", &["kitchen"]).unwrap()))
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let names: Vec<&str, 5> = note.tags().iter().filter_map(|tag| tag.value(0)).collect();
+        assert_eq!(names, ["esp32", "iot", "kitchen"]);
+        assert_eq!(
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .add_tags((0..6).map(|_| Tag::hashtag("esp32").unwrap()))
+                .err(),
+            Some(errors::Error::TooManyTags)
+        );
+    }
+
     #[test]
     fn test_get_two_tags() {
         let mut tags = Vec::new();
-        tags.push(String::from("l,labeled,another label")).unwrap();
-        tags.push(String::from("l,ignore the other label")).unwrap();
-        let note = Note {
-            id: [0; 64],
-            pubkey: [0; 64],
+        tags.push(Tag::try_from(["l", "labeled", "another label"]).unwrap())
+            .unwrap();
+        tags.push(Tag::try_from(["l", "ignore the other label"]).unwrap())
+            .unwrap();
+        let note: Note = Note {
+            id: [0; 32],
+            pubkey: [0; 32],
             created_at: 0,
             kind: NoteKinds::DM,
             tags,
             content: None,
-            sig: [0; 128],
+            sig: [0; 64],
         };
         let binding = note.get_tag("l").unwrap();
         let mut tags = binding.iter();
@@ -819,6 +1433,297 @@ mod tests {
         assert_eq!(*labels.next().unwrap(), "ignore the other label");
     }
 
+    #[test]
+    fn test_max_wire_size() {
+        let mut tags = Vec::new();
+        // quotes are escaped to two bytes each
+        let name = [b'"'; TAG_NAME_SIZE];
+        let value = [b'"'; TAG_VALUE_SIZE];
+        for _ in 0..5 {
+            let mut tag = Tag::new(core::str::from_utf8(&name).unwrap()).unwrap();
+            for _ in 0..MAX_TAG_VALUES {
+                tag.push_value(core::str::from_utf8(&value).unwrap())
+                    .unwrap();
+            }
+            tags.push(tag).unwrap();
+        }
+        let mut content: String<NOTE_SIZE> = String::new();
+        (0..NOTE_SIZE).for_each(|_| content.push('"').unwrap());
+        let note: Note = Note {
+            id: [0xaa; 32],
+            pubkey: [0xaa; 32],
+            created_at: u64::MAX,
+            kind: NoteKinds::Custom(u32::MAX),
+            tags,
+            content: Some(content),
+            sig: [0xaa; 64],
+        };
+        let msg = note.serialize_to_relay(ClientMsgKinds::Event).unwrap();
+        assert_eq!(msg.len(), MAX_NOTE_WIRE_SIZE);
+    }
+
+    #[test]
+    fn test_custom_sizes() {
+        let note = NoteBuilder::<64, 32, 2>::new(PRIVKEY)
+            .unwrap()
+            .content("21.5".into())
+            .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["l", "kitchen"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("21.5".into())
+            .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["l", "kitchen"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        // sizes only change the buffers, not the signed event
+        assert_eq!(note.id(), expected.id());
+        assert_eq!(note.sig(), expected.sig());
+
+        let json = expected.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        let parsed = Note::<64, 32, 2>::from_json(json).unwrap();
+        assert_eq!(parsed, note);
+    }
+
+    #[test]
+    fn test_large_dm() {
+        const RCVR_PRIVKEY: &str =
+            "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+        const RCVR_PUBKEY: &str =
+            "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+        let text = [b'a'; 600];
+        let text = core::str::from_utf8(&text).unwrap();
+        assert_eq!(
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .create_dm(text, RCVR_PUBKEY, [1; 16])
+                .err(),
+            Some(errors::Error::ContentOverflow)
+        );
+
+        let note = NoteBuilder::<1000, 64, 1>::new(PRIVKEY)
+            .unwrap()
+            .create_dm(text, RCVR_PUBKEY, [1; 16])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.read_dm(PRIVKEY).unwrap(), text);
+        let sender = note.pubkey();
+        let sender = core::str::from_utf8(&sender).unwrap();
+        let session = dm::DmSession::from_privkey(RCVR_PRIVKEY, sender).unwrap();
+        assert_eq!(note.read_dm_with_session(&session).unwrap(), text);
+
+        let note = NoteBuilder::<1000, 64, 1>::new(PRIVKEY)
+            .unwrap()
+            .create_dm_v2(text, RCVR_PUBKEY, [1; 32])
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.read_dm_v2(RCVR_PRIVKEY).unwrap(), text);
+    }
+
+    #[test]
+    fn test_custom_size_limits() {
+        let note = text_note();
+        let json = note.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        assert_eq!(
+            Note::<4, 32, 2>::from_json(json),
+            Err(errors::Error::ContentOverflow)
+        );
+        let tagged = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "a"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "b"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = tagged.to_json();
+        let json = core::str::from_utf8(&json).unwrap();
+        assert_eq!(
+            Note::<64, 32, 1>::from_json(json),
+            Err(errors::Error::TooManyTags)
+        );
+    }
+
+    #[test]
+    fn test_escaped_round_trip() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("he said \"hi\"\n\tand left \\o/".into())
+            .add_tag(Tag::try_from(["subject", "a \"quoted\" subject"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        assert!(core::str::from_utf8(&json)
+            .unwrap()
+            .starts_with(r#"{"content":"he said \"hi\"\n\tand left \\o/","#));
+        let parsed = Note::try_from(core::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(parsed, note);
+    }
+
+    #[test]
+    fn test_tag_with_comma_round_trips() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["content-warning", "spoilers, season 2"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["r", "wss://relay.example.com/?a=1,b=2", "read"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let json = note.to_json();
+        let parsed = Note::try_from(core::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(
+            parsed.get_tag("content-warning").unwrap()[0][0],
+            "spoilers, season 2"
+        );
+        assert_eq!(parsed, note);
+    }
+
+    #[test]
+    fn test_get_long_tag_names() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["expiration", "1686880020"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["content-warning", "spoilers"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["content", "not a prefix match"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.get_tag("expiration").unwrap()[0][0], "1686880020");
+        let warnings = note.get_tag("content-warning").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0][0], "spoilers");
+        assert_eq!(note.get_tag("content").unwrap().len(), 1);
+        assert!(note.get_tag("description").unwrap().is_empty());
+        let too_long = [b'a'; TAG_NAME_SIZE + 1];
+        assert_eq!(
+            note.get_tag(core::str::from_utf8(&too_long).unwrap()),
+            Err(errors::Error::TagNameTooLong)
+        );
+    }
+
+    /// Counts up from a seed, or fails every call
+    #[cfg(feature = "rand_core")]
+    struct TestRng(Option<u8>);
+
+    #[cfg(feature = "rand_core")]
+    impl rand_core::RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).unwrap()
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            let next = self.0.as_mut().ok_or(rand_core::Error::from(
+                core::num::NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap(),
+            ))?;
+            dest.iter_mut().for_each(|b| {
+                *b = *next;
+                *next = next.wrapping_add(1);
+            });
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "rand_core")]
+    impl rand_core::CryptoRng for TestRng {}
+
+    #[cfg(feature = "rand_core")]
+    #[test]
+    fn test_with_rng() {
+        let mut rng = TestRng(Some(1));
+        let first = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_with_rng(1686880020, &mut rng)
+            .unwrap();
+        let second = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_with_rng(1686880020, &mut rng)
+            .unwrap();
+        assert_eq!(first.id, second.id);
+        assert_ne!(first.sig, second.sig);
+        assert!(first.validate_signature().is_ok());
+        assert!(second.validate_signature().is_ok());
+
+        let dm = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_dm_with_rng(
+                "hello",
+                "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0",
+                &mut rng,
+            )
+            .unwrap()
+            .build_with_rng(1686880020, &mut rng)
+            .unwrap();
+        assert_eq!(dm.read_dm(PRIVKEY).unwrap(), "hello");
+
+        let mut failing = TestRng(None);
+        assert_eq!(
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .build_with_rng(1686880020, &mut failing),
+            Err(errors::Error::RngError)
+        );
+        assert_eq!(
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .create_dm_with_rng(
+                    "hello",
+                    "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0",
+                    &mut failing
+                )
+                .err(),
+            Some(errors::Error::RngError)
+        );
+    }
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(NoteKinds::from(3_u32), NoteKinds::ContactList);
+        assert!(matches!(NoteKinds::from(10002_u32), NoteKinds::RelayList));
+        assert!(matches!(NoteKinds::from(9735_u32), NoteKinds::ZapReceipt));
+        // the same value compares equal whichever variant holds it
+        assert_eq!(NoteKinds::Custom(7), NoteKinds::Reaction);
+        assert_eq!(NoteKinds::Replaceable(10002), NoteKinds::RelayList);
+        assert_ne!(NoteKinds::Custom(6), NoteKinds::Reaction);
+
+        assert!(NoteKinds::ShortNote.is_regular());
+        assert!(NoteKinds::Reaction.is_regular());
+        assert!(NoteKinds::ZapReceipt.is_regular());
+        assert!(!NoteKinds::Metadata.is_regular());
+        assert!(NoteKinds::Metadata.is_replaceable());
+        assert!(NoteKinds::ContactList.is_replaceable());
+        assert!(NoteKinds::RelayList.is_replaceable());
+        assert!(!NoteKinds::DM.is_replaceable());
+        assert!(NoteKinds::Auth.is_ephemeral());
+        assert!(!NoteKinds::Auth.is_regular());
+        assert!(NoteKinds::from(30078_u32).is_parameterized_replaceable());
+        assert!(!NoteKinds::RelayList.is_parameterized_replaceable());
+        assert!(!NoteKinds::Custom(40000).is_regular());
+    }
+
     #[test]
     fn test_auth_msg() {
         let note = Note::new_builder(PRIVKEY)
@@ -836,4 +1741,52 @@ mod tests {
         let expected = br#"{"content":"","created_at":1691712199,"id":"762b497576a41636c41eb5c74c0eb80894ecb2444c3e5117da0d00d9870d914a","kind":22242,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"afb892c683222936537ac1ea1ecdade47adf572e96773dfc6ca021d929d3485ecd7d086b14503e545312f61bd8ffdbd48887cd27b3ab2e4f70aab62a4a1afd1b","tags":[["challenge","challenge_me"],["relay","wss://relay.damus.io"]]}"#;
         assert_eq!(note.to_json(), expected);
     }
+
+    #[test]
+    fn test_build_large() {
+        // hashed form is longer than the old 1536 byte hash buffer
+        let content: String<4000> = core::iter::repeat_n('\n', 1000).collect();
+        let note = NoteBuilder::<4000>::new(PRIVKEY)
+            .unwrap()
+            .content(content)
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_serialize_into() {
+        let note = text_note();
+        let expected = text_note()
+            .serialize_to_relay(ClientMsgKinds::Auth)
+            .unwrap();
+        let mut frame = [0_u8; 1000];
+        let len = note
+            .serialize_into(ClientMsgKinds::Auth, &mut frame)
+            .unwrap();
+        assert_eq!(&frame[..len], expected.as_slice());
+        assert_eq!(
+            note.serialize_into(ClientMsgKinds::Auth, &mut frame[..100]),
+            Err(errors::Error::ContentOverflow)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_alloc_serialize_and_parse() {
+        let note = text_note();
+        let msg = note.serialize_to_vec(ClientMsgKinds::Event).unwrap();
+        assert_eq!(
+            msg.as_slice(),
+            note.serialize_to_relay(ClientMsgKinds::Event)
+                .unwrap()
+                .as_slice()
+        );
+
+        // padding makes the note longer than any serialized one, parsing has no size limit
+        let mut json = alloc::string::String::from(r#"{"content":"esptest","#);
+        json.push_str(&" ".repeat(MAX_NOTE_JSON_SIZE));
+        json.push_str(r#""created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#);
+        assert_eq!(Note::try_from(json.as_str()), Ok(text_note()));
+    }
 }
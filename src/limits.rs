@@ -0,0 +1,275 @@
+//! Relay limitations advertised in a relay information document ([NIP-11](https://github.com/nostr-protocol/nips/blob/master/11.md))
+//!
+//! A relay may refuse messages that are too long, events with too many tags or timestamps
+//! outside of a window around its own clock. Parse the `limitation` object of the relay's
+//! information document once, then serialize through [`Note::serialize_limited`] and
+//! [`Query::serialize_limited`](crate::query::Query::serialize_limited) to find out before sending.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{ClientMsgKinds, Note, Vec, errors::Error, limits::RelayLimits};
+//! let info = r#"{"name":"relay","limitation":{"max_message_length":200,"max_event_tags":2}}"#;
+//! let limits = RelayLimits::try_from(info).unwrap();
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut frame: Vec<u8, 1000> = Vec::new();
+//! let result = note.serialize_limited(ClientMsgKinds::Event, &limits, 1686880020, &mut frame);
+//! // the serialized note is longer than the 200 bytes the relay accepts
+//! assert_eq!(result, Err(Error::MessageTooLong));
+//! ```
+
+use crate::{
+    buffer::OutBuf,
+    errors::Error,
+    note_ref::{skip_value_bytes, split_number_bytes, split_object_bytes},
+    ClientMsgKinds, Note,
+};
+
+/// Limits a relay places on the messages it accepts.
+/// `None` means the relay did not advertise that limit.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct RelayLimits {
+    /// Longest websocket message, in bytes, the relay accepts
+    pub max_message_length: Option<u32>,
+    /// Longest subscription id the relay accepts
+    pub max_subid_length: Option<u32>,
+    /// Largest `limit` the relay honours in a filter
+    pub max_limit: Option<u32>,
+    /// Most tags an event may have
+    pub max_event_tags: Option<u32>,
+    /// Longest `content` an event may have, in characters
+    pub max_content_length: Option<u32>,
+    /// How many seconds in the past an event's `created_at` may be
+    pub created_at_lower_limit: Option<u32>,
+    /// How many seconds in the future an event's `created_at` may be
+    pub created_at_upper_limit: Option<u32>,
+}
+
+/// Problems that don't stop a message from being sent but may get it rejected
+#[derive(Debug, PartialEq)]
+pub enum LimitWarning {
+    /// `created_at` is further in the past than the relay accepts
+    CreatedAtTooOld,
+    /// `created_at` is further in the future than the relay accepts
+    CreatedAtTooNew,
+}
+
+/// True if `len` is over `limit`
+pub(crate) fn exceeds(len: usize, limit: Option<u32>) -> bool {
+    limit.is_some_and(|limit| len > limit as usize)
+}
+
+impl TryFrom<&str> for RelayLimits {
+    type Error = Error;
+
+    /// Reads the `limitation` object of a relay information document.
+    /// Only keys of the `limitation` object itself are read, and the first of repeated keys is
+    /// kept. Limits that aren't unsigned numbers are left `None`, as are all of them if the
+    /// document has no `limitation`.
+    /// Errors with `MalformedContent` if the document isn't a JSON object.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut limits = RelayLimits::default();
+        let (_, rest) = split_object_bytes(value.as_bytes().trim_ascii_start(), |key, value| {
+            if key != b"limitation" {
+                return skip_value_bytes(value);
+            }
+            split_object_bytes(value, |key, value| {
+                let limit = match key {
+                    b"max_message_length" => &mut limits.max_message_length,
+                    b"max_subid_length" => &mut limits.max_subid_length,
+                    b"max_limit" => &mut limits.max_limit,
+                    b"max_event_tags" => &mut limits.max_event_tags,
+                    b"max_content_length" => &mut limits.max_content_length,
+                    b"created_at_lower_limit" => &mut limits.created_at_lower_limit,
+                    b"created_at_upper_limit" => &mut limits.created_at_upper_limit,
+                    _ => return skip_value_bytes(value),
+                };
+                if !value.first().is_some_and(u8::is_ascii_digit) {
+                    return skip_value_bytes(value);
+                }
+                let (number, rest) = split_number_bytes(value)?;
+                limit.get_or_insert(number);
+                Ok(rest)
+            })
+            .map(|(_, rest)| rest)
+        })?;
+        if !rest.trim_ascii_start().is_empty() {
+            return Err(Error::MalformedContent);
+        }
+        Ok(limits)
+    }
+}
+
+impl RelayLimits {
+    /// Checks `note` against the limits that don't depend on serialization.
+    /// - `now` is the current unix time, used for the `created_at` window
+    ///
+    /// Errors with `TooManyTags` or `ContentOverflow` if the relay will refuse the note.
+    pub fn check_note<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+        now: u64,
+    ) -> Result<Option<LimitWarning>, Error> {
+        if exceeds(note.tags.len(), self.max_event_tags) {
+            return Err(Error::TooManyTags);
+        }
+        let content_len = note.content.as_ref().map_or(0, |c| c.chars().count());
+        if exceeds(content_len, self.max_content_length) {
+            return Err(Error::ContentOverflow);
+        }
+        if let Some(lower) = self.created_at_lower_limit {
+            if note.created_at < now.saturating_sub(lower.into()) {
+                return Ok(Some(LimitWarning::CreatedAtTooOld));
+            }
+        }
+        if let Some(upper) = self.created_at_upper_limit {
+            if note.created_at > now.saturating_add(upper.into()) {
+                return Ok(Some(LimitWarning::CreatedAtTooNew));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Serializes the note for sending to a relay with the given `limits`.
+    /// - `now` is the current unix time, used to check `created_at` against the relay's window
+    ///
+    /// Errors with `MessageTooLong` if the frame is longer than the relay accepts, in which case
+    /// `out` may hold part of it. A `created_at` outside of the relay's window is returned as a warning.
+    pub fn serialize_limited<B: OutBuf>(
+        &self,
+        msg_type: ClientMsgKinds,
+        limits: &RelayLimits,
+        now: u64,
+        out: &mut B,
+    ) -> Result<Option<LimitWarning>, Error> {
+        let warning = limits.check_note(self, now)?;
+        let start = out.len();
+        self.serialize_to_buf(msg_type, out)?;
+        if exceeds(out.len() - start, limits.max_message_length) {
+            return Err(Error::MessageTooLong);
+        }
+        Ok(warning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{query::Query, tag::Tag, vectors::PRIVKEY};
+    use heapless::Vec;
+    const INFO_DOC: &str = r#"{
+        "name": "relay.example.com",
+        "supported_nips": [1, 11],
+        "limitation": {
+            "max_message_length": 16384,
+            "max_subscriptions": 20,
+            "max_limit": 100,
+            "max_subid_length": 10,
+            "max_event_tags": 1,
+            "max_content_length": 8196,
+            "created_at_lower_limit": 3600,
+            "created_at_upper_limit": 60,
+            "auth_required": false
+        }
+    }"#;
+
+    #[test]
+    fn test_parse() {
+        let limits = RelayLimits::try_from(INFO_DOC).unwrap();
+        assert_eq!(limits.max_message_length, Some(16384));
+        assert_eq!(limits.max_limit, Some(100));
+        assert_eq!(limits.max_subid_length, Some(10));
+        assert_eq!(limits.created_at_upper_limit, Some(60));
+        assert_eq!(
+            RelayLimits::try_from(r#"{"name":"relay"}"#),
+            Ok(RelayLimits::default())
+        );
+    }
+
+    #[test]
+    fn test_only_limitation_keys() {
+        // keys in strings, in other objects or next to the limitation aren't limits
+        let info = r#"{
+            "description": "\"limitation\": {\"max_limit\": 1}",
+            "max_limit": 2,
+            "retention": [{"kinds": [0], "max_limit": 3}],
+            "limitation": {"payment": {"max_limit": 4}, "max_limit": 5, "max_event_tags": null}
+        }"#;
+        let limits = RelayLimits::try_from(info).unwrap();
+        assert_eq!(limits.max_limit, Some(5));
+        assert_eq!(limits.max_event_tags, None);
+        assert_eq!(
+            RelayLimits::try_from(r#"{"limitation":{"max_limit":5}"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            RelayLimits::try_from(r#"{"limitation":[]}"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            RelayLimits::try_from(r#"{"limitation":{}} x"#),
+            Err(Error::MalformedContent)
+        );
+    }
+
+    #[test]
+    fn test_note_limits() {
+        let limits = RelayLimits::try_from(INFO_DOC).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut frame: Vec<u8, 1000> = Vec::new();
+        let result = note.serialize_limited(ClientMsgKinds::Event, &limits, 1686880020, &mut frame);
+        assert_eq!(result, Ok(None));
+        assert_eq!(
+            limits.check_note(&note, 1686880020 + 7200),
+            Ok(Some(LimitWarning::CreatedAtTooOld))
+        );
+        assert_eq!(
+            limits.check_note(&note, 1686880020 - 120),
+            Ok(Some(LimitWarning::CreatedAtTooNew))
+        );
+
+        let tagged = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["l", "a"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["l", "b"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(
+            limits.check_note(&tagged, 1686880020),
+            Err(Error::TooManyTags)
+        );
+    }
+
+    #[test]
+    fn test_query_limits() {
+        let limits = RelayLimits::try_from(INFO_DOC).unwrap();
+        let mut query = Query::new();
+        query.limit = Some(500);
+        let mut msg: Vec<u8, 1000> = Vec::new();
+        query.serialize_limited("sub1", &limits, &mut msg).unwrap();
+        assert_eq!(msg.as_slice(), br#"["REQ","sub1",{"limit":100}]"#);
+        msg.clear();
+        assert_eq!(
+            query.serialize_limited("subscription_1", &limits, &mut msg),
+            Err(Error::MessageTooLong)
+        );
+    }
+}
@@ -1,38 +1,54 @@
 use core::str::FromStr;
 
-use aes::cipher::generic_array::GenericArray;
 use base64ct::{Base64, Encoding};
-use heapless::{String, Vec};
-use secp256k1::{ecdh, PublicKey, SecretKey, XOnlyPublicKey};
-
-// use aes::cipher::block_padding::Pkcs7;
-use aes::cipher::{ArrayLength, BlockDecryptMut, BlockEncryptMut, KeyIvInit, Unsigned};
-use aes::Aes256;
-use cbc::{Decryptor, Encryptor};
-
-type Aes256CbcEnc = Encryptor<Aes256>;
-type Aes256CbcDec = Decryptor<Aes256>;
+use heapless::String;
 
+use crate::backend::Aes256Backend;
+use crate::curve::{Curve, CurveBackend};
 use crate::errors::Error;
 use crate::MAX_DM_SIZE;
 
-/// heavily copied from rust-nostr
+/// Scratch space needed by `encrypt_in` and `decrypt_in` for DMs of up to [`MAX_DM_SIZE`]
+/// characters
+pub const DM_SCRATCH_SIZE: usize = 2 * MAX_DM_SIZE;
+
+// heavily copied from rust-nostr
 
-/// Encrypt
-pub fn encrypt(
-    sk: &SecretKey,
-    pk: &XOnlyPublicKey,
+/// Encrypt with AES-256 running on `aes` under the key from [`generate_shared_key`] into a
+/// content of up to `N` characters, using the stack for the ciphertext and its base64 encoding
+pub fn encrypt_sized<A: Aes256Backend, const N: usize>(
+    aes: &mut A,
+    key: &[u8; 32],
     text: &str,
     iv: [u8; 16],
-) -> Result<String<MAX_DM_SIZE>, Error> {
-    let key: [u8; 32] = generate_shared_key(sk, pk)?;
+) -> Result<String<N>, Error> {
+    let mut scratch = [[0_u8; N]; 2];
+    encrypt_with(aes, key, text, iv, scratch.as_flattened_mut())
+}
 
-    let mut cipher = Aes256CbcEnc::new(&key.into(), &iv.into());
-    let mut ciphertext = [16_u8; MAX_DM_SIZE];
+/// Same as [`encrypt_sized`], using `scratch` for the ciphertext and its base64 encoding.
+/// `scratch` must be at least `2 * N` bytes.
+pub fn encrypt_with<A: Aes256Backend, const N: usize>(
+    aes: &mut A,
+    key: &[u8; 32],
+    text: &str,
+    iv: [u8; 16],
+    scratch: &mut [u8],
+) -> Result<String<N>, Error> {
+    if scratch.len() < 2 * N {
+        return Err(Error::ScratchTooSmall);
+    }
+    let (ciphertext, enc_buf) = scratch.split_at_mut(N);
+    aes.set_key(key);
 
     // fill cipher text from slices of input
     let total_blocks = text.len() / 16 + 1;
+    if total_blocks * 16 > ciphertext.len() {
+        return Err(Error::ContentOverflow);
+    }
 
+    // CBC: each block is xored with the ciphertext of the one before, the first with the iv
+    let mut previous = iv;
     for i in 0..total_blocks {
         let end_slice = i * 16 + 16;
         let end_slice = if end_slice > text.len() {
@@ -40,152 +56,162 @@ pub fn encrypt(
         } else {
             end_slice
         };
-        let mut block = pad_block(&text[i * 16..end_slice].as_bytes(), 16);
-        cipher.encrypt_block_mut(&mut block);
-        block.iter().enumerate().for_each(|(j, b)| {
-            ciphertext[i * 16 + j] = *b;
-        });
+        let mut block = pad_block(&text.as_bytes()[i * 16..end_slice]);
+        xor_block(&mut block, &previous);
+        aes.encrypt_block(&mut block);
+        ciphertext[i * 16..i * 16 + 16].copy_from_slice(&block);
+        previous = block;
     }
 
     let encode_this = &ciphertext[0..total_blocks * 16];
-    let mut enc_buf = [0u8; MAX_DM_SIZE];
-    let encoded = Base64::encode(encode_this, &mut enc_buf).map_err(|_| Error::EncodeError)?;
+    let encoded = Base64::encode(encode_this, enc_buf).map_err(|_| Error::EncodeError)?;
 
     let mut enc_buf = [0u8; 32];
     let iv_str = Base64::encode(&iv, &mut enc_buf).map_err(|_| Error::EncodeError)?;
 
-    let mut output = String::from_str(&encoded).map_err(|_| Error::ContentOverflow)?;
+    let mut output = String::from_str(encoded).map_err(|_| Error::ContentOverflow)?;
     output
         .push_str("?iv=")
         .map_err(|_| Error::ContentOverflow)?;
     output
-        .push_str(&iv_str)
+        .push_str(iv_str)
         .map_err(|_| Error::ContentOverflow)?;
     Ok(output)
 }
 
-fn pad_block<B>(input: &[u8], block_size: usize) -> GenericArray<u8, B>
-where
-    B: ArrayLength<u8> + Unsigned,
-{
-    let input_len = input.len();
-    let padding_len = block_size - input_len;
-    let padding_byte = padding_len as u8;
-
-    let mut padded_input = GenericArray::default();
-    padded_input[..input_len].copy_from_slice(input);
+/// Pads the last, possibly partial, block with PKCS#7
+fn pad_block(input: &[u8]) -> [u8; 16] {
+    let padding_byte = (16 - input.len()) as u8;
+    let mut padded_input = [padding_byte; 16];
+    padded_input[..input.len()].copy_from_slice(input);
+    padded_input
+}
 
-    for i in input_len..(input_len + padding_len) {
-        padded_input[i] = padding_byte;
+/// Strips the PKCS#7 padding off the decrypted blocks.
+/// Errors with `InvalidPadding` unless it is 1 to 16 bytes that all hold its length.
+fn unpad(decrypted: &[u8]) -> Result<&[u8], Error> {
+    let pad_len = *decrypted.last().ok_or(Error::InvalidPadding)? as usize;
+    if pad_len == 0 || pad_len > 16 || pad_len > decrypted.len() {
+        return Err(Error::InvalidPadding);
+    }
+    let (text, padding) = decrypted.split_at(decrypted.len() - pad_len);
+    if padding.iter().any(|b| *b as usize != pad_len) {
+        return Err(Error::InvalidPadding);
     }
+    Ok(text)
+}
 
-    padded_input
+fn xor_block(block: &mut [u8; 16], with: &[u8; 16]) {
+    block.iter_mut().zip(with).for_each(|(b, w)| *b ^= w);
+}
+
+/// Decrypt with AES-256 running on `aes` under the key from [`generate_shared_key`] a content
+/// of up to `N` characters, using the stack for the decoded ciphertext
+pub fn decrypt_sized<A: Aes256Backend, const N: usize>(
+    aes: &mut A,
+    key: &[u8; 32],
+    encrypted_content: &str,
+) -> Result<String<N>, Error> {
+    let mut scratch = [[0_u8; N]; 2];
+    decrypt_with(aes, key, encrypted_content, scratch.as_flattened_mut())
 }
 
-/// Dectypt
-pub fn decrypt(
-    sk: &SecretKey,
-    pk: &XOnlyPublicKey,
+/// Same as [`decrypt_sized`], using `scratch` for the decoded ciphertext.
+/// `scratch` must be at least `2 * N` bytes.
+/// Errors with `MalformedContent` if the ciphertext isn't a whole number of blocks or the
+/// plaintext isn't UTF-8, and `InvalidPadding` if it decrypts to bad padding, such as when it
+/// was corrupted or encrypted under another key.
+pub fn decrypt_with<A: Aes256Backend, const N: usize>(
+    aes: &mut A,
+    key: &[u8; 32],
     encrypted_content: &str,
-) -> Result<String<MAX_DM_SIZE>, Error> {
-    let parsed_content: Vec<&str, 2> = encrypted_content.split("?iv=").collect();
-    if parsed_content.len() != 2 {
+    scratch: &mut [u8],
+) -> Result<String<N>, Error> {
+    if scratch.len() < 2 * N {
+        return Err(Error::ScratchTooSmall);
+    }
+    let (content, iv) = encrypted_content
+        .split_once("?iv=")
+        .ok_or(Error::MalformedContent)?;
+    if iv.contains("?iv=") {
         return Err(Error::MalformedContent);
     }
 
-    let mut decrypted_buf = [0_u8; MAX_DM_SIZE];
+    let (decrypted_buf, ciphertext) = scratch.split_at_mut(N);
 
     let encrypted_content =
-        Base64::decode(parsed_content[0], &mut decrypted_buf).map_err(|_| Error::EncodeError)?;
+        Base64::decode(content, decrypted_buf).map_err(|_| Error::EncodeError)?;
 
-    let mut decrypted_iv = [0_u8; MAX_DM_SIZE];
-    let iv =
-        Base64::decode(parsed_content[1], &mut decrypted_iv).map_err(|_| Error::EncodeError)?;
-    let key: [u8; 32] = generate_shared_key(sk, pk)?;
+    let mut decrypted_iv = [0_u8; 32];
+    let iv = Base64::decode(iv, &mut decrypted_iv).map_err(|_| Error::EncodeError)?;
+    let iv: [u8; 16] = iv.try_into().map_err(|_| Error::MalformedContent)?;
+    aes.set_key(key);
 
-    let mut cipher = Aes256CbcDec::new(&key.into(), iv.into());
-    let mut ciphertext = [16_u8; MAX_DM_SIZE];
-
-    // fill cipher text from slices of input
+    if encrypted_content.is_empty() || encrypted_content.len() % 16 != 0 {
+        return Err(Error::MalformedContent);
+    }
     let total_blocks = encrypted_content.len() / 16;
 
+    let mut previous = iv;
     for i in 0..total_blocks {
-        // check if on last block, look at last characters of block to calculate padding. save for later?
         let end_slice = i * 16 + 16;
-        let mut block = pad_block(&encrypted_content[i * 16..end_slice], 16);
-        cipher.decrypt_block_mut(&mut block);
-        block.iter().enumerate().for_each(|(j, b)| {
-            ciphertext[i * 16 + j] = *b;
-        });
-    }
-    let utf_8 = &ciphertext[0..total_blocks * 16];
-    let pad_digit = *utf_8.last().ok_or(Error::InternalError)? as usize;
-    let pad_digit = if pad_digit < 17 { pad_digit } else { 0 };
-    // println!("{:?}", utf_8);
-    // println!("{pad_digit}");
-    let utf_8 = &ciphertext[0..utf_8.len() - pad_digit];
+        let encrypted: [u8; 16] = encrypted_content[i * 16..end_slice]
+            .try_into()
+            .map_err(|_| Error::InternalError)?;
+        let mut block = encrypted;
+        aes.decrypt_block(&mut block);
+        xor_block(&mut block, &previous);
+        ciphertext[i * 16..end_slice].copy_from_slice(&block);
+        previous = encrypted;
+    }
+    let utf_8 = unpad(&ciphertext[0..total_blocks * 16])?;
 
+    let text = core::str::from_utf8(utf_8).map_err(|_| Error::MalformedContent)?;
     let mut output = String::new();
-
-    utf_8.iter().try_for_each(|b| {
-        output
-            .push(*b as char)
-            .map_err(|_| Error::ContentOverflow)?;
-        Ok(())
-    })?;
-
+    output.push_str(text).map_err(|_| Error::ContentOverflow)?;
     Ok(output)
 }
 
-/// Generate shared key
-fn generate_shared_key(sk: &SecretKey, pk: &XOnlyPublicKey) -> Result<[u8; 32], Error> {
-    let pk_normalized: PublicKey = normalize_schnorr_pk(pk)?;
-    let ssp = ecdh::shared_secret_point(&pk_normalized, sk);
-    let mut shared_key: [u8; 32] = [0u8; 32];
-    shared_key.copy_from_slice(&ssp[..32]);
-    Ok(shared_key)
-}
-
-/// Normalize Schnorr public key
-fn normalize_schnorr_pk(schnorr_pk: &XOnlyPublicKey) -> Result<PublicKey, Error> {
-    let mut pk: String<66> = String::from("02");
-    let mut bytes = [0_u8; 64];
-    base16ct::lower::encode(&schnorr_pk.serialize(), &mut bytes).map_err(|_| Error::EncodeError)?;
-    bytes.iter().try_for_each(|b| {
-        let c = char::from_u32(*b as u32).ok_or(Error::InternalError)?;
-        pk.push(c).map_err(|_| Error::InternalError)?;
-        Ok(())
-    })?;
-    Ok(PublicKey::from_str(&pk).map_err(|_| Error::InternalPubkeyError)?)
+/// Generate shared key from our secret key and the other side's x-only pubkey
+pub(crate) fn generate_shared_key(secret: &[u8; 32], pubkey: &[u8; 32]) -> Result<[u8; 32], Error> {
+    Curve::shared_x(secret, &Curve::parse_pubkey(pubkey)?)
 }
 
 #[cfg(test)]
 mod tests {
-    use secp256k1::{ffi::types::AlignedType, KeyPair};
-
-    use crate::Note;
+    use crate::{curve, Note};
 
     use super::*;
+    use crate::backend::SoftAes256;
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
     const _DM_RECV: &str = r#"{"content":"sZhES/uuV1uMmt9neb6OQw6mykdLYerAnTN+LodleSI=?iv=eM0mGFqFhxmmMwE4YPsQMQ==","created_at":1691110186,"id":"517a5f0f29f5037d763bbd5fbe96c9082c1d39eca917aa22b514c5effc36bab9","kind":4,"pubkey":"ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0","sig":"3097de7d5070b892b81b245a5b276eccd7cb283a29a934a71af4960188e55e87d639b774cc331eb9f94ea7c46373c52b8ab39bfee75fe4bb11a1dd4c187e1f3e","tags":[["p","098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"]]}"#;
     const _DM_SEND: &str = r#"{"content":"lPQ9iBd6abUrDBJbHWaL3qqhqsuAxK0aU80IgsZ2aqE=?iv=O1zZfD9HPiig1yuZEWX7uQ==","created_at":1691117390,"id":"c0be8c32d95f7599ccfe324711ad50890ee08985710997fcda1a1a3840a23d51","kind":4,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"8ee1e83ab037c9e9ff1ac97db88aa045b2f1d9204daa7fee25e5f42274ee8d5f4365b87677c4f27827ca043becc65c1f38f646d05adf3d2c570b66fea57e5918","tags":[["p","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"]]}"#;
-    const FROM_SKEY: &str = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
-    const MY_SKEY: &str = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
     const EXPCTD_MSG: &str = "hello from the internet";
 
+    /// Secret key and x-only pubkey
+    fn keys(privkey: &str) -> ([u8; 32], [u8; 32]) {
+        let key_pair = curve::keypair_from_hex(privkey).expect("test");
+        (Curve::secret_bytes(&key_pair), Curve::x_only(&key_pair))
+    }
+
     #[test]
     fn test_e2e() {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf).expect("test");
-        let key_pair = KeyPair::from_seckey_str(&sig_obj, FROM_SKEY).expect("test");
-        let pk = key_pair.x_only_public_key().0;
-
-        let my_sk = SecretKey::from_str(MY_SKEY).expect("test");
-        let encrypted = encrypt(&my_sk, &pk, EXPCTD_MSG, [0; 16]).expect("test");
+        let (from_sk, from_pk) = keys(OTHER_PRIVKEY);
+        let (my_sk, my_pk) = keys(PRIVKEY);
+        let encrypted: String<MAX_DM_SIZE> = encrypt_with(
+            &mut SoftAes256::default(),
+            &generate_shared_key(&my_sk, &from_pk).expect("test"),
+            EXPCTD_MSG,
+            [0; 16],
+            &mut [0; DM_SCRATCH_SIZE],
+        )
+        .expect("test");
 
-        let decrypted = decrypt(
-            &key_pair.secret_key(),
-            &my_sk.x_only_public_key(&sig_obj).0,
+        let decrypted: String<MAX_DM_SIZE> = decrypt_with(
+            &mut SoftAes256::default(),
+            &generate_shared_key(&from_sk, &my_pk).expect("test"),
             encrypted.as_str(),
+            &mut [0; DM_SCRATCH_SIZE],
         )
         .expect("test");
         assert_eq!(decrypted, "hello from the internet");
@@ -193,16 +219,14 @@ mod tests {
 
     #[test]
     fn test_decrypt() {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf).expect("test");
-        let key_pair = KeyPair::from_seckey_str(&sig_obj, FROM_SKEY).expect("test");
-
-        let my_sk = SecretKey::from_str(MY_SKEY).expect("test");
+        let (from_sk, _) = keys(OTHER_PRIVKEY);
+        let (_, my_pk) = keys(PRIVKEY);
 
-        let decrypted = decrypt(
-            &key_pair.secret_key(),
-            &my_sk.x_only_public_key(&sig_obj).0,
+        let decrypted: String<MAX_DM_SIZE> = decrypt_with(
+            &mut SoftAes256::default(),
+            &generate_shared_key(&from_sk, &my_pk).expect("test"),
             "sZhES/uuV1uMmt9neb6OQw6mykdLYerAnTN+LodleSI=?iv=eM0mGFqFhxmmMwE4YPsQMQ==",
+            &mut [0; DM_SCRATCH_SIZE],
         )
         .expect("test");
         assert_eq!(decrypted, "hello from the internet");
@@ -211,9 +235,92 @@ mod tests {
     #[test]
     fn test_rcvd_dm() {
         let note = Note::try_from(_DM_SEND).unwrap();
-        let msg = note
-            .read_dm("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
-            .unwrap();
+        let msg = note.read_dm(PRIVKEY).unwrap();
         assert_eq!(msg, String::<400>::from("hello from the internet"));
     }
+
+    #[test]
+    fn test_utf8() {
+        let key = [7; 32];
+        let mut aes = SoftAes256::default();
+        let text = "héllo wörld 🌍 ünïcode";
+        let encrypted: String<MAX_DM_SIZE> =
+            encrypt_sized(&mut aes, &key, text, [1; 16]).expect("test");
+        let decrypted: String<MAX_DM_SIZE> =
+            decrypt_sized(&mut aes, &key, &encrypted).expect("test");
+        assert_eq!(decrypted, text);
+
+        // a lone continuation byte, encrypted by hand with a zero iv
+        let mut block = pad_block(&[b'a', 0x80]);
+        aes.set_key(&key);
+        aes.encrypt_block(&mut block);
+        let mut enc_buf = [0_u8; 32];
+        let mut encrypted: String<MAX_DM_SIZE> = String::new();
+        encrypted
+            .push_str(Base64::encode(&block, &mut enc_buf).expect("test"))
+            .expect("test");
+        encrypted.push_str("?iv=").expect("test");
+        encrypted
+            .push_str(Base64::encode(&[0; 16], &mut enc_buf).expect("test"))
+            .expect("test");
+        assert_eq!(
+            decrypt_sized::<_, MAX_DM_SIZE>(&mut aes, &key, &encrypted),
+            Err(Error::MalformedContent)
+        );
+    }
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(unpad(&[b'a', 3, 3, 3]), Ok(&b"a"[..]));
+        assert_eq!(unpad(&[16; 16]), Ok(&b""[..]));
+        assert_eq!(unpad(&[b'a', 2, 3, 3]), Err(Error::InvalidPadding));
+        assert_eq!(unpad(&[b'a', 0]), Err(Error::InvalidPadding));
+        assert_eq!(unpad(&[17; 32]), Err(Error::InvalidPadding));
+        assert_eq!(unpad(&[3, 3]), Err(Error::InvalidPadding));
+        assert_eq!(unpad(&[]), Err(Error::InvalidPadding));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let (from_sk, _) = keys(OTHER_PRIVKEY);
+        let (_, my_pk) = keys(PRIVKEY);
+        let key = generate_shared_key(&from_sk, &my_pk).expect("test");
+        let mut aes = SoftAes256::default();
+        let decrypt = |content: &str, aes: &mut SoftAes256| {
+            decrypt_sized::<_, MAX_DM_SIZE>(aes, &key, content)
+        };
+
+        // the iv is xored into the first block, so changing its last byte changes the padding
+        // of a single block message
+        let encrypted: String<MAX_DM_SIZE> =
+            encrypt_sized(&mut aes, &key, "hello", [0; 16]).expect("test");
+        assert_eq!(decrypt(&encrypted, &mut aes).expect("test"), "hello");
+        let mut iv = [0_u8; 16];
+        iv[15] = 1;
+        let mut enc_buf = [0_u8; 32];
+        let iv = Base64::encode(&iv, &mut enc_buf).expect("test");
+        let mut corrupted: String<MAX_DM_SIZE> = String::new();
+        corrupted
+            .push_str(encrypted.split("?iv=").next().expect("test"))
+            .expect("test");
+        corrupted.push_str("?iv=").expect("test");
+        corrupted.push_str(iv).expect("test");
+        assert_eq!(decrypt(&corrupted, &mut aes), Err(Error::InvalidPadding));
+
+        // 15 bytes of ciphertext
+        assert_eq!(
+            decrypt("AAAAAAAAAAAAAAAAAAAA?iv=AAAAAAAAAAAAAAAAAAAAAA==", &mut aes),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            decrypt("?iv=AAAAAAAAAAAAAAAAAAAAAA==", &mut aes),
+            Err(Error::MalformedContent)
+        );
+        // a second iv marker doesn't panic
+        assert_eq!(
+            decrypt("AAAA?iv=BBBB?iv=CCCC", &mut aes),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(decrypt("AAAA", &mut aes), Err(Error::MalformedContent));
+    }
 }
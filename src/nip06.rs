@@ -0,0 +1,147 @@
+//! Key derivation from a seed per [NIP-06](https://github.com/nostr-protocol/nips/blob/master/06.md)
+//!
+//! Keys are derived with BIP32 along `m/44'/1237'/<account>'/0/0`, so one backed up seed can
+//! provision any number of device identities: give each device (or each sensor on a device) its
+//! own `account` index.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{nip06, Note};
+//! let seed = nip06::seed_from_mnemonic(
+//!     "leader monkey parrot ring guide accident before fence cannon height naive bean",
+//!     "",
+//! );
+//! let privkey = nip06::derive_privkey(&seed).unwrap();
+//! assert_eq!(privkey, "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a");
+//! let sensor_2 = nip06::derive_account(&seed, 2).unwrap();
+//! let note = Note::new_builder(&sensor_2).unwrap().build(1686880020, [0; 32]).unwrap();
+//! ```
+
+use heapless::String;
+use sha2::{Digest, Sha512};
+
+use crate::{
+    curve::{AlignedType, Curve, CurveBackend, CONTEXT_ITEMS},
+    errors::Error,
+};
+
+/// Coin type registered for nostr in SLIP-44
+const NOSTR_COIN_TYPE: u32 = 1237;
+const HARDENED: u32 = 0x8000_0000;
+const PBKDF2_ROUNDS: u32 = 2048;
+
+/// HMAC-SHA512 of the concatenation of `parts`
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> [u8; 64] {
+    let mut block = [0_u8; 128];
+    if key.len() > block.len() {
+        block[..64].copy_from_slice(&Sha512::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha512::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    parts.iter().for_each(|part| inner.update(part));
+    let mut outer = Sha512::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Turns a BIP39 mnemonic and optional passphrase into the 64 byte seed.
+/// The words are not checked against the BIP39 word list, and must already be in NFKD form
+/// (true of any plain ASCII mnemonic, such as the English list).
+pub fn seed_from_mnemonic(mnemonic: &str, passphrase: &str) -> [u8; 64] {
+    // PBKDF2-HMAC-SHA512, the seed is exactly one output block
+    let mut block = hmac_sha512(
+        mnemonic.as_bytes(),
+        &[b"mnemonic", passphrase.as_bytes(), &1_u32.to_be_bytes()],
+    );
+    let mut seed = block;
+    for _ in 1..PBKDF2_ROUNDS {
+        block = hmac_sha512(mnemonic.as_bytes(), &[&block]);
+        seed.iter_mut().zip(block.iter()).for_each(|(s, b)| *s ^= b);
+    }
+    seed
+}
+
+/// Derives the hex private key for account 0, the key NIP-06 wallets show for a seed
+pub fn derive_privkey(seed: &[u8]) -> Result<String<64>, Error> {
+    derive_account(seed, 0)
+}
+
+/// Derives the hex private key at `m/44'/1237'/<account>'/0/0`.
+/// Errors with `KeyDerivationError` if `account` is 2^31 or larger.
+pub fn derive_account(seed: &[u8], account: u32) -> Result<String<64>, Error> {
+    if account >= HARDENED {
+        return Err(Error::KeyDerivationError);
+    }
+    let mut buf = [AlignedType::zeroed(); CONTEXT_ITEMS];
+    let ctx = Curve::context(&mut buf)?;
+
+    let master = hmac_sha512(b"Bitcoin seed", &[seed]);
+    let mut key = [0_u8; 32];
+    key.copy_from_slice(&master[..32]);
+    let mut chain_code = [0_u8; 32];
+    chain_code.copy_from_slice(&master[32..]);
+
+    let path = [
+        44 | HARDENED,
+        NOSTR_COIN_TYPE | HARDENED,
+        account | HARDENED,
+        0,
+        0,
+    ];
+    for index in path {
+        let index_bytes = index.to_be_bytes();
+        let child = if index & HARDENED != 0 {
+            hmac_sha512(&chain_code, &[&[0], &key, &index_bytes])
+        } else {
+            let keypair = Curve::keypair(&ctx, &key).map_err(|_| Error::KeyDerivationError)?;
+            let pubkey = Curve::compressed_pubkey(&keypair);
+            hmac_sha512(&chain_code, &[&pubkey, &index_bytes])
+        };
+        let mut tweak = [0_u8; 32];
+        tweak.copy_from_slice(&child[..32]);
+        key = Curve::add_tweak(&key, &tweak)?;
+        chain_code.copy_from_slice(&child[32..]);
+    }
+
+    let mut hex = [0_u8; 64];
+    base16ct::lower::encode(&key, &mut hex).map_err(|_| Error::EncodeError)?;
+    let mut privkey = String::new();
+    privkey
+        .push_str(core::str::from_utf8(&hex).map_err(|_| Error::EncodeError)?)
+        .map_err(|_| Error::EncodeError)?;
+    Ok(privkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nip06_vectors() {
+        let seed = seed_from_mnemonic(
+            "what bleak badge arrange retreat wolf trade produce cricket blur garlic valid proud rude strong choose busy staff weather area salt hollow arm fade",
+            "",
+        );
+        assert_eq!(
+            derive_privkey(&seed).unwrap(),
+            "c15d739894c81a2fcfd3a2df85a0d2c0dbc47a280d092799f144d73d7ae78add"
+        );
+    }
+
+    #[test]
+    fn test_accounts() {
+        let seed = [7_u8; 64];
+        let first = derive_account(&seed, 1).unwrap();
+        assert_eq!(first, derive_account(&seed, 1).unwrap());
+        assert_ne!(first, derive_account(&seed, 2).unwrap());
+        assert_ne!(first, derive_privkey(&seed).unwrap());
+        assert_eq!(
+            derive_account(&seed, HARDENED),
+            Err(Error::KeyDerivationError)
+        );
+    }
+}
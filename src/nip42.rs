@@ -0,0 +1,200 @@
+//! Authentication of clients to relays per [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md)
+//!
+//! A relay sends `["AUTH", <challenge>]`, the client answers with a signed kind 22242 event
+//! naming the challenge and the relay. [`auth_response`] builds that answer in one call, and
+//! [`verify_auth_event`] checks one for devices acting as a relay.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{keys::{AlignedType, Keys, CONTEXT_SIZE}, nip42, relay_responses::AuthMessage};
+//! let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+//! let keys = Keys::new(
+//!     "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3",
+//!     &mut context,
+//! )
+//! .unwrap();
+//! let challenge = AuthMessage::try_from(r#"["AUTH","challenge_me"]"#).unwrap();
+//! let msg = nip42::auth_response(&keys, &challenge, "wss://relay.example.com", 1691712199, [0; 32])
+//!     .unwrap();
+//! assert!(msg.starts_with(br#"["AUTH",{"#));
+//! ```
+
+use crate::{
+    errors::{AuthRule, Error},
+    keys::Keys,
+    relay_responses::AuthMessage,
+    ClientMsgKinds, Note, NoteBuffer, NoteKinds,
+};
+
+/// How many seconds `created_at` of an auth event may be from the relay's clock
+pub const AUTH_TIME_WINDOW: u32 = 600;
+
+/// Builds, signs and serializes the `AUTH` reply to `auth` for the relay at `relay_url`.
+/// aux_rand should be generated from a random number generator.
+pub fn auth_response(
+    keys: &Keys,
+    auth: &AuthMessage,
+    relay_url: &str,
+    created_at: u64,
+    aux_rand: [u8; 32],
+) -> Result<NoteBuffer, Error> {
+    let note = Note::builder_with_keys(keys)
+        .create_auth(auth, relay_url)?
+        .build_with_keys(keys, created_at, aux_rand)?;
+    let mut output = NoteBuffer::new();
+    note.serialize_to_buf(ClientMsgKinds::Auth, &mut output)?;
+    Ok(output)
+}
+
+/// Same as [`auth_response`], writing the reply into `buf` and returning the number of bytes written.
+/// Errors with `ContentOverflow` if `buf` is too small.
+pub fn auth_response_into(
+    keys: &Keys,
+    auth: &AuthMessage,
+    relay_url: &str,
+    created_at: u64,
+    aux_rand: [u8; 32],
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let note = Note::builder_with_keys(keys)
+        .create_auth(auth, relay_url)?
+        .build_with_keys(keys, created_at, aux_rand)?;
+    note.serialize_into(ClientMsgKinds::Auth, buf)
+}
+
+/// Relay urls compared without case or a trailing slash
+fn same_relay(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/')
+        .eq_ignore_ascii_case(b.trim_end_matches('/'))
+}
+
+/// Checks an auth event received by a relay: it must be kind 22242, carry `expected_challenge`
+/// and `expected_relay` in its tags, have been created within [`AUTH_TIME_WINDOW`] of `now`,
+/// and have a valid signature.
+/// Errors with `InvalidAuth` naming the failed check, or the error from
+/// [`Note::validate_signature`].
+pub fn verify_auth_event<
+    const CONTENT: usize,
+    const TAG: usize,
+    const NTAGS: usize,
+    const VALUES: usize,
+>(
+    note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    expected_challenge: &str,
+    expected_relay: &str,
+    now: u64,
+) -> Result<(), Error> {
+    if note.kind() != NoteKinds::Auth {
+        return Err(Error::InvalidAuth(AuthRule::WrongKind));
+    }
+    let first_value = |name: &str| {
+        note.tags()
+            .iter()
+            .find(|tag| tag.name() == name)
+            .and_then(|tag| tag.value(0))
+    };
+    if first_value("challenge") != Some(expected_challenge) {
+        return Err(Error::InvalidAuth(AuthRule::ChallengeMismatch));
+    }
+    if !first_value("relay").is_some_and(|relay| same_relay(relay, expected_relay)) {
+        return Err(Error::InvalidAuth(AuthRule::RelayMismatch));
+    }
+    if note.created_at().abs_diff(now) > u64::from(AUTH_TIME_WINDOW) {
+        return Err(Error::InvalidAuth(AuthRule::Expired));
+    }
+    note.validate_signature()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{AlignedType, CONTEXT_SIZE};
+    use crate::vectors::PRIVKEY;
+
+    fn challenge() -> AuthMessage {
+        AuthMessage {
+            challenge_string: "challenge_me".into(),
+        }
+    }
+
+    #[test]
+    fn test_auth_response() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let msg = auth_response(
+            &keys,
+            &challenge(),
+            "wss://relay.damus.io",
+            1691712199,
+            [0; 32],
+        )
+        .unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_auth(&challenge(), "wss://relay.damus.io")
+            .unwrap()
+            .build(1691712199, [0; 32])
+            .unwrap()
+            .serialize_to_relay(ClientMsgKinds::Auth)
+            .unwrap();
+        assert_eq!(msg, expected);
+
+        let mut buf = [0_u8; 1024];
+        let len = auth_response_into(
+            &keys,
+            &challenge(),
+            "wss://relay.damus.io",
+            1691712199,
+            [0; 32],
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(&buf[..len], expected.as_slice());
+        assert_eq!(
+            auth_response_into(
+                &keys,
+                &challenge(),
+                "wss://relay.damus.io",
+                1691712199,
+                [0; 32],
+                &mut buf[..100],
+            ),
+            Err(Error::ContentOverflow)
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .create_auth(&challenge(), "wss://relay.damus.io/")
+            .unwrap()
+            .build(1691712199, [0; 32])
+            .unwrap();
+        let now = 1691712199 + 60;
+        assert_eq!(
+            verify_auth_event(&note, "challenge_me", "wss://Relay.Damus.io", now),
+            Ok(())
+        );
+        assert_eq!(
+            verify_auth_event(&note, "other", "wss://relay.damus.io", now),
+            Err(Error::InvalidAuth(AuthRule::ChallengeMismatch))
+        );
+        assert_eq!(
+            verify_auth_event(&note, "challenge_me", "wss://nos.lol", now),
+            Err(Error::InvalidAuth(AuthRule::RelayMismatch))
+        );
+        assert_eq!(
+            verify_auth_event(&note, "challenge_me", "wss://relay.damus.io", now + 3600),
+            Err(Error::InvalidAuth(AuthRule::Expired))
+        );
+        let short_note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1691712199, [0; 32])
+            .unwrap();
+        assert_eq!(
+            verify_auth_event(&short_note, "challenge_me", "wss://relay.damus.io", now),
+            Err(Error::InvalidAuth(AuthRule::WrongKind))
+        );
+    }
+}
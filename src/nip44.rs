@@ -0,0 +1,424 @@
+//! Versioned encryption per [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md), version 2
+//!
+//! Replaces the deprecated NIP-04 scheme. Both sides derive the same conversation key from their
+//! own private key and the other's pubkey, and each message is encrypted with ChaCha20 under keys
+//! derived from that conversation key and a random nonce, then authenticated with HMAC-SHA256.
+//! Plaintexts are padded so the payload only leaks a rough size.
+//!
+//! Everything runs on the stack. A payload has to fit in a note's content, which limits
+//! plaintexts to [`MAX_PLAINTEXT_SIZE`] bytes for the default note size. Notes with a larger
+//! `CONTENT` take longer ones.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::Note;
+//! let sender = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let receiver = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+//! let receiver_pubkey = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+//! // nonce should be generated from a random number generator
+//! let note = Note::new_builder(sender)
+//!     .unwrap()
+//!     .create_dm_v2("hello from the internet", receiver_pubkey, [1; 32])
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! assert_eq!(note.read_dm_v2(receiver).unwrap(), "hello from the internet");
+//! ```
+
+use base64ct::{Base64, Encoding};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use heapless::String;
+use sha2::{Digest, Sha256};
+
+use crate::backend::Sha256Backend;
+
+use crate::{
+    curve::{self, Curve, CurveBackend},
+    errors::Error,
+    keys::Keys,
+    nip04,
+    tag::Tag,
+    utils::from_hex,
+    Note, NoteBuilder, NoteKinds, MAX_DM_SIZE, NOTE_SIZE,
+};
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+/// Longest plaintext whose payload still fits in a note's content
+pub const MAX_PLAINTEXT_SIZE: usize = 224;
+/// Version byte, nonce and MAC around the ciphertext
+const OVERHEAD: usize = 1 + 32 + 32;
+
+/// HMAC-SHA256 of the concatenation of `parts`
+fn hmac_sha256<H: Sha256Backend>(hasher: &mut H, key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0_u8; 64];
+    hasher.reset();
+    if key.len() > block.len() {
+        hasher.update(key);
+        block[..32].copy_from_slice(&hasher.finish());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    hasher.update(&block.map(|b| b ^ 0x36));
+    parts.iter().for_each(|part| hasher.update(part));
+    let inner = hasher.finish();
+    hasher.update(&block.map(|b| b ^ 0x5c));
+    hasher.update(&inner);
+    hasher.finish()
+}
+
+/// Derives the key shared by the secret key `secret` and the x-only `pubkey`, the same from
+/// either side of the conversation.
+/// Errors with `InvalidPrivkey` or `InvalidPubkey` if either key is not valid.
+pub fn conversation_key(secret: &[u8; 32], pubkey: &[u8; 32]) -> Result<[u8; 32], Error> {
+    conversation_key_with(&mut Sha256::new(), secret, pubkey)
+}
+
+/// Same as [`conversation_key`], hashing with `hasher`, such as a
+/// [hardware accelerator](crate::backend)
+pub fn conversation_key_with<H: Sha256Backend>(
+    hasher: &mut H,
+    secret: &[u8; 32],
+    pubkey: &[u8; 32],
+) -> Result<[u8; 32], Error> {
+    let shared_x = nip04::generate_shared_key(secret, pubkey)?;
+    Ok(conversation_key_from_shared(hasher, &shared_x))
+}
+
+/// Conversation key from the x coordinate of the ECDH point, by HKDF-extract
+pub(crate) fn conversation_key_from_shared<H: Sha256Backend>(
+    hasher: &mut H,
+    shared_x: &[u8; 32],
+) -> [u8; 32] {
+    hmac_sha256(hasher, SALT, &[shared_x])
+}
+
+/// ChaCha20 key, ChaCha20 nonce and HMAC key for a single message
+struct MessageKeys {
+    chacha_key: [u8; 32],
+    chacha_nonce: [u8; 12],
+    hmac_key: [u8; 32],
+}
+
+/// HKDF-expand of the conversation key with the message nonce as info
+fn message_keys<H: Sha256Backend>(
+    hasher: &mut H,
+    conversation_key: &[u8; 32],
+    nonce: &[u8; 32],
+) -> MessageKeys {
+    let t1 = hmac_sha256(hasher, conversation_key, &[nonce, &[1]]);
+    let t2 = hmac_sha256(hasher, conversation_key, &[&t1, nonce, &[2]]);
+    let t3 = hmac_sha256(hasher, conversation_key, &[&t2, nonce, &[3]]);
+    let mut keys = MessageKeys {
+        chacha_key: t1,
+        chacha_nonce: [0; 12],
+        hmac_key: [0; 32],
+    };
+    keys.chacha_nonce.copy_from_slice(&t2[..12]);
+    keys.hmac_key[..20].copy_from_slice(&t2[12..]);
+    keys.hmac_key[20..].copy_from_slice(&t3[..12]);
+    keys
+}
+
+/// Length a plaintext of `len` bytes is padded to
+pub fn calc_padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+/// Encrypts `plaintext` into a base64 payload.
+/// `nonce` must be random and never reused.
+/// Errors with `MalformedContent` if `plaintext` is empty and `ContentOverflow` if it is longer
+/// than [`MAX_PLAINTEXT_SIZE`].
+pub fn encrypt(
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: [u8; 32],
+) -> Result<String<NOTE_SIZE>, Error> {
+    if plaintext.len() > MAX_PLAINTEXT_SIZE {
+        return Err(Error::ContentOverflow);
+    }
+    encrypt_sized(conversation_key, plaintext, nonce)
+}
+
+/// Same as [`encrypt`], for payloads of up to `N` characters, such as the layers of a
+/// [gift wrap](crate::gift_wrap). Uses about three times `N` bytes of stack.
+/// Errors with `MalformedContent` if `plaintext` is empty and `ContentOverflow` if the payload
+/// is longer than `N`.
+pub fn encrypt_sized<const N: usize>(
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: [u8; 32],
+) -> Result<String<N>, Error> {
+    encrypt_sized_with(&mut Sha256::new(), conversation_key, plaintext, nonce)
+}
+
+/// Same as [`encrypt_sized`], hashing with `hasher`, such as a
+/// [hardware accelerator](crate::backend)
+pub fn encrypt_sized_with<H: Sha256Backend, const N: usize>(
+    hasher: &mut H,
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: [u8; 32],
+) -> Result<String<N>, Error> {
+    let len = plaintext.len();
+    if len == 0 {
+        return Err(Error::MalformedContent);
+    }
+    let ciphertext_len = 2 + calc_padded_len(len);
+    let raw_len = OVERHEAD + ciphertext_len;
+    if len > u16::MAX as usize || raw_len > N / 4 * 3 {
+        return Err(Error::ContentOverflow);
+    }
+    let keys = message_keys(hasher, conversation_key, &nonce);
+
+    // version, nonce, u16 length, padded plaintext, mac
+    let mut raw = [0_u8; N];
+    raw[0] = VERSION;
+    raw[1..33].copy_from_slice(&nonce);
+    let ciphertext = &mut raw[33..33 + ciphertext_len];
+    ciphertext[..2].copy_from_slice(&(len as u16).to_be_bytes());
+    ciphertext[2..2 + len].copy_from_slice(plaintext.as_bytes());
+    ChaCha20::new(&keys.chacha_key.into(), &keys.chacha_nonce.into()).apply_keystream(ciphertext);
+    let mac = hmac_sha256(hasher, &keys.hmac_key, &[&nonce, ciphertext]);
+    raw[raw_len - 32..raw_len].copy_from_slice(&mac);
+
+    let mut encoded = [0_u8; N];
+    let encoded =
+        Base64::encode(&raw[..raw_len], &mut encoded).map_err(|_| Error::ContentOverflow)?;
+    let mut output = String::new();
+    output
+        .push_str(encoded)
+        .map_err(|_| Error::ContentOverflow)?;
+    Ok(output)
+}
+
+/// Decrypts a base64 payload made by [`encrypt`].
+/// Errors with `TypeNotAccepted` for payload versions other than 2, `InvalidMac` if the payload
+/// was tampered with or encrypted under another key, and `MalformedContent` if it is badly formed.
+pub fn decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String<MAX_DM_SIZE>, Error> {
+    if payload.len() > NOTE_SIZE {
+        return Err(Error::MalformedContent);
+    }
+    decrypt_sized(conversation_key, payload)
+}
+
+/// Same as [`decrypt`], for payloads of up to `N` characters made by [`encrypt_sized`].
+/// Uses about twice `N` bytes of stack.
+/// Errors like [`decrypt`], and with `MalformedContent` if `payload` is longer than `N`.
+pub fn decrypt_sized<const N: usize>(
+    conversation_key: &[u8; 32],
+    payload: &str,
+) -> Result<String<N>, Error> {
+    decrypt_sized_with(&mut Sha256::new(), conversation_key, payload)
+}
+
+/// Same as [`decrypt_sized`], hashing with `hasher`, such as a
+/// [hardware accelerator](crate::backend)
+pub fn decrypt_sized_with<H: Sha256Backend, const N: usize>(
+    hasher: &mut H,
+    conversation_key: &[u8; 32],
+    payload: &str,
+) -> Result<String<N>, Error> {
+    if payload.starts_with('#') {
+        return Err(Error::TypeNotAccepted);
+    }
+    if payload.len() > N {
+        return Err(Error::MalformedContent);
+    }
+    let mut raw = [0_u8; N];
+    let raw_len = Base64::decode(payload, &mut raw)
+        .map_err(|_| Error::MalformedContent)?
+        .len();
+    let raw = &mut raw[..raw_len];
+    // the shortest payload holds a 32 byte padded plaintext
+    if raw.len() < OVERHEAD + 2 + 32 {
+        return Err(Error::MalformedContent);
+    }
+    if raw[0] != VERSION {
+        return Err(Error::TypeNotAccepted);
+    }
+    let (body, mac) = raw.split_at_mut(raw.len() - 32);
+    let (header, ciphertext) = body.split_at_mut(33);
+    let nonce: [u8; 32] = header[1..].try_into().map_err(|_| Error::InternalError)?;
+    let keys = message_keys(hasher, conversation_key, &nonce);
+
+    // compare without exiting early so timing doesn't reveal the correct mac
+    let expected = hmac_sha256(hasher, &keys.hmac_key, &[&nonce, ciphertext]);
+    let diff = expected
+        .iter()
+        .zip(mac.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if diff != 0 {
+        return Err(Error::InvalidMac);
+    }
+
+    ChaCha20::new(&keys.chacha_key.into(), &keys.chacha_nonce.into()).apply_keystream(ciphertext);
+    let len = u16::from_be_bytes([ciphertext[0], ciphertext[1]]) as usize;
+    if len == 0 || ciphertext.len() != 2 + calc_padded_len(len) {
+        return Err(Error::MalformedContent);
+    }
+    let plaintext =
+        core::str::from_utf8(&ciphertext[2..2 + len]).map_err(|_| Error::MalformedContent)?;
+    let mut output = String::new();
+    output
+        .push_str(plaintext)
+        .map_err(|_| Error::ContentOverflow)?;
+    Ok(output)
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::create_dm`], but encrypts `content` with NIP-44 instead of NIP-04.
+    /// The note keeps kind 4, `nonce` should be generated from a random source.
+    /// The payload can be up to `CONTENT` characters.
+    pub fn create_dm_v2(
+        mut self,
+        content: &str,
+        rcvr_pubkey: &str,
+        nonce: [u8; 32],
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let mut raw = [0_u8; 32];
+        base16ct::lower::decode(rcvr_pubkey, &mut raw).map_err(|_| Error::InvalidPubkey)?;
+        let keypair = self.keypair.as_ref().ok_or(Error::MissingPrivkey)?;
+        let key = conversation_key(&Curve::secret_bytes(keypair), &raw)?;
+        self.note.content = Some(encrypt_sized(&key, content, nonce)?);
+        self.note.kind = NoteKinds::DM;
+        let tag = Tag::try_from(["p", rcvr_pubkey])?;
+        self.add_tag(tag)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Decrypts a DM made by [`NoteBuilder::create_dm_v2`], from either the sender's or the receiver's side.
+    pub fn read_dm_v2(&self, privkey: &str) -> Result<String<CONTENT>, Error> {
+        let keypair = curve::keypair_from_hex(privkey)?;
+        self.read_dm_v2_with_secret(&Curve::secret_bytes(&keypair), &Curve::x_only(&keypair))
+    }
+
+    /// Same as [`Note::read_dm_v2`], but takes already parsed keys
+    pub fn read_dm_v2_with_keys(&self, keys: &Keys) -> Result<String<CONTENT>, Error> {
+        self.read_dm_v2_with_secret(&keys.secret_key(), keys.pubkey_bytes())
+    }
+
+    fn read_dm_v2_with_secret(
+        &self,
+        secret: &[u8; 32],
+        own_pubkey: &[u8; 32],
+    ) -> Result<String<CONTENT>, Error> {
+        // the other side is the author, unless we wrote the note
+        let other = if *own_pubkey == self.pubkey {
+            let tags = self.get_tag("p")?;
+            let pubkey = tags
+                .first()
+                .and_then(|values| values.first())
+                .ok_or(Error::MalformedContent)?;
+            from_hex(pubkey.as_bytes()).map_err(|_| Error::InvalidPubkey)?
+        } else {
+            self.pubkey
+        };
+        let key = conversation_key(secret, &other)?;
+        decrypt_sized(&key, self.content().ok_or(Error::MalformedContent)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from the NIP-44 test vectors
+    const SEC1: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+    const SEC2: &str = "0000000000000000000000000000000000000000000000000000000000000002";
+    const CONVERSATION_KEY: &str =
+        "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d";
+    const PAYLOAD: &str = "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb";
+
+    fn vector_key() -> [u8; 32] {
+        let sk1 = curve::keypair_from_hex(SEC1).unwrap();
+        let sk2 = curve::keypair_from_hex(SEC2).unwrap();
+        let key = conversation_key(&Curve::secret_bytes(&sk1), &Curve::x_only(&sk2)).unwrap();
+        assert_eq!(
+            key,
+            conversation_key(&Curve::secret_bytes(&sk2), &Curve::x_only(&sk1)).unwrap()
+        );
+        key
+    }
+
+    #[test]
+    fn test_vector() {
+        let key = vector_key();
+        let mut expected = [0_u8; 32];
+        base16ct::lower::decode(CONVERSATION_KEY, &mut expected).unwrap();
+        assert_eq!(key, expected);
+        let mut nonce = [0_u8; 32];
+        nonce[31] = 1;
+        assert_eq!(encrypt(&key, "a", nonce).unwrap(), PAYLOAD);
+        assert_eq!(decrypt(&key, PAYLOAD).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_longest_vector() {
+        let mut key = [0_u8; 32];
+        base16ct::lower::decode(
+            "75fe686d21a035f0c7cd70da64ba307936e5ca0b20710496a6b6b5f573377bdd",
+            &mut key,
+        )
+        .unwrap();
+        let mut nonce = [0_u8; 32];
+        base16ct::lower::decode(
+            "e4cd5f7ce4eea024bc71b17ad456a986a74ac426c2c62b0a15eb5c5c8f888b68",
+            &mut nonce,
+        )
+        .unwrap();
+        let plaintext = "مُنَاقَشَةُ سُبُلِ اِسْتِخْدَامِ اللُّغَةِ فِي النُّظُمِ الْقَائِمَةِ وَفِيم يَخُصَّ التَّطْبِيقَاتُ الْحاسُوبِيَّةُ،";
+        let payload = "AuTNX3zk7qAkvHGxetRWqYanSsQmwsYrChXrXFyPiItoIBsWu1CB+sStla2M4VeANASHxM78i1CfHQQH1YbBy24Tng7emYW44ol6QkFD6D8Zq7QPl+8L1c47lx8RoODEQMvNCbOk5ffUV3/AhONHBXnffrI+0025c+uRGzfqpYki4lBqm9iYU+k3Tvjczq9wU0mkVDEaM34WiQi30MfkJdRbeeYaq6kNvGPunLb3xdjjs5DL720d61Flc5ZfoZm+CBhADy9D9XiVZYLKAlkijALJur9dATYKci6OBOoc2SJS2Clai5hOVzR0yVeyHRgRfH9aLSlWW5dXcUxTo7qqRjNf8W5+J4jF4gNQp5f5d0YA4vPAzjBwSP/5bGzNDslKfcAH";
+        assert_eq!(plaintext.len(), MAX_PLAINTEXT_SIZE);
+        assert_eq!(encrypt(&key, plaintext, nonce).unwrap(), payload);
+        assert_eq!(decrypt(&key, payload).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(calc_padded_len(1), 32);
+        assert_eq!(calc_padded_len(32), 32);
+        assert_eq!(calc_padded_len(33), 64);
+        assert_eq!(calc_padded_len(257), 320);
+        assert_eq!(calc_padded_len(65535), 65536);
+    }
+
+    #[test]
+    fn test_limits_and_tampering() {
+        let key = vector_key();
+        let longest = [b'a'; MAX_PLAINTEXT_SIZE];
+        let longest = core::str::from_utf8(&longest).unwrap();
+        let payload = encrypt(&key, longest, [7; 32]).unwrap();
+        assert_eq!(decrypt(&key, &payload).unwrap(), longest);
+        let too_long = [b'a'; MAX_PLAINTEXT_SIZE + 1];
+        assert_eq!(
+            encrypt(&key, core::str::from_utf8(&too_long).unwrap(), [7; 32]),
+            Err(Error::ContentOverflow)
+        );
+
+        let mut tampered: String<NOTE_SIZE> = String::new();
+        tampered.push_str(&PAYLOAD[..60]).unwrap();
+        tampered
+            .push(if &PAYLOAD[60..61] == "A" { 'B' } else { 'A' })
+            .unwrap();
+        tampered.push_str(&PAYLOAD[61..]).unwrap();
+        assert_eq!(decrypt(&key, &tampered), Err(Error::InvalidMac));
+        assert_eq!(decrypt(&key, "#unsupported"), Err(Error::TypeNotAccepted));
+    }
+}
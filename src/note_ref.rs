@@ -0,0 +1,682 @@
+//! Notes borrowed from the message they were parsed from
+//!
+//! A gateway that only inspects and forwards events doesn't need its own copy of the content and
+//! tags. [`NoteRef`] keeps every field as a slice of the original JSON, strings still escaped,
+//! and [`NoteRef::to_note`] copies it into an owned [`Note`] when one is needed.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{relay_responses::EventMessageRef, Note};
+//! let msg = r#"["EVENT", "sub_1", {"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[["t", "esp"]]}]"#;
+//! let event = EventMessageRef::try_from(msg).unwrap();
+//! assert_eq!(event.subscription_id, "sub_1");
+//! assert_eq!(event.note.content(), "esptest");
+//! assert_eq!(event.note.find_tag("t").unwrap().value(0), Some("esp"));
+//! // copy it out only if it has to be kept
+//! let note: Note = event.note.to_note().unwrap();
+//! assert_eq!(note.get_tag("t").unwrap()[0][0], "esp");
+//! ```
+
+use heapless::String;
+
+use crate::{
+    errors::{Error, Field},
+    hex::{HexId, HexPubkey},
+    parse_json::parse_tags,
+    utils::{from_hex, unescape_json},
+    Note, NoteKinds,
+};
+
+/// `json` from where `rest`, a part of its end, starts. Splits only happen next to ASCII
+/// punctuation, so this is always on a character boundary.
+fn suffix<'a>(json: &'a str, rest: &[u8]) -> &'a str {
+    &json[json.len() - rest.len()..]
+}
+
+/// Splits a JSON string off the front of `json`, returning it still escaped and what follows.
+/// The string isn't checked to be UTF-8.
+pub(crate) fn split_string_bytes(json: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let value = json.strip_prefix(b"\"").ok_or(Error::MalformedContent)?;
+    let mut escaped = false;
+    for (i, &c) in value.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => return Ok((&value[..i], &value[i + 1..])),
+            _ => {}
+        }
+    }
+    Err(Error::MalformedContent)
+}
+
+/// Splits a JSON string off the front of `json`, returning it still escaped and what follows
+pub(crate) fn split_string(json: &str) -> Result<(&str, &str), Error> {
+    let (value, rest) = split_string_bytes(json.as_bytes())?;
+    Ok((&json[1..=value.len()], suffix(json, rest)))
+}
+
+/// Same as [`split_string`], checking the string is UTF-8
+fn split_utf8(json: &[u8]) -> Result<(&str, &[u8]), Error> {
+    let (value, rest) = split_string_bytes(json)?;
+    let value = core::str::from_utf8(value).map_err(|_| Error::MalformedContent)?;
+    Ok((value, rest))
+}
+
+/// Splits an unsigned number off the front of `json`
+pub(crate) fn split_number_bytes<N: core::str::FromStr>(json: &[u8]) -> Result<(N, &[u8]), Error> {
+    let end = json
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(json.len());
+    let (number, rest) = json.split_at(end);
+    // digits are ASCII
+    let number = core::str::from_utf8(number)
+        .ok()
+        .and_then(|number| number.parse().ok())
+        .ok_or(Error::MalformedContent)?;
+    Ok((number, rest))
+}
+
+/// Splits an unsigned number off the front of `json`
+pub(crate) fn split_number<N: core::str::FromStr>(json: &str) -> Result<(N, &str), Error> {
+    let (number, rest) = split_number_bytes(json.as_bytes())?;
+    Ok((number, suffix(json, rest)))
+}
+
+/// Splits a JSON array off the front of `json`, calling `element` to skip over each element.
+/// `element` returns what follows the element it read.
+fn split_array_bytes<'a>(
+    json: &'a [u8],
+    mut element: impl FnMut(&'a [u8]) -> Result<&'a [u8], Error>,
+) -> Result<(&'a [u8], &'a [u8]), Error> {
+    let mut rest = json
+        .strip_prefix(b"[")
+        .ok_or(Error::MalformedContent)?
+        .trim_ascii_start();
+    let mut first = true;
+    loop {
+        if let Some(after) = rest.strip_prefix(b"]") {
+            return Ok(json.split_at(json.len() - after.len()));
+        }
+        if !first {
+            rest = rest
+                .strip_prefix(b",")
+                .ok_or(Error::MalformedContent)?
+                .trim_ascii_start();
+        }
+        first = false;
+        rest = element(rest)?.trim_ascii_start();
+    }
+}
+
+/// Splits a JSON object off the front of `json`, calling `member` with each key, still escaped,
+/// and the value following it. `member` returns what follows the value it read.
+pub(crate) fn split_object_bytes<'a>(
+    json: &'a [u8],
+    mut member: impl FnMut(&'a [u8], &'a [u8]) -> Result<&'a [u8], Error>,
+) -> Result<(&'a [u8], &'a [u8]), Error> {
+    let mut rest = json
+        .strip_prefix(b"{")
+        .ok_or(Error::MalformedContent)?
+        .trim_ascii_start();
+    let mut first = true;
+    loop {
+        if let Some(after) = rest.strip_prefix(b"}") {
+            return Ok(json.split_at(json.len() - after.len()));
+        }
+        if !first {
+            rest = rest
+                .strip_prefix(b",")
+                .ok_or(Error::MalformedContent)?
+                .trim_ascii_start();
+        }
+        first = false;
+        let (key, after) = split_string_bytes(rest)?;
+        let value = after
+            .trim_ascii_start()
+            .strip_prefix(b":")
+            .ok_or(Error::MalformedContent)?
+            .trim_ascii_start();
+        rest = member(key, value)?.trim_ascii_start();
+    }
+}
+
+/// Splits a JSON array off the front of `json`, calling `element` to skip over each element.
+/// `element` returns what follows the element it read.
+pub(crate) fn split_array<'a>(
+    json: &'a str,
+    mut element: impl FnMut(&'a str) -> Result<&'a str, Error>,
+) -> Result<(&'a str, &'a str), Error> {
+    let (array, _) = split_array_bytes(json.as_bytes(), |rest| {
+        element(suffix(json, rest)).map(str::as_bytes)
+    })?;
+    Ok(json.split_at(array.len()))
+}
+
+/// Skips over a tag, an array of strings
+fn skip_tag(json: &[u8]) -> Result<&[u8], Error> {
+    split_array_bytes(json, |value| {
+        split_string_bytes(value).map(|(_, rest)| rest)
+    })
+    .map(|(_, rest)| rest)
+}
+
+/// Skips over any JSON value, for fields a note doesn't have. The value ends at the comma or
+/// closing bracket that follows it.
+/// Errors with `MalformedContent` if the value is empty, a bracket is closed by the wrong kind
+/// or it nests more than 64 deep.
+pub(crate) fn skip_value_bytes(json: &[u8]) -> Result<&[u8], Error> {
+    let mut depth = 0_u32;
+    // one bit per open bracket, set for an object
+    let mut objects = 0_u64;
+    let mut empty = true;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &c) in json.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b',' | b'}' | b']' if depth == 0 => {
+                return if empty {
+                    Err(Error::MalformedContent)
+                } else {
+                    Ok(&json[i..])
+                };
+            }
+            b'{' | b'[' if depth < u64::BITS => {
+                objects = objects << 1 | u64::from(c == b'{');
+                depth += 1;
+            }
+            b'}' | b']' => {
+                if (objects & 1 == 1) != (c == b'}') {
+                    return Err(Error::MalformedContent);
+                }
+                objects >>= 1;
+                depth -= 1;
+            }
+            b'{' | b'[' => return Err(Error::MalformedContent),
+            _ if c.is_ascii_whitespace() => continue,
+            _ => {}
+        }
+        empty = false;
+    }
+    Err(Error::MalformedContent)
+}
+
+/// Skips over any JSON value, for fields a note doesn't have
+pub(crate) fn skip_value(json: &str) -> Result<&str, Error> {
+    skip_value_bytes(json.as_bytes()).map(|rest| suffix(json, rest))
+}
+
+/// A note whose fields point into the JSON it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteRef<'a> {
+    id: &'a str,
+    pubkey: &'a str,
+    created_at: u64,
+    kind: NoteKinds,
+    /// The whole `[[...],...]` array
+    tags: &'a str,
+    content: &'a str,
+    sig: &'a str,
+    /// Offsets of the `tags` and `content` keys, for errors found when copying them out
+    tags_at: usize,
+    content_at: usize,
+}
+
+/// `Malformed` error for `field`, whose key starts at `offset`
+fn malformed(field: Field, offset: usize) -> Error {
+    Error::Malformed { field, offset }
+}
+
+/// Turns a `MalformedContent` error while reading `field` into a `Malformed` one
+fn locate(field: Field, offset: usize) -> impl Fn(Error) -> Error {
+    move |error| match error {
+        Error::MalformedContent => malformed(field, offset),
+        error => error,
+    }
+}
+
+/// The note field stored under `key`, if any
+fn field_of(key: &[u8]) -> Option<Field> {
+    match key {
+        b"id" => Some(Field::Id),
+        b"pubkey" => Some(Field::Pubkey),
+        b"created_at" => Some(Field::CreatedAt),
+        b"kind" => Some(Field::Kind),
+        b"tags" => Some(Field::Tags),
+        b"content" => Some(Field::Content),
+        b"sig" => Some(Field::Sig),
+        _ => None,
+    }
+}
+
+impl<'a> TryFrom<&'a str> for NoteRef<'a> {
+    type Error = Error;
+    /// Parses the JSON object of a note, allowing whitespace anywhere between tokens.
+    /// Checks that the id, pubkey and sig are hex of the right length but not that they're valid,
+    /// convert it with [`NoteRef::to_note`] and call [`Note::verify`] for that.
+    /// Errors with `Malformed` for a field that can't be read, its offset is where the key of the
+    /// field starts in `json`.
+    fn try_from(json: &'a str) -> Result<Self, Self::Error> {
+        NoteRef::parse(json.as_bytes(), true)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for NoteRef<'a> {
+    type Error = Error;
+    /// Same as parsing from a `&str`, for JSON still in the bytes it was received as.
+    /// Only the strings of the note's fields are checked to be UTF-8, errors with `Malformed` if
+    /// they aren't.
+    fn try_from(json: &'a [u8]) -> Result<Self, Self::Error> {
+        NoteRef::parse(json, true)
+    }
+}
+
+impl<'a> NoteRef<'a> {
+    /// Parses the JSON object of a note. Only keys of the object itself are read, so one written
+    /// inside a string value can't be taken for a field. Unknown keys are skipped and the first
+    /// of repeated keys is kept. The object must be closed and only whitespace may follow it.
+    /// Unless `with_sig` is set the `sig` may be left out, it's then empty.
+    pub(crate) fn parse(json: &'a [u8], with_sig: bool) -> Result<Self, Error> {
+        let (mut id, mut pubkey, mut created_at, mut kind) = (None, None, None, None);
+        let (mut tags, mut content, mut sig) = (None, None, None);
+        let mut rest = json
+            .trim_ascii_start()
+            .strip_prefix(b"{")
+            .ok_or(Error::MalformedContent)?
+            .trim_ascii_start();
+        // the field read last, blamed if no comma follows it
+        let mut last = None;
+        let mut first = true;
+        // an object cut short runs out where the next comma or key should be
+        while !rest.starts_with(b"}") {
+            if !first {
+                rest = rest
+                    .strip_prefix(b",")
+                    .ok_or(match last {
+                        Some((field, at)) => malformed(field, at),
+                        None => Error::MalformedContent,
+                    })?
+                    .trim_ascii_start();
+            }
+            first = false;
+            let at = json.len() - rest.len();
+            let (key, after) = split_string_bytes(rest)?;
+            rest = after
+                .trim_ascii_start()
+                .strip_prefix(b":")
+                .ok_or(Error::MalformedContent)?
+                .trim_ascii_start();
+            let field = field_of(key);
+            let read = match field {
+                Some(field @ (Field::Id | Field::Pubkey | Field::Content | Field::Sig)) => {
+                    split_utf8(rest).map(|(value, after)| {
+                        let slot = match field {
+                            Field::Id => &mut id,
+                            Field::Pubkey => &mut pubkey,
+                            Field::Content => &mut content,
+                            _ => &mut sig,
+                        };
+                        slot.get_or_insert((value, at));
+                        after
+                    })
+                }
+                Some(Field::CreatedAt) => split_number_bytes(rest).map(|(value, after)| {
+                    created_at.get_or_insert(value);
+                    after
+                }),
+                Some(Field::Kind) => split_number_bytes::<u32>(rest).map(|(value, after)| {
+                    kind.get_or_insert(value);
+                    after
+                }),
+                Some(_) => split_array_bytes(rest, skip_tag).and_then(|(value, after)| {
+                    // only the strings of the tags can be other than ASCII
+                    let value = core::str::from_utf8(value).map_err(|_| Error::MalformedContent)?;
+                    tags.get_or_insert((value, at));
+                    Ok(after)
+                }),
+                None => skip_value_bytes(rest),
+            };
+            rest = match field {
+                Some(field) => read.map_err(locate(field, at))?,
+                None => read?,
+            }
+            .trim_ascii_start();
+            last = field.map(|field| (field, at));
+        }
+        if !rest[1..].trim_ascii_start().is_empty() {
+            return Err(Error::MalformedContent);
+        }
+
+        let missing = Error::EventMissingField;
+        let note = NoteRef {
+            id: id.ok_or(missing(Field::Id))?.0,
+            pubkey: pubkey.ok_or(missing(Field::Pubkey))?.0,
+            created_at: created_at.ok_or(missing(Field::CreatedAt))?,
+            kind: kind.ok_or(missing(Field::Kind))?.into(),
+            tags: tags.ok_or(missing(Field::Tags))?.0,
+            content: content.ok_or(missing(Field::Content))?.0,
+            sig: match sig {
+                Some((sig, _)) => sig,
+                None if with_sig => return Err(missing(Field::Sig)),
+                None => "",
+            },
+            tags_at: tags.map_or(0, |(_, at)| at),
+            content_at: content.map_or(0, |(_, at)| at),
+        };
+        note.id_bytes()?;
+        note.pubkey_bytes()?;
+        if sig.is_some() {
+            note.sig_bytes()?;
+        }
+        Ok(note)
+    }
+
+    /// Hex encoded id of the note
+    pub fn id(&self) -> &'a str {
+        self.id
+    }
+
+    /// Hex encoded pubkey of the note's author
+    pub fn pubkey(&self) -> &'a str {
+        self.pubkey
+    }
+
+    /// Unix timestamp the note was created at
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Kind of the note
+    pub fn kind(&self) -> NoteKinds {
+        self.kind
+    }
+
+    /// Content of the note, still JSON escaped
+    pub fn content(&self) -> &'a str {
+        self.content
+    }
+
+    /// Hex encoded signature of the note
+    pub fn sig(&self) -> &'a str {
+        self.sig
+    }
+
+    /// The tags as their unparsed JSON array
+    pub fn tags_json(&self) -> &'a str {
+        self.tags
+    }
+
+    /// Iterates over the tags
+    pub fn tags(&self) -> TagRefs<'a> {
+        TagRefs {
+            rest: self.tags.get(1..).unwrap_or_default(),
+        }
+    }
+
+    /// First tag named `name`
+    pub fn find_tag(&self, name: &str) -> Option<TagRef<'a>> {
+        self.tags().find(|tag| tag.name() == Some(name))
+    }
+
+    /// Errors with `EventNotValid` if the id isn't 64 hex characters
+    fn id_bytes(&self) -> Result<[u8; 32], Error> {
+        HexId::try_from(self.id).map(|id| *id.as_bytes())
+    }
+
+    /// Errors with `InvalidPubkey` if the pubkey isn't 64 hex characters
+    fn pubkey_bytes(&self) -> Result<[u8; 32], Error> {
+        HexPubkey::try_from(self.pubkey).map(|pubkey| *pubkey.as_bytes())
+    }
+
+    /// Errors with `InvalidSignature` if the sig isn't 128 hex characters
+    fn sig_bytes(&self) -> Result<[u8; 64], Error> {
+        from_hex(self.sig.as_bytes()).map_err(|_| Error::InvalidSignature)
+    }
+
+    /// Content of the note, unescaped into a string of up to `N` bytes.
+    /// Errors with `ContentOverflow` if it doesn't fit.
+    pub fn content_into<const N: usize>(&self) -> Result<String<N>, Error> {
+        unescape_json(self.content)
+    }
+
+    /// Copies the note into an owned [`Note`].
+    /// Errors with `ContentOverflow` or `TooManyTags` if it doesn't fit the note's sizes, and
+    /// `Malformed` if the content or a tag can't be unescaped.
+    pub fn to_note<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.note_with_sig(self.sig_bytes()?)
+    }
+
+    /// Copies the note into an owned [`Note`] signed with `sig`
+    pub(crate) fn note_with_sig<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        sig: [u8; 64],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let content = if self.content.is_empty() {
+            None
+        } else {
+            Some(
+                self.content_into()
+                    .map_err(locate(Field::Content, self.content_at))?,
+            )
+        };
+        Ok(Note {
+            id: self.id_bytes()?,
+            pubkey: self.pubkey_bytes()?,
+            created_at: self.created_at,
+            kind: self.kind,
+            tags: parse_tags(self.tags).map_err(locate(Field::Tags, self.tags_at))?,
+            content,
+            sig,
+        })
+    }
+}
+
+/// Iterator over the tags of a [`NoteRef`]
+#[derive(Debug, Clone)]
+pub struct TagRefs<'a> {
+    /// What follows the tags already read
+    rest: &'a str,
+}
+
+impl<'a> Iterator for TagRefs<'a> {
+    type Item = TagRef<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        let rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        // the array was checked when parsing, so this only stops at its end
+        let after = suffix(rest, skip_tag(rest.as_bytes()).ok()?);
+        self.rest = after;
+        Some(TagRef {
+            json: &rest[..rest.len() - after.len()],
+        })
+    }
+}
+
+/// A tag of a [`NoteRef`], its values still JSON escaped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TagRef<'a> {
+    json: &'a str,
+}
+
+impl<'a> TagRef<'a> {
+    /// Name of the tag, `None` for an empty tag
+    pub fn name(&self) -> Option<&'a str> {
+        self.strings().next()
+    }
+
+    /// The value at `index`, not counting the name
+    pub fn value(&self, index: usize) -> Option<&'a str> {
+        self.values().nth(index)
+    }
+
+    /// Values following the name
+    pub fn values(&self) -> impl Iterator<Item = &'a str> {
+        self.strings().skip(1)
+    }
+
+    /// Iterates over the strings of the tag, starting with its name
+    fn strings(&self) -> impl Iterator<Item = &'a str> {
+        let mut rest = self.json.get(1..).unwrap_or_default();
+        core::iter::from_fn(move || {
+            let trimmed = rest.trim_start();
+            let trimmed = trimmed.strip_prefix(',').unwrap_or(trimmed).trim_start();
+            let (value, after) = split_string(trimmed).ok()?;
+            rest = after;
+            Some(value)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{TEXT_NOTE_CONTENT, TEXT_NOTE_JSON};
+
+    const TAGGED: &str = r#" {
+        "id": "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",
+        "pubkey": "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf",
+        "created_at": 1686880020,
+        "kind": 1,
+        "tags": [ ["e", "abc", "wss://r.example"], [], ["t", "a \"quoted\", [tag]"] ],
+        "content": "line\nbreak",
+        "extra": {"nested": [1, "}"]},
+        "sig": "89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab"
+    }"#;
+
+    #[test]
+    fn test_matches_owned() {
+        let note_ref = NoteRef::try_from(TEXT_NOTE_JSON).unwrap();
+        assert_eq!(note_ref.content(), TEXT_NOTE_CONTENT);
+        assert_eq!(note_ref.tags().count(), 0);
+        let note: Note = note_ref.to_note().unwrap();
+        assert_eq!(note, Note::try_from(TEXT_NOTE_JSON).unwrap());
+        assert_eq!(note.id(), note_ref.id().as_bytes());
+        // the slices point into the json
+        let range = TEXT_NOTE_JSON.as_bytes().as_ptr_range();
+        assert!(range.contains(&note_ref.content().as_ptr()));
+    }
+
+    #[test]
+    fn test_tags_and_whitespace() {
+        let note_ref = NoteRef::try_from(TAGGED).unwrap();
+        assert_eq!(note_ref.created_at(), 1686880020);
+        assert_eq!(note_ref.kind(), NoteKinds::ShortNote);
+        assert_eq!(note_ref.content(), r"line\nbreak");
+        assert_eq!(note_ref.content_into::<16>().unwrap(), "line\nbreak");
+        assert_eq!(note_ref.tags().count(), 3);
+        let e = note_ref.find_tag("e").unwrap();
+        assert_eq!(e.name(), Some("e"));
+        assert!(e.values().eq(["abc", "wss://r.example"]));
+        assert_eq!(e.value(1), Some("wss://r.example"));
+        assert_eq!(e.value(2), None);
+        assert_eq!(note_ref.tags().nth(1).unwrap().name(), None);
+        assert_eq!(note_ref.tags().nth(1).unwrap().values().count(), 0);
+        let t = note_ref.find_tag("t").unwrap();
+        assert_eq!(t.value(0), Some(r#"a \"quoted\", [tag]"#));
+        assert!(note_ref.find_tag("p").is_none());
+
+        let note: Note = note_ref.to_note().unwrap();
+        assert_eq!(note.get_tag("t").unwrap()[0][0], "a \"quoted\", [tag]");
+        assert_eq!(note.content(), Some("line\nbreak"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            NoteRef::try_from(r#"{"content":"hi"}"#),
+            Err(Error::EventMissingField(Field::Id))
+        );
+        // a value cut short is blamed on its field
+        let cut = &TEXT_NOTE_JSON[..100];
+        assert_eq!(
+            NoteRef::try_from(cut),
+            Err(Error::Malformed {
+                field: Field::Id,
+                offset: cut.find(r#""id""#).unwrap()
+            })
+        );
+        assert_eq!(
+            NoteRef::try_from(r#"{"tags":[["e",1]]}"#),
+            Err(Error::Malformed {
+                field: Field::Tags,
+                offset: 1
+            })
+        );
+        assert_eq!(
+            NoteRef::try_from(r#"{"extra":[}"#),
+            Err(Error::MalformedContent)
+        );
+        // an empty value can't swallow the member after it
+        assert_eq!(
+            NoteRef::try_from(r#"{"extra":,"id":"x"}"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            NoteRef::try_from(r#"{"extra": }"#),
+            Err(Error::MalformedContent)
+        );
+        // closed by the wrong kind of bracket
+        assert_eq!(
+            NoteRef::try_from(r#"{"extra":[}],"id":"x"}"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            NoteRef::try_from(r#"{"extra":{"a":[1]]}"#),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(skip_value("[{}, [\"]\"]] ,"), Ok(","));
+        // missing its closing brace, blamed on the field read last
+        let unclosed = TEXT_NOTE_JSON.strip_suffix('}').unwrap();
+        assert_eq!(
+            NoteRef::try_from(unclosed),
+            Err(Error::Malformed {
+                field: Field::Tags,
+                offset: unclosed.find(r#""tags""#).unwrap()
+            })
+        );
+        assert_eq!(NoteRef::try_from("{"), Err(Error::MalformedContent));
+        let mut trailing: String<512> = TEXT_NOTE_JSON.into();
+        trailing.push_str(" \n").unwrap();
+        assert!(NoteRef::try_from(trailing.as_str()).is_ok());
+        trailing.push_str("x").unwrap();
+        assert_eq!(
+            NoteRef::try_from(trailing.as_str()),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            Note::try_from(trailing.as_str()),
+            Err(Error::MalformedContent)
+        );
+        assert!(matches!(
+            Note::try_from(unclosed),
+            Err(Error::Malformed { .. })
+        ));
+        let bad_id = r#"{"content":"esptest","created_at":1686880020,"id":"x515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+        assert_eq!(NoteRef::try_from(bad_id), Err(Error::EventNotValid));
+        let note_ref = NoteRef::try_from(TAGGED).unwrap();
+        assert_eq!(
+            note_ref.to_note::<4, 100, 5, 4>(),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(
+            note_ref.to_note::<400, 100, 1, 4>(),
+            Err(Error::TooManyTags)
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Queue of notes waiting to be accepted by a relay
+//!
+//! Notes are retried with an exponentially growing delay until the relay answers with an
+//! accepting `OK` message or the maximum number of attempts is reached. Rejections are sorted
+//! with [`Failure::of`], and a note the relay will never take, such as one answered with
+//! `invalid:` or `blocked:`, is given up on right away. Time is supplied by the caller as unix
+//! seconds, so the queue works without a clock of its own.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{ClientMsgKinds, Note, outbox::{Outbox, OkOutcome}, relay_responses::OkMessage};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("reading: 21C".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! // retry after 30s, 60s, 120s..., give up after 5 attempts
+//! let mut outbox: Outbox<8> = Outbox::new(30, 5);
+//! outbox.push(note).unwrap();
+//!
+//! let now = 1686880020;
+//! if let Some(note) = outbox.next_due(now) {
+//!     let mut frame: nostr_nostd::Vec<u8, 1000> = nostr_nostd::Vec::new();
+//!     note.serialize_to_buf(ClientMsgKinds::Event, &mut frame).unwrap();
+//!     // send frame to the relay
+//! }
+//! // later, the relay answers
+//! # let id = *outbox.iter().next().unwrap().note().id_bytes();
+//! # let ok = OkMessage { event_id: nostr_nostd::hex::HexId::from_bytes(id), accepted: true, info: "".into() };
+//! assert_eq!(outbox.handle_ok(&ok), Some(OkOutcome::Delivered));
+//! assert!(outbox.is_empty());
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    errors::Error,
+    hex::HexId,
+    relay_responses::{MessagePrefix, OkMessage},
+    retry::Failure,
+    Note,
+};
+
+/// A queued note and its delivery state
+pub struct OutboxEntry {
+    note: Note,
+    attempts: u8,
+    next_attempt_at: u64,
+    last_error: Option<String<180>>,
+}
+
+impl OutboxEntry {
+    /// The queued note
+    pub fn note(&self) -> &Note {
+        &self.note
+    }
+
+    /// Number of times the note has been handed out for sending
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
+    /// Unix time at which the note is next due
+    pub fn next_attempt_at(&self) -> u64 {
+        self.next_attempt_at
+    }
+
+    /// Message from the last `OK` rejecting the note, if any
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_ref().map(|err| err.as_str())
+    }
+}
+
+/// What happened to a queued note after an `OK` message
+#[derive(Debug, PartialEq)]
+pub enum OkOutcome {
+    /// The relay accepted the note (or already had it) and it was removed from the queue
+    Delivered,
+    /// The relay rejected the note, it will be retried
+    WillRetry,
+    /// The relay rejected the note for good or no attempts are left, it was removed from the
+    /// queue
+    GaveUp,
+}
+
+/// Fixed-capacity queue of up to `N` notes awaiting delivery
+pub struct Outbox<const N: usize> {
+    entries: Vec<OutboxEntry, N>,
+    retry_interval: u32,
+    max_attempts: u8,
+}
+
+impl<const N: usize> Outbox<N> {
+    /// Creates an empty outbox.
+    /// - `retry_interval` is the delay in seconds before the first retry, doubled for every further attempt
+    /// - `max_attempts` is the number of sends before a rejected note is dropped
+    pub const fn new(retry_interval: u32, max_attempts: u8) -> Self {
+        Outbox {
+            entries: Vec::new(),
+            retry_interval,
+            max_attempts,
+        }
+    }
+
+    /// Number of queued notes
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the queued notes
+    pub fn iter(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter()
+    }
+
+    /// Queues `note` to be sent as soon as possible.
+    /// Errors with `QueueFull` if `N` notes are already queued.
+    pub fn push(&mut self, note: Note) -> Result<(), Error> {
+        self.entries
+            .push(OutboxEntry {
+                note,
+                attempts: 0,
+                next_attempt_at: 0,
+                last_error: None,
+            })
+            .map_err(|_| Error::QueueFull)
+    }
+
+    /// Returns the next note due at `now` and records the attempt.
+    /// Notes that have never been sent come first, then the longest overdue.
+    pub fn next_due(&mut self, now: u64) -> Option<&Note> {
+        let pos = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.next_attempt_at <= now && entry.attempts < self.max_attempts)
+            .min_by_key(|(_, entry)| (entry.attempts != 0, entry.next_attempt_at))
+            .map(|(pos, _)| pos)?;
+        let entry = &mut self.entries[pos];
+        let backoff = self
+            .retry_interval
+            .saturating_mul(1 << entry.attempts.min(16));
+        entry.attempts += 1;
+        entry.next_attempt_at = now.saturating_add(backoff.into());
+        Some(&entry.note)
+    }
+
+    /// Records a relay's `OK` answer for a queued note.
+    /// Returns `None` if the event id does not belong to a queued note.
+    pub fn handle_ok(&mut self, ok: &OkMessage) -> Option<OkOutcome> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|entry| entry.note.id_bytes() == ok.event_id.as_bytes())?;
+        // a relay that already has the event counts as a delivery
+        if ok.accepted || ok.prefix() == Some(MessagePrefix::Duplicate) {
+            self.entries.swap_remove(pos);
+            return Some(OkOutcome::Delivered);
+        }
+        let entry = &mut self.entries[pos];
+        match Failure::of(ok.prefix()) {
+            Failure::Transient | Failure::AuthRequired if entry.attempts < self.max_attempts => {
+                entry.last_error = Some(ok.info.clone());
+                Some(OkOutcome::WillRetry)
+            }
+            _ => {
+                self.entries.swap_remove(pos);
+                Some(OkOutcome::GaveUp)
+            }
+        }
+    }
+
+    /// Removes and returns a note that used up its attempts without the relay answering the
+    /// last one, once the retry delay after it has passed. Call it regularly so these notes
+    /// don't hold on to their slot.
+    pub fn poll_expired(&mut self, now: u64) -> Option<Note> {
+        let pos = self.entries.iter().position(|entry| {
+            entry.attempts >= self.max_attempts && entry.next_attempt_at <= now
+        })?;
+        Some(self.entries.swap_remove(pos).note)
+    }
+
+    /// Removes the note with `id` from the queue, returning it
+    pub fn remove(&mut self, id: &HexId) -> Option<Note> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|entry| entry.note.id_bytes() == id.as_bytes())?;
+        Some(self.entries.swap_remove(pos).note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::note_at;
+
+    fn ok_for(note: &Note, accepted: bool, info: &str) -> OkMessage {
+        OkMessage {
+            event_id: HexId::from_bytes(*note.id_bytes()),
+            accepted,
+            info: info.into(),
+        }
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let mut outbox: Outbox<2> = Outbox::new(10, 3);
+        outbox.push(note_at(1)).unwrap();
+        assert!(outbox.next_due(100).is_some());
+        // in flight until the retry interval passes
+        assert!(outbox.next_due(105).is_none());
+        let reject = ok_for(&note_at(1), false, "rate-limited: slow down");
+        assert_eq!(outbox.handle_ok(&reject), Some(OkOutcome::WillRetry));
+        assert_eq!(
+            outbox.iter().next().unwrap().last_error(),
+            Some("rate-limited: slow down")
+        );
+        assert!(outbox.next_due(110).is_some());
+        // second retry waits twice as long
+        assert!(outbox.next_due(125).is_none());
+        assert!(outbox.next_due(130).is_some());
+        assert_eq!(outbox.handle_ok(&reject), Some(OkOutcome::GaveUp));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn test_permanent_rejection() {
+        let mut outbox: Outbox<2> = Outbox::new(10, 3);
+        outbox.push(note_at(1)).unwrap();
+        outbox.push(note_at(2)).unwrap();
+        outbox.next_due(100);
+        outbox.next_due(100);
+        let invalid = ok_for(&note_at(1), false, "invalid: bad signature");
+        assert_eq!(outbox.handle_ok(&invalid), Some(OkOutcome::GaveUp));
+        let blocked = ok_for(&note_at(2), false, "blocked: not on the allow list");
+        assert_eq!(outbox.handle_ok(&blocked), Some(OkOutcome::GaveUp));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut outbox: Outbox<1> = Outbox::new(10, 2);
+        outbox.push(note_at(1)).unwrap();
+        assert!(outbox.next_due(100).is_some());
+        assert!(outbox.next_due(110).is_some());
+        // no answer to the last attempt, it gets the same delay a retry would have
+        assert!(outbox.next_due(130).is_none());
+        assert!(outbox.poll_expired(129).is_none());
+        assert_eq!(outbox.poll_expired(130), Some(note_at(1)));
+        assert!(outbox.is_empty());
+        outbox.push(note_at(2)).unwrap();
+    }
+
+    #[test]
+    fn test_delivered_and_full() {
+        let mut outbox: Outbox<1> = Outbox::new(10, 3);
+        outbox.push(note_at(1)).unwrap();
+        assert_eq!(outbox.push(note_at(2)), Err(Error::QueueFull));
+        outbox.next_due(0);
+        let dup = ok_for(&note_at(1), false, "duplicate: already have this event");
+        assert_eq!(outbox.handle_ok(&dup), Some(OkOutcome::Delivered));
+        assert_eq!(outbox.handle_ok(&dup), None);
+
+        outbox.push(note_at(2)).unwrap();
+        let upper = note_at(2).id().to_ascii_uppercase();
+        let id = HexId::try_from(&upper[..]).unwrap();
+        assert_eq!(outbox.remove(&id), Some(note_at(2)));
+        assert!(outbox.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! Backfilling history one page at a time
+//!
+//! Relays answer a `REQ` with at most `limit` stored events, newest first. [`Paginator`] asks
+//! again with `until` lowered to the oldest event of the last page, until the relay runs out
+//! of events or the pages reach back to a target `since`, so a device can walk through
+//! history without holding more than one page.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     pagination::{PageStatus, Paginator},
+//!     query::Query,
+//!     relay_responses::RelayMessage,
+//!     Vec,
+//! };
+//! let mut query = Query::new();
+//! query.limit = Some(1);
+//! // everything back to 1686880000
+//! let mut pages = Paginator::new("history", query, 1686880000).unwrap();
+//! let mut req: Vec<u8, 256> = Vec::new();
+//! assert!(pages.next_req(&mut req).unwrap());
+//! assert_eq!(req, br#"["REQ","history",{"since":1686880000,"limit":1}]"#);
+//!
+//! // send req, then feed what the relay answers
+//! let event = RelayMessage::try_from(r#"["EVENT","history",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#).unwrap();
+//! assert_eq!(pages.handle(&event), None);
+//! let eose = RelayMessage::try_from(r#"["EOSE","history"]"#).unwrap();
+//! assert_eq!(pages.handle(&eose), Some(PageStatus::More));
+//!
+//! req.clear();
+//! assert!(pages.next_req(&mut req).unwrap());
+//! assert_eq!(
+//!     req,
+//!     br#"["REQ","history",{"since":1686880000,"until":1686880020,"limit":1}]"#
+//! );
+//! ```
+
+use crate::{
+    buffer::OutBuf,
+    errors::Error,
+    query::{Query, SubscriptionId},
+    relay_responses::RelayMessage,
+    Note,
+};
+
+/// Where a [`Paginator`] stands after a page ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PageStatus {
+    /// There may be older events, [`Paginator::next_req`] writes the request for them
+    More,
+    /// Every event back to `since` has been received
+    Done,
+}
+
+/// Walks a [`Query`] back through history in windows of `limit` events
+pub struct Paginator {
+    subscription_id: SubscriptionId,
+    query: Query,
+    /// Oldest `created_at` seen on the current page
+    oldest: Option<u64>,
+    /// Events received on the current page
+    received: u32,
+    done: bool,
+}
+
+impl Paginator {
+    /// Pages through `query` back to `since`, under `subscription_id`. The query's `limit`
+    /// is the size of a page, without one everything comes in a single page. Its `until`, if
+    /// set, is where the first page starts.
+    /// Errors with `InvalidSubscriptionId` if `subscription_id` is not a valid
+    /// [`SubscriptionId`].
+    pub fn new(subscription_id: &str, mut query: Query, since: u64) -> Result<Self, Error> {
+        let subscription_id = SubscriptionId::try_from(subscription_id)?;
+        query.since = Some(since);
+        Ok(Paginator {
+            subscription_id,
+            query,
+            oldest: None,
+            received: 0,
+            done: false,
+        })
+    }
+
+    /// Id the requests are sent under
+    pub fn subscription_id(&self) -> &str {
+        &self.subscription_id
+    }
+
+    /// The query of the current window
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// True once every event back to `since` has been received
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Writes the `REQ` for the current window into `out`, starting a new page.
+    /// Returns false, writing nothing, once every event back to `since` has been received.
+    /// The relay replaces the previous page's subscription, since they share an id.
+    /// Errors with `ContentOverflow` if `out` runs out of room.
+    pub fn next_req<B: OutBuf>(&mut self, out: &mut B) -> Result<bool, Error> {
+        if self.done {
+            return Ok(false);
+        }
+        self.oldest = None;
+        self.received = 0;
+        self.query.serialize_to_buf(&self.subscription_id, out)?;
+        Ok(true)
+    }
+
+    /// Records a note received on the current page
+    pub fn on_note<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &mut self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) {
+        self.received = self.received.saturating_add(1);
+        let created_at = note.created_at();
+        self.oldest = Some(
+            self.oldest
+                .map_or(created_at, |oldest| oldest.min(created_at)),
+        );
+    }
+
+    /// Ends the current page, moving the window back to its oldest event.
+    /// Events sharing the oldest timestamp may be split across pages, so the next window
+    /// includes that timestamp again unless the page made no progress, which would repeat it
+    /// forever.
+    pub fn on_eose(&mut self) -> PageStatus {
+        let full = self.query.limit.is_some_and(|limit| self.received >= limit);
+        let next_until = match self.oldest {
+            Some(oldest) if full => {
+                if self.query.until == Some(oldest) {
+                    oldest.checked_sub(1)
+                } else {
+                    Some(oldest)
+                }
+            }
+            // the relay had fewer events than fit on a page
+            _ => None,
+        };
+        let since = self.query.since.unwrap_or(0);
+        match next_until {
+            Some(until) if until >= since => {
+                self.query.until = Some(until);
+                PageStatus::More
+            }
+            _ => {
+                self.done = true;
+                PageStatus::Done
+            }
+        }
+    }
+
+    /// Feeds a relay message to the paginator, returning the status once `EOSE` ends the page.
+    /// Messages for other subscriptions are ignored.
+    pub fn handle(&mut self, msg: &RelayMessage) -> Option<PageStatus> {
+        match msg {
+            RelayMessage::Event(event)
+                if event.subscription_id == self.subscription_id.as_str() =>
+            {
+                self.on_note(&event.note);
+                None
+            }
+            RelayMessage::Eose(eose) if eose.subscription_id == self.subscription_id.as_str() => {
+                Some(self.on_eose())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::vectors::note_at;
+
+    fn paginator(limit: Option<u32>) -> Paginator {
+        let mut query = Query::new();
+        query.limit = limit;
+        Paginator::new("page", query, 100).unwrap()
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut pages = paginator(Some(2));
+        let mut req: Vec<u8, 128> = Vec::new();
+        assert_eq!(pages.next_req(&mut req), Ok(true));
+        pages.on_note(&note_at(300));
+        pages.on_note(&note_at(250));
+        assert_eq!(pages.on_eose(), PageStatus::More);
+        assert_eq!(pages.query().until, Some(250));
+
+        req.clear();
+        assert_eq!(pages.next_req(&mut req), Ok(true));
+        assert_eq!(
+            req,
+            br#"["REQ","page",{"since":100,"until":250,"limit":2}]"#
+        );
+        // a full page at the same timestamp moves past it
+        pages.on_note(&note_at(250));
+        pages.on_note(&note_at(250));
+        assert_eq!(pages.on_eose(), PageStatus::More);
+        assert_eq!(pages.query().until, Some(249));
+
+        req.clear();
+        pages.next_req(&mut req).unwrap();
+        pages.on_note(&note_at(120));
+        assert_eq!(pages.on_eose(), PageStatus::Done);
+        assert!(pages.is_done());
+        req.clear();
+        assert_eq!(pages.next_req(&mut req), Ok(false));
+        assert!(req.is_empty());
+    }
+
+    #[test]
+    fn test_done() {
+        // without a limit everything comes at once
+        let mut pages = paginator(None);
+        let mut req: Vec<u8, 512> = Vec::new();
+        pages.next_req(&mut req).unwrap();
+        pages.on_note(&note_at(300));
+        assert_eq!(pages.on_eose(), PageStatus::Done);
+
+        // an empty page
+        let mut pages = paginator(Some(2));
+        pages.next_req(&mut req).unwrap();
+        assert_eq!(pages.on_eose(), PageStatus::Done);
+
+        // a full page reaching past since
+        let mut pages = paginator(Some(1));
+        pages.next_req(&mut req).unwrap();
+        pages.on_note(&note_at(100));
+        assert_eq!(pages.on_eose(), PageStatus::More);
+        pages.next_req(&mut req).unwrap();
+        pages.on_note(&note_at(100));
+        assert_eq!(pages.on_eose(), PageStatus::Done);
+
+        assert_eq!(
+            Paginator::new(core::str::from_utf8(&[b'a'; 65]).unwrap(), Query::new(), 0).err(),
+            Some(Error::InvalidSubscriptionId)
+        );
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut pages = paginator(Some(1));
+        let mut req: Vec<u8, 128> = Vec::new();
+        pages.next_req(&mut req).unwrap();
+        let other = RelayMessage::try_from(r#"["EOSE","other"]"#).unwrap();
+        assert_eq!(pages.handle(&other), None);
+        let eose = RelayMessage::try_from(r#"["EOSE","page"]"#).unwrap();
+        assert_eq!(pages.handle(&eose), Some(PageStatus::Done));
+    }
+}
@@ -0,0 +1,640 @@
+//! Talk to several relays, and track which of them accepted a published note
+//!
+//! The pool does no networking itself. [`RelayPool::publish`] serializes the note once and
+//! hands the frame to a callback for every relay, then [`RelayPool::handle_ok`] records the
+//! `OK` message each relay answers with.
+//!
+//! Each relay is known by the index [`RelayPool::add_relay`] returned. Frames received from a
+//! relay go to [`RelayPool::receive`] with its index, which keeps that relay's authentication
+//! and subscriptions up to date, and [`RelayPool::poll_transmit`] hands back frames to send
+//! along with the index of the relay they are for.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{hex::HexId, Note, pool::RelayPool, relay_responses::OkMessage};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut pool: RelayPool<2> = RelayPool::new();
+//! let damus = pool.add_relay("wss://relay.damus.io").unwrap();
+//! let nos = pool.add_relay("wss://nos.lol").unwrap();
+//! pool.publish(&note, |_url, _frame| {
+//!     // write frame to the websocket connected to url
+//!     Ok(())
+//! })
+//! .unwrap();
+//!
+//! let ok = OkMessage { event_id: HexId::from_bytes(*note.id_bytes()), accepted: true, info: "".into() };
+//! pool.handle_ok(damus, &ok);
+//! let summary = pool.summary();
+//! assert_eq!(summary.accepted, 1);
+//! assert_eq!(summary.pending, 1);
+//! assert!(summary.is_success());
+//! # let _ = nos;
+//! ```
+//!
+//! ```
+//! use nostr_nostd::{
+//!     keys::{AlignedType, Keys, CONTEXT_SIZE},
+//!     pool::{PoolEvent, RelayPool},
+//!     query::Query,
+//!     NoteBuffer,
+//! };
+//! let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+//! let keys = Keys::new(
+//!     "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3",
+//!     &mut context,
+//! )
+//! .unwrap();
+//! let mut pool: RelayPool<2> = RelayPool::new();
+//! pool.add_relay("wss://relay.damus.io").unwrap();
+//! let nos = pool.add_relay("wss://nos.lol").unwrap();
+//! pool.subscribe(&Query::new(), |_index, _frame| {
+//!     // write frame to the websocket of the relay at index
+//!     Ok(())
+//! })
+//! .unwrap();
+//!
+//! let event = pool.receive(nos, br#"["AUTH","challenge_me"]"#).unwrap();
+//! assert_eq!(event, PoolEvent::AuthRequested);
+//! let mut frame = NoteBuffer::new();
+//! while let Some(index) = pool.poll_transmit(&keys, 1686880020, [0; 32], &mut frame).unwrap() {
+//!     assert_eq!(index, nos);
+//!     // write frame to the websocket of the relay at index
+//!     frame.clear();
+//! }
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    buffer::OutBuf,
+    client::AuthState,
+    errors::Error,
+    hex::HexId,
+    keys::Keys,
+    query::Query,
+    relay_responses::{MessagePrefix, NoticeMessage, OkMessage, RelayMessage},
+    seen::SeenCache,
+    subscription_manager::{Received, SubscriptionManager},
+    ClientMsgKinds, Note, NoteBuffer, MAX_REQ_SIZE,
+};
+
+/// Longest relay url the pool can store
+pub const RELAY_URL_SIZE: usize = 64;
+/// Subscriptions each relay of a pool holds by default
+pub const POOL_SUBS: usize = 4;
+
+/// Where a relay stands with the note currently being published
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PublishStatus {
+    /// Nothing has been published to this relay
+    Idle,
+    /// The frame was sent and no `OK` has arrived yet
+    Pending,
+    /// The relay accepted the note
+    Accepted,
+    /// The relay answered with `OK false`
+    Rejected,
+    /// The send callback returned an error for this relay
+    SendFailed,
+}
+
+/// Counts of relays in each state for the note being published
+#[derive(Debug, PartialEq, Default)]
+pub struct PublishSummary {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub pending: usize,
+    pub failed: usize,
+}
+
+impl PublishSummary {
+    /// True once at least one relay accepted the note
+    pub fn is_success(&self) -> bool {
+        self.accepted > 0
+    }
+
+    /// True once no relay is still expected to answer
+    pub fn is_settled(&self) -> bool {
+        self.pending == 0
+    }
+}
+
+/// What a frame received from one of the relays meant
+// an Event holds a whole note, there is no heap to box it on
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum PoolEvent {
+    /// An event arrived on the relay's subscription in `slot`
+    Event { slot: usize, note: Note },
+    /// An event already seen arrived on the relay's subscription in `slot`, see
+    /// [`RelayPool::receive_unseen`]
+    Duplicate { slot: usize },
+    /// The relay's subscription in `slot` has received every stored event
+    Eose { slot: usize },
+    /// The relay ended its subscription in `slot`, the reason is in the subscription's state
+    Closed { slot: usize },
+    /// The relay counted `count` events for its subscription in `slot`
+    Count { slot: usize, count: u16 },
+    /// The relay asked for authentication, the reply is waiting in [`RelayPool::poll_transmit`]
+    AuthRequested,
+    /// The relay accepted the authentication reply
+    Authenticated,
+    /// The relay rejected the authentication reply
+    AuthFailed,
+    /// The relay answered the note being published, leaving it in this status
+    Published(PublishStatus),
+    /// A human-readable message from the relay
+    Notice(NoticeMessage),
+    /// The message was about a relay, subscription or note the pool doesn't know
+    Ignored,
+}
+
+struct Relay<const SUBS: usize> {
+    url: String<RELAY_URL_SIZE>,
+    status: PublishStatus,
+    auth: AuthState,
+    subscriptions: SubscriptionManager<SUBS>,
+}
+
+/// A set of up to `N` relays publishing one note at a time, each holding up to `SUBS`
+/// subscriptions
+pub struct RelayPool<const N: usize, const SUBS: usize = POOL_SUBS> {
+    relays: Vec<Relay<SUBS>, N>,
+    event_id: Option<HexId>,
+}
+
+impl<const N: usize, const SUBS: usize> Default for RelayPool<N, SUBS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const SUBS: usize> RelayPool<N, SUBS> {
+    /// Creates a pool with no relays
+    pub const fn new() -> Self {
+        RelayPool {
+            relays: Vec::new(),
+            event_id: None,
+        }
+    }
+
+    /// Adds a relay, returning the index used to report its messages.
+    /// Errors with `QueueFull` if the pool already holds `N` relays.
+    pub fn add_relay(&mut self, url: &str) -> Result<usize, Error> {
+        let mut relay_url = String::new();
+        relay_url
+            .push_str(url)
+            .map_err(|_| Error::ContentOverflow)?;
+        self.relays
+            .push(Relay {
+                url: relay_url,
+                status: PublishStatus::Idle,
+                auth: AuthState::None,
+                subscriptions: SubscriptionManager::new(),
+            })
+            .map_err(|_| Error::QueueFull)?;
+        Ok(self.relays.len() - 1)
+    }
+
+    /// Number of relays in the pool
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    /// True if the pool has no relays
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    /// Url of the relay at `index`
+    pub fn url(&self, index: usize) -> Option<&str> {
+        self.relays.get(index).map(|relay| relay.url.as_str())
+    }
+
+    /// Status of the relay at `index` for the note being published
+    pub fn status(&self, index: usize) -> Option<PublishStatus> {
+        self.relays.get(index).map(|relay| relay.status)
+    }
+
+    /// Where authentication to the relay at `index` stands
+    pub fn auth_state(&self, index: usize) -> Option<&AuthState> {
+        self.relays.get(index).map(|relay| &relay.auth)
+    }
+
+    /// Subscriptions held with the relay at `index`
+    pub fn subscriptions(&self, index: usize) -> Option<&SubscriptionManager<SUBS>> {
+        self.relays.get(index).map(|relay| &relay.subscriptions)
+    }
+
+    /// Starts a subscription on the relay at `index`, writing its `REQ` into `out` and
+    /// returning its slot.
+    /// Errors with `InternalError` if there is no relay at `index`. On other errors `out` may end
+    /// in an unfinished `REQ`, see [`SubscriptionManager::subscribe`].
+    pub fn subscribe_to<B: OutBuf>(
+        &mut self,
+        index: usize,
+        query: &Query,
+        out: &mut B,
+    ) -> Result<usize, Error> {
+        let relay = self.relays.get_mut(index).ok_or(Error::InternalError)?;
+        relay.subscriptions.subscribe(query, out)
+    }
+
+    /// Starts a subscription on every relay, passing each `REQ` to `send` with the index of
+    /// the relay it is for.
+    /// Stops at the first error, from either a relay running out of subscriptions or `send`.
+    pub fn subscribe<F>(&mut self, query: &Query, mut send: F) -> Result<(), Error>
+    where
+        F: FnMut(usize, &[u8]) -> Result<(), Error>,
+    {
+        let mut frame: Vec<u8, MAX_REQ_SIZE> = Vec::new();
+        for index in 0..self.relays.len() {
+            frame.clear();
+            self.subscribe_to(index, query, &mut frame)?;
+            send(index, &frame)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the subscription in `slot` of the relay at `index`, writing its `CLOSE` into `out`
+    /// if the relay still has it open.
+    /// Errors with `InternalError` if there is no relay at `index`.
+    pub fn unsubscribe<B: OutBuf>(
+        &mut self,
+        index: usize,
+        slot: usize,
+        out: &mut B,
+    ) -> Result<(), Error> {
+        let relay = self.relays.get_mut(index).ok_or(Error::InternalError)?;
+        relay.subscriptions.close(slot, out)
+    }
+
+    /// Forgets the authentication and subscriptions of the relay at `index`, call when its
+    /// connection drops
+    pub fn on_disconnected(&mut self, index: usize) {
+        if let Some(relay) = self.relays.get_mut(index) {
+            relay.auth = AuthState::None;
+            relay.subscriptions.clear();
+        }
+    }
+
+    /// Handles a frame received from the relay at `index`.
+    /// Errors if the frame isn't a relay message this crate can parse.
+    pub fn receive(&mut self, index: usize, frame: &[u8]) -> Result<PoolEvent, Error> {
+        let msg = RelayMessage::try_from(frame)?;
+        let Some(relay) = self.relays.get_mut(index) else {
+            return Ok(PoolEvent::Ignored);
+        };
+        let slot = relay.subscriptions.handle(&msg);
+        Ok(self.handle(index, msg, slot))
+    }
+
+    /// Same as [`RelayPool::receive`], but an `EVENT` whose id is in `seen`, such as one another
+    /// relay of the pool already delivered, is reported as [`PoolEvent::Duplicate`] without
+    /// copying the note out or checking its signature. Ids of events that pass the checks are
+    /// added to `seen`.
+    pub fn receive_unseen<const S: usize>(
+        &mut self,
+        index: usize,
+        frame: &[u8],
+        seen: &mut SeenCache<S>,
+    ) -> Result<PoolEvent, Error> {
+        let Some(relay) = self.relays.get_mut(index) else {
+            RelayMessage::try_from(frame)?;
+            return Ok(PoolEvent::Ignored);
+        };
+        Ok(match relay.subscriptions.handle_unseen(frame, seen)? {
+            Received::Duplicate { slot } => PoolEvent::Duplicate { slot },
+            Received::Message { slot, msg } => self.handle(index, msg, slot),
+        })
+    }
+
+    /// Updates the state of the relay at `index` with a message parsed from a frame it sent,
+    /// already recorded against its subscription in `slot`
+    fn handle(&mut self, index: usize, msg: RelayMessage, slot: Option<usize>) -> PoolEvent {
+        let Some(relay) = self.relays.get_mut(index) else {
+            return PoolEvent::Ignored;
+        };
+        match msg {
+            RelayMessage::Auth(auth) => {
+                relay.auth = AuthState::Challenged(auth);
+                PoolEvent::AuthRequested
+            }
+            RelayMessage::Ok(ok) => {
+                if let AuthState::Sent(id) = &relay.auth {
+                    if *id == ok.event_id {
+                        return if ok.accepted {
+                            relay.auth = AuthState::Authenticated;
+                            PoolEvent::Authenticated
+                        } else {
+                            relay.auth = AuthState::Failed(ok.info);
+                            PoolEvent::AuthFailed
+                        };
+                    }
+                }
+                self.handle_ok(index, &ok)
+                    .map_or(PoolEvent::Ignored, PoolEvent::Published)
+            }
+            RelayMessage::Notice(notice) => PoolEvent::Notice(notice),
+            RelayMessage::Event(event) => match slot {
+                Some(slot) => PoolEvent::Event {
+                    slot,
+                    note: event.note,
+                },
+                None => PoolEvent::Ignored,
+            },
+            RelayMessage::Eose(_) => {
+                slot.map_or(PoolEvent::Ignored, |slot| PoolEvent::Eose { slot })
+            }
+            RelayMessage::Closed(_) => {
+                slot.map_or(PoolEvent::Ignored, |slot| PoolEvent::Closed { slot })
+            }
+            RelayMessage::Count(count) => {
+                slot.map_or(PoolEvent::Ignored, |slot| PoolEvent::Count {
+                    slot,
+                    count: count.count,
+                })
+            }
+        }
+    }
+
+    /// Writes the next frame to send into `out`, returning the index of the relay it is for,
+    /// or `None` if there is nothing to send. These are authentication replies signed by
+    /// `keys` at unix time `now` with `aux_rand`, one for each relay that sent a challenge.
+    pub fn poll_transmit<B: OutBuf>(
+        &mut self,
+        keys: &Keys,
+        now: u64,
+        aux_rand: [u8; 32],
+        out: &mut B,
+    ) -> Result<Option<usize>, Error> {
+        for (index, relay) in self.relays.iter_mut().enumerate() {
+            if let AuthState::Challenged(auth) = &relay.auth {
+                let reply = Note::builder_with_keys(keys)
+                    .create_auth(auth, &relay.url)?
+                    .build_with_keys(keys, now, aux_rand)?;
+                reply.serialize_to_buf(ClientMsgKinds::Auth, out)?;
+                relay.auth = AuthState::Sent(reply.hex_id());
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Serializes `note` as an `EVENT` frame and passes it to `send` once per relay, with the relay's url.
+    /// Any earlier publish is forgotten. A relay whose `send` call errors is marked `SendFailed`.
+    pub fn publish<F>(&mut self, note: &Note, mut send: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, &[u8]) -> Result<(), Error>,
+    {
+        let mut frame = NoteBuffer::new();
+        note.serialize_to_buf(ClientMsgKinds::Event, &mut frame)?;
+        self.event_id = Some(note.hex_id());
+        self.relays.iter_mut().for_each(|relay| {
+            relay.status = match send(&relay.url, &frame) {
+                Ok(()) => PublishStatus::Pending,
+                Err(_) => PublishStatus::SendFailed,
+            };
+        });
+        Ok(())
+    }
+
+    /// Records an `OK` message received from the relay at `index`.
+    /// Messages about other events are ignored and `None` is returned.
+    pub fn handle_ok(&mut self, index: usize, ok: &OkMessage) -> Option<PublishStatus> {
+        if self.event_id? != ok.event_id {
+            return None;
+        }
+        let relay = self.relays.get_mut(index)?;
+        // a relay that already has the event counts as accepting it
+        relay.status = if ok.accepted || ok.prefix() == Some(MessagePrefix::Duplicate) {
+            PublishStatus::Accepted
+        } else {
+            PublishStatus::Rejected
+        };
+        Some(relay.status)
+    }
+
+    /// Aggregates the status of every relay for the note being published
+    pub fn summary(&self) -> PublishSummary {
+        let mut summary = PublishSummary::default();
+        self.relays.iter().for_each(|relay| match relay.status {
+            PublishStatus::Accepted => summary.accepted += 1,
+            PublishStatus::Rejected => summary.rejected += 1,
+            PublishStatus::Pending => summary.pending += 1,
+            PublishStatus::SendFailed => summary.failed += 1,
+            PublishStatus::Idle => {}
+        });
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hex::HexId,
+        keys::{AlignedType, CONTEXT_SIZE},
+        nip42::verify_auth_event,
+        subscription_manager::SubscriptionState,
+        vectors::{text_note, PRIVKEY},
+    };
+
+    fn ok_msg(accepted: bool, info: &str) -> OkMessage {
+        OkMessage {
+            event_id: HexId::from_bytes(*text_note().id_bytes()),
+            accepted,
+            info: info.into(),
+        }
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let mut pool: RelayPool<3> = RelayPool::new();
+        pool.add_relay("wss://a.example").unwrap();
+        pool.add_relay("wss://b.example").unwrap();
+        pool.add_relay("wss://c.example").unwrap();
+        assert_eq!(pool.add_relay("wss://d.example"), Err(Error::QueueFull));
+        let mut sent = 0;
+        pool.publish(&text_note(), |url, frame| {
+            assert!(frame.starts_with(br#"["EVENT",{"#));
+            sent += 1;
+            if url == "wss://c.example" {
+                Err(Error::InternalError)
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+        assert_eq!(sent, 3);
+        assert_eq!(pool.status(2), Some(PublishStatus::SendFailed));
+        assert!(!pool.summary().is_settled());
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let mut pool: RelayPool<2> = RelayPool::new();
+        let a = pool.add_relay("wss://a.example").unwrap();
+        let b = pool.add_relay("wss://b.example").unwrap();
+        pool.publish(&text_note(), |_, _| Ok(())).unwrap();
+        assert_eq!(
+            pool.handle_ok(a, &ok_msg(false, "blocked: not on allow list")),
+            Some(PublishStatus::Rejected)
+        );
+        assert!(!pool.summary().is_success());
+        assert_eq!(
+            pool.handle_ok(b, &ok_msg(false, "duplicate: have it")),
+            Some(PublishStatus::Accepted)
+        );
+        let summary = pool.summary();
+        assert!(summary.is_success() && summary.is_settled());
+        assert_eq!(summary.rejected, 1);
+
+        let mut other = ok_msg(true, "");
+        other.event_id = HexId::from_bytes([0; 32]);
+        assert_eq!(pool.handle_ok(a, &other), None);
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut pool: RelayPool<2, 2> = RelayPool::new();
+        let a = pool.add_relay("wss://a.example").unwrap();
+        let b = pool.add_relay("wss://b.example").unwrap();
+        let mut sent: Vec<usize, 4> = Vec::new();
+        pool.subscribe(&Query::new(), |index, frame| {
+            assert_eq!(frame, br#"["REQ","sub_0",{}]"#);
+            sent.push(index).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sent, [a, b]);
+        let mut out: Vec<u8, 64> = Vec::new();
+        assert_eq!(pool.subscribe_to(b, &Query::new(), &mut out), Ok(1));
+        assert_eq!(out, br#"["REQ","sub_1",{}]"#);
+
+        assert_eq!(
+            pool.receive(b, br#"["EOSE","sub_0"]"#),
+            Ok(PoolEvent::Eose { slot: 0 })
+        );
+        assert!(pool.subscriptions(b).unwrap().get(0).unwrap().is_live());
+        assert_eq!(
+            pool.subscriptions(a).unwrap().get(0).unwrap().state(),
+            &SubscriptionState::Loading
+        );
+        assert_eq!(
+            pool.receive(a, br#"["EOSE","sub_1"]"#),
+            Ok(PoolEvent::Ignored)
+        );
+        assert_eq!(
+            pool.receive(5, br#"["EOSE","sub_0"]"#),
+            Ok(PoolEvent::Ignored)
+        );
+        let event = pool.receive(a, br#"["EVENT","sub_0",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#);
+        assert_eq!(
+            event,
+            Ok(PoolEvent::Event {
+                slot: 0,
+                note: text_note()
+            })
+        );
+
+        out.clear();
+        pool.unsubscribe(b, 1, &mut out).unwrap();
+        assert_eq!(out, br#"["CLOSE","sub_1"]"#);
+        assert_eq!(
+            pool.subscribe_to(3, &Query::new(), &mut out),
+            Err(Error::InternalError)
+        );
+        pool.on_disconnected(a);
+        assert!(pool.subscriptions(a).unwrap().is_empty());
+        assert_eq!(pool.subscriptions(b).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_receive_unseen() {
+        const EVENT: &[u8] = br#"["EVENT","sub_0",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+        let mut pool: RelayPool<2> = RelayPool::new();
+        let a = pool.add_relay("wss://a.example").unwrap();
+        let b = pool.add_relay("wss://b.example").unwrap();
+        pool.subscribe(&Query::new(), |_, _| Ok(())).unwrap();
+        let mut seen: SeenCache<8> = SeenCache::new();
+
+        assert_eq!(
+            pool.receive_unseen(a, EVENT, &mut seen),
+            Ok(PoolEvent::Event {
+                slot: 0,
+                note: text_note()
+            })
+        );
+        assert_eq!(
+            pool.receive_unseen(b, EVENT, &mut seen),
+            Ok(PoolEvent::Duplicate { slot: 0 })
+        );
+        assert_eq!(pool.subscriptions(a).unwrap().get(0).unwrap().events(), 1);
+        assert_eq!(pool.subscriptions(b).unwrap().get(0).unwrap().events(), 0);
+        assert_eq!(seen.len(), 1);
+        assert_eq!(
+            pool.receive_unseen(b, br#"["EOSE","sub_0"]"#, &mut seen),
+            Ok(PoolEvent::Eose { slot: 0 })
+        );
+        assert_eq!(
+            pool.receive_unseen(5, EVENT, &mut seen),
+            Ok(PoolEvent::Ignored)
+        );
+    }
+
+    #[test]
+    fn test_auth_per_relay() {
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let mut pool: RelayPool<2> = RelayPool::new();
+        let a = pool.add_relay("wss://a.example").unwrap();
+        let b = pool.add_relay("wss://b.example").unwrap();
+        let mut out = NoteBuffer::new();
+        assert_eq!(pool.poll_transmit(&keys, 100, [0; 32], &mut out), Ok(None));
+
+        assert_eq!(
+            pool.receive(b, br#"["AUTH","challenge_me"]"#),
+            Ok(PoolEvent::AuthRequested)
+        );
+        assert_eq!(pool.auth_state(a), Some(&AuthState::None));
+        assert_eq!(
+            pool.poll_transmit(&keys, 100, [0; 32], &mut out),
+            Ok(Some(b))
+        );
+        let json = core::str::from_utf8(&out[r#"["AUTH","#.len()..out.len() - 1]).unwrap();
+        let auth = Note::try_from(json).unwrap();
+        assert_eq!(
+            verify_auth_event(&auth, "challenge_me", "wss://b.example", 100),
+            Ok(())
+        );
+        out.clear();
+        assert_eq!(pool.poll_transmit(&keys, 100, [0; 32], &mut out), Ok(None));
+
+        let mut ok: Vec<u8, 256> = Vec::new();
+        ok.extend_from_slice(br#"["OK",""#).unwrap();
+        // matched on the decoded id, whatever case the relay writes it in
+        ok.extend_from_slice(&auth.id().to_ascii_uppercase())
+            .unwrap();
+        ok.extend_from_slice(br#"",true,""]"#).unwrap();
+        assert_eq!(pool.receive(a, &ok), Ok(PoolEvent::Ignored));
+        assert_eq!(pool.receive(b, &ok), Ok(PoolEvent::Authenticated));
+        assert_eq!(pool.auth_state(b), Some(&AuthState::Authenticated));
+
+        pool.publish(&text_note(), |_, _| Ok(())).unwrap();
+        let mut ok: Vec<u8, 256> = Vec::new();
+        ok.extend_from_slice(br#"["OK",""#).unwrap();
+        ok.extend_from_slice(&text_note().id()).unwrap();
+        ok.extend_from_slice(br#"",false,"blocked: no"]"#).unwrap();
+        assert_eq!(
+            pool.receive(a, &ok),
+            Ok(PoolEvent::Published(PublishStatus::Rejected))
+        );
+        pool.on_disconnected(b);
+        assert_eq!(pool.auth_state(b), Some(&AuthState::None));
+    }
+}
@@ -0,0 +1,340 @@
+//! Proof of work per [NIP-13](https://github.com/nostr-protocol/nips/blob/master/13.md)
+//!
+//! Some relays only accept events whose id starts with a number of zero bits. Mining adds a
+//! `["nonce", <nonce>, <target>]` tag and counts the nonce up until the id is small enough.
+//! That can take millions of hashes, so [`PowOp::mine_step`] tries a bounded number of nonces
+//! per call and the main loop keeps running in between.
+//!
+//! Everything in the hashed form of the note before the nonce is hashed once up front, each
+//! attempt only hashes the nonce and what follows it. [`NoteBuilder::mine_with_hasher`] mines
+//! with a hardware [`Sha256Backend`] instead.
+//!
+//! # Example
+//! ```
+//! use core::task::Poll;
+//! use nostr_nostd::{Note, buffer::SCRATCH_SIZE};
+//! let mut scratch = [0_u8; SCRATCH_SIZE];
+//! let mut op = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .mine(1686880020, 8, [0; 32], &mut scratch)
+//!     .unwrap();
+//! let note = loop {
+//!     if let Poll::Ready(note) = op.mine_step(100) {
+//!         break note.unwrap();
+//!     }
+//!     // run other tasks here
+//! };
+//! assert!(note.pow_difficulty() >= 8);
+//! assert_eq!(note.pow_target(), Some(8));
+//! ```
+
+use core::task::Poll;
+
+use sha2::Sha256;
+
+use crate::{
+    backend::Sha256Backend,
+    buffer::{OutBuf, SliceBuf, StoredBuf},
+    curve::KeyPair,
+    errors::Error,
+    tag::{Tag, MAX_TAG_VALUES, TAG_VALUE_SIZE},
+    utils::{to_decimal_str, write_escaped, write_hex},
+    Note, NoteBuilder, MAX_TAGS, NOTE_SIZE,
+};
+
+/// Number of leading zero bits in `hash`
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// A note whose id is being mined to a difficulty, created by [`NoteBuilder::mine`]
+pub struct PowOp<
+    'a,
+    H: Sha256Backend + Clone = Sha256,
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    keypair: KeyPair,
+    note: Option<Note<CONTENT, TAG, NTAGS, VALUES>>,
+    aux_rnd: [u8; 32],
+    difficulty: u8,
+    nonce: u64,
+    /// State after hashing everything up to the nonce
+    prefix: H,
+    /// Hashed form of the note after the nonce
+    suffix: &'a [u8],
+}
+
+impl<
+        H: Sha256Backend + Clone,
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    > PowOp<'_, H, CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Next nonce to be tried
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Tries up to `budget_iterations` nonces.
+    /// Returns the signed note once its id meets the difficulty, calling it again afterwards
+    /// errors with `InternalError`.
+    pub fn mine_step(
+        &mut self,
+        budget_iterations: u32,
+    ) -> Poll<Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error>> {
+        if self.note.is_none() {
+            return Poll::Ready(Err(Error::InternalError));
+        }
+        for _ in 0..budget_iterations.max(1) {
+            let nonce = to_decimal_str(self.nonce);
+            let mut hasher = self.prefix.clone();
+            hasher.update(nonce.as_bytes());
+            hasher.update(self.suffix);
+            let hash = hasher.finish();
+            if leading_zero_bits(&hash) >= u32::from(self.difficulty) {
+                return Poll::Ready(self.finish(&nonce, hash));
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+        Poll::Pending
+    }
+
+    /// Adds the winning nonce tag, sets the id and signs
+    fn finish(
+        &mut self,
+        nonce: &str,
+        id: [u8; 32],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let mut note = self.note.take().ok_or(Error::InternalError)?;
+        let target = to_decimal_str(self.difficulty.into());
+        let tag = Tag::try_from(["nonce", nonce, &target])?;
+        note.tags.push(tag).map_err(|_| Error::TooManyTags)?;
+        note.id = id;
+        note.set_sig(&self.keypair, &self.aux_rnd)?;
+        Ok(note)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Sets `created_at` and returns a [`PowOp`] that mines the id to at least `difficulty`
+    /// leading zero bits, then signs.
+    /// `scratch` holds the serialized note until the op is finished and
+    /// should be at least [`buffer::SCRATCH_SIZE`](crate::buffer::SCRATCH_SIZE) bytes.
+    /// Errors with `TooManyTags` if the note has no room for the nonce tag, `ContentOverflow` if
+    /// its tags can't hold a 20 digit nonce, and `MissingPrivkey` if the builder was created
+    /// from a [`Signer`](crate::signer::Signer).
+    pub fn mine(
+        self,
+        created_at: u64,
+        difficulty: u8,
+        aux_rnd: [u8; 32],
+        scratch: &mut [u8],
+    ) -> Result<PowOp<'_, Sha256, CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.mine_with_hasher(created_at, difficulty, aux_rnd, scratch, Sha256::default())
+    }
+
+    /// Same as [`NoteBuilder::mine`], hashing with `hasher`. Each attempt hashes with a clone
+    /// of the state reached after the part before the nonce.
+    pub fn mine_with_hasher<H: Sha256Backend + Clone>(
+        mut self,
+        created_at: u64,
+        difficulty: u8,
+        aux_rnd: [u8; 32],
+        scratch: &mut [u8],
+        mut prefix: H,
+    ) -> Result<PowOp<'_, H, CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let keypair = self.keypair.take().ok_or(Error::MissingPrivkey)?;
+        if self.note.tags.is_full() {
+            return Err(Error::TooManyTags);
+        }
+        // the tag added once mined, with the longest nonce there can be
+        let target = to_decimal_str(difficulty.into());
+        Tag::<TAG, VALUES>::try_from(["nonce", &to_decimal_str(u64::MAX), &target])?;
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        let note = &self.note;
+
+        let mut out = SliceBuf::new(&mut *scratch);
+        prefix.reset();
+        let write_prefix = |out: &mut SliceBuf| -> Result<(), Error> {
+            out.push_slice(br#"[0,""#)?;
+            write_hex(out, &note.pubkey)?;
+            out.push_slice(br#"","#)?;
+            out.push_slice(note.timestamp_bytes().as_bytes())?;
+            out.push_byte(b',')?;
+            out.push_slice(note.kind.serialize().as_bytes())?;
+            out.push_slice(b",[")?;
+            for tag in note.tags.iter() {
+                tag.write_json(out)?;
+                out.push_byte(b',')?;
+            }
+            out.push_slice(br#"["nonce",""#)
+        };
+        write_prefix(&mut out).map_err(|_| Error::ScratchTooSmall)?;
+        prefix.update(out.as_slice());
+
+        let mut out = SliceBuf::new(scratch);
+        let write_suffix = |out: &mut SliceBuf| -> Result<(), Error> {
+            out.push_slice(br#"",""#)?;
+            out.push_slice(to_decimal_str(difficulty.into()).as_bytes())?;
+            out.push_slice(br#""]],""#)?;
+            if let Some(content) = &note.content {
+                write_escaped(out, content)?;
+            }
+            out.push_slice(br#""]"#)
+        };
+        write_suffix(&mut out).map_err(|_| Error::ScratchTooSmall)?;
+
+        Ok(PowOp {
+            keypair,
+            note: Some(self.note),
+            aux_rnd,
+            difficulty,
+            nonce: 0,
+            prefix,
+            suffix: out.into_slice(),
+        })
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Number of leading zero bits in the id, the work the note proves
+    pub fn pow_difficulty(&self) -> u32 {
+        leading_zero_bits(&self.id)
+    }
+
+    /// Difficulty the author committed to in the `nonce` tag, if any.
+    /// A note with more work than it committed to may have been mined for a lower target and got lucky.
+    pub fn pow_target(&self) -> Option<u32> {
+        self.tags
+            .iter()
+            .find(|tag| tag.name() == "nonce")
+            .and_then(|tag| tag.value(1))
+            .and_then(|target| target.parse().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{buffer::SCRATCH_SIZE, utils::from_hex, vectors::PRIVKEY};
+
+    #[test]
+    fn test_mine() {
+        let mut scratch = [0_u8; SCRATCH_SIZE];
+        let mut op = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("he said \"hi\"".into())
+            .add_tag(Tag::try_from(["t", "pow"]).unwrap())
+            .unwrap()
+            .mine(1686880020, 10, [0; 32], &mut scratch)
+            .unwrap();
+        let mut steps = 0;
+        let note = loop {
+            steps += 1;
+            if let Poll::Ready(note) = op.mine_step(64) {
+                break note.unwrap();
+            }
+        };
+        assert!(steps > 1);
+        assert!(note.pow_difficulty() >= 10);
+        assert_eq!(note.pow_target(), Some(10));
+        assert_eq!(note.get_tag("t").unwrap()[0][0], "pow");
+        assert_eq!(op.mine_step(1), Poll::Ready(Err(Error::InternalError)));
+
+        // the mined id is the one recomputed from the note
+        assert_eq!(note.verify(), Ok(()));
+    }
+
+    /// Counts the pieces fed to it and its clones
+    #[derive(Clone, Default)]
+    struct Counting<'a> {
+        inner: Sha256,
+        updates: Option<&'a core::cell::Cell<usize>>,
+    }
+
+    impl Sha256Backend for Counting<'_> {
+        fn reset(&mut self) {
+            Sha256Backend::reset(&mut self.inner)
+        }
+
+        fn update(&mut self, data: &[u8]) {
+            if let Some(updates) = self.updates {
+                updates.set(updates.get() + 1);
+            }
+            Sha256Backend::update(&mut self.inner, data)
+        }
+
+        fn finish(&mut self) -> [u8; 32] {
+            self.inner.finish()
+        }
+    }
+
+    #[test]
+    fn test_mine_with_hasher() {
+        let updates = core::cell::Cell::new(0);
+        let sha = Counting {
+            updates: Some(&updates),
+            ..Default::default()
+        };
+        let mut scratch = [0_u8; SCRATCH_SIZE];
+        let mut op = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .mine_with_hasher(1686880020, 4, [0; 32], &mut scratch, sha)
+            .unwrap();
+        let note = loop {
+            if let Poll::Ready(note) = op.mine_step(64) {
+                break note.unwrap();
+            }
+        };
+        assert!(note.pow_difficulty() >= 4);
+        assert!(note.verify_id().is_ok());
+        // the prefix once, then the nonce and the suffix for each attempt
+        assert_eq!(updates.get(), 1 + 2 * (op.nonce() as usize + 1));
+    }
+
+    #[test]
+    fn test_nonce_tag_checked_up_front() {
+        let mut scratch = [0_u8; SCRATCH_SIZE];
+        // tag values of 16 bytes can't hold a 20 digit nonce
+        let op =
+            NoteBuilder::<16, 16>::new(PRIVKEY)
+                .unwrap()
+                .mine(1686880020, 4, [0; 32], &mut scratch);
+        assert!(matches!(op, Err(Error::ContentOverflow)));
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let mut note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        note.id =
+            from_hex(b"000000000e9d97a1ab09fc381030b346cdd7a142ad57e6df0b46dc9bef6c7e2d").unwrap();
+        assert_eq!(note.pow_difficulty(), 36);
+        note.id =
+            from_hex(b"7000000000000000000000000000000000000000000000000000000000000000").unwrap();
+        assert_eq!(note.pow_difficulty(), 1);
+        assert_eq!(note.pow_target(), None);
+        assert_eq!(leading_zero_bits(&[0, 0, 0x10]), 19);
+    }
+}
@@ -1,53 +1,314 @@
 //! Build queries to get events from relays
 //!
-//! - where `subscription_id` is an arbitrary, non-empty string of max length 64 chars
+//! - where `subscription_id` is a non-empty string of max length 64 chars, see [`SubscriptionId`]
 //!
 //! # Example
 //! ```
 //! use nostr_nostd::query::Query;
-//!     let mut query = Query::new();
+//! let mut query = Query::new();
 //! query
-//!     .authors
-//!     .push(*b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
+//!     .add_author("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
 //!     .unwrap();
+//! // ids and authors can also be prefixes
+//! query.add_id("b515da91").unwrap();
 //! let msg = query.serialize_to_relay("test_subscription_1").unwrap();
 //! // can send msg to relay, and event will be returned as a list of: ["EVENT","test_subscription_1",{event_1_json}],etc...
 //! ```
+//!
+//! Queries can also be built in one chain, each step reporting bad hex or a full list
+//! ```
+//! use nostr_nostd::{query::Query, NoteKinds};
+//! # fn main() -> Result<(), nostr_nostd::errors::Error> {
+//! let query = Query::new()
+//!     .author("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")?
+//!     .kind(NoteKinds::ShortNote)?
+//!     .since(1686880020)
+//!     .limit(10);
+//! let msg = query.serialize_to_relay("test_subscription_1")?;
+//! # Ok(())
+//! # }
+//! ```
 
-use heapless::Vec;
-use secp256k1::{ffi::types::AlignedType, KeyPair};
+use heapless::{String, Vec};
 
-use crate::{errors, utils::to_decimal_str, NoteKinds};
+use crate::{
+    buffer::{OutBuf, SliceBuf},
+    curve::{self, Curve, CurveBackend},
+    errors,
+    hex::{HexId, HexPubkey},
+    keys::Keys,
+    limits::{exceeds, RelayLimits},
+    note_ref::{skip_value, split_array, split_number, split_string},
+    tag::Tag,
+    utils::{to_decimal_str, unescape_json, write_escaped},
+    Note, NoteKinds,
+};
 
 const QUERY_VEC_LEN: usize = 5;
+/// Most tag filters, besides `#e` and `#p`, a query holds
+const QUERY_TAG_FILTERS: usize = 2;
+/// Longest value of a tag filter
+pub const TAG_FILTER_VALUE_SIZE: usize = 64;
+
+/// Longest `"key":["<64 hex>",...]` list
+const MAX_HEX_LIST_SIZE: usize = r#""authors":[]"#.len() + QUERY_VEC_LEN * 67;
+/// Longest `"#<letter>":[...]` list, with every character escaped to two bytes
+const MAX_TAG_FILTER_SIZE: usize =
+    r##""#x":[]"##.len() + QUERY_VEC_LEN * (2 * TAG_FILTER_VALUE_SIZE + 3);
+/// Longest `REQ` message a query can serialize to, with a 64 character subscription id
+pub(crate) const MAX_REQ_SIZE: usize = r#"["REQ","",{}]"#.len()
+    + 64
+    + 4 * MAX_HEX_LIST_SIZE
+    + QUERY_TAG_FILTERS * MAX_TAG_FILTER_SIZE
+    + r#""kinds":[]"#.len()
+    + QUERY_VEC_LEN * 11
+    + 2 * (r#""since":"#.len() + 20)
+    + r#""limit":"#.len()
+    + 10
+    // commas between the keys
+    + 7
+    + QUERY_TAG_FILTERS;
+
+/// Longest subscription id relays accept
+pub const SUBSCRIPTION_ID_SIZE: usize = 64;
+
+/// Subscription id, checked to be something a relay accepts and that can be written into a
+/// message without escaping: 1 to 64 characters, none of them a quote, backslash or control
+/// character
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SubscriptionId(String<SUBSCRIPTION_ID_SIZE>);
+
+impl SubscriptionId {
+    /// Short id from the first 4 bytes of `pubkey` and `counter`, like `098ef66b:7`.
+    /// Ids stay unique for a device as long as it doesn't reuse a counter value.
+    pub fn generate(counter: u32, pubkey: &HexPubkey) -> Self {
+        let mut id = String::new();
+        for byte in &pubkey.to_hex()[..8] {
+            // 8 hex characters, a colon and at most 10 digits always fit
+            let _ = id.push(char::from(*byte));
+        }
+        let _ = id.push(':');
+        let _ = id.push_str(&to_decimal_str(counter.into()));
+        SubscriptionId(id)
+    }
+
+    /// The id as sent to the relay
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for SubscriptionId {
+    type Error = errors::Error;
+
+    /// Errors with `InvalidSubscriptionId` if `value` is empty, longer than 64 characters, or
+    /// holds a quote, backslash or control character.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let needs_escape = value
+            .chars()
+            .any(|c| c == '"' || c == '\\' || c.is_control());
+        if value.is_empty() || needs_escape {
+            return Err(errors::Error::InvalidSubscriptionId);
+        }
+        let mut id = String::new();
+        id.push_str(value)
+            .map_err(|_| errors::Error::InvalidSubscriptionId)?;
+        Ok(SubscriptionId(id))
+    }
+}
+
+impl core::ops::Deref for SubscriptionId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for SubscriptionId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
 /// Get a `CLOSE` message to send to the relay to end a previously started subscription
-pub fn close_subscription(id: &str) -> Vec<u8, 100> {
+/// Errors with `InvalidSubscriptionId` if `id` is not a valid [`SubscriptionId`].
+pub fn close_subscription(id: &str) -> Result<Vec<u8, 100>, errors::Error> {
     let mut output: Vec<u8, 100> = Vec::new();
-    br#"["CLOSE",""#.iter().for_each(|b| output.push(*b).unwrap());
-    id.chars().for_each(|b| output.push(b as u8).unwrap());
-    br#""]"#.iter().for_each(|b| output.push(*b).unwrap());
-    output
+    close_subscription_to_buf(id, &mut output)?;
+    Ok(output)
+}
+
+/// Writes a `CLOSE` message into any [`OutBuf`].
+/// Errors with `InvalidSubscriptionId` if `id` is not a valid [`SubscriptionId`], and with
+/// `ContentOverflow` if `out` runs out of room.
+pub fn close_subscription_to_buf<B: OutBuf>(id: &str, out: &mut B) -> Result<(), errors::Error> {
+    let id = SubscriptionId::try_from(id)?;
+    out.push_slice(br#"["CLOSE",""#)?;
+    out.push_slice(id.as_bytes())?;
+    out.push_slice(br#""]"#)
+}
+
+/// Writes a `CLOSE` message into `buf`, returning the number of bytes written
+pub fn close_subscription_into(id: &str, buf: &mut [u8]) -> Result<usize, errors::Error> {
+    let mut out = SliceBuf::new(buf);
+    close_subscription_to_buf(id, &mut out)?;
+    Ok(out.len())
+}
+
+/// Event id or pubkey, or a prefix of one, held as up to 64 hex characters.
+/// Stored decoded, so a full id takes 32 bytes rather than 64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HexPrefix {
+    bytes: [u8; 32],
+    /// number of hex characters
+    len: u8,
+}
+
+impl HexPrefix {
+    /// Number of hex characters
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// True if no characters are held, which can't happen for a parsed prefix
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The `i`th hex character, as a lowercase ASCII byte
+    fn hex_char(&self, i: usize) -> u8 {
+        let byte = self.bytes[i / 2];
+        let nibble = if i.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        };
+        b"0123456789abcdef"[nibble as usize]
+    }
+
+    /// True if the hex encoded `full` starts with this prefix
+    pub fn matches(&self, full: &[u8; 64]) -> bool {
+        (0..self.len()).all(|i| full[i].to_ascii_lowercase() == self.hex_char(i))
+    }
+
+    /// Writes the prefix as lowercase hex
+    fn write_hex<B: OutBuf>(&self, out: &mut B) -> Result<(), errors::Error> {
+        for i in 0..self.len() {
+            out.push_byte(self.hex_char(i))?;
+        }
+        Ok(())
+    }
+}
+
+impl HexPrefix {
+    /// Prefix of an event id, 1 to 64 hex characters of either case.
+    /// Errors with `EventNotValid` otherwise, like [`HexId`].
+    pub fn parse_id(hex: &str) -> Result<Self, errors::Error> {
+        HexPrefix::parse(hex).ok_or(errors::Error::EventNotValid)
+    }
+
+    /// Prefix of a pubkey, 1 to 64 hex characters of either case.
+    /// Errors with `InvalidPubkey` otherwise, like [`HexPubkey`].
+    pub fn parse_pubkey(hex: &str) -> Result<Self, errors::Error> {
+        HexPrefix::parse(hex).ok_or(errors::Error::InvalidPubkey)
+    }
+
+    /// Decodes 1 to 64 hex characters of either case
+    fn parse(hex: &str) -> Option<Self> {
+        if hex.is_empty() || hex.len() > 64 {
+            return None;
+        }
+        let mut bytes = [0_u8; 32];
+        for (i, c) in hex.chars().enumerate() {
+            let nibble = c.to_digit(16)? as u8;
+            bytes[i / 2] |= if i.is_multiple_of(2) {
+                nibble << 4
+            } else {
+                nibble
+            };
+        }
+        Some(HexPrefix {
+            bytes,
+            len: hex.len() as u8,
+        })
+    }
+
+    /// The whole of `bytes`, 64 hex characters
+    fn whole(bytes: [u8; 32]) -> Self {
+        HexPrefix { bytes, len: 64 }
+    }
+}
+
+impl From<HexId> for HexPrefix {
+    fn from(id: HexId) -> Self {
+        HexPrefix::whole(*id.as_bytes())
+    }
+}
+
+impl From<HexPubkey> for HexPrefix {
+    fn from(pubkey: HexPubkey) -> Self {
+        HexPrefix::whole(*pubkey.as_bytes())
+    }
+}
+
+/// Filter on a single letter tag, serialized as `"#<letter>":[<values>]`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TagFilter {
+    letter: char,
+    values: Vec<String<TAG_FILTER_VALUE_SIZE>, QUERY_VEC_LEN>,
+}
+
+impl TagFilter {
+    /// Letter of the tags matched
+    pub fn letter(&self) -> char {
+        self.letter
+    }
+
+    /// Tag values matched, a tag matches if its first value is any of these
+    pub fn values(&self) -> impl Iterator<Item = &str> {
+        self.values.iter().map(|value| value.as_str())
+    }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Query {
-    /// a list of event ids or prefixes
-    pub ids: Vec<[u8; 64], QUERY_VEC_LEN>,
-    /// a list of pubkeys or prefixes, the pubkey of an event must be one of these
-    pub authors: Vec<[u8; 64], QUERY_VEC_LEN>,
+    /// a list of event ids or prefixes, see [`Query::add_id`]
+    pub ids: Vec<HexPrefix, QUERY_VEC_LEN>,
+    /// a list of pubkeys or prefixes, the pubkey of an event must be one of these, see [`Query::add_author`]
+    pub authors: Vec<HexPrefix, QUERY_VEC_LEN>,
     /// a list of a kind numbers
     pub kinds: Vec<NoteKinds, QUERY_VEC_LEN>,
     /// a list of event ids that are referenced in an "e" tag
-    pub ref_events: Vec<[u8; 64], QUERY_VEC_LEN>,
+    pub ref_events: Vec<HexId, QUERY_VEC_LEN>,
     /// a list of pubkeys that are referenced in a "p" tag
-    pub ref_pks: Vec<[u8; 64], QUERY_VEC_LEN>,
+    pub ref_pks: Vec<HexPubkey, QUERY_VEC_LEN>,
+    /// filters on other single letter tags, such as `#t` or `#d`, see [`Query::add_tag_filter`]
+    pub tag_filters: Vec<TagFilter, QUERY_TAG_FILTERS>,
     /// an integer unix timestamp in seconds, events must be newer than this to pass
-    pub since: Option<u32>,
+    pub since: Option<u64>,
     /// an integer unix timestamp in seconds, events must be older than this to pass
-    pub until: Option<u32>,
+    pub until: Option<u64>,
     /// maximum number of events to be returned in the initial query
     pub limit: Option<u32>,
 }
 
+impl Default for Query {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Query {
     /// Creates a new query with all fields initialized empty
     #[inline]
@@ -58,6 +319,7 @@ impl Query {
             kinds: Vec::new(),
             ref_events: Vec::new(),
             ref_pks: Vec::new(),
+            tag_filters: Vec::new(),
             since: None,
             until: None,
             limit: None,
@@ -67,17 +329,19 @@ impl Query {
     /// Sets #p tag and kind tag to search for NIP04 messages
     #[inline]
     pub fn get_my_dms(&mut self, privkey: &str) -> Result<(), errors::Error> {
-        let mut buf = [AlignedType::zeroed(); 64];
-        let sig_obj = secp256k1::Secp256k1::preallocated_new(&mut buf)
-            .map_err(|_| errors::Error::Secp256k1Error)?;
-        let key_pair: KeyPair = KeyPair::from_seckey_str(&sig_obj, privkey)
-            .map_err(|_| errors::Error::InvalidPrivkey)?;
-        let pubkey = key_pair.x_only_public_key().0;
-        let pubkey = &pubkey.serialize();
-        let mut msg = [0_u8; 64];
-        base16ct::lower::encode(pubkey, &mut msg).map_err(|_| errors::Error::EncodeError)?;
+        let key_pair = curve::keypair_from_hex(privkey)?;
+        self.add_my_dms(HexPubkey::from_bytes(Curve::x_only(&key_pair)))
+    }
+
+    /// Same as [`Query::get_my_dms`], but takes already parsed keys
+    #[inline]
+    pub fn get_my_dms_with_keys(&mut self, keys: &Keys) -> Result<(), errors::Error> {
+        self.add_my_dms(HexPubkey::from_bytes(*keys.pubkey_bytes()))
+    }
+
+    fn add_my_dms(&mut self, pubkey: HexPubkey) -> Result<(), errors::Error> {
         self.ref_pks
-            .push(msg)
+            .push(pubkey)
             .map_err(|_| errors::Error::QueryBuilderOverflow)?;
         self.kinds
             .push(NoteKinds::DM)
@@ -85,233 +349,655 @@ impl Query {
         Ok(())
     }
 
-    fn to_json(self) -> Result<Vec<u8, 1000>, errors::Error> {
-        let mut json = Vec::new();
-        let mut remove_inner_list_comma = false;
-        let mut add_obj_comma = false;
-        json.push(123).expect("impossible"); // { char
-        if self.ids.len() > 0 {
-            br#""id":["#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-        }
-        self.ids.iter().try_for_each(|val| {
-            // 34 = " char
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            val.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = true;
-            json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            Ok(())
-        })?;
-        if remove_inner_list_comma {
-            json.pop();
-            json.push(93).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = false;
-        }
-        if self.authors.len() > 0 {
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            }
-            br#""authors":["#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-        }
-        self.authors.iter().try_for_each(|val| {
-            // 34 = " char
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            val.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = true;
-            json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            Ok(())
-        })?;
-        if remove_inner_list_comma {
-            json.pop();
-            json.push(93).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = false;
+    /// A query for the DMs exchanged between the pubkeys `mine` and `theirs`, in either
+    /// direction. Notes either of them sent to themselves match too, since one filter can't
+    /// pair authors with recipients.
+    /// Errors with `InvalidPubkey` unless both are 64 hex characters.
+    pub fn dms_with(mine: &str, theirs: &str) -> Result<Self, errors::Error> {
+        Query::new()
+            .ref_pubkey(mine)?
+            .ref_pubkey(theirs)?
+            .author(mine)?
+            .author(theirs)?
+            .kind(NoteKinds::DM)
+    }
+
+    /// A query for the short notes replying to, or otherwise referencing, `event_id`.
+    /// Errors with `EventNotValid` unless `event_id` is 64 hex characters.
+    pub fn replies_to(event_id: &str) -> Result<Self, errors::Error> {
+        Query::new().ref_event(event_id)?.kind(NoteKinds::ShortNote)
+    }
+
+    /// A query for the latest profile metadata of `pubkey`, kind 0.
+    /// Errors with `InvalidPubkey` unless `pubkey` is 64 hex characters.
+    pub fn profile_of(pubkey: &str) -> Result<Self, errors::Error> {
+        Query::latest_from(pubkey, NoteKinds::Metadata, 1)
+    }
+
+    /// A query for the `limit` latest notes of `kind` by `author`.
+    /// Errors with `InvalidPubkey` unless `author` is 64 hex characters.
+    pub fn latest_from(author: &str, kind: NoteKinds, limit: u32) -> Result<Self, errors::Error> {
+        HexPubkey::try_from(author)?;
+        Ok(Query::new().author(author)?.kind(kind)?.limit(limit))
+    }
+
+    /// Adds `kind` to `kinds`.
+    /// Errors with `QueryBuilderOverflow` if `kinds` is full.
+    pub fn add_kind(&mut self, kind: NoteKinds) -> Result<(), errors::Error> {
+        self.kinds
+            .push(kind)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Adds an event id to `ref_events`, matching notes that reference it in an `e` tag.
+    /// Errors with `EventNotValid` unless `id` is 64 hex characters and with
+    /// `QueryBuilderOverflow` if `ref_events` is full.
+    pub fn add_ref_event(&mut self, id: &str) -> Result<(), errors::Error> {
+        self.ref_events
+            .push(HexId::try_from(id)?)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Adds a pubkey to `ref_pks`, matching notes that reference it in a `p` tag.
+    /// Errors with `InvalidPubkey` unless `pubkey` is 64 hex characters and with
+    /// `QueryBuilderOverflow` if `ref_pks` is full.
+    pub fn add_ref_pubkey(&mut self, pubkey: &str) -> Result<(), errors::Error> {
+        self.ref_pks
+            .push(HexPubkey::try_from(pubkey)?)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Chainable [`Query::add_id`]
+    pub fn id(mut self, id: &str) -> Result<Self, errors::Error> {
+        self.add_id(id)?;
+        Ok(self)
+    }
+
+    /// Chainable [`Query::add_author`]
+    pub fn author(mut self, pubkey: &str) -> Result<Self, errors::Error> {
+        self.add_author(pubkey)?;
+        Ok(self)
+    }
+
+    /// Chainable [`Query::add_kind`]
+    pub fn kind(mut self, kind: NoteKinds) -> Result<Self, errors::Error> {
+        self.add_kind(kind)?;
+        Ok(self)
+    }
+
+    /// Chainable [`Query::add_ref_event`]
+    pub fn ref_event(mut self, id: &str) -> Result<Self, errors::Error> {
+        self.add_ref_event(id)?;
+        Ok(self)
+    }
+
+    /// Chainable [`Query::add_ref_pubkey`]
+    pub fn ref_pubkey(mut self, pubkey: &str) -> Result<Self, errors::Error> {
+        self.add_ref_pubkey(pubkey)?;
+        Ok(self)
+    }
+
+    /// Chainable [`Query::add_tag_filter`]
+    pub fn tag(mut self, letter: char, value: &str) -> Result<Self, errors::Error> {
+        self.add_tag_filter(letter, value)?;
+        Ok(self)
+    }
+
+    /// Only matches notes created at or after `since`
+    pub fn since(mut self, since: u64) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only matches notes created at or before `until`
+    pub fn until(mut self, until: u64) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Asks for at most `limit` stored notes
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Adds an event id, or a prefix of one, to `ids`.
+    /// Errors with `EventNotValid` if `id` isn't 1 to 64 hex characters and with
+    /// `QueryBuilderOverflow` if `ids` is full.
+    pub fn add_id(&mut self, id: &str) -> Result<(), errors::Error> {
+        let id = HexPrefix::parse_id(id)?;
+        self.ids
+            .push(id)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Adds a pubkey, or a prefix of one, to `authors`.
+    /// Errors with `InvalidPubkey` if `pubkey` isn't 1 to 64 hex characters and with
+    /// `QueryBuilderOverflow` if `authors` is full.
+    pub fn add_author(&mut self, pubkey: &str) -> Result<(), errors::Error> {
+        let pubkey = HexPrefix::parse_pubkey(pubkey)?;
+        self.authors
+            .push(pubkey)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Adds `value` to the filter on `#<letter>` tags, such as `#t` for hashtags or `#d` for
+    /// parameterized replaceable events.
+    /// Errors with `MalformedContent` if `letter` isn't an ASCII letter or is `e` or `p`, which
+    /// are set through `ref_events` and `ref_pks`, and with `QueryBuilderOverflow` if there is
+    /// no room for the filter or value.
+    pub fn add_tag_filter(&mut self, letter: char, value: &str) -> Result<(), errors::Error> {
+        if !letter.is_ascii_alphabetic() || letter == 'e' || letter == 'p' {
+            return Err(errors::Error::MalformedContent);
         }
-        if self.ref_pks.len() > 0 {
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
+        let mut stored = String::new();
+        stored
+            .push_str(value)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)?;
+        let pos = match self.tag_filters.iter().position(|f| f.letter == letter) {
+            Some(pos) => pos,
+            None => {
+                self.tag_filters
+                    .push(TagFilter {
+                        letter,
+                        values: Vec::new(),
+                    })
+                    .map_err(|_| errors::Error::QueryBuilderOverflow)?;
+                self.tag_filters.len() - 1
             }
-            br##""#p":["##.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-        }
-        self.ref_pks.iter().try_for_each(|val| {
-            // 34 = " char
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            val.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = true;
-            json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            Ok(())
-        })?;
-        if remove_inner_list_comma {
-            json.pop();
-            json.push(93).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = false;
-        }
-        if self.ref_events.len() > 0 {
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
+        };
+        self.tag_filters[pos]
+            .values
+            .push(stored)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// The filter on `#<letter>` tags, if one was added
+    pub fn tag_filter(&self, letter: char) -> Option<&TagFilter> {
+        self.tag_filters.iter().find(|f| f.letter == letter)
+    }
+
+    /// True if `note` passes the filter, following NIP-01: every field that is set must match,
+    /// and a list matches if any of its entries does. `limit` is ignored.
+    /// Useful for dropping events a relay sent outside of the filter, or for handing the events
+    /// of one subscription out to several consumers.
+    pub fn matches<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> bool {
+        let any_prefix = |list: &[HexPrefix], hex: &[u8; 64]| {
+            list.is_empty() || list.iter().any(|prefix| prefix.matches(hex))
+        };
+        any_prefix(&self.ids, &note.id())
+            && any_prefix(&self.authors, &note.pubkey())
+            && (self.kinds.is_empty()
+                || self
+                    .kinds
+                    .iter()
+                    .any(|kind| kind.as_u32() == note.kind().as_u32()))
+            && (self.ref_events.is_empty()
+                || any_tag(note.tags(), "e", self.ref_events.iter().map(HexId::to_hex)))
+            && (self.ref_pks.is_empty()
+                || any_tag(note.tags(), "p", self.ref_pks.iter().map(HexPubkey::to_hex)))
+            && self.tag_filters.iter().all(|filter| {
+                let mut name = [0_u8; 4];
+                let name = filter.letter.encode_utf8(&mut name);
+                any_tag(note.tags(), name, filter.values().map(str::as_bytes))
+            })
+            && self.since.is_none_or(|since| note.created_at() >= since)
+            && self.until.is_none_or(|until| note.created_at() <= until)
+    }
+
+    /// Writes the filter as a JSON object, lowering `limit` to `max_limit` if it is larger
+    pub(crate) fn write_json<B: OutBuf>(
+        &self,
+        out: &mut B,
+        max_limit: Option<u32>,
+    ) -> Result<(), errors::Error> {
+        let mut add_obj_comma = false;
+        out.push_byte(b'{')?;
+        write_prefix_list(out, br#""ids":"#, &self.ids, &mut add_obj_comma)?;
+        write_prefix_list(out, br#""authors":"#, &self.authors, &mut add_obj_comma)?;
+        write_hex_list(
+            out,
+            br##""#p":"##,
+            self.ref_pks.iter().map(HexPubkey::to_hex),
+            &mut add_obj_comma,
+        )?;
+        write_hex_list(
+            out,
+            br##""#e":"##,
+            self.ref_events.iter().map(HexId::to_hex),
+            &mut add_obj_comma,
+        )?;
+        for filter in &self.tag_filters {
+            write_key(out, br##""#"##, &mut add_obj_comma)?;
+            out.push_byte(filter.letter as u8)?;
+            out.push_slice(br#"":["#)?;
+            for (i, value) in filter.values.iter().enumerate() {
+                if i > 0 {
+                    out.push_byte(b',')?;
+                }
+                out.push_byte(b'"')?;
+                write_escaped(out, value)?;
+                out.push_byte(b'"')?;
             }
-            br##""#e":["##.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
+            out.push_byte(b']')?;
         }
-        self.ref_events.iter().try_for_each(|val| {
-            // 34 = " char
-            json.push(34).map_err(|_| errors::Error::ContentOverflow)?;
-            val.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            remove_inner_list_comma = true;
-            json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            Ok(())
-        })?;
-        if remove_inner_list_comma {
-            json.pop();
-            json.push(93).map_err(|_| errors::Error::ContentOverflow)?;
-            remove_inner_list_comma = false;
-        }
-        if self.kinds.len() > 0 {
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
+        if !self.kinds.is_empty() {
+            write_key(out, br#""kinds":"#, &mut add_obj_comma)?;
+            out.push_byte(b'[')?;
+            for (i, kind) in self.kinds.iter().enumerate() {
+                if i > 0 {
+                    out.push_byte(b',')?;
+                }
+                out.push_slice(kind.serialize().as_bytes())?;
             }
-            br#""kinds":["#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-        }
-        self.kinds.iter().try_for_each(|kind| {
-            kind.serialize().chars().try_for_each(|b| {
-                json.push(b as u8)
-                    .map_err(|_| errors::Error::QueryBuilderOverflow)?;
-                Ok(())
-            })?;
-            remove_inner_list_comma = true;
-            json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            Ok(())
-        })?;
-        if remove_inner_list_comma {
-            json.pop();
-            json.push(93).map_err(|_| errors::Error::ContentOverflow)?;
-            // remove_inner_list_comma = false;
+            out.push_byte(b']')?;
         }
-
         if let Some(since) = self.since {
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            }
-            // add since
-            br#""since":"#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-            to_decimal_str(since).chars().try_for_each(|val| {
-                json.push(val as u8)
-                    .map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
+            write_key(out, br#""since":"#, &mut add_obj_comma)?;
+            out.push_slice(to_decimal_str(since).as_bytes())?;
         }
         if let Some(until) = self.until {
-            // add until
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            }
-            br#""until":"#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            add_obj_comma = true;
-            to_decimal_str(until).chars().try_for_each(|val| {
-                json.push(val as u8)
-                    .map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
+            write_key(out, br#""until":"#, &mut add_obj_comma)?;
+            out.push_slice(to_decimal_str(until).as_bytes())?;
         }
-
         if let Some(limit) = self.limit {
-            // add limit
-            if add_obj_comma {
-                json.push(44).map_err(|_| errors::Error::ContentOverflow)?;
-            }
-            br#""limit":"#.iter().try_for_each(|b| {
-                json.push(*b).map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
-            // add_obj_comma = true;
-            to_decimal_str(limit).chars().try_for_each(|val| {
-                json.push(val as u8)
-                    .map_err(|_| errors::Error::ContentOverflow)?;
-                Ok(())
-            })?;
+            let limit = max_limit.map_or(limit, |max| limit.min(max));
+            write_key(out, br#""limit":"#, &mut add_obj_comma)?;
+            out.push_slice(to_decimal_str(limit.into()).as_bytes())?;
         }
-
-        json.push(125).expect("impossible"); // } char
-        Ok(json)
+        out.push_byte(b'}')
     }
 
     /// Serializes the note for sending to relay.
     /// Can error if too many tags/ids/events/etc have been supplied.
     /// - `subscription_id` will be included with returned events from relay
-    /// - `subscription_id` length must be <= 64 characters
+    /// - `subscription_id` must be a valid [`SubscriptionId`], or this errors with
+    ///   `InvalidSubscriptionId`
     #[inline]
-    pub fn serialize_to_relay(self, subscription_id: &str) -> Result<Vec<u8, 1000>, errors::Error> {
-        let mut output: Vec<u8, 1000> = Vec::new();
-        // fill in output
-        r#"["REQ",""#.as_bytes().iter().try_for_each(|bs| {
-            output
-                .push(*bs)
-                .map_err(|_| errors::Error::QueryBuilderOverflow)?;
-            Ok(())
-        })?;
-        subscription_id
-            .chars()
-            .for_each(|c| output.push(c as u8).expect("impossible"));
-        // append ", to subscription id
-        output.push(34).expect("impossible");
-        output.push(44).expect("impossible");
-        let json = self.to_json()?;
-        json.iter().try_for_each(|bs| {
-            output
-                .push(*bs)
-                .map_err(|_| errors::Error::QueryBuilderOverflow)?;
-            Ok(())
-        })?;
-        output
-            .push(93)
+    pub fn serialize_to_relay(
+        self,
+        subscription_id: &str,
+    ) -> Result<Vec<u8, MAX_REQ_SIZE>, errors::Error> {
+        let subscription_id = SubscriptionId::try_from(subscription_id)?;
+        let mut output = Vec::new();
+        self.serialize_to_buf(&subscription_id, &mut output)
             .map_err(|_| errors::Error::QueryBuilderOverflow)?;
         Ok(output)
     }
+
+    /// Serializes the `REQ` message into a heap allocated buffer
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn serialize_to_vec(
+        &self,
+        subscription_id: &str,
+    ) -> Result<alloc::vec::Vec<u8>, errors::Error> {
+        let mut output = alloc::vec::Vec::new();
+        self.serialize_to_buf(subscription_id, &mut output)?;
+        Ok(output)
+    }
+
+    /// Serializes the `REQ` message into any [`OutBuf`].
+    /// Errors with `InvalidSubscriptionId` if `subscription_id` is not a valid
+    /// [`SubscriptionId`], and with `ContentOverflow` if `out` runs out of room.
+    #[inline]
+    pub fn serialize_to_buf<B: OutBuf>(
+        &self,
+        subscription_id: &str,
+        out: &mut B,
+    ) -> Result<(), errors::Error> {
+        write_req(out, subscription_id, core::slice::from_ref(self), None)
+    }
+
+    /// Serializes the `REQ` message into `buf`, returning the number of bytes written.
+    /// Errors with `ContentOverflow` if `buf` is too small.
+    #[inline]
+    pub fn serialize_into(
+        &self,
+        subscription_id: &str,
+        buf: &mut [u8],
+    ) -> Result<usize, errors::Error> {
+        let mut out = SliceBuf::new(buf);
+        self.serialize_to_buf(subscription_id, &mut out)?;
+        Ok(out.len())
+    }
+
+    /// Serializes the `REQ` message into any [`OutBuf`], respecting a relay's advertised limits.
+    /// `limit` is lowered to the relay's `max_limit`, and the subscription id and message length are checked.
+    /// Errors with `MessageTooLong` if the relay would refuse the message, in which case `out` may hold part of it.
+    pub fn serialize_limited<B: OutBuf>(
+        &self,
+        subscription_id: &str,
+        limits: &RelayLimits,
+        out: &mut B,
+    ) -> Result<(), errors::Error> {
+        write_req_limited(out, subscription_id, core::slice::from_ref(self), limits)
+    }
+}
+
+impl TryFrom<&str> for Query {
+    type Error = errors::Error;
+
+    /// Parses a filter object as a client sends it in a `REQ`, allowing whitespace between
+    /// tokens. Keys a query has no field for, such as `search`, are skipped.
+    /// Errors with `MalformedContent` if the filter is badly formed, with `EventNotValid` or
+    /// `InvalidPubkey` if an `ids` or `authors` value isn't hex or a `#e` or `#p` value isn't a
+    /// whole id or pubkey, and with `QueryBuilderOverflow` if it holds more entries than a query does.
+    fn try_from(json: &str) -> Result<Self, Self::Error> {
+        let mut query = Query::new();
+        let mut rest = json
+            .trim_start()
+            .strip_prefix('{')
+            .ok_or(errors::Error::MalformedContent)?
+            .trim_start();
+        let mut first = true;
+        while !rest.starts_with('}') {
+            if !first {
+                rest = rest
+                    .strip_prefix(',')
+                    .ok_or(errors::Error::MalformedContent)?
+                    .trim_start();
+            }
+            first = false;
+            let (key, after) = split_string(rest)?;
+            rest = after
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or(errors::Error::MalformedContent)?
+                .trim_start();
+            rest = match key {
+                "ids" => split_string_list(rest, |id| query.add_id(id))?,
+                "authors" => split_string_list(rest, |pubkey| query.add_author(pubkey))?,
+                "#e" => split_string_list(rest, |id| query.add_ref_event(id))?,
+                "#p" => split_string_list(rest, |pk| query.add_ref_pubkey(pk))?,
+                "kinds" => {
+                    let (_, after) = split_array(rest, |value| {
+                        let (kind, after) = split_number::<u32>(value)?;
+                        query
+                            .kinds
+                            .push(kind.into())
+                            .map_err(|_| errors::Error::QueryBuilderOverflow)?;
+                        Ok(after)
+                    })?;
+                    after
+                }
+                "since" | "until" => {
+                    let (value, after) = split_number(rest)?;
+                    match key {
+                        "since" => query.since = Some(value),
+                        _ => query.until = Some(value),
+                    }
+                    after
+                }
+                "limit" => {
+                    let (value, after) = split_number(rest)?;
+                    query.limit = Some(value);
+                    after
+                }
+                _ => match key.strip_prefix('#').map(str::chars) {
+                    Some(mut letter) if key.len() == 2 => {
+                        let letter = letter.next().ok_or(errors::Error::MalformedContent)?;
+                        split_string_list(rest, |value| {
+                            let value: String<TAG_FILTER_VALUE_SIZE> = unescape_json(value)
+                                .map_err(|_| errors::Error::QueryBuilderOverflow)?;
+                            query.add_tag_filter(letter, &value)
+                        })?
+                    }
+                    _ => skip_value(rest)?,
+                },
+            }
+            .trim_start();
+        }
+        Ok(query)
+    }
+}
+
+/// Splits an array of strings off the front of `json`, calling `element` with each one still
+/// escaped, and returns what follows
+fn split_string_list<'a>(
+    json: &'a str,
+    mut element: impl FnMut(&'a str) -> Result<(), errors::Error>,
+) -> Result<&'a str, errors::Error> {
+    let (_, rest) = split_array(json, |value| {
+        let (value, after) = split_string(value)?;
+        element(value)?;
+        Ok(after)
+    })?;
+    Ok(rest)
+}
+
+/// Several filters sent in one `REQ`, a relay returns events matching any of them
+/// ```
+/// use nostr_nostd::{NoteKinds, hex::HexPubkey, query::{MultiQuery, Query}};
+/// let me = HexPubkey::try_from("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf").unwrap();
+/// let mut dms = Query::new();
+/// dms.kinds.push(NoteKinds::DM).unwrap();
+/// dms.ref_pks.push(me).unwrap();
+/// let mut mentions = Query::new();
+/// mentions.kinds.push(NoteKinds::ShortNote).unwrap();
+/// mentions.ref_pks.push(me).unwrap();
+/// let mut multi: MultiQuery<2> = MultiQuery::new();
+/// multi.push(dms).unwrap();
+/// multi.push(mentions).unwrap();
+/// let mut buf = [0_u8; 300];
+/// let len = multi.serialize_into("inbox", &mut buf).unwrap();
+/// assert!(buf[..len].starts_with(br#"["REQ","inbox",{"#));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MultiQuery<const N: usize> {
+    /// the filters, in the order they are sent
+    pub filters: Vec<Query, N>,
+}
+
+impl<const N: usize> Default for MultiQuery<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MultiQuery<N> {
+    /// Creates a `REQ` with no filters
+    #[inline]
+    pub fn new() -> Self {
+        MultiQuery {
+            filters: Vec::new(),
+        }
+    }
+
+    /// Adds a filter.
+    /// Errors with `QueryBuilderOverflow` if `N` filters are already held.
+    pub fn push(&mut self, query: Query) -> Result<(), errors::Error> {
+        self.filters
+            .push(query)
+            .map_err(|_| errors::Error::QueryBuilderOverflow)
+    }
+
+    /// Serializes the `REQ` message into a heap allocated buffer
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn serialize_to_vec(
+        &self,
+        subscription_id: &str,
+    ) -> Result<alloc::vec::Vec<u8>, errors::Error> {
+        let mut output = alloc::vec::Vec::new();
+        self.serialize_to_buf(subscription_id, &mut output)?;
+        Ok(output)
+    }
+
+    /// Serializes the `REQ` message into any [`OutBuf`].
+    /// Errors with `ContentOverflow` if `out` runs out of room.
+    #[inline]
+    pub fn serialize_to_buf<B: OutBuf>(
+        &self,
+        subscription_id: &str,
+        out: &mut B,
+    ) -> Result<(), errors::Error> {
+        write_req(out, subscription_id, &self.filters, None)
+    }
+
+    /// Serializes the `REQ` message into `buf`, returning the number of bytes written.
+    /// Errors with `ContentOverflow` if `buf` is too small.
+    #[inline]
+    pub fn serialize_into(
+        &self,
+        subscription_id: &str,
+        buf: &mut [u8],
+    ) -> Result<usize, errors::Error> {
+        let mut out = SliceBuf::new(buf);
+        self.serialize_to_buf(subscription_id, &mut out)?;
+        Ok(out.len())
+    }
+
+    /// True if `note` passes any of the filters
+    pub fn matches<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> bool {
+        self.filters.iter().any(|filter| filter.matches(note))
+    }
+
+    /// Same as [`Query::serialize_limited`], with the relay's `max_limit` applied to every filter
+    pub fn serialize_limited<B: OutBuf>(
+        &self,
+        subscription_id: &str,
+        limits: &RelayLimits,
+        out: &mut B,
+    ) -> Result<(), errors::Error> {
+        write_req_limited(out, subscription_id, &self.filters, limits)
+    }
+}
+
+/// Writes `["REQ","<subscription_id>",<filter>,...]`, erroring with `InvalidSubscriptionId`
+/// unless `subscription_id` is a valid [`SubscriptionId`]
+fn write_req<B: OutBuf>(
+    out: &mut B,
+    subscription_id: &str,
+    filters: &[Query],
+    max_limit: Option<u32>,
+) -> Result<(), errors::Error> {
+    let subscription_id = SubscriptionId::try_from(subscription_id)?;
+    out.push_slice(br#"["REQ",""#)?;
+    out.push_slice(subscription_id.as_bytes())?;
+    out.push_byte(b'"')?;
+    for filter in filters {
+        out.push_byte(b',')?;
+        filter.write_json(out, max_limit)?;
+    }
+    out.push_byte(b']')
+}
+
+/// Writes a `REQ`, erroring with `MessageTooLong` if the relay would refuse it
+fn write_req_limited<B: OutBuf>(
+    out: &mut B,
+    subscription_id: &str,
+    filters: &[Query],
+    limits: &RelayLimits,
+) -> Result<(), errors::Error> {
+    if exceeds(subscription_id.len(), limits.max_subid_length) {
+        return Err(errors::Error::MessageTooLong);
+    }
+    let start = out.len();
+    write_req(out, subscription_id, filters, limits.max_limit)?;
+    if exceeds(out.len() - start, limits.max_message_length) {
+        return Err(errors::Error::MessageTooLong);
+    }
+    Ok(())
+}
+
+/// True if one of `tags` is named `name` and has its first value in `values`
+fn any_tag<const VALUE: usize, const VALUES: usize>(
+    tags: &[Tag<VALUE, VALUES>],
+    name: &str,
+    mut values: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> bool {
+    values.any(|value| {
+        tags.iter().any(|tag| {
+            tag.name() == name && tag.value(0).map(str::as_bytes) == Some(value.as_ref())
+        })
+    })
+}
+
+/// Writes `"key":`, preceded by a comma if this is not the first key of the object
+fn write_key<B: OutBuf>(
+    out: &mut B,
+    key: &[u8],
+    add_obj_comma: &mut bool,
+) -> Result<(), errors::Error> {
+    if *add_obj_comma {
+        out.push_byte(b',')?;
+    }
+    *add_obj_comma = true;
+    out.push_slice(key)
+}
+
+/// Writes `"key":["val1","val2"]`, or nothing if `values` is empty
+fn write_hex_list<B: OutBuf>(
+    out: &mut B,
+    key: &[u8],
+    values: impl Iterator<Item = [u8; 64]>,
+    add_obj_comma: &mut bool,
+) -> Result<(), errors::Error> {
+    let mut values = values.peekable();
+    if values.peek().is_none() {
+        return Ok(());
+    }
+    write_key(out, key, add_obj_comma)?;
+    out.push_byte(b'[')?;
+    for (i, val) in values.enumerate() {
+        if i > 0 {
+            out.push_byte(b',')?;
+        }
+        out.push_byte(b'"')?;
+        out.push_slice(&val)?;
+        out.push_byte(b'"')?;
+    }
+    out.push_byte(b']')
+}
+
+/// Writes `"key":["prefix1","prefix2"]`, or nothing if `values` is empty
+fn write_prefix_list<B: OutBuf>(
+    out: &mut B,
+    key: &[u8],
+    values: &[HexPrefix],
+    add_obj_comma: &mut bool,
+) -> Result<(), errors::Error> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    write_key(out, key, add_obj_comma)?;
+    out.push_byte(b'[')?;
+    for (i, val) in values.iter().enumerate() {
+        if i > 0 {
+            out.push_byte(b',')?;
+        }
+        out.push_byte(b'"')?;
+        val.write_hex(out)?;
+        out.push_byte(b'"')?;
+    }
+    out.push_byte(b']')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    const PRIVKEY: &str = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+    use crate::{
+        buffer::StoredBuf,
+        vectors::{OTHER_PUBKEY, PRIVKEY, PUBKEY},
+    };
 
     #[test]
     fn test_dms() {
@@ -326,14 +1012,163 @@ mod tests {
             .expect("test");
         let expected = br##"["REQ","my_dms",{"#p":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"],"kinds":[4]}]"##;
         assert_eq!(query, expected);
+
+        let mut context = [crate::keys::AlignedType::zeroed(); crate::keys::CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        let mut query = Query::new();
+        query.get_my_dms_with_keys(&keys).unwrap();
+        assert_eq!(query.serialize_to_relay("my_dms").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_constructors() {
+        let mut out: Vec<u8, 512> = Vec::new();
+        Query::dms_with(PUBKEY, OTHER_PUBKEY)
+            .unwrap()
+            .serialize_to_buf("dm", &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            br##"["REQ","dm",{"authors":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"],"#p":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"],"kinds":[4]}]"##
+        );
+
+        out.clear();
+        Query::replies_to("b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8")
+            .unwrap()
+            .serialize_to_buf("replies", &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            br##"["REQ","replies",{"#e":["b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"],"kinds":[1]}]"##
+        );
+
+        out.clear();
+        Query::profile_of(OTHER_PUBKEY)
+            .unwrap()
+            .serialize_to_buf("profile", &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            br#"["REQ","profile",{"authors":["ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"],"kinds":[0],"limit":1}]"#
+        );
+
+        let latest = Query::latest_from(OTHER_PUBKEY, NoteKinds::IOT, 10).unwrap();
+        assert_eq!(latest.kinds.as_slice(), &[NoteKinds::IOT]);
+        assert_eq!(latest.limit, Some(10));
+
+        // prefixes are refused, uppercase is written back lowercase
+        assert_eq!(
+            Query::profile_of("098ef66b").err(),
+            Some(errors::Error::InvalidPubkey)
+        );
+        assert_eq!(
+            Query::replies_to("B515DA91AC5DF638FAE0A6E658E03ACC1DDA6152DD2107D02D5702CCFCF927E8")
+                .unwrap()
+                .ref_events[0]
+                .to_hex(),
+            *b"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"
+        );
+        assert_eq!(
+            Query::dms_with(PUBKEY, "zz").err(),
+            Some(errors::Error::InvalidPubkey)
+        );
+    }
+
+    #[test]
+    fn test_chained() {
+        const PK: &str = "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+        let query = Query::new()
+            .id("b515da91")
+            .unwrap()
+            .author(PK)
+            .unwrap()
+            .kind(NoteKinds::ShortNote)
+            .unwrap()
+            .ref_pubkey(PK)
+            .unwrap()
+            .tag('t', "esp32")
+            .unwrap()
+            .since(10)
+            .until(20)
+            .limit(5);
+        let mut manual = Query::new();
+        manual.add_id("b515da91").unwrap();
+        manual.add_author(PK).unwrap();
+        manual.add_kind(NoteKinds::ShortNote).unwrap();
+        manual.add_ref_pubkey(PK).unwrap();
+        manual.add_tag_filter('t', "esp32").unwrap();
+        manual.since = Some(10);
+        manual.until = Some(20);
+        manual.limit = Some(5);
+        assert_eq!(query, manual);
+
+        assert_eq!(
+            Query::new().author("xyz").err(),
+            Some(errors::Error::InvalidPubkey)
+        );
+        assert_eq!(
+            Query::new().ref_event("b515da91").err(),
+            Some(errors::Error::EventNotValid)
+        );
+        let full = (0..QUERY_VEC_LEN).try_fold(Query::new(), |query, kind| {
+            query.kind(NoteKinds::from(kind as u32))
+        });
+        assert_eq!(
+            full.unwrap().kind(NoteKinds::ShortNote).err(),
+            Some(errors::Error::QueryBuilderOverflow)
+        );
     }
 
     #[test]
     fn test_close() {
         let sub_id = "sub_1";
-        let closed = close_subscription(sub_id);
+        let closed = close_subscription(sub_id).unwrap();
         let expected = br#"["CLOSE","sub_1"]"#;
         assert_eq!(closed, expected);
+        assert_eq!(
+            close_subscription(&"x".repeat(100)),
+            Err(errors::Error::InvalidSubscriptionId)
+        );
+
+        let mut buf = [0_u8; 32];
+        let len = close_subscription_into(sub_id, &mut buf).unwrap();
+        assert_eq!(&buf[..len], expected);
+        assert_eq!(
+            close_subscription_into(sub_id, &mut buf[..10]),
+            Err(errors::Error::ContentOverflow)
+        );
+    }
+
+    #[test]
+    fn test_subscription_id() {
+        let pubkey =
+            HexPubkey::try_from("098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf")
+                .unwrap();
+        let id = SubscriptionId::generate(7, &pubkey);
+        assert_eq!(id.as_str(), "098ef66b:7");
+        assert_eq!(
+            SubscriptionId::generate(u32::MAX, &pubkey).as_str(),
+            "098ef66b:4294967295"
+        );
+        assert_eq!(SubscriptionId::try_from("098ef66b:7"), Ok(id.clone()));
+        assert!(SubscriptionId::try_from(&*"s".repeat(64)).is_ok());
+
+        for bad in ["", r#"a"b"#, r"a\b", "a\nb"] {
+            assert_eq!(
+                SubscriptionId::try_from(bad),
+                Err(errors::Error::InvalidSubscriptionId)
+            );
+        }
+        assert_eq!(
+            SubscriptionId::try_from(&*"s".repeat(65)),
+            Err(errors::Error::InvalidSubscriptionId)
+        );
+        assert_eq!(
+            Query::new().serialize_to_relay(r#"x"]"#),
+            Err(errors::Error::InvalidSubscriptionId)
+        );
+        let req = Query::new().serialize_to_relay(&id).unwrap();
+        assert_eq!(req, br#"["REQ","098ef66b:7",{}]"#);
     }
 
     #[test]
@@ -344,18 +1179,19 @@ mod tests {
             kinds: Vec::new(),
             ref_events: Vec::new(),
             ref_pks: Vec::new(),
+            tag_filters: Vec::new(),
             since: Some(10_000),
             until: Some(10_001),
             limit: Some(10),
         };
         query
             .ref_pks
-            .push([97; 64])
+            .push(HexPubkey::from_bytes([0xaa; 32]))
             .map_err(|_| errors::Error::ContentOverflow)
             .expect("test");
         query
             .ref_pks
-            .push([98; 64])
+            .push(HexPubkey::from_bytes([0xbb; 32]))
             .map_err(|_| errors::Error::ContentOverflow)
             .expect("test");
         query
@@ -376,4 +1212,219 @@ mod tests {
         let expected = br##"["REQ","subscription_1",{"#p":["aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa","bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"],"kinds":[5732,1005],"since":10000,"until":10001,"limit":10}]"##;
         assert_eq!(query, expected);
     }
+
+    #[test]
+    fn test_max_size() {
+        let mut query = Query::new();
+        for _ in 0..QUERY_VEC_LEN {
+            query
+                .add_id(core::str::from_utf8(&[b'a'; 64]).expect("test"))
+                .expect("test");
+            query
+                .add_author(core::str::from_utf8(&[b'a'; 64]).expect("test"))
+                .expect("test");
+            query
+                .ref_pks
+                .push(HexPubkey::from_bytes([0xaa; 32]))
+                .expect("test");
+            query
+                .ref_events
+                .push(HexId::from_bytes([0xaa; 32]))
+                .expect("test");
+            query.kinds.push(NoteKinds::Custom(u32::MAX)).expect("test");
+            for letter in ['a', 'b'] {
+                let value = core::str::from_utf8(&[b'"'; TAG_FILTER_VALUE_SIZE]).expect("test");
+                query.add_tag_filter(letter, value).expect("test");
+            }
+        }
+        query.since = Some(u64::MAX);
+        query.until = Some(u64::MAX);
+        query.limit = Some(u32::MAX);
+        let sub_id = core::str::from_utf8(&[b's'; 64]).expect("test");
+        let msg = query.serialize_to_relay(sub_id).expect("test");
+        assert!(msg.len() <= MAX_REQ_SIZE);
+    }
+
+    #[test]
+    fn test_ids_and_events() {
+        let mut query = Query::new();
+        query
+            .add_id(core::str::from_utf8(&[97; 64]).expect("test"))
+            .expect("test");
+        query
+            .ref_events
+            .push(HexId::from_bytes([0xbb; 32]))
+            .expect("test");
+        let mut storage = [0_u8; 300];
+        let mut buf = SliceBuf::new(&mut storage);
+        query.serialize_to_buf("sub", &mut buf).expect("test");
+        let expected = br##"["REQ","sub",{"ids":["aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"],"#e":["bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"]}]"##;
+        assert_eq!(buf.as_slice(), expected);
+
+        let mut storage = [0_u8; 300];
+        let len = query.serialize_into("sub", &mut storage).expect("test");
+        assert_eq!(&storage[..len], expected);
+    }
+
+    #[test]
+    fn test_multi_query() {
+        let mut dms = Query::new();
+        dms.get_my_dms(PRIVKEY).expect("test");
+        let mut mentions = Query::new();
+        mentions
+            .ref_pks
+            .push(HexPubkey::from_bytes([0xaa; 32]))
+            .expect("test");
+        mentions.limit = Some(50);
+        let mut multi: MultiQuery<2> = MultiQuery::new();
+        multi.push(dms).expect("test");
+        multi.push(mentions).expect("test");
+        assert_eq!(
+            multi.push(Query::new()),
+            Err(errors::Error::QueryBuilderOverflow)
+        );
+
+        let mut buf = [0_u8; 300];
+        let len = multi.serialize_into("inbox", &mut buf).expect("test");
+        let expected = br##"["REQ","inbox",{"#p":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"],"kinds":[4]},{"#p":["aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"],"limit":50}]"##;
+        assert_eq!(&buf[..len], expected);
+
+        let limits = RelayLimits {
+            max_limit: Some(20),
+            ..Default::default()
+        };
+        let mut out: Vec<u8, 300> = Vec::new();
+        multi
+            .serialize_limited("inbox", &limits, &mut out)
+            .expect("test");
+        assert!(out.ends_with(br#""limit":20}]"#));
+    }
+
+    #[test]
+    fn test_tag_filters() {
+        let mut query = Query::new();
+        query.add_tag_filter('t', "nostr").expect("test");
+        query.add_tag_filter('d', "my \"list\"").expect("test");
+        query.add_tag_filter('t', "esp32").expect("test");
+        assert_eq!(
+            query.add_tag_filter('x', "no room"),
+            Err(errors::Error::QueryBuilderOverflow)
+        );
+        assert_eq!(
+            query.add_tag_filter('p', "use ref_pks"),
+            Err(errors::Error::MalformedContent)
+        );
+        assert_eq!(
+            query.add_tag_filter('#', "not a letter"),
+            Err(errors::Error::MalformedContent)
+        );
+        let hashtags = query.tag_filter('t').expect("test");
+        assert!(hashtags.values().eq(["nostr", "esp32"]));
+
+        query.kinds.push(NoteKinds::ShortNote).expect("test");
+        let msg = query.serialize_to_relay("tags").expect("test");
+        let expected =
+            br##"["REQ","tags",{"#t":["nostr","esp32"],"#d":["my \"list\""],"kinds":[1]}]"##;
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let mut query = Query::new();
+        query.add_id("B515DA91").expect("test");
+        query.add_author("098ef").expect("test");
+        assert_eq!(query.add_id(""), Err(errors::Error::EventNotValid));
+        assert_eq!(query.add_id("b515xx"), Err(errors::Error::EventNotValid));
+        assert_eq!(
+            query.add_author(core::str::from_utf8(&[b'a'; 65]).expect("test")),
+            Err(errors::Error::InvalidPubkey)
+        );
+        assert_eq!(query.add_author("098eg"), Err(errors::Error::InvalidPubkey));
+        let msg = query.serialize_to_relay("prefix").expect("test");
+        assert_eq!(
+            msg,
+            br#"["REQ","prefix",{"ids":["b515da91"],"authors":["098ef"]}]"#
+        );
+
+        let pubkey = b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+        let prefix = HexPrefix::parse_pubkey("098ef").expect("test");
+        assert_eq!(prefix.len(), 5);
+        assert!(prefix.matches(pubkey));
+        assert!(!HexPrefix::parse_pubkey("098e0")
+            .expect("test")
+            .matches(pubkey));
+        let whole = HexPrefix::from(HexPubkey::try_from(&pubkey[..]).expect("test"));
+        assert_eq!(whole.len(), 64);
+        assert!(whole.matches(pubkey));
+    }
+
+    #[test]
+    fn test_matches() {
+        let other = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+        let note = crate::Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["p", other]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "esp32"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+
+        assert!(Query::new().matches(&note));
+        let mut query = Query::new();
+        query.add_author("098ef66b").expect("test");
+        query.kinds.push(NoteKinds::DM).expect("test");
+        query.kinds.push(NoteKinds::ShortNote).expect("test");
+        query
+            .ref_pks
+            .push(
+                HexPubkey::try_from(
+                    "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0",
+                )
+                .unwrap(),
+            )
+            .expect("test");
+        query.add_tag_filter('t', "nostr").expect("test");
+        query.add_tag_filter('t', "esp32").expect("test");
+        query.since = Some(1686880020);
+        query.until = Some(1686880020);
+        assert!(query.matches(&note));
+
+        let mut wrong_author = Query::new();
+        wrong_author.add_author("ed98").expect("test");
+        assert!(!wrong_author.matches(&note));
+        let mut wrong_tag = Query::new();
+        wrong_tag.add_tag_filter('t', "bitcoin").expect("test");
+        assert!(!wrong_tag.matches(&note));
+        let mut missing_event = Query::new();
+        missing_event
+            .ref_events
+            .push(HexId::from_bytes([0xaa; 32]))
+            .expect("test");
+        assert!(!missing_event.matches(&note));
+        let mut too_old = Query::new();
+        too_old.since = Some(1686880021);
+        assert!(!too_old.matches(&note));
+
+        let mut multi: MultiQuery<2> = MultiQuery::new();
+        multi.push(wrong_author).expect("test");
+        assert!(!multi.matches(&note));
+        multi.push(query).expect("test");
+        assert!(multi.matches(&note));
+    }
+
+    #[test]
+    fn test_timestamps_past_u32() {
+        let json = r#"{"since":1686880020,"until":4294967296,"limit":10}"#;
+        let query = Query::try_from(json).expect("test");
+        assert_eq!(query.since, Some(1686880020));
+        assert_eq!(query.until, Some(4294967296));
+        let msg = query.serialize_to_relay("sub").expect("test");
+        let expected = br#"["REQ","sub",{"since":1686880020,"until":4294967296,"limit":10}]"#;
+        assert_eq!(msg.as_slice(), expected);
+        assert_eq!(
+            Query::try_from(r#"{"limit":4294967296}"#),
+            Err(errors::Error::MalformedContent)
+        );
+    }
 }
@@ -0,0 +1,174 @@
+//! Reactions ([NIP-25](https://github.com/nostr-protocol/nips/blob/master/25.md)) and
+//! reposts ([NIP-18](https://github.com/nostr-protocol/nips/blob/master/18.md))
+//!
+//! Both point at the note they're about with `e` and `p` tags, filled in from the note itself.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{reactions::REACTION_KIND, Note};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let received = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let like = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .create_reaction(&received, "+")
+//!     .unwrap()
+//!     .build(1686880021, [0; 32])
+//!     .unwrap();
+//! assert_eq!(like.kind().as_u32(), REACTION_KIND);
+//! let id = received.id();
+//! assert_eq!(like.get_tag("e").unwrap()[0][0].as_bytes(), id.as_slice());
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{errors::Error, tag::Tag, Note, NoteBuilder, NoteKinds};
+
+/// Kind of a reaction note
+pub const REACTION_KIND: u32 = 7;
+/// Kind of a repost of a short text note
+pub const REPOST_KIND: u32 = 6;
+/// Kind of a repost of any other note
+pub const GENERIC_REPOST_KIND: u32 = 16;
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 7 reaction to `target`, with `e`, `p` and `k` tags for it.
+    /// `content` is `+` for a like, `-` for a dislike, or an emoji.
+    /// Errors with `ContentOverflow` if `content` is longer than `CONTENT`.
+    pub fn create_reaction<const C: usize, const T: usize, const N: usize, const V: usize>(
+        mut self,
+        target: &Note<C, T, N, V>,
+        content: &str,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        const { assert!(3 <= NTAGS, "a reaction needs three tags") };
+        let mut reaction = String::new();
+        reaction
+            .push_str(content)
+            .map_err(|_| Error::ContentOverflow)?;
+        let (id, pubkey) = (target.id(), target.pubkey());
+        let kind = target.kind.serialize();
+        let mut tags = Vec::new();
+        // the assert above leaves room for all three
+        let _ = tags.push(Tag::try_from(["e", hex_str(&id)?])?);
+        let _ = tags.push(Tag::try_from(["p", hex_str(&pubkey)?])?);
+        let _ = tags.push(Tag::try_from(["k", kind.as_str()])?);
+        self.note.tags = tags;
+        self.note.kind = NoteKinds::Reaction;
+        self.note.content = Some(reaction);
+        Ok(self)
+    }
+
+    /// Turns the note into a repost of `target`, with `e` and `p` tags for it.
+    /// Short text notes become a kind 6 repost, anything else a kind 16 generic repost that
+    /// also gets a `k` tag, so up to three tags are used either way.
+    /// The content is the JSON of `target` when it fits in `CONTENT`, and left empty otherwise
+    /// as NIP-18 allows.
+    pub fn create_repost<const C: usize, const T: usize, const N: usize, const V: usize>(
+        mut self,
+        target: &Note<C, T, N, V>,
+        relay_hint: &str,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        const { assert!(3 <= NTAGS, "a repost needs up to three tags") };
+        let (id, pubkey) = (target.id(), target.pubkey());
+        let mut tags = Vec::new();
+        let _ = tags.push(Tag::try_from(["e", hex_str(&id)?, relay_hint])?);
+        let _ = tags.push(Tag::try_from(["p", hex_str(&pubkey)?])?);
+        self.note.kind = if target.kind == NoteKinds::ShortNote {
+            NoteKinds::Repost
+        } else {
+            let kind = target.kind.serialize();
+            let _ = tags.push(Tag::try_from(["k", kind.as_str()])?);
+            NoteKinds::GenericRepost
+        };
+        self.note.tags = tags;
+        let mut json: Vec<u8, CONTENT> = Vec::new();
+        self.note.content = match target.write_json(&mut json) {
+            Ok(()) => {
+                let json = core::str::from_utf8(&json).map_err(|_| Error::EncodeError)?;
+                let mut content = String::new();
+                // the same length as the buffer it was written to
+                let _ = content.push_str(json);
+                Some(content)
+            }
+            Err(_) => None,
+        };
+        Ok(self)
+    }
+}
+
+/// Borrows hex from an id or pubkey as a string
+fn hex_str(hex: &[u8]) -> Result<&str, Error> {
+    core::str::from_utf8(hex).map_err(|_| Error::EncodeError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, OTHER_PRIVKEY};
+
+    #[test]
+    fn test_reaction() {
+        let target = text_note();
+        let reaction = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_reaction(&target, "🤙")
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert_eq!(reaction.kind(), NoteKinds::Reaction);
+        assert_eq!(reaction.content(), Some("🤙"));
+        let pubkey = target.pubkey();
+        assert_eq!(
+            reaction.get_tag("p").unwrap()[0][0].as_bytes(),
+            pubkey.as_slice()
+        );
+        assert_eq!(reaction.get_tag("k").unwrap()[0][0], "1");
+        assert!(reaction.verify().is_ok());
+
+        let builder = NoteBuilder::<2>::new(OTHER_PRIVKEY).unwrap();
+        assert!(matches!(
+            builder.create_reaction(&target, "+++"),
+            Err(Error::ContentOverflow)
+        ));
+    }
+
+    #[test]
+    fn test_repost() {
+        let target = text_note();
+        let repost = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_repost(&target, "wss://relay.example.com")
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert_eq!(repost.kind(), NoteKinds::Repost);
+        assert_eq!(
+            repost.get_tag("e").unwrap()[0][1],
+            "wss://relay.example.com"
+        );
+        assert!(repost.get_tag("k").unwrap().is_empty());
+        let reposted = Note::try_from(repost.content().unwrap()).unwrap();
+        assert_eq!(reposted, target);
+
+        let dm = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::DM)
+            .build(1686880020, [0; 32])
+            .unwrap();
+        // too long to embed in a 100 byte content
+        let repost = NoteBuilder::<100>::new(OTHER_PRIVKEY)
+            .unwrap()
+            .create_repost(&dm, "")
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert_eq!(repost.kind(), NoteKinds::GenericRepost);
+        assert_eq!(repost.get_tag("k").unwrap()[0][0], "4");
+        assert_eq!(repost.content(), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Relay lists ([NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md)) and outbox-model relay selection
+//!
+//! A kind 10002 note lists the relays a user writes to and reads from as `r` tags.
+//! [`publish_relays`] combines these lists to pick where a note should be sent: the author's
+//! write relays, so followers find it, and the read relays of every user it mentions with a
+//! `p` tag, so they see it.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, NoteKinds, relay_list::{publish_relays, RelayList}, tag::Tag};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let list_note = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .set_kind(NoteKinds::RelayList)
+//!     .add_tag(Tag::try_from(["r", "wss://relay.damus.io"]).unwrap())
+//!     .unwrap()
+//!     .add_tag(Tag::try_from(["r", "wss://nos.lol", "read"]).unwrap())
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let lists = [RelayList::try_from(&list_note).unwrap()];
+//!
+//! let note = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let relays = publish_relays::<4>(&note, &lists, 4);
+//! assert_eq!(relays.len(), 1);
+//! assert_eq!(relays[0], "wss://relay.damus.io");
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{errors::Error, pool::RELAY_URL_SIZE, Note};
+
+/// Kind of a relay list note
+pub const RELAY_LIST_KIND: u32 = 10002;
+
+/// One `r` tag of a relay list
+#[derive(Debug, PartialEq, Clone)]
+pub struct RelayEntry {
+    pub url: String<RELAY_URL_SIZE>,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// The relays a user reads from and writes to, parsed from their kind 10002 note
+#[derive(Debug, PartialEq, Clone)]
+pub struct RelayList {
+    pubkey: [u8; 64],
+    relays: Vec<RelayEntry, 5>,
+}
+
+impl RelayList {
+    /// Hex pubkey of the user the list belongs to
+    pub fn pubkey(&self) -> &[u8; 64] {
+        &self.pubkey
+    }
+
+    /// Iterates over every listed relay
+    pub fn iter(&self) -> impl Iterator<Item = &RelayEntry> {
+        self.relays.iter()
+    }
+
+    /// Urls of the relays the user publishes to
+    pub fn write_relays(&self) -> impl Iterator<Item = &str> {
+        self.relays
+            .iter()
+            .filter(|relay| relay.write)
+            .map(|relay| relay.url.as_str())
+    }
+
+    /// Urls of the relays the user reads from
+    pub fn read_relays(&self) -> impl Iterator<Item = &str> {
+        self.relays
+            .iter()
+            .filter(|relay| relay.read)
+            .map(|relay| relay.url.as_str())
+    }
+}
+
+impl TryFrom<&Note> for RelayList {
+    type Error = Error;
+
+    /// Parses the `r` tags of a kind 10002 note.
+    /// Relays with urls longer than [`RELAY_URL_SIZE`] are skipped.
+    fn try_from(note: &Note) -> Result<Self, Self::Error> {
+        if note.kind.as_u32() != RELAY_LIST_KIND {
+            return Err(Error::InvalidType);
+        }
+        let mut relays = Vec::new();
+        for values in note.get_tag("r")? {
+            let Some(url) = values.first() else {
+                continue;
+            };
+            let mut relay_url = String::new();
+            if relay_url.push_str(url).is_err() {
+                continue;
+            }
+            // no marker means the relay is used for both
+            let marker = values.get(1).copied();
+            let entry = RelayEntry {
+                url: relay_url,
+                read: marker != Some("write"),
+                write: marker != Some("read"),
+            };
+            // a note holds at most 5 tags, so this can't overflow
+            relays.push(entry).map_err(|_| Error::TooManyTags)?;
+        }
+        Ok(RelayList {
+            pubkey: note.pubkey(),
+            relays,
+        })
+    }
+}
+
+/// Picks up to `max` relays (and at most `M`) to publish `note` to, from the relay lists in `lists`.
+///
+/// The author's write relays and the read relays of each user in the note's `p` tags are taken
+/// in turn, one from each user per round, so every recipient gets at least one relay before any
+/// user gets a second. Duplicate urls are only returned once. Users without a list in `lists` are skipped.
+pub fn publish_relays<'a, const M: usize>(
+    note: &Note,
+    lists: &'a [RelayList],
+    max: usize,
+) -> Vec<&'a str, M> {
+    let mut selected: Vec<&str, M> = Vec::new();
+    let find_list = |pubkey: &[u8]| lists.iter().find(|list| list.pubkey == pubkey);
+
+    // the author first, then mentioned users in tag order
+    let mut sources: Vec<(&RelayList, bool), 6> = Vec::new();
+    if let Some(list) = find_list(&note.pubkey()) {
+        sources.push((list, true)).ok();
+    }
+    if let Ok(mentions) = note.get_tag("p") {
+        mentions
+            .iter()
+            .filter_map(|values| values.first())
+            .filter_map(|pubkey| find_list(pubkey.as_bytes()))
+            .for_each(|list| {
+                sources.push((list, false)).ok();
+            });
+    }
+
+    let limit = max.min(M);
+    for round in 0..5 {
+        for (list, is_author) in sources.iter() {
+            if selected.len() >= limit {
+                return selected;
+            }
+            let url = if *is_author {
+                list.write_relays().nth(round)
+            } else {
+                list.read_relays().nth(round)
+            };
+            if let Some(url) = url {
+                if !selected.contains(&url) {
+                    selected.push(url).ok();
+                }
+            }
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tag::Tag,
+        vectors::{OTHER_PRIVKEY, PRIVKEY},
+        NoteKinds,
+    };
+
+    fn relay_list(privkey: &str, first: &[&str], second: &[&str]) -> RelayList {
+        let note = Note::new_builder(privkey)
+            .unwrap()
+            .set_kind(NoteKinds::RelayList)
+            .add_tag(Tag::try_from(first).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(second).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        RelayList::try_from(&note).unwrap()
+    }
+
+    #[test]
+    fn test_parse_markers() {
+        let list = relay_list(
+            PRIVKEY,
+            &["r", "wss://a.example", "write"],
+            &["r", "wss://b.example"],
+        );
+        assert_eq!(list.write_relays().count(), 2);
+        let reads: Vec<&str, 2> = list.read_relays().collect();
+        assert_eq!(reads, ["wss://b.example"]);
+
+        let short_note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(RelayList::try_from(&short_note), Err(Error::InvalidType));
+    }
+
+    #[test]
+    fn test_author_and_mentions() {
+        let mine = relay_list(
+            PRIVKEY,
+            &["r", "wss://a.example", "write"],
+            &["r", "wss://b.example", "write"],
+        );
+        let theirs = relay_list(
+            OTHER_PRIVKEY,
+            &["r", "wss://a.example", "read"],
+            &["r", "wss://c.example", "read"],
+        );
+        let p_tag = Tag::try_from(["p", core::str::from_utf8(theirs.pubkey()).unwrap()]).unwrap();
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .add_tag(p_tag)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let lists = [mine, theirs];
+
+        let all = publish_relays::<5>(&note, &lists, 5);
+        assert_eq!(
+            all,
+            ["wss://a.example", "wss://b.example", "wss://c.example"]
+        );
+        // the shared relay reaches both users, so a cap of two still covers the mention
+        let capped = publish_relays::<5>(&note, &lists, 2);
+        assert_eq!(capped, ["wss://a.example", "wss://b.example"]);
+    }
+}
@@ -1,5 +1,8 @@
 //! Handle messages from relays
 //!
+//! Devices acting as a relay can write the same messages with [`ok`], [`eose`], [`event`],
+//! [`closed`], [`notice`] and [`auth`].
+//!
 //! # Example
 //! ```
 //! use nostr_nostd::{Note, String, ClientMsgKinds,relay_responses};
@@ -9,6 +12,7 @@
 //! let msg_type = ResponseTypes::try_from(auth_msg_from_relay).unwrap();
 //! let msg: AuthMessage = match msg_type {
 //!     ResponseTypes::Auth => AuthMessage::try_from(auth_msg_from_relay).unwrap(),
+//!     ResponseTypes::Closed => panic!("handle other messages here"),
 //!     ResponseTypes::Count => panic!("handle other messages here"),
 //!     ResponseTypes::Eose => panic!("handle other messages here"),
 //!     ResponseTypes::Event => panic!("handle other messages here"),
@@ -24,22 +28,27 @@
 //!     .unwrap()
 //!     .build(1686880020, aux_rand)
 //!     .unwrap();
-//! let msg = note.serialize_to_relay(ClientMsgKinds::Auth);
+//! let msg = note.serialize_to_relay(ClientMsgKinds::Auth).unwrap();
 //! ```
 //!
 use heapless::String;
 
-use crate::{errors::Error, Note};
+use crate::{
+    buffer::OutBuf,
+    errors::{Error, Field},
+    hex::HexId,
+    note_ref::{
+        skip_value_bytes, split_number_bytes, split_object_bytes, split_string_bytes, NoteRef,
+    },
+    utils::{unescape_json, write_escaped, write_hex},
+    Note,
+};
 const CHALLENGE_STRING_SIZE: usize = 64;
-const AUTH_STR: &str = r#"["AUTH","#;
-const COUNT_STR: &str = r#"["COUNT","#;
-const EOSE_STR: &str = r#"["EOSE","#;
-const EVENT_STR: &str = r#"["EVENT","#;
-const NOTICE_STR: &str = r#"["NOTICE","#;
-const OK_STR: &str = r#"["OK","#;
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ResponseTypes {
     Auth,
+    Closed,
     Count,
     Eose,
     Event,
@@ -48,232 +57,576 @@ pub enum ResponseTypes {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct AuthMessage {
     pub challenge_string: String<CHALLENGE_STRING_SIZE>,
 }
 
+/// Machine-readable prefix a relay puts in front of the reason in some messages, per NIP-01
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessagePrefix {
+    Duplicate,
+    Pow,
+    Blocked,
+    RateLimited,
+    Invalid,
+    Restricted,
+    Mute,
+    Error,
+    AuthRequired,
+}
+
+impl MessagePrefix {
+    /// Splits a known prefix off of `message`, returning it and the human-readable rest
+    fn split(message: &str) -> Option<(MessagePrefix, &str)> {
+        let (prefix, rest) = message.split_once(':')?;
+        let prefix = match prefix {
+            "duplicate" => MessagePrefix::Duplicate,
+            "pow" => MessagePrefix::Pow,
+            "blocked" => MessagePrefix::Blocked,
+            "rate-limited" => MessagePrefix::RateLimited,
+            "invalid" => MessagePrefix::Invalid,
+            "restricted" => MessagePrefix::Restricted,
+            "mute" => MessagePrefix::Mute,
+            "error" => MessagePrefix::Error,
+            "auth-required" => MessagePrefix::AuthRequired,
+            _ => return None,
+        };
+        Some((prefix, rest.trim_start()))
+    }
+}
+
+/// Sent by a relay when it ends a subscription on its side
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClosedMessage {
+    pub subscription_id: String<64>,
+    pub message: String<180>,
+}
+
+impl ClosedMessage {
+    /// The machine-readable prefix of the message, if it has a known one
+    pub fn prefix(&self) -> Option<MessagePrefix> {
+        MessagePrefix::split(&self.message).map(|(prefix, _)| prefix)
+    }
+
+    /// The message without its machine-readable prefix
+    pub fn reason(&self) -> &str {
+        MessagePrefix::split(&self.message).map_or(&self.message, |(_, rest)| rest)
+    }
+}
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CountMessage {
     pub subscription_id: String<64>,
     pub count: u16,
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct EoseMessage {
     pub subscription_id: String<64>,
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct EventMessage {
     pub subscription_id: String<64>,
     pub note: Note,
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct NoticeMessage {
     pub message: String<180>,
 }
+
+impl NoticeMessage {
+    /// The machine-readable prefix of the message, if it has a known one
+    pub fn prefix(&self) -> Option<MessagePrefix> {
+        MessagePrefix::split(&self.message).map(|(prefix, _)| prefix)
+    }
+
+    /// The message without its machine-readable prefix
+    pub fn reason(&self) -> &str {
+        MessagePrefix::split(&self.message).map_or(&self.message, |(_, rest)| rest)
+    }
+}
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct OkMessage {
-    pub event_id: String<64>,
+    pub event_id: HexId,
     pub accepted: bool,
     pub info: String<180>,
 }
 
-impl TryFrom<&str> for ResponseTypes {
-    type Error = Error;
-    fn try_from(value: &str) -> Result<ResponseTypes, Self::Error> {
-        if value.starts_with(AUTH_STR) {
-            Ok(Self::Auth)
-        } else if value.starts_with(COUNT_STR) {
-            Ok(Self::Count)
-        } else if value.starts_with(EOSE_STR) {
-            Ok(Self::Eose)
-        } else if value.starts_with(EVENT_STR) {
-            Ok(Self::Event)
-        } else if value.starts_with(NOTICE_STR) {
-            Ok(Self::Notice)
-        } else if value.starts_with(OK_STR) {
-            Ok(Self::Ok)
+impl OkMessage {
+    /// The machine-readable prefix of the info, if it has a known one
+    pub fn prefix(&self) -> Option<MessagePrefix> {
+        MessagePrefix::split(&self.info).map(|(prefix, _)| prefix)
+    }
+
+    /// The info without its machine-readable prefix
+    pub fn reason(&self) -> &str {
+        MessagePrefix::split(&self.info).map_or(&self.info, |(_, rest)| rest)
+    }
+}
+
+/// Any message a relay sends, parsed along with its payload in one call.
+/// Messages can be parsed from the bytes they arrive in as well, only their strings, and the
+/// content and tags of an event, are checked to be UTF-8.
+/// ```
+/// use nostr_nostd::relay_responses::RelayMessage;
+/// match RelayMessage::try_from(r#"["AUTH", "encrypt this"]"#).unwrap() {
+///     RelayMessage::Auth(auth) => assert_eq!(auth.challenge_string, "encrypt this"),
+///     _ => panic!("handle other messages here"),
+/// }
+/// let frame: &[u8] = br#"["EOSE", "sub_1"]"#;
+/// assert!(matches!(RelayMessage::try_from(frame), Ok(RelayMessage::Eose(_))));
+/// ```
+// an EventMessage holds a whole note, there is no heap to box it on
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RelayMessage {
+    Auth(AuthMessage),
+    Closed(ClosedMessage),
+    Count(CountMessage),
+    Eose(EoseMessage),
+    Event(EventMessage),
+    Notice(NoticeMessage),
+    Ok(OkMessage),
+}
+
+/// Reads the elements of a relay message, a JSON array, one at a time.
+/// Any whitespace is allowed between elements. Works on the bytes of the message, only strings
+/// are checked to be UTF-8.
+pub(crate) struct Tokens<'a> {
+    msg: &'a [u8],
+    rest: &'a [u8],
+    first: bool,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(msg: &'a [u8]) -> Result<Self, Error> {
+        let rest = msg
+            .trim_ascii_start()
+            .strip_prefix(b"[")
+            .ok_or(Error::MalformedContent)?;
+        Ok(Tokens {
+            msg,
+            rest,
+            first: true,
+        })
+    }
+
+    /// Offset in the message of `element`, which was read from it
+    pub(crate) fn offset_of(&self, element: &[u8]) -> usize {
+        element.as_ptr() as usize - self.msg.as_ptr() as usize
+    }
+
+    /// `element`, which was read from these tokens, as a slice of `msg`, the string they were
+    /// made from. Elements start and end on ASCII punctuation, so no UTF-8 check is needed.
+    pub(crate) fn str_in<'s>(&self, msg: &'s str, element: &[u8]) -> Result<&'s str, Error> {
+        let start = self.offset_of(element);
+        msg.get(start..start + element.len())
+            .ok_or(Error::InternalError)
+    }
+
+    /// `Malformed` error for the element starting at the current position
+    fn malformed(&self) -> Error {
+        Error::Malformed {
+            field: Field::Element,
+            offset: self.msg.len() - self.rest.len(),
+        }
+    }
+
+    /// Moves past the whitespace and comma in front of the next element
+    fn next_element(&mut self) -> Result<(), Error> {
+        self.rest = self.rest.trim_ascii_start();
+        if !self.first {
+            self.rest = self
+                .rest
+                .strip_prefix(b",")
+                .ok_or(self.malformed())?
+                .trim_ascii_start();
+        }
+        self.first = false;
+        Ok(())
+    }
+
+    /// True if the array has no more elements
+    pub(crate) fn at_end(&self) -> bool {
+        self.rest.trim_ascii_start().starts_with(b"]")
+    }
+
+    /// Checks the array is closed after the last element read and only whitespace follows it.
+    /// Errors with `Malformed` at the first byte that shouldn't be there.
+    pub(crate) fn end(mut self) -> Result<(), Error> {
+        self.rest = self.rest.trim_ascii_start();
+        self.rest = self
+            .rest
+            .strip_prefix(b"]")
+            .ok_or(self.malformed())?
+            .trim_ascii_start();
+        if self.rest.is_empty() {
+            Ok(())
         } else {
-            Err(Error::InvalidType)
+            Err(self.malformed())
         }
     }
+
+    /// The next element as a string, still escaped.
+    /// Errors with `Malformed` if it isn't a string or isn't UTF-8.
+    pub(crate) fn string(&mut self) -> Result<&'a str, Error> {
+        self.next_element()?;
+        let (value, rest) = split_string_bytes(self.rest).map_err(|_| self.malformed())?;
+        let string = core::str::from_utf8(value).map_err(|_| self.malformed())?;
+        self.rest = rest;
+        Ok(string)
+    }
+
+    /// The next element as unparsed JSON, such as an object, number or bool
+    pub(crate) fn raw(&mut self) -> Result<&'a [u8], Error> {
+        self.next_element()?;
+        let rest = skip_value_bytes(self.rest).map_err(|_| self.malformed())?;
+        let value = self.rest[..self.rest.len() - rest.len()].trim_ascii_end();
+        self.rest = rest;
+        Ok(value)
+    }
 }
 
-impl TryFrom<&str> for AuthMessage {
-    type Error = Error;
-    fn try_from(value: &str) -> Result<AuthMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Auth {
-            Err(Error::TypeNotAccepted)
-        } else {
-            let start_index = AUTH_STR.len() + 2;
-            let end_index = value.len() - 2; // Exclude the trailing '"]'
+/// Parses from the `&str` of a message by way of its bytes
+macro_rules! try_from_str {
+    ($($name:ident),*) => {
+        $(
+            impl TryFrom<&str> for $name {
+                type Error = Error;
+                fn try_from(value: &str) -> Result<$name, Self::Error> {
+                    $name::try_from(value.as_bytes())
+                }
+            }
+        )*
+    };
+}
 
-            if end_index - start_index > CHALLENGE_STRING_SIZE {
-                return Err(Error::ContentOverflow);
-            };
+try_from_str!(
+    ResponseTypes,
+    AuthMessage,
+    ClosedMessage,
+    CountMessage,
+    EoseMessage,
+    EventMessage,
+    NoticeMessage,
+    OkMessage,
+    RelayMessage
+);
 
-            // Extract the challenge string and create an AuthMessage
-            let challenge_string = &value[start_index..end_index];
-            Ok(AuthMessage {
-                challenge_string: challenge_string.into(),
-            })
+impl TryFrom<&[u8]> for ResponseTypes {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<ResponseTypes, Self::Error> {
+        let label = Tokens::new(value)
+            .and_then(|mut tokens| tokens.string())
+            .map_err(|_| Error::InvalidType)?;
+        match label {
+            "AUTH" => Ok(Self::Auth),
+            "CLOSED" => Ok(Self::Closed),
+            "COUNT" => Ok(Self::Count),
+            "EOSE" => Ok(Self::Eose),
+            "EVENT" => Ok(Self::Event),
+            "NOTICE" => Ok(Self::Notice),
+            "OK" => Ok(Self::Ok),
+            _ => Err(Error::InvalidType),
         }
     }
 }
 
-impl TryFrom<&str> for CountMessage {
+/// Tokens of `value` past its type, errors with `TypeNotAccepted` if it isn't `expected`
+fn tokens_of(value: &[u8], expected: ResponseTypes) -> Result<Tokens<'_>, Error> {
+    if ResponseTypes::try_from(value)? != expected {
+        return Err(Error::TypeNotAccepted);
+    }
+    let mut tokens = Tokens::new(value)?;
+    tokens.string()?;
+    Ok(tokens)
+}
+
+impl TryFrom<&[u8]> for AuthMessage {
     type Error = Error;
-    fn try_from(value: &str) -> Result<CountMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Count {
-            Err(Error::TypeNotAccepted)
+    fn try_from(value: &[u8]) -> Result<AuthMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Auth)?;
+        let challenge_string = unescape_json(tokens.string()?)?;
+        tokens.end()?;
+        Ok(AuthMessage { challenge_string })
+    }
+}
+
+impl TryFrom<&[u8]> for ClosedMessage {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<ClosedMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Closed)?;
+        let subscription_id = unescape_json(tokens.string()?)?;
+        // the message is optional
+        let message = if tokens.at_end() {
+            String::new()
         } else {
-            let start_index = COUNT_STR.len() + 2;
-            let end_index = start_index + 64; // an id is 64 characters
+            unescape_json(tokens.string()?)?
+        };
+        tokens.end()?;
+        Ok(ClosedMessage {
+            subscription_id,
+            message,
+        })
+    }
+}
 
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
+impl TryFrom<&[u8]> for CountMessage {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<CountMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Count)?;
+        let subscription_id = unescape_json(tokens.string()?)?;
+        let object = tokens.raw()?;
+        let offset = tokens.offset_of(object);
+        tokens.end()?;
+        let malformed = || Error::Malformed {
+            field: Field::Element,
+            offset,
+        };
+        let mut count = None;
+        split_object_bytes(object, |key, value| {
+            if key != b"count" {
+                return skip_value_bytes(value);
             }
+            let (number, rest) = split_number_bytes(value)?;
+            count = Some(number);
+            Ok(rest)
+        })
+        .map_err(|_| malformed())?;
+        Ok(CountMessage {
+            subscription_id,
+            count: count.ok_or_else(malformed)?,
+        })
+    }
+}
 
-            // Extract the challenge string and create an AuthMessage
-            let id = &value[start_index..end_index];
-            let start_index = end_index + r#"", {"count": "#.len();
-            let end_index = value.len() - r#"}]"#.len();
-            let count_str = &value[start_index..end_index];
-            let num = u16::from_str_radix(count_str, 10).map_err(|_| Error::MalformedContent)?;
-            Ok(CountMessage {
-                subscription_id: id.into(),
-                count: num,
-            })
-        }
+impl TryFrom<&[u8]> for EoseMessage {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<EoseMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Eose)?;
+        let subscription_id = unescape_json(tokens.string()?)?;
+        tokens.end()?;
+        Ok(EoseMessage { subscription_id })
     }
 }
 
-impl TryFrom<&str> for EoseMessage {
+impl TryFrom<&[u8]> for EventMessage {
     type Error = Error;
-    fn try_from(value: &str) -> Result<EoseMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Eose {
-            Err(Error::TypeNotAccepted)
-        } else {
-            let start_index = EOSE_STR.len() + 2;
-            let end_index = start_index + 64; // an id is 64 characters
+    fn try_from(value: &[u8]) -> Result<EventMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Event)?;
+        let subscription_id = unescape_json(tokens.string()?)?;
+        let note = tokens.raw()?;
+        let offset = tokens.offset_of(note);
+        tokens.end()?;
+        Ok(EventMessage {
+            subscription_id,
+            note: Note::try_from(note).map_err(|e| e.offset_by(offset))?,
+        })
+    }
+}
 
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
-            }
+/// An [`EventMessage`] whose note points into the message instead of being copied out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventMessageRef<'a> {
+    /// Still JSON escaped
+    pub subscription_id: &'a str,
+    pub note: NoteRef<'a>,
+}
 
-            // Extract the challenge string and create an AuthMessage
-            let id = &value[start_index..end_index];
-            Ok(EoseMessage {
-                subscription_id: id.into(),
-            })
-        }
+impl<'a> TryFrom<&'a str> for EventMessageRef<'a> {
+    type Error = Error;
+    fn try_from(value: &'a str) -> Result<EventMessageRef<'a>, Self::Error> {
+        EventMessageRef::try_from(value.as_bytes())
     }
 }
 
-impl TryFrom<&str> for EventMessage {
+impl<'a> TryFrom<&'a [u8]> for EventMessageRef<'a> {
     type Error = Error;
-    fn try_from(value: &str) -> Result<EventMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Event {
-            Err(Error::TypeNotAccepted)
-        } else {
-            let start_index = EVENT_STR.len();
-            let value = &value[start_index..];
-            let subscription = value.split(",").next().ok_or(Error::EventNotValid)?;
-            let subscription_id: String<64> = subscription[1..subscription.len() - 1].into();
-
-            let end_index = value.len() - 2;
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
-            }
-            let event_json = &value[subscription_id.len()..end_index];
-            Ok(EventMessage {
-                subscription_id,
-                note: Note::try_from(event_json)?,
-            })
-        }
+    fn try_from(value: &'a [u8]) -> Result<EventMessageRef<'a>, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Event)?;
+        let subscription_id = tokens.string()?;
+        let note = tokens.raw()?;
+        let offset = tokens.offset_of(note);
+        tokens.end()?;
+        Ok(EventMessageRef {
+            subscription_id,
+            note: NoteRef::try_from(note).map_err(|e| e.offset_by(offset))?,
+        })
     }
 }
 
-impl TryFrom<&str> for NoticeMessage {
+impl TryFrom<EventMessageRef<'_>> for EventMessage {
     type Error = Error;
-    fn try_from(value: &str) -> Result<NoticeMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Notice {
-            Err(Error::TypeNotAccepted)
-        } else {
-            let start_index = COUNT_STR.len() + 3;
-            let end_index = value.len() - 2;
 
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
-            }
+    /// Copies the event out of the message, checking the note's id and signature like
+    /// [`EventMessage::try_from`] does
+    fn try_from(value: EventMessageRef<'_>) -> Result<EventMessage, Self::Error> {
+        let note: Note = value.note.to_note()?;
+        note.verify()?;
+        Ok(EventMessage {
+            subscription_id: unescape_json(value.subscription_id)?,
+            note,
+        })
+    }
+}
 
-            // Extract the challenge string and create an AuthMessage
-            let msg = &value[start_index..end_index];
-            Ok(NoticeMessage {
-                message: msg.into(),
-            })
-        }
+impl TryFrom<&[u8]> for NoticeMessage {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<NoticeMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Notice)?;
+        let message = unescape_json(tokens.string()?)?;
+        tokens.end()?;
+        Ok(NoticeMessage { message })
     }
 }
 
-impl TryFrom<&str> for OkMessage {
+impl TryFrom<&[u8]> for OkMessage {
     type Error = Error;
-    fn try_from(value: &str) -> Result<OkMessage, Self::Error> {
-        let msg_type = ResponseTypes::try_from(value)?;
-        if msg_type != ResponseTypes::Ok {
-            Err(Error::TypeNotAccepted)
+    fn try_from(value: &[u8]) -> Result<OkMessage, Self::Error> {
+        let mut tokens = tokens_of(value, ResponseTypes::Ok)?;
+        let id = tokens.string()?;
+        let event_id = HexId::try_from(id).map_err(|_| Error::Malformed {
+            field: Field::Id,
+            offset: tokens.offset_of(id.as_bytes()),
+        })?;
+        let accepted = match tokens.raw()? {
+            b"true" => true,
+            b"false" => false,
+            other => {
+                return Err(Error::Malformed {
+                    field: Field::Element,
+                    offset: tokens.offset_of(other),
+                })
+            }
+        };
+        // some relays leave out the message when accepting
+        let info = if tokens.at_end() {
+            String::new()
         } else {
-            let start_index = OK_STR.len() + 2;
-            let end_index = start_index + 64; // an id is 64 characters
+            unescape_json(tokens.string()?)?
+        };
+        tokens.end()?;
+        Ok(OkMessage {
+            event_id,
+            accepted,
+            info,
+        })
+    }
+}
 
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
-            }
-            let id = &value[start_index..end_index];
-            let start_index = end_index + 3;
-            let end_index = start_index + 5;
-            let true_false = &value[start_index..end_index];
-            let accepted = if true_false == "false" {
-                false
-            } else if true_false == "true," {
-                true
-            } else {
-                return Err(Error::MalformedContent);
-            };
-            let start_index = if accepted {
-                end_index + 2
-            } else {
-                end_index + 3
-            };
-            let end_index = value.len() - 2;
-            if value.len() < end_index {
-                return Err(Error::ContentOverflow);
-            }
-            let info = &value[start_index..end_index];
-            Ok(OkMessage {
-                event_id: id.into(),
-                accepted,
-                info: info.into(),
-            })
-        }
+impl TryFrom<&[u8]> for RelayMessage {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<RelayMessage, Self::Error> {
+        Ok(match ResponseTypes::try_from(value)? {
+            ResponseTypes::Auth => RelayMessage::Auth(value.try_into()?),
+            ResponseTypes::Closed => RelayMessage::Closed(value.try_into()?),
+            ResponseTypes::Count => RelayMessage::Count(value.try_into()?),
+            ResponseTypes::Eose => RelayMessage::Eose(value.try_into()?),
+            ResponseTypes::Event => RelayMessage::Event(value.try_into()?),
+            ResponseTypes::Notice => RelayMessage::Notice(value.try_into()?),
+            ResponseTypes::Ok => RelayMessage::Ok(value.try_into()?),
+        })
     }
 }
 
+/// Writes `["<label>","<escaped value>"`, leaving the array open
+fn write_start<B: OutBuf>(out: &mut B, label: &[u8], value: &str) -> Result<(), Error> {
+    out.push_slice(b"[\"")?;
+    out.push_slice(label)?;
+    out.push_slice(b"\",\"")?;
+    write_escaped(out, value)?;
+    out.push_byte(b'"')
+}
+
+/// Writes the `OK` a relay answers an `EVENT` with, `message` can be empty when accepting.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn ok<B: OutBuf>(
+    event_id: &[u8; 32],
+    accepted: bool,
+    message: &str,
+    out: &mut B,
+) -> Result<(), Error> {
+    out.push_slice(br#"["OK",""#)?;
+    write_hex(out, event_id)?;
+    out.push_slice(if accepted {
+        br#"",true,""#
+    } else {
+        br#"",false,""#
+    })?;
+    write_escaped(out, message)?;
+    out.push_slice(br#""]"#)
+}
+
+/// Writes the `EOSE` sent once the stored events of a subscription have all been sent.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn eose<B: OutBuf>(subscription_id: &str, out: &mut B) -> Result<(), Error> {
+    write_start(out, b"EOSE", subscription_id)?;
+    out.push_byte(b']')
+}
+
+/// Writes an `EVENT` carrying `note` to a subscription.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn event<
+    B: OutBuf,
+    const CONTENT: usize,
+    const TAG: usize,
+    const NTAGS: usize,
+    const VALUES: usize,
+>(
+    subscription_id: &str,
+    note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    out: &mut B,
+) -> Result<(), Error> {
+    write_start(out, b"EVENT", subscription_id)?;
+    out.push_byte(b',')?;
+    note.write_json(out)?;
+    out.push_byte(b']')
+}
+
+/// Writes the `CLOSED` sent when the relay ends or refuses a subscription.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn closed<B: OutBuf>(subscription_id: &str, reason: &str, out: &mut B) -> Result<(), Error> {
+    write_start(out, b"CLOSED", subscription_id)?;
+    out.push_slice(b",\"")?;
+    write_escaped(out, reason)?;
+    out.push_slice(br#""]"#)
+}
+
+/// Writes a `NOTICE` with a message for humans.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn notice<B: OutBuf>(message: &str, out: &mut B) -> Result<(), Error> {
+    write_start(out, b"NOTICE", message)?;
+    out.push_byte(b']')
+}
+
+/// Writes the `AUTH` challenge a client has to sign to authenticate.
+/// Errors with `ContentOverflow` if `out` runs out of room.
+pub fn auth<B: OutBuf>(challenge: &str, out: &mut B) -> Result<(), Error> {
+    write_start(out, b"AUTH", challenge)?;
+    out.push_byte(b']')
+}
+
 #[cfg(test)]
 mod tests {
     use heapless::Vec;
 
-    use crate::Note;
+    use crate::{utils::from_hex, Note};
 
     use super::*;
     const AUTH_MSG: &str = r#"["AUTH", "encrypt me"]"#;
+    const CLOSED_MSG: &str = r#"["CLOSED", "sub_1", "shutting down"]"#;
     const COUNT_MSG: &str = r#"["COUNT", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8", {"count": 5}]"#;
     const EOSE_MSG: &str =
         r#"["EOSE", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"]"#;
@@ -293,6 +646,31 @@ mod tests {
         assert_eq!(auth_msg, expected_msg);
     }
 
+    #[test]
+    fn test_closed() {
+        let msg = ClosedMessage::try_from(
+            r#"["CLOSED", "sub_1", "auth-required: we only serve \"DMs\" to authenticated users"]"#,
+        )
+        .unwrap();
+        assert_eq!(msg.subscription_id, "sub_1");
+        assert_eq!(msg.prefix(), Some(MessagePrefix::AuthRequired));
+        assert_eq!(
+            msg.reason(),
+            r#"we only serve "DMs" to authenticated users"#
+        );
+
+        let msg = ClosedMessage::try_from(r#"["CLOSED","sub_1","shutting down"]"#).unwrap();
+        assert_eq!(msg.prefix(), None);
+        assert_eq!(msg.reason(), "shutting down");
+        assert_eq!(
+            ClosedMessage::try_from(r#"["CLOSED","sub_1"#),
+            Err(Error::Malformed {
+                field: Field::Element,
+                offset: 10
+            })
+        );
+    }
+
     #[test]
     fn test_count() {
         let msg = CountMessage::try_from(COUNT_MSG).expect("infallible");
@@ -311,6 +689,11 @@ mod tests {
             message: "restricted: we can't serve DMs to unauthenticated users, does your client implement NIP-42?".into()
         };
         assert_eq!(msg, expected_notice);
+        assert_eq!(msg.prefix(), Some(MessagePrefix::Restricted));
+        assert_eq!(
+            msg.reason(),
+            "we can't serve DMs to unauthenticated users, does your client implement NIP-42?"
+        );
     }
 
     #[test]
@@ -326,14 +709,14 @@ mod tests {
     #[test]
     fn test_event() {
         let msg = EventMessage::try_from(EVENT_MSG).expect("infallible");
-        let expected_event = Note {
+        let expected_event: Note = Note {
             content: Some("esptest".into()),
-            id: *b"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",
-            pubkey: *b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf",
+            id: from_hex(b"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8").unwrap(),
+            pubkey: from_hex(b"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf").unwrap(),
             created_at: 1686880020,
             kind: crate::NoteKinds::ShortNote,
             tags: Vec::new(),
-            sig: *b"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab",
+            sig: from_hex(b"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab").unwrap(),
         };
         let event_msg = EventMessage {
             subscription_id: "sub_1".into(),
@@ -342,14 +725,333 @@ mod tests {
         assert_eq!(msg, event_msg);
     }
 
+    #[test]
+    fn test_event_ref() {
+        let msg = EventMessageRef::try_from(EVENT_MSG).unwrap();
+        assert_eq!(msg.subscription_id, "sub_1");
+        let note: Note = msg.note.to_note().unwrap();
+        assert_eq!(note, EventMessage::try_from(EVENT_MSG).unwrap().note);
+        assert_eq!(
+            EventMessage::try_from(msg),
+            EventMessage::try_from(EVENT_MSG)
+        );
+        // copying it out checks the signature
+        let forged = EVENT_MSG.replace("esptest", "esptesT");
+        let forged = EventMessageRef::try_from(forged.as_str()).unwrap();
+        assert_eq!(EventMessage::try_from(forged), Err(Error::EventNotValid));
+        assert_eq!(
+            EventMessageRef::try_from(r#"["EOSE","sub_1"]"#),
+            Err(Error::TypeNotAccepted)
+        );
+    }
+
     #[test]
     fn test_ok() {
         let msg = OkMessage::try_from(OK_MSG).expect("infallible");
         let expected_msg = OkMessage {
-            event_id: "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8".into(),
+            event_id: HexId::try_from(
+                "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",
+            )
+            .unwrap(),
             accepted: false,
             info: "duplicate event".into(),
         };
         assert_eq!(msg, expected_msg);
+        assert_eq!(msg.prefix(), None);
+
+        let msg = OkMessage {
+            event_id: HexId::try_from(
+                "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",
+            )
+            .unwrap(),
+            accepted: false,
+            info: "rate-limited:slow down".into(),
+        };
+        assert_eq!(msg.prefix(), Some(MessagePrefix::RateLimited));
+        assert_eq!(msg.reason(), "slow down");
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let msg = OkMessage::try_from(
+            r#"[ "OK" ,"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",true,""]"#,
+        )
+        .unwrap();
+        assert!(msg.accepted);
+        assert_eq!(msg.info, "");
+        let msg = OkMessage::try_from(
+            r#"["OK","b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",  true ]"#,
+        )
+        .unwrap();
+        assert!(msg.accepted);
+        let msg =
+            CountMessage::try_from("[\"COUNT\",\n\t\"sub_1\" , { \"count\" :12 }\n]").unwrap();
+        assert_eq!(msg.subscription_id, "sub_1");
+        assert_eq!(msg.count, 12);
+        // only the top level count is read
+        let msg = CountMessage::try_from(
+            r#"["COUNT","sub_1",{"meta":{"count":99},"note":"\"count\":7","count":3}]"#,
+        )
+        .unwrap();
+        assert_eq!(msg.count, 3);
+        let msg = EoseMessage::try_from(r#"["EOSE","sub_1"]"#).unwrap();
+        assert_eq!(msg.subscription_id, "sub_1");
+        let msg = NoticeMessage::try_from(r#"["NOTICE",   "hi"  ]"#).unwrap();
+        assert_eq!(msg.message, "hi");
+    }
+
+    #[test]
+    fn test_malformed() {
+        let malformed = |offset| Error::Malformed {
+            field: Field::Element,
+            offset,
+        };
+        const OK_START: &str =
+            r#"["OK","b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8""#;
+        let mut msg: Vec<u8, 128> = Vec::new();
+        msg.extend_from_slice(OK_START.as_bytes()).unwrap();
+        msg.push(b']').unwrap();
+        assert_eq!(
+            OkMessage::try_from(core::str::from_utf8(&msg).unwrap()).unwrap_err(),
+            malformed(OK_START.len())
+        );
+        msg.pop();
+        msg.extend_from_slice(br#",maybe,""]"#).unwrap();
+        assert_eq!(
+            OkMessage::try_from(core::str::from_utf8(&msg).unwrap()).unwrap_err(),
+            malformed(OK_START.len() + 1)
+        );
+        assert_eq!(
+            OkMessage::try_from(r#"["OK","abc",true,""]"#).unwrap_err(),
+            Error::Malformed {
+                field: Field::Id,
+                offset: 7
+            }
+        );
+        assert_eq!(
+            CountMessage::try_from(r#"["COUNT","sub_1",{"count":}]"#).unwrap_err(),
+            malformed(17)
+        );
+        assert_eq!(
+            CountMessage::try_from(r#"["COUNT","sub_1",{"meta":{"count":99}}]"#).unwrap_err(),
+            malformed(17)
+        );
+        assert_eq!(
+            EoseMessage::try_from(r#"["EOSE","sub_1"#).unwrap_err(),
+            malformed(8)
+        );
+        assert_eq!(
+            NoticeMessage::try_from(r#"["NOTICE"]"#).unwrap_err(),
+            malformed(9)
+        );
+        // errors in the note point into the whole message
+        let msg = r#"["EVENT", "sub_1", {"content":"esptest!","created_at":soon,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+        assert_eq!(
+            EventMessage::try_from(msg),
+            Err(Error::Malformed {
+                field: Field::CreatedAt,
+                offset: msg.find(r#""created_at""#).unwrap()
+            })
+        );
+        assert_eq!(
+            EoseMessage::try_from(r#"["NOTICE","hi"]"#),
+            Err(Error::TypeNotAccepted)
+        );
+    }
+
+    #[test]
+    fn test_unclosed_and_extra_elements() {
+        let malformed = |msg: &str, at: &str| Error::Malformed {
+            field: Field::Element,
+            offset: msg.rfind(at).unwrap(),
+        };
+        // every message ends after its last element, cut short or with more it's malformed
+        for msg in [
+            AUTH_MSG, CLOSED_MSG, COUNT_MSG, EOSE_MSG, EVENT_MSG, NOTICE_MSG, OK_MSG,
+        ] {
+            let unclosed = msg.strip_suffix(']').unwrap();
+            // blamed on the end, or on an object or number that runs into it
+            assert!(matches!(
+                RelayMessage::try_from(unclosed),
+                Err(Error::Malformed {
+                    field: Field::Element,
+                    ..
+                })
+            ));
+            let mut extra: String<1024> = unclosed.into();
+            extra.push_str(",1,2]").unwrap();
+            assert_eq!(
+                RelayMessage::try_from(extra.as_str()),
+                Err(malformed(&extra, ",1,2]"))
+            );
+            let mut trailing: String<1024> = msg.into();
+            trailing.push_str(" \n").unwrap();
+            assert!(RelayMessage::try_from(trailing.as_str()).is_ok());
+            trailing.push_str("]").unwrap();
+            assert_eq!(
+                RelayMessage::try_from(trailing.as_str()),
+                Err(malformed(&trailing, "]"))
+            );
+        }
+        let msg = r#"["EOSE","sub",1,2]"#;
+        assert_eq!(EoseMessage::try_from(msg), Err(malformed(msg, ",1,2]")));
+        let msg = r#"["OK","b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",true,"",""]"#;
+        assert_eq!(OkMessage::try_from(msg), Err(malformed(msg, r#","""#)));
+        let event = EVENT_MSG.strip_suffix(']').unwrap();
+        assert!(matches!(
+            EventMessageRef::try_from(event),
+            Err(Error::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_relay_message() {
+        assert_eq!(
+            RelayMessage::try_from(AUTH_MSG),
+            AuthMessage::try_from(AUTH_MSG).map(RelayMessage::Auth)
+        );
+        assert_eq!(
+            RelayMessage::try_from(COUNT_MSG),
+            CountMessage::try_from(COUNT_MSG).map(RelayMessage::Count)
+        );
+        assert_eq!(
+            RelayMessage::try_from(EOSE_MSG),
+            EoseMessage::try_from(EOSE_MSG).map(RelayMessage::Eose)
+        );
+        assert_eq!(
+            RelayMessage::try_from(EVENT_MSG),
+            EventMessage::try_from(EVENT_MSG).map(RelayMessage::Event)
+        );
+        assert_eq!(
+            RelayMessage::try_from(NOTICE_MSG),
+            NoticeMessage::try_from(NOTICE_MSG).map(RelayMessage::Notice)
+        );
+        assert!(matches!(
+            RelayMessage::try_from(OK_MSG),
+            Ok(RelayMessage::Ok(OkMessage {
+                accepted: false,
+                ..
+            }))
+        ));
+        assert_eq!(
+            RelayMessage::try_from(r#"["CLOSED","sub_1"]"#),
+            ClosedMessage::try_from(r#"["CLOSED","sub_1"]"#).map(RelayMessage::Closed)
+        );
+        assert_eq!(
+            RelayMessage::try_from(r#"["UNKNOWN","sub_1"]"#),
+            Err(Error::InvalidType)
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        for msg in [AUTH_MSG, COUNT_MSG, EOSE_MSG, EVENT_MSG, NOTICE_MSG, OK_MSG] {
+            assert_eq!(
+                RelayMessage::try_from(msg.as_bytes()),
+                RelayMessage::try_from(msg)
+            );
+        }
+        assert_eq!(
+            RelayMessage::try_from(&b"[\"EOSE\",\"sub_\xff\"]"[..]),
+            Err(Error::Malformed {
+                field: Field::Element,
+                offset: 8
+            })
+        );
+
+        // only the string fields of the note are checked to be UTF-8
+        let (start, note) = EVENT_MSG.split_at(EVENT_MSG.find('{').unwrap() + 1);
+        let mut msg: heapless::Vec<u8, 700> = heapless::Vec::new();
+        msg.extend_from_slice(start.as_bytes()).unwrap();
+        msg.extend_from_slice(b"\"extra\":\"\xff\",").unwrap();
+        msg.extend_from_slice(note.as_bytes()).unwrap();
+        assert_eq!(
+            RelayMessage::try_from(msg.as_slice()),
+            RelayMessage::try_from(EVENT_MSG)
+        );
+        // and a borrowed note is read in place
+        let event = EventMessageRef::try_from(msg.as_slice()).unwrap();
+        assert_eq!(event.note.content(), "esptest");
+        assert!(msg.as_ptr_range().contains(&event.note.content().as_ptr()));
+        let content = msg.windows(7).position(|w| w == b"esptest").unwrap();
+        msg[content] = 0xff;
+        let malformed = || Error::Malformed {
+            field: Field::Content,
+            offset: content - r#""content":""#.len(),
+        };
+        assert_eq!(RelayMessage::try_from(msg.as_slice()), Err(malformed()));
+        assert_eq!(
+            EventMessageRef::try_from(msg.as_slice()).err(),
+            Some(malformed())
+        );
+
+        // frames cut short or carrying extra elements are rejected in place too
+        let frame = EVENT_MSG.as_bytes();
+        let unclosed = &frame[..frame.len() - 1];
+        assert!(matches!(
+            EventMessageRef::try_from(unclosed),
+            Err(Error::Malformed { .. })
+        ));
+        let mut extra: heapless::Vec<u8, 700> = heapless::Vec::new();
+        extra.extend_from_slice(unclosed).unwrap();
+        extra.extend_from_slice(b",\"\xff\"]").unwrap();
+        let at_extra = || Error::Malformed {
+            field: Field::Element,
+            offset: unclosed.len(),
+        };
+        assert_eq!(EventMessageRef::try_from(extra.as_slice()), Err(at_extra()));
+        assert_eq!(RelayMessage::try_from(extra.as_slice()), Err(at_extra()));
+        assert_eq!(
+            RelayMessage::try_from(&br#"["EOSE","sub_1"]]"#[..]),
+            Err(Error::Malformed {
+                field: Field::Element,
+                offset: 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_responses() {
+        let note = crate::vectors::text_note();
+        let mut out: Vec<u8, 1024> = Vec::new();
+        ok(
+            note.id_bytes(),
+            false,
+            "duplicate: already have it",
+            &mut out,
+        )
+        .unwrap();
+        let msg = OkMessage::try_from(core::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(msg.event_id.as_bytes(), note.id_bytes());
+        assert!(!msg.accepted);
+        assert_eq!(msg.prefix(), Some(MessagePrefix::Duplicate));
+
+        out.clear();
+        eose("sub \"1\"", &mut out).unwrap();
+        assert_eq!(out, br#"["EOSE","sub \"1\""]"#);
+        let msg = EoseMessage::try_from(core::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(msg.subscription_id, "sub \"1\"");
+
+        out.clear();
+        event("sub_1", &note, &mut out).unwrap();
+        let msg = EventMessage::try_from(core::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!((msg.subscription_id.as_str(), msg.note), ("sub_1", note));
+
+        out.clear();
+        closed("sub_1", "auth-required: sign in first", &mut out).unwrap();
+        let msg = ClosedMessage::try_from(core::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(msg.prefix(), Some(MessagePrefix::AuthRequired));
+
+        out.clear();
+        notice("slow down", &mut out).unwrap();
+        assert_eq!(out, br#"["NOTICE","slow down"]"#);
+
+        out.clear();
+        auth("challenge_me", &mut out).unwrap();
+        let msg = AuthMessage::try_from(core::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(msg.challenge_string, "challenge_me");
+
+        let mut small: Vec<u8, 8> = Vec::new();
+        assert_eq!(notice("slow down", &mut small), Err(Error::ContentOverflow));
     }
 }
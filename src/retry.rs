@@ -0,0 +1,444 @@
+//! Deciding whether to resend after a relay turns something down
+//!
+//! A relay rejecting an event answers `["OK", <id>, false, "<prefix>: <reason>"]`, and one
+//! ending a subscription answers `["CLOSED", <id>, "<prefix>: <reason>"]`. [`Failure::of`]
+//! sorts the machine-readable prefix into what can be done about it, and [`PublishTracker`]
+//! correlates those answers with what was sent, recommending a [`RetryAction`] with a bounded
+//! number of attempts. Like [`backoff`](crate::backoff), it does no IO and keeps no clock.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     backoff::Backoff,
+//!     hex::HexId,
+//!     relay_responses::OkMessage,
+//!     retry::{PublishTracker, RetryAction},
+//! };
+//! let id = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+//! // retry after 2 to 4 seconds at first, doubling up to 60, at most 3 sends
+//! let mut tracker: PublishTracker<4> = PublishTracker::new(Backoff::new(4, 60), 3);
+//! let now = 1686880020;
+//! // send the event, then
+//! tracker.sent_event(&HexId::try_from(id).unwrap()).unwrap();
+//!
+//! let ok = OkMessage {
+//!     event_id: HexId::try_from(id).unwrap(),
+//!     accepted: false,
+//!     info: "rate-limited: slow down".into(),
+//! };
+//! // pass a value from the device's RNG for jitter
+//! assert_eq!(tracker.handle_ok(&ok, now, 0), Some(RetryAction::ResendAt(now + 2)));
+//! assert_eq!(tracker.next_due(now + 1), None);
+//! assert_eq!(tracker.next_due(now + 2), Some(id));
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    backoff::Backoff,
+    errors::Error,
+    hex::HexId,
+    relay_responses::{ClosedMessage, MessagePrefix, OkMessage},
+};
+
+/// What kind of failure a relay reported, going by its machine-readable prefix
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Failure {
+    /// The relay may accept the same message later, such as `rate-limited:`, `error:` or no
+    /// prefix at all
+    Transient,
+    /// The relay wants the client to authenticate first
+    AuthRequired,
+    /// The relay wants more proof of work, which means building a new event with a new id
+    Pow,
+    /// Sending the same message again won't help, such as `blocked:`, `invalid:`,
+    /// `restricted:` or `mute:`
+    Permanent,
+}
+
+impl Failure {
+    /// Sorts a rejection by its prefix. `duplicate:` is not a failure, the relay already
+    /// has the event, and is treated as [`Failure::Permanent`] here since resending is
+    /// pointless.
+    pub fn of(prefix: Option<MessagePrefix>) -> Self {
+        match prefix {
+            None | Some(MessagePrefix::RateLimited) | Some(MessagePrefix::Error) => {
+                Failure::Transient
+            }
+            Some(MessagePrefix::AuthRequired) => Failure::AuthRequired,
+            Some(MessagePrefix::Pow) => Failure::Pow,
+            Some(MessagePrefix::Duplicate)
+            | Some(MessagePrefix::Blocked)
+            | Some(MessagePrefix::Invalid)
+            | Some(MessagePrefix::Restricted)
+            | Some(MessagePrefix::Mute) => Failure::Permanent,
+        }
+    }
+}
+
+/// What to do about a tracked message after the relay answered it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RetryAction {
+    /// The relay accepted the event or already had it, it is no longer tracked
+    Delivered,
+    /// Resend at this unix time, [`PublishTracker::next_due`] hands it out then
+    ResendAt(u64),
+    /// Authenticate and resend, it is due right away
+    Authenticate,
+    /// Don't resend, it is no longer tracked. Carries the failure the relay answered with
+    /// last, also when it is one worth retrying but no attempts are left.
+    GiveUp(Failure),
+}
+
+struct Tracked {
+    id: String<64>,
+    /// The decoded id, for a message tracked as an event
+    event: Option<HexId>,
+    attempts: u8,
+    /// `None` while waiting for the relay to answer
+    resend_at: Option<u64>,
+    backoff: Backoff,
+}
+
+/// Tracks up to `N` sent events or subscriptions until the relay accepts or finally rejects
+/// them
+pub struct PublishTracker<const N: usize> {
+    entries: Vec<Tracked, N>,
+    backoff: Backoff,
+    max_attempts: u8,
+}
+
+impl<const N: usize> PublishTracker<N> {
+    /// Creates an empty tracker.
+    /// - `backoff` is copied for every tracked message and picks the delay before each resend
+    /// - `max_attempts` is the number of sends before a message is given up on
+    pub const fn new(backoff: Backoff, max_attempts: u8) -> Self {
+        PublishTracker {
+            entries: Vec::new(),
+            backoff,
+            max_attempts,
+        }
+    }
+
+    /// Number of tracked messages
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if nothing is tracked
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of times the message with `id` has been sent, 0 if it isn't tracked
+    pub fn attempts(&self, id: &str) -> u8 {
+        self.position(id)
+            .map_or(0, |pos| self.entries[pos].attempts)
+    }
+
+    /// Records that the subscription with `id` was sent, counting an attempt if it was
+    /// already tracked. The id is matched as given, the way a `CLOSED` answer carries it back.
+    /// Errors with `ContentOverflow` if `id` is longer than 64 bytes and `QueueFull` if `N`
+    /// messages are already tracked.
+    pub fn sent(&mut self, id: &str) -> Result<(), Error> {
+        if let Some(pos) = self.position(id) {
+            self.resent(pos);
+            return Ok(());
+        }
+        let mut tracked = String::new();
+        tracked.push_str(id).map_err(|_| Error::ContentOverflow)?;
+        self.track(tracked, None)
+    }
+
+    /// Records that the event with `id` was sent, counting an attempt if it was already
+    /// tracked. [`PublishTracker::next_due`] hands the id back as lowercase hex.
+    /// Errors with `QueueFull` if `N` messages are already tracked.
+    pub fn sent_event(&mut self, id: &HexId) -> Result<(), Error> {
+        if let Some(pos) = self.position_event(id) {
+            self.resent(pos);
+            return Ok(());
+        }
+        let hex = id.to_hex();
+        let hex = core::str::from_utf8(&hex).map_err(|_| Error::EncodeError)?;
+        self.track(hex.into(), Some(*id))
+    }
+
+    fn resent(&mut self, pos: usize) {
+        let entry = &mut self.entries[pos];
+        entry.attempts = entry.attempts.saturating_add(1);
+        entry.resend_at = None;
+    }
+
+    fn track(&mut self, id: String<64>, event: Option<HexId>) -> Result<(), Error> {
+        self.entries
+            .push(Tracked {
+                id,
+                event,
+                attempts: 1,
+                resend_at: None,
+                backoff: self.backoff.clone(),
+            })
+            .map_err(|_| Error::QueueFull)
+    }
+
+    /// Returns the id of a message due to be resent at `now`, the longest overdue first.
+    /// Call [`PublishTracker::sent`] once it has been resent.
+    pub fn next_due(&self, now: u64) -> Option<&str> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some((entry.resend_at?, entry)))
+            .filter(|(at, _)| *at <= now)
+            .min_by_key(|(at, _)| *at)
+            .map(|(_, entry)| entry.id.as_str())
+    }
+
+    /// Records a relay's `OK` answer to a tracked event, `random` is any value from the
+    /// caller's RNG used for jitter.
+    /// Returns `None` if the event id isn't tracked.
+    pub fn handle_ok(&mut self, ok: &OkMessage, now: u64, random: u32) -> Option<RetryAction> {
+        let pos = self.position_event(&ok.event_id)?;
+        // a relay that already has the event counts as a delivery
+        if ok.accepted || ok.prefix() == Some(MessagePrefix::Duplicate) {
+            self.entries.swap_remove(pos);
+            return Some(RetryAction::Delivered);
+        }
+        Some(self.failed(pos, Failure::of(ok.prefix()), now, random))
+    }
+
+    /// Records a relay's `CLOSED` answer to a tracked subscription, `random` is any value
+    /// from the caller's RNG used for jitter.
+    /// Returns `None` if the subscription id isn't tracked.
+    pub fn handle_closed(
+        &mut self,
+        closed: &ClosedMessage,
+        now: u64,
+        random: u32,
+    ) -> Option<RetryAction> {
+        let pos = self.entries.iter().position(|entry| {
+            entry.event.is_none() && entry.id.as_str() == closed.subscription_id.as_str()
+        })?;
+        Some(self.failed(pos, Failure::of(closed.prefix()), now, random))
+    }
+
+    /// Stops tracking the message with `id`, returning true if it was tracked
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.position(id) {
+            Some(pos) => {
+                self.entries.swap_remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Position of the subscription with `id`, or of the event with `id` in either case
+    fn position(&self, id: &str) -> Option<usize> {
+        let event = HexId::try_from(id).ok();
+        self.entries.iter().position(|entry| match entry.event {
+            Some(tracked) => event == Some(tracked),
+            None => entry.id.as_str() == id,
+        })
+    }
+
+    fn position_event(&self, id: &HexId) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.event == Some(*id))
+    }
+
+    fn failed(&mut self, pos: usize, failure: Failure, now: u64, random: u32) -> RetryAction {
+        let entry = &mut self.entries[pos];
+        let action = match failure {
+            _ if entry.attempts >= self.max_attempts => RetryAction::GiveUp(failure),
+            Failure::Transient => {
+                RetryAction::ResendAt(now.saturating_add(entry.backoff.next_delay(random).into()))
+            }
+            Failure::AuthRequired => RetryAction::Authenticate,
+            Failure::Pow | Failure::Permanent => RetryAction::GiveUp(failure),
+        };
+        match action {
+            RetryAction::ResendAt(at) => entry.resend_at = Some(at),
+            RetryAction::Authenticate => entry.resend_at = Some(now),
+            _ => {
+                self.entries.swap_remove(pos);
+            }
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ID: &str = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+
+    fn id() -> HexId {
+        HexId::try_from(ID).unwrap()
+    }
+
+    fn ok(accepted: bool, info: &str) -> OkMessage {
+        OkMessage {
+            event_id: id(),
+            accepted,
+            info: info.into(),
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(Failure::of(None), Failure::Transient);
+        assert_eq!(
+            Failure::of(Some(MessagePrefix::RateLimited)),
+            Failure::Transient
+        );
+        assert_eq!(Failure::of(Some(MessagePrefix::Error)), Failure::Transient);
+        assert_eq!(
+            Failure::of(Some(MessagePrefix::AuthRequired)),
+            Failure::AuthRequired
+        );
+        assert_eq!(Failure::of(Some(MessagePrefix::Pow)), Failure::Pow);
+        assert_eq!(
+            Failure::of(Some(MessagePrefix::Blocked)),
+            Failure::Permanent
+        );
+        assert_eq!(
+            Failure::of(Some(MessagePrefix::Invalid)),
+            Failure::Permanent
+        );
+    }
+
+    #[test]
+    fn test_bounded_retries() {
+        let mut tracker: PublishTracker<2> = PublishTracker::new(Backoff::new(10, 100), 3);
+        let limited = ok(false, "rate-limited: slow down");
+        assert_eq!(tracker.handle_ok(&limited, 0, 0), None);
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&limited, 100, 0),
+            Some(RetryAction::ResendAt(105))
+        );
+        assert_eq!(tracker.next_due(104), None);
+        assert_eq!(tracker.next_due(105), Some(ID));
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(tracker.next_due(200), None);
+        // the delay doubles
+        assert_eq!(
+            tracker.handle_ok(&limited, 200, 0),
+            Some(RetryAction::ResendAt(210))
+        );
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(tracker.attempts(ID), 3);
+        assert_eq!(
+            tracker.handle_ok(&limited, 300, 0),
+            Some(RetryAction::GiveUp(Failure::Transient))
+        );
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_actions() {
+        let mut tracker: PublishTracker<1> = PublishTracker::new(Backoff::new(10, 100), 3);
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(tracker.sent("other"), Err(Error::QueueFull));
+        assert_eq!(
+            tracker.handle_ok(
+                &ok(false, "auth-required: we only accept events from members"),
+                50,
+                0
+            ),
+            Some(RetryAction::Authenticate)
+        );
+        assert_eq!(tracker.next_due(50), Some(ID));
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&ok(false, "duplicate: already have it"), 60, 0),
+            Some(RetryAction::Delivered)
+        );
+        assert!(tracker.is_empty());
+
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&ok(false, "pow: difficulty 25 is required"), 60, 0),
+            Some(RetryAction::GiveUp(Failure::Pow))
+        );
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&ok(false, "blocked: you are banned"), 60, 0),
+            Some(RetryAction::GiveUp(Failure::Permanent))
+        );
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&ok(true, ""), 60, 0),
+            Some(RetryAction::Delivered)
+        );
+        tracker.sent_event(&id()).unwrap();
+        assert!(tracker.remove(ID));
+        assert!(!tracker.remove(ID));
+
+        // out of attempts, the last failure is reported as is
+        let mut tracker: PublishTracker<1> = PublishTracker::new(Backoff::new(10, 100), 1);
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(
+            tracker.handle_ok(&ok(false, "auth-required: members only"), 0, 0),
+            Some(RetryAction::GiveUp(Failure::AuthRequired))
+        );
+    }
+
+    #[test]
+    fn test_mixed_case_id() {
+        let mut tracker: PublishTracker<1> = PublishTracker::new(Backoff::new(10, 100), 3);
+        let upper = "B515DA91AC5DF638FAE0A6E658E03ACC1DDA6152DD2107D02D5702CCFCF927E8";
+        tracker
+            .sent_event(&HexId::try_from(upper).unwrap())
+            .unwrap();
+        tracker.sent_event(&id()).unwrap();
+        assert_eq!(tracker.attempts(ID), 2);
+        assert_eq!(tracker.attempts(upper), 2);
+        assert_eq!(
+            tracker.handle_ok(&ok(false, "rate-limited: slow down"), 0, 0),
+            Some(RetryAction::ResendAt(5))
+        );
+        assert_eq!(tracker.next_due(5), Some(ID));
+        assert!(tracker.remove(upper));
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_closed() {
+        let mut tracker: PublishTracker<3> = PublishTracker::new(Backoff::new(10, 100), 3);
+        tracker.sent("sub_0").unwrap();
+        tracker.sent("sub_1").unwrap();
+        // a subscription id that happens to be hex is kept as given
+        let hex_sub = "B515DA91AC5DF638FAE0A6E658E03ACC1DDA6152DD2107D02D5702CCFCF927E8";
+        tracker.sent(hex_sub).unwrap();
+        let closed = |id: &str, message: &str| ClosedMessage {
+            subscription_id: id.into(),
+            message: message.into(),
+        };
+        assert_eq!(
+            tracker.handle_closed(&closed("sub_0", "error: shutting down"), 0, 10),
+            Some(RetryAction::ResendAt(9))
+        );
+        assert_eq!(
+            tracker.handle_closed(&closed("sub_1", "restricted: not allowed"), 0, 0),
+            Some(RetryAction::GiveUp(Failure::Permanent))
+        );
+        assert_eq!(
+            tracker.handle_closed(&closed("sub_2", "error: shutting down"), 0, 0),
+            None
+        );
+        assert_eq!(tracker.handle_ok(&ok(true, ""), 0, 0), None);
+        assert_eq!(
+            tracker.handle_closed(&closed(ID, "error: shutting down"), 0, 0),
+            None
+        );
+        assert_eq!(
+            tracker.handle_closed(&closed(hex_sub, "error: shutting down"), 0, 0),
+            Some(RetryAction::ResendAt(5))
+        );
+        assert_eq!(tracker.next_due(5), Some(hex_sub));
+        assert_eq!(tracker.len(), 2);
+    }
+}
@@ -0,0 +1,273 @@
+//! Moving an identity to a new key
+//!
+//! [`KeyRotation`] produces the events needed to switch keys: a last note from the old key
+//! pointing to the new one, a [NIP-26](https://github.com/nostr-protocol/nips/blob/master/26.md)
+//! delegation letting the old key keep publishing for the new one during the transition, and
+//! fresh metadata (kind 0) and relay list (kind 10002) notes signed by the new key.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{rotation::KeyRotation, NoteBuilder};
+//! let old_privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let new_privkey = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+//! let rotation = KeyRotation::new(old_privkey, new_privkey).unwrap();
+//! let now = 1686880020;
+//! let announcement = rotation.announcement("Moving to a new key", now, [0; 32]).unwrap();
+//! assert!(announcement.content().unwrap().contains("nostr:npub1"));
+//! // the old key may publish on behalf of the new one for a week
+//! let delegation = rotation.delegation(now + 7 * 24 * 60 * 60, [0; 32]).unwrap();
+//! // which its notes show with a tag holding the 128 character signature
+//! let delegated = NoteBuilder::<400, 128>::new(old_privkey)
+//!     .unwrap()
+//!     .add_tag(delegation.to_tag().unwrap())
+//!     .unwrap()
+//!     .build(now, [0; 32])
+//!     .unwrap();
+//! let metadata = rotation
+//!     .metadata(r#"{"name":"esp32"}"#, now, [0; 32])
+//!     .unwrap();
+//! ```
+
+use heapless::String;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    curve, errors::Error, relay_list::RelayList, tag::Tag, utils::to_bech32, utils::to_decimal_str,
+    Note, NoteKinds, NOTE_SIZE,
+};
+
+/// A NIP-26 delegation token, signed by the delegator
+#[derive(Debug, PartialEq)]
+pub struct Delegation {
+    /// Hex pubkey of the key delegating its authority
+    pub delegator: [u8; 64],
+    /// Query string of conditions the delegated events must meet, eg `created_at<1687484820`
+    pub conditions: String<64>,
+    /// Hex Schnorr signature of the delegation string by the delegator
+    pub sig: [u8; 128],
+}
+
+impl Delegation {
+    /// The `["delegation", <delegator>, <conditions>, <sig>]` tag a delegated note carries.
+    /// The signature is 128 characters, so `TAG` must be at least 128, more than the default
+    /// [`TAG_VALUE_SIZE`](crate::tag::TAG_VALUE_SIZE).
+    /// Errors with `ContentOverflow` if a value is longer than `TAG`.
+    pub fn to_tag<const TAG: usize, const VALUES: usize>(&self) -> Result<Tag<TAG, VALUES>, Error> {
+        let delegator = core::str::from_utf8(&self.delegator).map_err(|_| Error::EncodeError)?;
+        let sig = core::str::from_utf8(&self.sig).map_err(|_| Error::EncodeError)?;
+        Tag::try_from(["delegation", delegator, &self.conditions, sig])
+    }
+}
+
+/// Builds the events for moving from one key to another
+pub struct KeyRotation<'a> {
+    old_privkey: &'a str,
+    new_privkey: &'a str,
+    old_pubkey: [u8; 64],
+    new_pubkey: [u8; 64],
+}
+
+impl<'a> KeyRotation<'a> {
+    /// Errors with `InvalidPrivkey` if either key is invalid
+    pub fn new(old_privkey: &'a str, new_privkey: &'a str) -> Result<Self, Error> {
+        // building a note is the one place keys are parsed, reuse it for the pubkeys
+        let old_pubkey = Note::new_builder(old_privkey)?.note.pubkey();
+        let new_pubkey = Note::new_builder(new_privkey)?.note.pubkey();
+        Ok(KeyRotation {
+            old_privkey,
+            new_privkey,
+            old_pubkey,
+            new_pubkey,
+        })
+    }
+
+    /// Hex pubkey of the new key
+    pub fn new_pubkey(&self) -> &[u8; 64] {
+        &self.new_pubkey
+    }
+
+    /// A short note from the old key ending with `message`, a `nostr:npub...` link to the new key and a `p` tag for it
+    pub fn announcement(
+        &self,
+        message: &str,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note, Error> {
+        let mut raw_pubkey = [0_u8; 32];
+        base16ct::lower::decode(self.new_pubkey, &mut raw_pubkey)
+            .map_err(|_| Error::EncodeError)?;
+        let npub: String<63> = to_bech32("npub", &raw_pubkey)?;
+
+        let mut content: String<NOTE_SIZE> = String::new();
+        if !message.is_empty() {
+            content
+                .push_str(message)
+                .map_err(|_| Error::ContentOverflow)?;
+            content.push(' ').map_err(|_| Error::ContentOverflow)?;
+        }
+        content
+            .push_str("nostr:")
+            .map_err(|_| Error::ContentOverflow)?;
+        content
+            .push_str(&npub)
+            .map_err(|_| Error::ContentOverflow)?;
+
+        let new_pubkey = core::str::from_utf8(&self.new_pubkey).map_err(|_| Error::EncodeError)?;
+        let p_tag = Tag::try_from(["p", new_pubkey])?;
+        Note::new_builder(self.old_privkey)?
+            .content(content)
+            .add_tag(p_tag)?
+            .build(created_at, aux_rnd)
+    }
+
+    /// Signs a delegation from the new key to the old one, valid for events created before `until`
+    pub fn delegation(&self, until: u64, aux_rnd: [u8; 32]) -> Result<Delegation, Error> {
+        let mut conditions: String<64> = String::from("created_at<");
+        conditions
+            .push_str(&to_decimal_str(until))
+            .map_err(|_| Error::ContentOverflow)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"nostr:delegation:");
+        hasher.update(self.old_pubkey);
+        hasher.update(b":");
+        hasher.update(conditions.as_bytes());
+        let digest = hasher.finalize();
+
+        let key_pair = curve::keypair_from_hex(self.new_privkey)?;
+        let sig = curve::sign(&key_pair, &digest.into(), &aux_rnd)?;
+
+        let mut delegation = Delegation {
+            delegator: self.new_pubkey,
+            conditions,
+            sig: [0; 128],
+        };
+        base16ct::lower::encode(&sig, &mut delegation.sig).map_err(|_| Error::EncodeError)?;
+        Ok(delegation)
+    }
+
+    /// A kind 0 metadata note from the new key, `metadata` being its JSON content
+    pub fn metadata(
+        &self,
+        metadata: &str,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note, Error> {
+        let mut content = String::new();
+        content
+            .push_str(metadata)
+            .map_err(|_| Error::ContentOverflow)?;
+        Note::new_builder(self.new_privkey)?
+            .set_kind(NoteKinds::Metadata)
+            .content(content)
+            .build(created_at, aux_rnd)
+    }
+
+    /// A kind 10002 note from the new key listing the same relays as `relays`
+    pub fn relay_list(
+        &self,
+        relays: &RelayList,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note, Error> {
+        let mut builder = Note::new_builder(self.new_privkey)?.set_kind(NoteKinds::RelayList);
+        for relay in relays.iter() {
+            let mut tag = Tag::try_from(["r", &relay.url])?;
+            match (relay.read, relay.write) {
+                (true, false) => tag.push_value("read")?,
+                (false, true) => tag.push_value("write")?,
+                _ => {}
+            }
+            // the list came from a note, so it has at most 5 tags
+            builder
+                .note
+                .tags
+                .push(tag)
+                .map_err(|_| Error::TooManyTags)?;
+        }
+        builder.build(created_at, aux_rnd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{Curve, CurveBackend};
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
+    use crate::NoteBuilder;
+
+    #[test]
+    fn test_announcement() {
+        let rotation = KeyRotation::new(PRIVKEY, OTHER_PRIVKEY).unwrap();
+        let note = rotation.announcement("moved", 1686880020, [0; 32]).unwrap();
+        assert!(note.content().unwrap().starts_with("moved nostr:npub1"));
+        let p_tags = note.get_tag("p").unwrap();
+        assert_eq!(p_tags[0][0].as_bytes(), rotation.new_pubkey().as_slice());
+        assert_eq!(
+            note.pubkey(),
+            Note::new_builder(PRIVKEY).unwrap().note.pubkey()
+        );
+    }
+
+    #[test]
+    fn test_delegation_signature() {
+        let rotation = KeyRotation::new(PRIVKEY, OTHER_PRIVKEY).unwrap();
+        let delegation = rotation.delegation(1687484820, [0; 32]).unwrap();
+        assert_eq!(delegation.conditions, "created_at<1687484820");
+
+        let mut token: String<160> = String::from("nostr:delegation:");
+        token
+            .push_str(core::str::from_utf8(&rotation.old_pubkey).unwrap())
+            .unwrap();
+        token.push_str(":created_at<1687484820").unwrap();
+        let digest = Sha256::digest(token.as_bytes());
+
+        let mut raw = [0_u8; 64];
+        base16ct::lower::decode(delegation.sig, &mut raw).unwrap();
+        let mut pubkey = [0_u8; 32];
+        base16ct::lower::decode(delegation.delegator, &mut pubkey).unwrap();
+        curve::verify(&Curve::parse_pubkey(&pubkey).unwrap(), &digest.into(), &raw).unwrap();
+    }
+
+    #[test]
+    fn test_delegation_tag() {
+        let rotation = KeyRotation::new(PRIVKEY, OTHER_PRIVKEY).unwrap();
+        let delegation = rotation.delegation(1687484820, [0; 32]).unwrap();
+        assert_eq!(delegation.to_tag::<100, 4>(), Err(Error::ContentOverflow));
+
+        let note = NoteBuilder::<NOTE_SIZE, 128>::new(PRIVKEY)
+            .unwrap()
+            .content("from the old key".into())
+            .add_tag(delegation.to_tag().unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let received = Note::<NOTE_SIZE, 128>::from_json_bytes(&note.to_json()).unwrap();
+        assert_eq!(received, note);
+        let tag = &received.tags()[0];
+        assert_eq!(tag.name(), "delegation");
+        assert_eq!(tag.value(0).unwrap().as_bytes(), delegation.delegator);
+        assert_eq!(tag.value(1), Some("created_at<1687484820"));
+        assert_eq!(tag.value(2).unwrap().as_bytes(), delegation.sig);
+    }
+
+    #[test]
+    fn test_relay_list() {
+        let old_list = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::RelayList)
+            .add_tag(Tag::try_from(["r", "wss://a.example", "read"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["r", "wss://b.example"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let relays = RelayList::try_from(&old_list).unwrap();
+        let rotation = KeyRotation::new(PRIVKEY, OTHER_PRIVKEY).unwrap();
+        let new_list = rotation.relay_list(&relays, 1686880021, [0; 32]).unwrap();
+        assert!(new_list.validate_signature().is_ok());
+        let moved = RelayList::try_from(&new_list).unwrap();
+        assert!(moved.iter().eq(relays.iter()));
+        assert_eq!(moved.pubkey(), rotation.new_pubkey());
+    }
+}
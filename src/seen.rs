@@ -0,0 +1,210 @@
+//! Remembering recently seen event ids
+//!
+//! The same filter subscribed on several relays delivers every event once per relay.
+//! [`SeenCache`] remembers the ids of the last `N` events, so copies can be dropped before
+//! paying for their signature check. Ids are kept in a ring, the oldest making room for the
+//! newest, and indexed by a hash table so a lookup takes the same few steps however full the
+//! cache is.
+//!
+//! [`Client::receive_unseen`](crate::client::Client::receive_unseen),
+//! [`RelayPool::receive_unseen`](crate::pool::RelayPool::receive_unseen) and
+//! [`SubscriptionManager::handle_unseen`](crate::subscription_manager::SubscriptionManager::handle_unseen)
+//! take a received frame and look its id up before parsing the note out of it, with a cache
+//! that can be shared by the clients of several relays.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::seen::SeenCache;
+//! let mut seen: SeenCache<2> = SeenCache::new();
+//! assert!(seen.insert(&[1; 32]));
+//! assert!(!seen.insert(&[1; 32]));
+//! seen.insert(&[2; 32]);
+//! // the oldest id makes room
+//! seen.insert(&[3; 32]);
+//! assert!(!seen.contains(&[1; 32]));
+//! assert!(seen.contains(&[3; 32]));
+//! ```
+
+/// Marks an empty slot of the index
+const EMPTY: u16 = u16::MAX;
+
+/// Ids of the last `N` events seen, `N` can be up to 65534
+pub struct SeenCache<const N: usize> {
+    /// Ids in the order they were seen, starting at `next` once full
+    ids: [[u8; 32]; N],
+    len: usize,
+    /// Slot of `ids` the next id is written to
+    next: usize,
+    /// Open addressing table of positions in `ids`, twice as long so probes stay short
+    index: [[u16; N]; 2],
+}
+
+impl<const N: usize> Default for SeenCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SeenCache<N> {
+    /// Creates an empty cache
+    pub const fn new() -> Self {
+        const { assert!(N > 0 && N < EMPTY as usize, "N must be between 1 and 65534") };
+        SeenCache {
+            ids: [[0; 32]; N],
+            len: 0,
+            next: 0,
+            index: [[EMPTY; N]; 2],
+        }
+    }
+
+    /// Number of ids remembered
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if no ids are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Forgets every id
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+        self.index = [[EMPTY; N]; 2];
+    }
+
+    /// True if `id` is one of the last `N` ids inserted
+    pub fn contains(&self, id: &[u8; 32]) -> bool {
+        self.find(id).is_some()
+    }
+
+    /// Remembers `id`, forgetting the oldest one if the cache is full.
+    /// Returns false if `id` was already remembered, in which case nothing changes.
+    pub fn insert(&mut self, id: &[u8; 32]) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        if self.len == N {
+            let oldest = self.ids[self.next];
+            if let Some(slot) = self.find(&oldest) {
+                self.remove_slot(slot);
+            }
+        } else {
+            self.len += 1;
+        }
+        self.ids[self.next] = *id;
+        let mut slot = Self::home(id);
+        let index = self.index.as_flattened_mut();
+        while index[slot] != EMPTY {
+            slot = (slot + 1) % index.len();
+        }
+        // N is below EMPTY, checked in new
+        index[slot] = self.next as u16;
+        self.next = (self.next + 1) % N;
+        true
+    }
+
+    /// Slot of the index `id` hashes to. Ids are SHA-256 hashes, so their first bytes are
+    /// already spread evenly.
+    fn home(id: &[u8; 32]) -> usize {
+        u32::from_le_bytes([id[0], id[1], id[2], id[3]]) as usize % (2 * N)
+    }
+
+    /// Slot of the index pointing at `id`
+    fn find(&self, id: &[u8; 32]) -> Option<usize> {
+        let index = self.index.as_flattened();
+        let mut slot = Self::home(id);
+        while index[slot] != EMPTY {
+            if self.ids[index[slot] as usize] == *id {
+                return Some(slot);
+            }
+            slot = (slot + 1) % index.len();
+        }
+        None
+    }
+
+    /// Empties `slot` of the index, moving later entries of its probe run back so every
+    /// entry stays reachable from its home slot
+    fn remove_slot(&mut self, mut slot: usize) {
+        let len = 2 * N;
+        let mut next = slot;
+        loop {
+            next = (next + 1) % len;
+            let position = self.index.as_flattened()[next];
+            if position == EMPTY {
+                break;
+            }
+            let home = Self::home(&self.ids[position as usize]);
+            // entries whose home lies cyclically in (slot, next] are already reachable
+            let reachable = if slot <= next {
+                slot < home && home <= next
+            } else {
+                slot < home || home <= next
+            };
+            if !reachable {
+                let index = self.index.as_flattened_mut();
+                index[slot] = position;
+                slot = next;
+            }
+        }
+        self.index.as_flattened_mut()[slot] = EMPTY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An id hashing to `home`, told apart by `tag`
+    fn id(home: u32, tag: u8) -> [u8; 32] {
+        let mut id = [tag; 32];
+        id[..4].copy_from_slice(&home.to_le_bytes());
+        id
+    }
+
+    #[test]
+    fn test_insert_and_evict() {
+        let mut seen: SeenCache<3> = SeenCache::new();
+        assert!(seen.is_empty());
+        for tag in 0..3 {
+            assert!(seen.insert(&id(tag.into(), tag)));
+        }
+        assert_eq!(seen.len(), 3);
+        assert!(!seen.insert(&id(1, 1)));
+        assert!(seen.insert(&id(3, 3)));
+        assert_eq!(seen.len(), 3);
+        assert!(!seen.contains(&id(0, 0)));
+        assert!(seen.contains(&id(1, 1)));
+        assert!(seen.contains(&id(3, 3)));
+        seen.clear();
+        assert!(seen.is_empty());
+        assert!(!seen.contains(&id(1, 1)));
+        assert!(seen.insert(&id(1, 1)));
+    }
+
+    #[test]
+    fn test_collisions() {
+        // every id hashes to the same slot, and wraps around the end of the index
+        let mut seen: SeenCache<4> = SeenCache::new();
+        for tag in 0..20 {
+            assert!(seen.insert(&id(7, tag)));
+            for older in tag.saturating_sub(3)..=tag {
+                assert!(seen.contains(&id(7, older)), "{tag} {older}");
+            }
+            if tag >= 4 {
+                assert!(!seen.contains(&id(7, tag - 4)));
+            }
+        }
+        // a mix of homes, evicting from the middle of probe runs
+        let mut seen: SeenCache<5> = SeenCache::new();
+        let ids: [[u8; 32]; 12] =
+            core::array::from_fn(|i| id([9, 0, 9, 1, 8, 9, 0, 2, 9, 9, 1, 0][i], i as u8));
+        for (i, new) in ids.iter().enumerate() {
+            assert!(seen.insert(new));
+            for (j, old) in ids[..=i].iter().enumerate() {
+                assert_eq!(seen.contains(old), i - j < 5, "{i} {j}");
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Signing with keys held outside of the crate
+//!
+//! Secure elements such as the ATECC608 never hand out their private key. Implement [`Signer`]
+//! for the device and create the builder with [`NoteBuilder::new_with_signer`]: the crate
+//! serializes and hashes the note, and the device only has to produce a BIP-340 Schnorr
+//! signature over the 32 byte event id.
+//!
+//! Builders created this way can't encrypt DMs, make templates or sign stepwise, those error
+//! with `MissingPrivkey`.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{errors::Error, signer::Signer, NoteBuilder};
+//! # use nostr_nostd::keys::{AlignedType, Keys, CONTEXT_SIZE};
+//! struct SecureElement<'a> {
+//!     // driver handle
+//! #   keys: Keys<'a>,
+//! }
+//!
+//! impl Signer for SecureElement<'_> {
+//!     fn public_key(&mut self) -> Result<[u8; 32], Error> {
+//!         // read the x-only pubkey from the device
+//! #       Ok(*self.keys.pubkey_bytes())
+//!     }
+//!
+//!     fn sign(&mut self, digest: &[u8; 32]) -> Result<[u8; 64], Error> {
+//!         // have the device sign `digest`
+//! #       self.keys.sign(digest, &[0; 32])
+//!     }
+//! }
+//!
+//! # fn connect() -> SecureElement<'static> {
+//! #     let context = Box::leak(Box::new([AlignedType::zeroed(); CONTEXT_SIZE]));
+//! #     let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! #     SecureElement { keys: Keys::new(privkey, context).unwrap() }
+//! # }
+//! let mut device = connect();
+//! let note = NoteBuilder::new_with_signer(&mut device)
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build_with_signer(&mut device, 1686880020)
+//!     .unwrap();
+//! assert!(note.validate_signature().is_ok());
+//! ```
+
+use crate::{errors::Error, Note, NoteBuilder, NoteKinds, Vec};
+
+/// A device or service that holds a private key and signs with it
+pub trait Signer {
+    /// The x-only public key of the signing key.
+    /// Errors are passed through to the caller, `SignerError` is available for device failures.
+    fn public_key(&mut self) -> Result<[u8; 32], Error>;
+
+    /// BIP-340 Schnorr signature of `digest`, the note's id
+    fn sign(&mut self, digest: &[u8; 32]) -> Result<[u8; 64], Error>;
+}
+
+impl NoteBuilder {
+    /// Returns a NoteBuilder whose notes are signed by `signer`, see [`NoteBuilder::build_with_signer`]
+    #[inline]
+    pub fn new_with_signer<S: Signer>(signer: &mut S) -> Result<Self, Error> {
+        NoteBuilder::from_signer(signer)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::new_with_signer`], for notes with custom buffer sizes
+    #[inline]
+    pub fn from_signer<S: Signer>(signer: &mut S) -> Result<Self, Error> {
+        let pubkey = signer.public_key()?;
+        Ok(NoteBuilder {
+            keypair: None,
+            created_at_window: None,
+            content_overflow: false,
+            note: Note {
+                id: [0; 32],
+                pubkey,
+                created_at: 0,
+                kind: NoteKinds::ShortNote,
+                tags: Vec::new(),
+                content: None,
+                sig: [0; 64],
+            },
+        })
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Set the 'created_at', compute the id and have `signer` sign it.
+    /// `signer` should be the one the builder was created with, otherwise the note won't validate.
+    #[inline]
+    pub fn build_with_signer<S: Signer>(
+        mut self,
+        signer: &mut S,
+        created_at: u64,
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        self.note.set_id()?;
+        self.note.sig = signer.sign(&self.note.id)?;
+        Ok(self.note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{self, Curve, CurveBackend, KeyPair};
+    use crate::tag::Tag;
+    use crate::vectors::PRIVKEY;
+
+    struct SoftSigner {
+        keypair: KeyPair,
+        fail: bool,
+    }
+
+    impl SoftSigner {
+        fn new() -> Self {
+            SoftSigner {
+                keypair: curve::keypair_from_hex(PRIVKEY).unwrap(),
+                fail: false,
+            }
+        }
+    }
+
+    impl Signer for SoftSigner {
+        fn public_key(&mut self) -> Result<[u8; 32], Error> {
+            Ok(Curve::x_only(&self.keypair))
+        }
+
+        fn sign(&mut self, digest: &[u8; 32]) -> Result<[u8; 64], Error> {
+            if self.fail {
+                return Err(Error::SignerError);
+            }
+            curve::sign(&self.keypair, digest, &[0; 32])
+        }
+    }
+
+    #[test]
+    fn test_matches_privkey() {
+        let mut signer = SoftSigner::new();
+        let note = NoteBuilder::new_with_signer(&mut signer)
+            .unwrap()
+            .content("esptest".into())
+            .add_tag(Tag::try_from(["t", "test"]).unwrap())
+            .unwrap()
+            .build_with_signer(&mut signer, 1686880020)
+            .unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .add_tag(Tag::try_from(["t", "test"]).unwrap())
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(note, expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut signer = SoftSigner::new();
+        let builder = NoteBuilder::new_with_signer(&mut signer).unwrap();
+        signer.fail = true;
+        assert_eq!(
+            builder.build_with_signer(&mut signer, 1686880020),
+            Err(Error::SignerError)
+        );
+        let builder = NoteBuilder::new_with_signer(&mut signer).unwrap();
+        assert_eq!(
+            builder.build(1686880020, [0; 32]),
+            Err(Error::MissingPrivkey)
+        );
+    }
+}
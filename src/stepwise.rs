@@ -0,0 +1,435 @@
+//! Signing and verification split into small steps for cooperative schedulers
+//!
+//! [`NoteBuilder::build`](crate::NoteBuilder::build) and [`Note::validate_signature`] run to
+//! completion, hashing the whole note and then signing or checking the signature in one call.
+//! [`IncrementalSign`] and [`IncrementalVerify`] do the same work a piece at a time: each call to
+//! `step` returns [`Poll::Pending`] until the result is ready.
+//!
+//! The id is hashed at most `budget` bytes per step. The note is serialized again on every
+//! hashing step rather than kept in a buffer, so no scratch space is needed. The scalar
+//! multiplication is done two bits per step, two doublings and one addition per point, after
+//! 2 steps building a table of multiples, so both take 130 steps once the id is hashed.
+//!
+//! Needs the `k256` feature, as `libsecp256k1` can't stop part way through a signature.
+//! The signatures are the same as the ones [`NoteBuilder::build`](crate::NoteBuilder::build)
+//! makes.
+//!
+//! The hashing goes through a [`Sha256Backend`], `sha2` unless one is passed to the `_with_hasher`
+//! functions.
+//!
+//! # Example
+//! ```
+//! use core::task::Poll;
+//! use nostr_nostd::Note;
+//! let mut op = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build_stepwise(1686880020, [0; 32])
+//!     .unwrap();
+//! let note = loop {
+//!     if let Poll::Ready(note) = op.step(64) {
+//!         break note.unwrap();
+//!     }
+//!     // run other tasks here
+//! };
+//! assert!(note.validate_signature().is_ok());
+//! ```
+
+use core::task::Poll;
+
+use sha2::Sha256;
+
+use crate::{
+    backend::Sha256Backend,
+    buffer::OutBuf,
+    curve::{KeyPair, StepwiseSign, StepwiseVerify},
+    errors::Error,
+    tag::{MAX_TAG_VALUES, TAG_VALUE_SIZE},
+    Note, NoteBuilder, MAX_TAGS, NOTE_SIZE,
+};
+
+/// Bytes hashed per call to `poll`, one SHA-256 block
+pub const DEFAULT_BUDGET: usize = 64;
+
+/// Feeds the bytes of the serialization from `skip` up to `end` into `hasher` and drops the rest.
+/// Stops the serialization with `ContentOverflow` once it is past `end`.
+struct HashWindow<'h, H: Sha256Backend> {
+    hasher: &'h mut H,
+    skip: usize,
+    end: usize,
+    len: usize,
+}
+
+impl<H: Sha256Backend> OutBuf for HashWindow<'_, H> {
+    fn push_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        let start = self.skip.clamp(self.len, self.len + data.len()) - self.len;
+        let stop = self.end.clamp(self.len, self.len + data.len()) - self.len;
+        if start < stop {
+            self.hasher.update(&data[start..stop]);
+        }
+        self.len += data.len();
+        if self.len > self.end {
+            return Err(Error::ContentOverflow);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Feeds up to `budget` more bytes of the hashed form of `note` into `hasher`, returns true once
+/// all of it has been hashed
+fn hash_some<
+    H: Sha256Backend,
+    const CONTENT: usize,
+    const TAG: usize,
+    const NTAGS: usize,
+    const VALUES: usize,
+>(
+    hasher: &mut H,
+    note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    hashed: &mut usize,
+    budget: usize,
+) -> bool {
+    let end = *hashed + budget.max(1);
+    let mut window = HashWindow {
+        hasher,
+        skip: *hashed,
+        end,
+        len: 0,
+    };
+    let done = note.write_hash_str(&mut window).is_ok();
+    *hashed = end;
+    done
+}
+
+#[allow(clippy::large_enum_variant)]
+enum SignState {
+    Hashing,
+    Signing(StepwiseSign),
+    Done,
+}
+
+/// A note being hashed and then signed a piece at a time, created by
+/// [`NoteBuilder::build_stepwise`]
+pub struct IncrementalSign<
+    H: Sha256Backend = Sha256,
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    keypair: KeyPair,
+    note: Option<Note<CONTENT, TAG, NTAGS, VALUES>>,
+    aux_rnd: [u8; 32],
+    hashed: usize,
+    hasher: H,
+    state: SignState,
+}
+
+impl<
+        H: Sha256Backend,
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    > IncrementalSign<H, CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Hashes at most `budget` more bytes, or does the next step of the signature once the
+    /// hash is done.
+    /// Returns the signed note once finished, calling it again afterwards errors with `InternalError`.
+    pub fn step(
+        &mut self,
+        budget: usize,
+    ) -> Poll<Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error>> {
+        let Some(note) = self.note.as_mut() else {
+            return Poll::Ready(Err(Error::InternalError));
+        };
+        match &mut self.state {
+            SignState::Hashing => {
+                if hash_some(&mut self.hasher, note, &mut self.hashed, budget) {
+                    note.id = self.hasher.finish();
+                    match StepwiseSign::new(&self.keypair, &note.id, &self.aux_rnd) {
+                        Ok(op) => self.state = SignState::Signing(op),
+                        Err(e) => {
+                            self.state = SignState::Done;
+                            self.note = None;
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                }
+                Poll::Pending
+            }
+            SignState::Signing(op) => {
+                let Some(sig) = op.step() else {
+                    return Poll::Pending;
+                };
+                self.state = SignState::Done;
+                let Some(mut note) = self.note.take() else {
+                    return Poll::Ready(Err(Error::InternalError));
+                };
+                note.sig = sig;
+                Poll::Ready(Ok(note))
+            }
+            SignState::Done => Poll::Ready(Err(Error::InternalError)),
+        }
+    }
+
+    /// Same as [`IncrementalSign::step`] with a budget of [`DEFAULT_BUDGET`]
+    pub fn poll(&mut self) -> Poll<Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error>> {
+        self.step(DEFAULT_BUDGET)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Same as [`NoteBuilder::build`], but returns an [`IncrementalSign`] that hashes and signs
+    /// the note over many calls.
+    /// Errors with `MissingPrivkey` if the builder was created from a [`Signer`](crate::signer::Signer).
+    pub fn build_stepwise(
+        self,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+    ) -> Result<IncrementalSign<Sha256, CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.build_stepwise_with_hasher(created_at, aux_rnd, Sha256::default())
+    }
+
+    /// Same as [`NoteBuilder::build_stepwise`], hashing with `hasher`. Pass `&mut` a backend
+    /// to keep using it afterwards.
+    pub fn build_stepwise_with_hasher<H: Sha256Backend>(
+        mut self,
+        created_at: u64,
+        aux_rnd: [u8; 32],
+        mut hasher: H,
+    ) -> Result<IncrementalSign<H, CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let keypair = self.keypair.take().ok_or(Error::MissingPrivkey)?;
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        hasher.reset();
+        Ok(IncrementalSign {
+            keypair,
+            note: Some(self.note),
+            aux_rnd,
+            hashed: 0,
+            hasher,
+            state: SignState::Hashing,
+        })
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum VerifyState {
+    Hashing,
+    Verifying(StepwiseVerify),
+    Done,
+}
+
+/// A note's id being recomputed and its signature checked a piece at a time, created by
+/// [`Note::verify_stepwise`]
+pub struct IncrementalVerify<
+    'a,
+    H: Sha256Backend = Sha256,
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    note: &'a Note<CONTENT, TAG, NTAGS, VALUES>,
+    hashed: usize,
+    hasher: H,
+    state: VerifyState,
+}
+
+impl<
+        H: Sha256Backend,
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    > IncrementalVerify<'_, H, CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Hashes at most `budget` more bytes, or does the next step of the signature check once
+    /// the hash is done.
+    /// Errors with `EventNotValid` if the id doesn't match the note's contents, otherwise as
+    /// [`Note::validate_signature`]. Calling it again after it is ready errors with `InternalError`.
+    pub fn step(&mut self, budget: usize) -> Poll<Result<(), Error>> {
+        match &mut self.state {
+            VerifyState::Hashing => {
+                if hash_some(&mut self.hasher, self.note, &mut self.hashed, budget) {
+                    if self.hasher.finish() != self.note.id {
+                        self.state = VerifyState::Done;
+                        return Poll::Ready(Err(Error::EventNotValid));
+                    }
+                    match StepwiseVerify::new(&self.note.pubkey, &self.note.id, &self.note.sig) {
+                        Ok(op) => self.state = VerifyState::Verifying(op),
+                        Err(e) => {
+                            self.state = VerifyState::Done;
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                }
+                Poll::Pending
+            }
+            VerifyState::Verifying(op) => match op.step() {
+                Some(result) => {
+                    self.state = VerifyState::Done;
+                    Poll::Ready(result)
+                }
+                None => Poll::Pending,
+            },
+            VerifyState::Done => Poll::Ready(Err(Error::InternalError)),
+        }
+    }
+
+    /// Same as [`IncrementalVerify::step`] with a budget of [`DEFAULT_BUDGET`]
+    pub fn poll(&mut self) -> Poll<Result<(), Error>> {
+        self.step(DEFAULT_BUDGET)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Returns an [`IncrementalVerify`] that recomputes the note's id and checks its signature
+    /// over many calls
+    pub fn verify_stepwise(&self) -> IncrementalVerify<'_, Sha256, CONTENT, TAG, NTAGS, VALUES> {
+        self.verify_stepwise_with_hasher(Sha256::default())
+    }
+
+    /// Same as [`Note::verify_stepwise`], hashing with `hasher`
+    pub fn verify_stepwise_with_hasher<H: Sha256Backend>(
+        &self,
+        mut hasher: H,
+    ) -> IncrementalVerify<'_, H, CONTENT, TAG, NTAGS, VALUES> {
+        hasher.reset();
+        IncrementalVerify {
+            note: self,
+            hashed: 0,
+            hasher,
+            state: VerifyState::Hashing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::{Tag, TAG_NAME_SIZE};
+    use crate::vectors::PRIVKEY;
+    use heapless::String;
+
+    fn run<T>(mut step: impl FnMut() -> Poll<T>) -> (T, usize) {
+        let mut steps = 1;
+        loop {
+            if let Poll::Ready(out) = step() {
+                return (out, steps);
+            }
+            steps += 1;
+        }
+    }
+
+    #[test]
+    fn test_sign_matches_build() {
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut op = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_stepwise(1686880020, [0; 32])
+            .unwrap();
+        let (note, steps) = run(|| op.step(16));
+        assert_eq!(note, Ok(expected));
+        // the hashed form is 96 bytes, so 6 hashing steps, 2 to build the table and 128 windows
+        assert_eq!(steps, 136);
+        assert_eq!(op.poll(), Poll::Ready(Err(Error::InternalError)));
+    }
+
+    #[test]
+    fn test_max_size() {
+        // every tag and the content full of characters that escape to two bytes
+        let value: String<TAG_VALUE_SIZE> = core::iter::repeat_n('"', TAG_VALUE_SIZE).collect();
+        let name: String<TAG_NAME_SIZE> = core::iter::repeat_n('"', TAG_NAME_SIZE).collect();
+        let tag = Tag::try_from([name.as_str(), &value, &value, &value, &value]).unwrap();
+        let builder = || {
+            let mut builder = Note::new_builder(PRIVKEY)
+                .unwrap()
+                .content(core::iter::repeat_n('"', NOTE_SIZE).collect());
+            for _ in 0..MAX_TAGS {
+                builder = builder.add_tag(tag.clone()).unwrap();
+            }
+            builder
+        };
+        let expected = builder().build(u64::MAX, [0; 32]).unwrap();
+
+        let mut op = builder().build_stepwise(u64::MAX, [0; 32]).unwrap();
+        assert_eq!(run(|| op.poll()).0, Ok(expected.clone()));
+        let mut op = expected.verify_stepwise();
+        assert_eq!(run(|| op.poll()).0, Ok(()));
+    }
+
+    #[test]
+    fn test_verify() {
+        let note = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        let mut op = note.verify_stepwise();
+        assert_eq!(run(|| op.poll()).0, Ok(()));
+
+        let mut forged = note.clone();
+        forged.sig[63] ^= 1;
+        let mut op = forged.verify_stepwise();
+        assert_eq!(run(|| op.poll()).0, Err(Error::InvalidSignature));
+
+        let mut tampered = note;
+        tampered.created_at += 1;
+        let mut op = tampered.verify_stepwise();
+        assert_eq!(run(|| op.poll()).0, Err(Error::EventNotValid));
+    }
+
+    /// Counts the bytes it was fed
+    #[derive(Default)]
+    struct Counting {
+        inner: Sha256,
+        fed: usize,
+    }
+
+    impl Sha256Backend for Counting {
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+
+        fn update(&mut self, data: &[u8]) {
+            self.fed += data.len();
+            self.inner.update(data)
+        }
+
+        fn finish(&mut self) -> [u8; 32] {
+            self.inner.finish()
+        }
+    }
+
+    #[test]
+    fn test_with_hasher() {
+        let mut sha = Counting::default();
+        let mut op = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_stepwise_with_hasher(1686880020, [0; 32], &mut sha)
+            .unwrap();
+        let note = run(|| op.step(32)).0.unwrap();
+        // each of the 96 hashed bytes once
+        assert_eq!(sha.fed, 96);
+
+        let mut op = note.verify_stepwise_with_hasher(&mut sha);
+        assert_eq!(run(|| op.poll()).0, Ok(()));
+        assert_eq!(sha.fed, 192);
+    }
+}
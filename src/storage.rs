@@ -0,0 +1,330 @@
+//! Fixed-size records for persisting notes to flash or EEPROM
+//!
+//! Each record is [`RECORD_SIZE`] bytes: a small header followed by the
+//! [binary encoding](crate::binary) of the note, padded with `0xFF` (the erased state of flash).
+//!
+//! | field   | size                                      |
+//! |---------|-------------------------------------------|
+//! | magic   | 2 bytes, `b"NN"`, zeroed once deleted       |
+//! | version | 1 byte, currently `1`                     |
+//! | length  | 2 bytes, little endian length of payload  |
+//! | crc     | 4 bytes, little endian CRC-32 of payload  |
+//! | payload | `length` bytes, then `0xFF` padding       |
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, storage};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut page = [0xFF_u8; storage::RECORD_SIZE];
+//! storage::write_record(&note, &mut page).unwrap();
+//! // ... write page to flash, read it back after a reboot ...
+//! assert_eq!(storage::read_record(&page).unwrap(), note);
+//! ```
+//!
+//! A flash region holding consecutive records can be replayed with [`Archive`]:
+//! ```
+//! # use nostr_nostd::{Note, storage};
+//! # let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//! #     .unwrap()
+//! #     .build(1686880020, [0; 32])
+//! #     .unwrap();
+//! let mut region = [0xFF_u8; 4 * storage::RECORD_SIZE];
+//! let slot = storage::next_free_slot(&region).unwrap();
+//! storage::write_record(&note, &mut region[slot * storage::RECORD_SIZE..]).unwrap();
+//! for (slot, stored) in storage::Archive::new(&region) {
+//!     // resend `stored`, then mark `slot` as sent
+//! }
+//! ```
+
+use crate::{
+    binary::MAX_BINARY_SIZE, buffer::OutBuf, buffer::SliceBuf, errors::Error, query::Query,
+    store::NoteStorage, utils::crc32, Note,
+};
+
+const MAGIC: [u8; 2] = *b"NN";
+/// Magic of a deleted record, flash can clear bits without erasing the page
+const DELETED: [u8; 2] = [0; 2];
+/// Version of the record header
+pub const RECORD_VERSION: u8 = 1;
+/// Size of the header preceding the payload
+pub const HEADER_SIZE: usize = 2 + 1 + 2 + 4;
+/// Size of every record written by [`write_record`]
+pub const RECORD_SIZE: usize = HEADER_SIZE + MAX_BINARY_SIZE;
+
+/// Writes `note` as a record into the first [`RECORD_SIZE`] bytes of `record`.
+/// Errors with `ContentOverflow` if `record` is shorter than that.
+pub fn write_record(note: &Note, record: &mut [u8]) -> Result<(), Error> {
+    if record.len() < RECORD_SIZE {
+        return Err(Error::ContentOverflow);
+    }
+    let (header, payload) = record[..RECORD_SIZE].split_at_mut(HEADER_SIZE);
+    let mut payload_buf = SliceBuf::new(payload);
+    note.encode_binary(&mut payload_buf)?;
+    let len = payload_buf.len();
+    let payload = payload_buf.into_slice();
+    let crc = crc32(payload);
+
+    let mut header_buf = SliceBuf::new(header);
+    header_buf.push_slice(&MAGIC)?;
+    header_buf.push_byte(RECORD_VERSION)?;
+    header_buf.push_slice(&(len as u16).to_le_bytes())?;
+    header_buf.push_slice(&crc.to_le_bytes())?;
+
+    record[HEADER_SIZE + len..RECORD_SIZE]
+        .iter_mut()
+        .for_each(|b| *b = 0xFF);
+    Ok(())
+}
+
+/// Reads a record written by [`write_record`].
+/// - `RecordEmpty` if the header is still erased (all `0xFF`)
+/// - `RecordCorrupted` if the header is invalid or the CRC does not match
+pub fn read_record(record: &[u8]) -> Result<Note, Error> {
+    if record.len() < HEADER_SIZE {
+        return Err(Error::RecordCorrupted);
+    }
+    let header = &record[..HEADER_SIZE];
+    if header.iter().all(|b| *b == 0xFF) {
+        return Err(Error::RecordEmpty);
+    }
+    if header[..2] != MAGIC || header[2] != RECORD_VERSION {
+        return Err(Error::RecordCorrupted);
+    }
+    let len = u16::from_le_bytes([header[3], header[4]]) as usize;
+    let crc = u32::from_le_bytes([header[5], header[6], header[7], header[8]]);
+    let payload = record
+        .get(HEADER_SIZE..HEADER_SIZE + len)
+        .ok_or(Error::RecordCorrupted)?;
+    if crc32(payload) != crc {
+        return Err(Error::RecordCorrupted);
+    }
+    Note::decode_binary(payload).map_err(|_| Error::RecordCorrupted)
+}
+
+/// Iterates over the records in a byte region, such as memory mapped flash.
+///
+/// Yields the slot index and note of every valid record. Deleted records are skipped, as are
+/// corrupted ones (counted in [`Archive::skipped`]); iteration ends at the first erased slot
+/// or the end of the region.
+pub struct Archive<'a> {
+    region: &'a [u8],
+    slot: usize,
+    skipped: usize,
+    done: bool,
+}
+
+impl<'a> Archive<'a> {
+    /// Creates an iterator over the records in `region`, starting at slot 0
+    pub fn new(region: &'a [u8]) -> Self {
+        Archive {
+            region,
+            slot: 0,
+            skipped: 0,
+            done: false,
+        }
+    }
+
+    /// Number of corrupted records skipped so far
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl Iterator for Archive<'_> {
+    type Item = (usize, Note);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let start = self.slot * RECORD_SIZE;
+            let record = self.region.get(start..start + RECORD_SIZE)?;
+            let slot = self.slot;
+            self.slot += 1;
+            if record[..2] == DELETED {
+                continue;
+            }
+            match read_record(record) {
+                Ok(note) => return Some((slot, note)),
+                // nothing is written past the first erased slot
+                Err(Error::RecordEmpty) => self.done = true,
+                Err(_) => self.skipped += 1,
+            }
+        }
+        None
+    }
+}
+
+/// Index of the first erased slot in `region`, or `None` if the region is full
+pub fn next_free_slot(region: &[u8]) -> Option<usize> {
+    region
+        .chunks_exact(RECORD_SIZE)
+        .position(|record| record[..HEADER_SIZE].iter().all(|b| *b == 0xFF))
+}
+
+/// A region of records used as [`NoteStorage`], such as memory mapped flash or a RAM copy of a
+/// flash partition. Notes are written to the first erased slot, and deleting one zeroes the
+/// magic of its record so it doesn't need an erase.
+/// ```
+/// use nostr_nostd::{storage::{RecordRegion, RECORD_SIZE}, store::{InsertOutcome, NoteStorage}, NoteBuilder};
+/// let note = NoteBuilder::new("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+///     .unwrap()
+///     .content("hello".into())
+///     .build(1686880020, [0; 32])
+///     .unwrap();
+/// let mut region = [0xFF_u8; 4 * RECORD_SIZE];
+/// let mut storage = RecordRegion::new(&mut region);
+/// assert_eq!(storage.insert(note.clone()), Ok(InsertOutcome::Inserted));
+/// assert_eq!(storage.insert(note), Ok(InsertOutcome::Duplicate));
+/// ```
+pub struct RecordRegion<'a> {
+    region: &'a mut [u8],
+}
+
+impl<'a> RecordRegion<'a> {
+    /// Uses the records in `region`, erased slots are set to `0xFF`
+    pub fn new(region: &'a mut [u8]) -> Self {
+        RecordRegion { region }
+    }
+
+    /// Slot of the valid record holding the note with the id `id`
+    fn slot_of(&self, id: &[u8; 32]) -> Option<usize> {
+        Archive::new(self.region)
+            .find(|(_, note)| note.id == *id)
+            .map(|(slot, _)| slot)
+    }
+}
+
+impl NoteStorage for RecordRegion<'_> {
+    fn put(&mut self, note: Note) -> Result<(), Error> {
+        let slot = next_free_slot(self.region).ok_or(Error::StoreFull)?;
+        write_record(&note, &mut self.region[slot * RECORD_SIZE..])
+    }
+
+    fn load(&self, id: &[u8; 32]) -> Result<Option<Note>, Error> {
+        Ok(Archive::new(self.region)
+            .find(|(_, note)| note.id == *id)
+            .map(|(_, note)| note))
+    }
+
+    fn contains(&self, id: &[u8; 32]) -> Result<bool, Error> {
+        Ok(self.slot_of(id).is_some())
+    }
+
+    fn for_each_matching(
+        &self,
+        filter: &Query,
+        f: &mut dyn FnMut(&Note) -> bool,
+    ) -> Result<(), Error> {
+        for (_, note) in Archive::new(self.region) {
+            if filter.matches(&note) && !f(&note) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, id: &[u8; 32]) -> Result<bool, Error> {
+        let Some(slot) = self.slot_of(id) else {
+            return Ok(false);
+        };
+        let start = slot * RECORD_SIZE;
+        self.region[start..start + 2].copy_from_slice(&DELETED);
+        Ok(true)
+    }
+
+    fn replace(&mut self, old: &[u8; 32], note: Note) -> Result<(), Error> {
+        // write first, so a full region keeps the old version
+        self.put(note)?;
+        self.delete(old).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, PRIVKEY};
+
+    #[test]
+    fn test_round_trip() {
+        let mut page = [0_u8; RECORD_SIZE];
+        write_record(&text_note(), &mut page).unwrap();
+        assert_eq!(read_record(&page), Ok(text_note()));
+    }
+
+    #[test]
+    fn test_corruption() {
+        let mut page = [0_u8; RECORD_SIZE];
+        write_record(&text_note(), &mut page).unwrap();
+        page[HEADER_SIZE + 40] ^= 1;
+        assert_eq!(read_record(&page), Err(Error::RecordCorrupted));
+        assert_eq!(read_record(&[0xFF; RECORD_SIZE]), Err(Error::RecordEmpty));
+    }
+
+    #[test]
+    fn test_archive_skips_corrupted() {
+        let mut region = [0xFF_u8; 4 * RECORD_SIZE];
+        for slot in 0..3 {
+            write_record(&text_note(), &mut region[slot * RECORD_SIZE..]).unwrap();
+        }
+        // simulate power loss during the write of slot 1
+        region[RECORD_SIZE + HEADER_SIZE + 10] ^= 0xFF;
+        assert_eq!(next_free_slot(&region), Some(3));
+
+        let mut archive = Archive::new(&region);
+        assert_eq!(archive.next(), Some((0, text_note())));
+        assert_eq!(archive.next(), Some((2, text_note())));
+        assert_eq!(archive.next(), None);
+        assert_eq!(archive.skipped(), 1);
+    }
+
+    #[test]
+    fn test_record_region() {
+        use crate::{store::InsertOutcome, NoteKinds};
+        let relay_list = |created_at| {
+            Note::new_builder(PRIVKEY)
+                .unwrap()
+                .set_kind(NoteKinds::RelayList)
+                .build(created_at, [0; 32])
+                .unwrap()
+        };
+        let mut region = [0xFF_u8; 3 * RECORD_SIZE];
+        let mut storage = RecordRegion::new(&mut region);
+        assert_eq!(storage.insert(text_note()), Ok(InsertOutcome::Inserted));
+        assert_eq!(storage.insert(relay_list(10)), Ok(InsertOutcome::Inserted));
+        assert_eq!(storage.insert(relay_list(5)), Ok(InsertOutcome::Stale));
+        assert_eq!(storage.insert(relay_list(20)), Ok(InsertOutcome::Replaced));
+        assert_eq!(storage.load(relay_list(10).id_bytes()), Ok(None));
+        assert_eq!(storage.contains(relay_list(10).id_bytes()), Ok(false));
+        assert_eq!(storage.contains(relay_list(20).id_bytes()), Ok(true));
+        assert_eq!(
+            storage.load(relay_list(20).id_bytes()),
+            Ok(Some(relay_list(20)))
+        );
+
+        let mut count = 0;
+        storage
+            .for_each_matching(&Query::new(), &mut |_| {
+                count += 1;
+                true
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        // the replaced record's slot stays used until the region is erased, and a full region
+        // keeps the old version
+        assert_eq!(storage.insert(relay_list(30)), Err(Error::StoreFull));
+        assert_eq!(
+            storage.load(relay_list(20).id_bytes()),
+            Ok(Some(relay_list(20)))
+        );
+        assert_eq!(storage.delete(text_note().id_bytes()), Ok(true));
+        assert_eq!(storage.delete(text_note().id_bytes()), Ok(false));
+
+        let mut archive = Archive::new(&region);
+        assert_eq!(archive.next(), Some((2, relay_list(20))));
+        assert_eq!(archive.next(), None);
+        assert_eq!(archive.skipped(), 0);
+    }
+}
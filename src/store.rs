@@ -0,0 +1,388 @@
+//! In-memory store for events
+//!
+//! Holds notes the way a relay does, for a small relay or a client that has to work offline.
+//! Notes are told apart by id and ephemeral ones aren't stored. The NIP-01 rules apply to
+//! replaceable events (kinds 0, 3 and 10000-19999), keyed by kind and pubkey, and addressable
+//! events (kinds 30000-39999), keyed by kind, pubkey and `d` tag: only the newest event for each
+//! key is kept, and ties on `created_at` keep the lowest id.
+//! [`EventStore::query`] hands out the notes passing a filter, newest first.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, NoteKinds, store::{EventStore, InsertOutcome}, tag::Tag};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .set_kind(NoteKinds::RelayList)
+//!     .add_tag(Tag::try_from(["r", "wss://relay.example.com"]).unwrap())
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let pubkey = note.hex_pubkey();
+//! let mut store: EventStore<4> = EventStore::new();
+//! assert_eq!(store.insert(note), Ok(InsertOutcome::Inserted));
+//! assert!(store.get(NoteKinds::RelayList, &pubkey).is_some());
+//! ```
+
+use heapless::Vec;
+
+use crate::{errors::Error, hex::HexPubkey, query::Query, Note, NoteKinds};
+
+/// Result of inserting a note into a [`NoteStorage`]
+#[derive(Debug, PartialEq)]
+pub enum InsertOutcome {
+    /// Nothing was stored for this key before
+    Inserted,
+    /// The note replaced an older version
+    Replaced,
+    /// A newer version is already stored, the note was dropped
+    Stale,
+    /// The same note is already stored
+    Duplicate,
+}
+
+/// Where an event store keeps its notes, such as RAM, external flash, FRAM or an SD card.
+///
+/// Implementations only store, find and remove notes. [`NoteStorage::insert`] applies the
+/// NIP-01 rules on top, so every backend keeps replaceable events the same way.
+/// [`EventStore`] keeps notes in RAM, and [`storage::RecordRegion`](crate::storage::RecordRegion)
+/// in a region of flash records.
+pub trait NoteStorage {
+    /// Stores `note` as is, without checking for other versions of it.
+    /// Errors with `StoreFull` if there is no room left.
+    fn put(&mut self, note: Note) -> Result<(), Error>;
+
+    /// A copy of the stored note with the id `id`, if any
+    fn load(&self, id: &[u8; 32]) -> Result<Option<Note>, Error>;
+
+    /// True if the note with the id `id` is stored.
+    /// The default loads the note, implementations that can check without copying it should
+    /// override it.
+    fn contains(&self, id: &[u8; 32]) -> Result<bool, Error> {
+        Ok(self.load(id)?.is_some())
+    }
+
+    /// Calls `f` with each stored note passing `filter`, until it returns false.
+    /// `limit` may be ignored, as may the order of the notes.
+    fn for_each_matching(
+        &self,
+        filter: &Query,
+        f: &mut dyn FnMut(&Note) -> bool,
+    ) -> Result<(), Error>;
+
+    /// Removes the note with the id `id`, returning false if it wasn't stored
+    fn delete(&mut self, id: &[u8; 32]) -> Result<bool, Error>;
+
+    /// Replaces the stored note with the id `old` by `note`.
+    /// The default deletes before storing, implementations that can run out of room should
+    /// override it so `old` is kept when `note` can't be stored.
+    fn replace(&mut self, old: &[u8; 32], note: Note) -> Result<(), Error> {
+        self.delete(old)?;
+        self.put(note)
+    }
+
+    /// Stores `note`, unless it or a newer version of it is already held. A newer version of a
+    /// replaceable or addressable note replaces the stored one.
+    /// - `InvalidType` if the note is ephemeral, relays pass those on without storing them
+    /// - `StoreFull` if the note doesn't replace another and there is no room left
+    fn insert(&mut self, note: Note) -> Result<InsertOutcome, Error> {
+        let kind = note.kind;
+        if kind.is_ephemeral() {
+            return Err(Error::InvalidType);
+        }
+        if self.contains(&note.id)? {
+            return Ok(InsertOutcome::Duplicate);
+        }
+        let mut replaced = None;
+        if kind.is_replaceable() || kind.is_parameterized_replaceable() {
+            let mut filter = Query::new();
+            // a new query has room for one of each
+            let _ = filter.kinds.push(kind);
+            let _ = filter.authors.push(note.hex_pubkey().into());
+            let mut stale = false;
+            self.for_each_matching(&filter, &mut |stored| {
+                if kind.is_parameterized_replaceable() && d_tag(stored) != d_tag(&note) {
+                    return true;
+                }
+                let newer = note.created_at > stored.created_at
+                    || (note.created_at == stored.created_at && note.id < stored.id);
+                if newer {
+                    replaced = Some(stored.id);
+                } else {
+                    stale = true;
+                }
+                false
+            })?;
+            if stale {
+                return Ok(InsertOutcome::Stale);
+            }
+        }
+        match replaced {
+            Some(id) => {
+                self.replace(&id, note)?;
+                Ok(InsertOutcome::Replaced)
+            }
+            None => {
+                self.put(note)?;
+                Ok(InsertOutcome::Inserted)
+            }
+        }
+    }
+}
+
+/// Fixed-capacity store holding up to `N` notes in RAM
+pub struct EventStore<const N: usize> {
+    notes: Vec<Note, N>,
+}
+
+/// Value of the first `d` tag, or `""` if there is none
+fn d_tag(note: &Note) -> &str {
+    note.identifier().unwrap_or_default()
+}
+
+impl<const N: usize> Default for EventStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventStore<N> {
+    /// Creates an empty store
+    pub const fn new() -> Self {
+        EventStore { notes: Vec::new() }
+    }
+
+    /// Number of notes stored
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    /// True if nothing is stored
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Iterates over every stored note
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter()
+    }
+
+    /// Stores `note`, unless it or a newer version of it is already held.
+    /// Same as [`NoteStorage::insert`], without having to import the trait.
+    pub fn insert(&mut self, note: Note) -> Result<InsertOutcome, Error> {
+        NoteStorage::insert(self, note)
+    }
+
+    /// The note with the id `id`, if it is stored
+    pub fn get_by_id(&self, id: &[u8; 32]) -> Option<&Note> {
+        self.notes.iter().find(|note| note.id == *id)
+    }
+
+    /// Stored notes passing `filter`, newest first, up to its `limit`
+    pub fn query<'a>(&'a self, filter: &'a Query) -> impl Iterator<Item = &'a Note> + 'a {
+        let limit = filter.limit.map_or(usize::MAX, |limit| limit as usize);
+        self.notes
+            .iter()
+            .filter(move |note| filter.matches(*note))
+            .take(limit)
+    }
+
+    /// Latest replaceable event of `kind` published by `pubkey`
+    pub fn get(&self, kind: NoteKinds, pubkey: &HexPubkey) -> Option<&Note> {
+        self.notes
+            .iter()
+            .find(|note| note.kind == kind && note.pubkey == *pubkey.as_bytes())
+    }
+
+    /// Latest addressable event of `kind` published by `pubkey` with the `d` tag `identifier`
+    pub fn get_addressable(
+        &self,
+        kind: NoteKinds,
+        pubkey: &HexPubkey,
+        identifier: &str,
+    ) -> Option<&Note> {
+        self.notes.iter().find(|note| {
+            note.kind == kind && note.pubkey == *pubkey.as_bytes() && d_tag(note) == identifier
+        })
+    }
+
+    /// Removes and returns the note stored for `kind` and `pubkey`, and `identifier` for addressable kinds
+    pub fn remove(
+        &mut self,
+        kind: NoteKinds,
+        pubkey: &HexPubkey,
+        identifier: &str,
+    ) -> Option<Note> {
+        let pos = self.notes.iter().position(|note| {
+            note.kind == kind
+                && note.pubkey == *pubkey.as_bytes()
+                && (!kind.is_parameterized_replaceable() || d_tag(note) == identifier)
+        })?;
+        Some(self.notes.remove(pos))
+    }
+}
+
+impl<const N: usize> NoteStorage for EventStore<N> {
+    fn put(&mut self, note: Note) -> Result<(), Error> {
+        if self.notes.is_full() {
+            return Err(Error::StoreFull);
+        }
+        // kept newest first, so queries can stop at their limit
+        let pos = self
+            .notes
+            .iter()
+            .position(|stored| stored.created_at < note.created_at)
+            .unwrap_or(self.notes.len());
+        // room was checked above
+        let _ = self.notes.insert(pos, note);
+        Ok(())
+    }
+
+    fn load(&self, id: &[u8; 32]) -> Result<Option<Note>, Error> {
+        Ok(self.get_by_id(id).cloned())
+    }
+
+    fn contains(&self, id: &[u8; 32]) -> Result<bool, Error> {
+        Ok(self.get_by_id(id).is_some())
+    }
+
+    fn for_each_matching(
+        &self,
+        filter: &Query,
+        f: &mut dyn FnMut(&Note) -> bool,
+    ) -> Result<(), Error> {
+        for note in self.query(filter) {
+            if !f(note) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, id: &[u8; 32]) -> Result<bool, Error> {
+        match self.notes.iter().position(|note| note.id == *id) {
+            Some(pos) => {
+                self.notes.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tag::Tag,
+        vectors::{text_note, PRIVKEY},
+    };
+
+    fn relay_list(created_at: u64) -> Note {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::RelayList)
+            .build(created_at, [0; 32])
+            .unwrap()
+    }
+
+    fn app_data(d: &str, created_at: u64) -> Note {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::ParameterizedReplaceable(30078))
+            .add_tag(Tag::try_from(["d", d]).unwrap())
+            .unwrap()
+            .build(created_at, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_replaceable_keeps_newest() {
+        let mut store: EventStore<2> = EventStore::new();
+        assert_eq!(store.insert(relay_list(10)), Ok(InsertOutcome::Inserted));
+        assert_eq!(store.insert(relay_list(5)), Ok(InsertOutcome::Stale));
+        assert_eq!(store.insert(relay_list(20)), Ok(InsertOutcome::Replaced));
+        assert_eq!(store.len(), 1);
+        let pubkey = relay_list(0).pubkey();
+        let stored = store
+            .get(
+                NoteKinds::RelayList,
+                &HexPubkey::try_from(&pubkey[..]).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(stored.created_at, 20);
+        // the pubkey matches whatever case it was written in
+        let upper = pubkey.to_ascii_uppercase();
+        let upper = HexPubkey::try_from(&upper[..]).unwrap();
+        assert_eq!(store.get(NoteKinds::RelayList, &upper), Some(stored));
+    }
+
+    #[test]
+    fn test_addressable_keyed_by_d_tag() {
+        let mut store: EventStore<2> = EventStore::new();
+        store.insert(app_data("config", 10)).unwrap();
+        store.insert(app_data("state", 10)).unwrap();
+        assert_eq!(store.insert(app_data("other", 10)), Err(Error::StoreFull));
+        assert_eq!(
+            store.insert(app_data("config", 11)),
+            Ok(InsertOutcome::Replaced)
+        );
+        let pubkey = relay_list(0).hex_pubkey();
+        let kind = NoteKinds::ParameterizedReplaceable(30078);
+        assert_eq!(
+            store
+                .get_addressable(kind, &pubkey, "config")
+                .unwrap()
+                .created_at,
+            11
+        );
+        assert!(store.remove(kind, &pubkey, "state").is_some());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_regular_and_ephemeral() {
+        let mut store: EventStore<3> = EventStore::new();
+        let note = text_note();
+        assert_eq!(store.insert(text_note()), Ok(InsertOutcome::Inserted));
+        assert_eq!(store.insert(text_note()), Ok(InsertOutcome::Duplicate));
+        assert_eq!(store.get_by_id(note.id_bytes()), Some(&note));
+        assert_eq!(store.load(note.id_bytes()), Ok(Some(note.clone())));
+        assert_eq!(store.contains(note.id_bytes()), Ok(true));
+        let ephemeral = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::Ephemeral(20001))
+            .build(10, [0; 32])
+            .unwrap();
+        assert_eq!(store.insert(ephemeral), Err(Error::InvalidType));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut store: EventStore<4> = EventStore::new();
+        store.insert(app_data("config", 10)).unwrap();
+        store.insert(text_note()).unwrap();
+        store.insert(relay_list(30)).unwrap();
+        store.insert(app_data("state", 20)).unwrap();
+        // newest first
+        let created: Vec<u64, 4> = store.iter().map(|note| note.created_at).collect();
+        assert_eq!(created, [1686880020, 30, 20, 10]);
+
+        let mut filter = Query::new();
+        filter
+            .kinds
+            .push(NoteKinds::ParameterizedReplaceable(30078))
+            .unwrap();
+        assert_eq!(store.query(&filter).count(), 2);
+        filter.limit = Some(1);
+        assert_eq!(store.query(&filter).count(), 1);
+        assert_eq!(store.query(&filter).next().map(d_tag), Some("state"));
+        filter.kinds.clear();
+        filter.limit = None;
+        assert_eq!(store.query(&filter).count(), 4);
+
+        // replacing keeps the order
+        store.insert(app_data("config", 40)).unwrap();
+        assert_eq!(store.iter().nth(1).map(d_tag), Some("config"));
+    }
+}
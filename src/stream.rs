@@ -0,0 +1,337 @@
+//! Incremental parsing of relay messages split across websocket frames
+//!
+//! Push each chunk of bytes as it arrives, [`StreamParser`] buffers partial messages and yields
+//! every [`RelayMessage`] as soon as its closing bracket is seen.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{relay_responses::RelayMessage, stream::StreamParser};
+//! let mut parser: StreamParser<256> = StreamParser::new();
+//! assert!(parser.push(br#"["NOTICE", "hel"#).next().is_none());
+//! let mut messages = parser.push(br#"lo"]["EOSE","sub_1"]"#);
+//! assert!(matches!(messages.next(), Some(Ok(RelayMessage::Notice(_)))));
+//! assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+//! assert!(messages.next().is_none());
+//! ```
+
+use heapless::Vec;
+
+use crate::{errors::Error, relay_responses::RelayMessage, MAX_RELAY_MSG_SIZE};
+
+/// Buffers relay messages of up to `N` bytes until they are complete
+pub struct StreamParser<const N: usize = MAX_RELAY_MSG_SIZE> {
+    /// The current message in `start..parsed`, then bytes of an earlier chunk not yet parsed
+    buf: Vec<u8, N>,
+    start: usize,
+    parsed: usize,
+    /// Part of an earlier chunk didn't fit in `buf` when its iterator was dropped
+    lost_tail: bool,
+    /// How many arrays and objects are open
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// The current message didn't fit in the buffer, its remaining bytes are dropped
+    overflowed: bool,
+}
+
+impl<const N: usize> Default for StreamParser<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> StreamParser<N> {
+    /// Creates a parser with nothing buffered
+    pub const fn new() -> Self {
+        StreamParser {
+            buf: Vec::new(),
+            start: 0,
+            parsed: 0,
+            lost_tail: false,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            overflowed: false,
+        }
+    }
+
+    /// Feeds the next chunk received from the relay.
+    /// The returned iterator yields each message completed by `chunk`, anything left over stays
+    /// buffered for the next call. Bytes the iterator hasn't got to when it is dropped are kept
+    /// too, and parsed first by the next call. If they don't all fit in the `N` byte buffer, the
+    /// next call yields `Err(MessageTooLong)` in place of the cut off message.
+    pub fn push<'a>(&'a mut self, chunk: &'a [u8]) -> Messages<'a, N> {
+        Messages {
+            parser: self,
+            chunk,
+        }
+    }
+
+    /// Number of bytes held, of an unfinished message and of chunks not parsed yet
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    /// Drops any unfinished message, such as after the connection to the relay is lost
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Takes the next byte held in `buf` or, once there are none left, `chunk`.
+    /// Returns `None` when there are no bytes left, otherwise the result of the message the
+    /// byte completes, if any.
+    fn step(&mut self, chunk: &mut &[u8]) -> Option<Option<Result<RelayMessage, Error>>> {
+        let byte = if let Some(&byte) = self.buf.get(self.parsed) {
+            self.parsed += 1;
+            if self.depth == 0 && byte == b'[' {
+                self.start = self.parsed - 1;
+            } else if self.depth == 0 || self.overflowed {
+                // nothing to keep of bytes between messages or of a message too long to parse
+                self.start = self.parsed;
+            }
+            byte
+        } else {
+            let (&byte, rest) = chunk.split_first()?;
+            *chunk = rest;
+            if self.depth > 0 || byte == b'[' {
+                self.store(byte);
+            }
+            byte
+        };
+        Some(self.scan(byte))
+    }
+
+    /// Tracks where in a message `byte` is, returning the result of the message it completes,
+    /// if any
+    fn scan(&mut self, byte: u8) -> Option<Result<RelayMessage, Error>> {
+        if self.depth == 0 {
+            return match byte {
+                b'[' => {
+                    self.depth = 1;
+                    None
+                }
+                // whitespace between messages
+                _ if byte.is_ascii_whitespace() => None,
+                _ => Some(Err(Error::MalformedContent)),
+            };
+        }
+        match byte {
+            _ if self.escaped => self.escaped = false,
+            b'\\' if self.in_string => self.escaped = true,
+            b'"' => self.in_string = !self.in_string,
+            _ if self.in_string => {}
+            b'[' | b'{' => self.depth += 1,
+            b']' | b'}' => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    return Some(self.finish());
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Appends a byte of the current message, only called once every held byte is parsed
+    fn store(&mut self, byte: u8) {
+        if self.overflowed {
+            return;
+        }
+        self.compact();
+        if self.buf.push(byte).is_err() {
+            self.overflowed = true;
+            self.buf.clear();
+        }
+        self.parsed = self.buf.len();
+    }
+
+    /// Moves the current message and anything after it to the front of `buf`
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buf.copy_within(self.start.., 0);
+            self.buf.truncate(self.buf.len() - self.start);
+            self.parsed -= self.start;
+            self.start = 0;
+        }
+    }
+
+    /// Parses the buffered message and readies for the next one
+    fn finish(&mut self) -> Result<RelayMessage, Error> {
+        let overflowed = self.overflowed;
+        self.overflowed = false;
+        let result = if overflowed {
+            Err(Error::MessageTooLong)
+        } else {
+            RelayMessage::try_from(&self.buf[self.start..self.parsed])
+        };
+        self.start = self.parsed;
+        if self.parsed == self.buf.len() {
+            self.buf.clear();
+            self.start = 0;
+            self.parsed = 0;
+        }
+        result
+    }
+
+    /// Keeps the bytes of a chunk its iterator didn't get to, for the next call to `push`
+    fn keep(&mut self, rest: &[u8]) {
+        if rest.is_empty() {
+            return;
+        }
+        self.compact();
+        let room = N - self.buf.len();
+        if rest.len() > room {
+            self.lost_tail = true;
+        }
+        self.buf
+            .extend_from_slice(&rest[..rest.len().min(room)])
+            .ok();
+    }
+
+    /// Drops whatever was cut off when a tail didn't fit, returning the error for it
+    fn cut_off(&mut self) -> Option<Result<RelayMessage, Error>> {
+        if !self.lost_tail || self.parsed < self.buf.len() {
+            return None;
+        }
+        *self = Self::new();
+        Some(Err(Error::MessageTooLong))
+    }
+}
+
+/// Messages completed by a chunk pushed into a [`StreamParser`].
+/// Yields `Err(MessageTooLong)` for a message that didn't fit in the parser and
+/// `Err(MalformedContent)` for bytes outside of any message.
+/// Dropping it before the end keeps the rest of the chunk in the parser.
+pub struct Messages<'a, const N: usize> {
+    parser: &'a mut StreamParser<N>,
+    chunk: &'a [u8],
+}
+
+impl<const N: usize> Iterator for Messages<'_, N> {
+    type Item = Result<RelayMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.parser.cut_off() {
+                return Some(result);
+            }
+            if let Some(result) = self.parser.step(&mut self.chunk)? {
+                return Some(result);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Drop for Messages<'_, N> {
+    fn drop(&mut self) {
+        self.parser.keep(self.chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay_responses::{EoseMessage, OkMessage};
+    const EVENT_MSG: &[u8] = br#"["EVENT","sub \"1\" ]", {"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let mut parser: StreamParser = StreamParser::new();
+        let mut done = 0;
+        for byte in EVENT_MSG.chunks(1) {
+            for msg in parser.push(byte) {
+                let Ok(RelayMessage::Event(event)) = msg else {
+                    panic!("expected an event");
+                };
+                assert_eq!(event.subscription_id, r#"sub "1" ]"#);
+                done += 1;
+            }
+        }
+        assert_eq!(done, 1);
+        assert_eq!(parser.pending(), 0);
+    }
+
+    #[test]
+    fn test_several_per_chunk() {
+        let mut parser: StreamParser<200> = StreamParser::new();
+        let chunk = br#"["EOSE","sub_1"]
+            ["OK","b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8",true,""]["EOSE","#;
+        let mut messages = parser.push(chunk);
+        assert_eq!(
+            messages.next(),
+            Some(Ok(RelayMessage::Eose(EoseMessage {
+                subscription_id: "sub_1".into()
+            })))
+        );
+        assert!(matches!(
+            messages.next(),
+            Some(Ok(RelayMessage::Ok(OkMessage { accepted: true, .. })))
+        ));
+        assert_eq!(messages.next(), None);
+        drop(messages);
+        assert_eq!(parser.pending(), r#"["EOSE","#.len());
+        let mut messages = parser.push(br#""sub_2"]"#);
+        assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+    }
+
+    #[test]
+    fn test_resume_after_drop() {
+        let mut parser: StreamParser<200> = StreamParser::new();
+        let mut messages = parser.push(br#"["EOSE","sub_1"] ["EOSE","sub_2"]["EOSE","#);
+        assert_eq!(
+            messages.next(),
+            Some(Ok(RelayMessage::Eose(EoseMessage {
+                subscription_id: "sub_1".into()
+            })))
+        );
+        drop(messages);
+        assert_eq!(parser.pending(), r#" ["EOSE","sub_2"]["EOSE","#.len());
+
+        let mut messages = parser.push(br#""sub_3"]"#);
+        assert_eq!(
+            messages.next(),
+            Some(Ok(RelayMessage::Eose(EoseMessage {
+                subscription_id: "sub_2".into()
+            })))
+        );
+        assert_eq!(
+            messages.next(),
+            Some(Ok(RelayMessage::Eose(EoseMessage {
+                subscription_id: "sub_3".into()
+            })))
+        );
+        assert_eq!(messages.next(), None);
+        drop(messages);
+        assert_eq!(parser.pending(), 0);
+    }
+
+    #[test]
+    fn test_tail_too_long() {
+        let mut parser: StreamParser<24> = StreamParser::new();
+        let mut messages = parser.push(br#"["EOSE","a"]["EOSE","b"]["NOTICE","cut off"]"#);
+        assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+        drop(messages);
+
+        let mut messages = parser.push(br#"["EOSE","c"]"#);
+        assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+        assert_eq!(messages.next(), Some(Err(Error::MessageTooLong)));
+        assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+        assert_eq!(messages.next(), None);
+    }
+
+    #[test]
+    fn test_too_long() {
+        let mut parser: StreamParser<16> = StreamParser::new();
+        let mut messages = parser.push(br#"["NOTICE","far too long for the buffer"]["EOSE","a"]"#);
+        assert_eq!(messages.next(), Some(Err(Error::MessageTooLong)));
+        assert!(matches!(messages.next(), Some(Ok(RelayMessage::Eose(_)))));
+        drop(messages);
+
+        let mut messages = parser.push(b"x[");
+        assert_eq!(messages.next(), Some(Err(Error::MalformedContent)));
+        assert_eq!(messages.next(), None);
+        drop(messages);
+        parser.reset();
+        assert_eq!(parser.pending(), 0);
+    }
+}
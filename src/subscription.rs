@@ -0,0 +1,113 @@
+//! Subscription lifecycle hooks
+//!
+//! Implement [`SubscriptionHooks`] to be told when a subscription's stored events have all been
+//! sent, when a relay closes it, or when it expires, then feed relay messages through [`dispatch`]
+//! or [`SubscriptionManager::handle_with_hooks`]. Deadlines are kept by the
+//! [`SubscriptionManager`], whose [`SubscriptionManager::poll`] runs `on_expired`.
+//! Every hook has an empty default, so only the interesting ones need to be written.
+//!
+//! [`SubscriptionManager`]: crate::subscription_manager::SubscriptionManager
+//! [`SubscriptionManager::handle_with_hooks`]: crate::subscription_manager::SubscriptionManager::handle_with_hooks
+//! [`SubscriptionManager::poll`]: crate::subscription_manager::SubscriptionManager::poll
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::subscription::{dispatch, SubscriptionHooks};
+//! #[derive(Default)]
+//! struct App {
+//!     caught_up: bool,
+//! }
+//! impl SubscriptionHooks for App {
+//!     fn on_eose(&mut self, _subscription_id: &str) {
+//!         self.caught_up = true;
+//!     }
+//! }
+//! let mut app = App::default();
+//! let eose = r#"["EOSE", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"]"#;
+//! dispatch(eose, &mut app).unwrap();
+//! assert!(app.caught_up);
+//! ```
+
+use crate::{
+    errors::Error,
+    relay_responses::{ClosedMessage, EoseMessage, ResponseTypes},
+};
+
+/// Callbacks for changes in a subscription's lifecycle
+pub trait SubscriptionHooks {
+    /// The relay has sent every stored event matching the subscription, new events follow live
+    fn on_eose(&mut self, _subscription_id: &str) {}
+
+    /// The relay ended the subscription, with its reason
+    fn on_closed(&mut self, _subscription_id: &str, _reason: &str) {}
+
+    /// The subscription ran past the deadline it was given with
+    /// [`SubscriptionManager::set_deadline`](crate::subscription_manager::SubscriptionManager::set_deadline)
+    fn on_expired(&mut self, _subscription_id: &str) {}
+}
+
+/// Hooks that ignore every event
+pub struct NoHooks;
+
+impl SubscriptionHooks for NoHooks {}
+
+/// Runs the hooks matching the relay message `msg`, returning its type.
+/// Messages that don't affect a subscription's lifecycle are only classified.
+pub fn dispatch<H: SubscriptionHooks>(msg: &str, hooks: &mut H) -> Result<ResponseTypes, Error> {
+    let msg_type = ResponseTypes::try_from(msg)?;
+    match msg_type {
+        ResponseTypes::Eose => {
+            let eose = EoseMessage::try_from(msg)?;
+            hooks.on_eose(&eose.subscription_id);
+        }
+        ResponseTypes::Closed => {
+            let closed = ClosedMessage::try_from(msg)?;
+            hooks.on_closed(&closed.subscription_id, &closed.message);
+        }
+        _ => {}
+    }
+    Ok(msg_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const SUB_ID: &str = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+
+    #[derive(Default)]
+    struct Recorder {
+        eose: usize,
+        closed: Option<heapless::String<64>>,
+    }
+
+    impl SubscriptionHooks for Recorder {
+        fn on_eose(&mut self, subscription_id: &str) {
+            assert_eq!(subscription_id, SUB_ID);
+            self.eose += 1;
+        }
+
+        fn on_closed(&mut self, subscription_id: &str, reason: &str) {
+            assert_eq!(subscription_id, SUB_ID);
+            self.closed = Some(reason.into());
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let mut recorder = Recorder::default();
+        let eose =
+            r#"["EOSE", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"]"#;
+        assert_eq!(dispatch(eose, &mut recorder), Ok(ResponseTypes::Eose));
+        let notice = r#"["NOTICE","hello"]"#;
+        assert_eq!(dispatch(notice, &mut recorder), Ok(ResponseTypes::Notice));
+        assert_eq!(recorder.eose, 1);
+        assert_eq!(recorder.closed, None);
+        let closed = r#"["CLOSED", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8", "error: \"shutting\" down"]"#;
+        assert_eq!(dispatch(closed, &mut recorder), Ok(ResponseTypes::Closed));
+        assert_eq!(
+            recorder.closed.as_deref(),
+            Some(r#"error: "shutting" down"#)
+        );
+        assert_eq!(dispatch("[]", &mut NoHooks), Err(Error::InvalidType));
+    }
+}
@@ -0,0 +1,486 @@
+//! Bookkeeping for open subscriptions
+//!
+//! [`SubscriptionManager`] hands out subscription ids, writes the `REQ` and `CLOSE` messages
+//! for them, and routes parsed relay messages to the subscription they belong to, tracking
+//! whether each one is still loading stored events, live, or closed by the relay. A
+//! subscription can be given a deadline, after which [`SubscriptionManager::poll`] reports it
+//! to the [`SubscriptionHooks`].
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     Vec,
+//!     query::Query,
+//!     relay_responses::RelayMessage,
+//!     subscription_manager::{SubscriptionManager, SubscriptionState},
+//! };
+//! let mut subs: SubscriptionManager<4> = SubscriptionManager::new();
+//! let mut req: Vec<u8, 256> = Vec::new();
+//! let slot = subs.subscribe(&Query::new(), &mut req).unwrap();
+//! assert_eq!(req, br#"["REQ","sub_0",{}]"#);
+//!
+//! let eose = RelayMessage::try_from(r#"["EOSE","sub_0"]"#).unwrap();
+//! assert_eq!(subs.handle(&eose), Some(slot));
+//! assert_eq!(subs.get(slot).unwrap().state(), &SubscriptionState::Live);
+//! ```
+
+use heapless::String;
+
+use crate::{
+    buffer::OutBuf,
+    errors::Error,
+    query::{close_subscription_to_buf, Query},
+    relay_responses::{EventMessageRef, RelayMessage, ResponseTypes},
+    seen::SeenCache,
+    subscription::{NoHooks, SubscriptionHooks},
+    utils::{from_hex, to_decimal_str},
+};
+
+/// Longest subscription id the manager hands out
+pub const SUB_ID_SIZE: usize = 16;
+const SUB_ID_PREFIX: &str = "sub_";
+
+/// Where a subscription is in its lifecycle
+#[derive(Debug, PartialEq, Clone)]
+pub enum SubscriptionState {
+    /// The relay is still sending stored events
+    Loading,
+    /// The relay sent `EOSE`, new events follow as they arrive
+    Live,
+    /// The relay ended the subscription, with its reason
+    Closed(String<180>),
+}
+
+/// What [`SubscriptionManager::handle_unseen`] made of a received frame
+// a Message holds a whole note, there is no heap to box it on
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    /// The parsed message, and the slot of the subscription it names as
+    /// [`SubscriptionManager::handle`] returns it
+    Message {
+        slot: Option<usize>,
+        msg: RelayMessage,
+    },
+    /// An `EVENT` already seen arrived on the subscription in `slot`
+    Duplicate { slot: usize },
+}
+
+/// A subscription held by a [`SubscriptionManager`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subscription {
+    id: String<SUB_ID_SIZE>,
+    state: SubscriptionState,
+    events: u32,
+    deadline: Option<u64>,
+}
+
+impl Subscription {
+    /// Id sent to the relay
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Current state
+    pub fn state(&self) -> &SubscriptionState {
+        &self.state
+    }
+
+    /// Number of events received
+    pub fn events(&self) -> u32 {
+        self.events
+    }
+
+    /// True once the relay has sent every stored event
+    pub fn is_live(&self) -> bool {
+        self.state == SubscriptionState::Live
+    }
+
+    /// Unix time after which the subscription expires, if it was given one and hasn't yet
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+}
+
+/// Up to `N` subscriptions to one relay, each kept in a numbered slot
+pub struct SubscriptionManager<const N: usize> {
+    slots: [Option<Subscription>; N],
+    next_id: u32,
+}
+
+impl<const N: usize> Default for SubscriptionManager<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SubscriptionManager<N> {
+    /// Creates a manager with no subscriptions
+    pub fn new() -> Self {
+        SubscriptionManager {
+            slots: core::array::from_fn(|_| None),
+            next_id: 0,
+        }
+    }
+
+    /// Number of subscriptions held, including ones closed by the relay
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    /// True if no subscriptions are held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The subscription in `slot`
+    pub fn get(&self, slot: usize) -> Option<&Subscription> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    /// Slot of the subscription with `id`
+    pub fn find(&self, id: &str) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|sub| sub.as_ref().is_some_and(|sub| sub.id == id))
+    }
+
+    /// Reserves a slot for a new subscription with a fresh id, returning the slot.
+    /// Errors with `QueueFull` if all `N` slots are taken.
+    pub fn allocate(&mut self) -> Result<usize, Error> {
+        let slot = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::QueueFull)?;
+        let mut id = String::new();
+        id.push_str(SUB_ID_PREFIX)
+            .and_then(|_| id.push_str(&to_decimal_str(self.next_id.into())))
+            .map_err(|_| Error::InternalError)?;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.slots[slot] = Some(Subscription {
+            id,
+            state: SubscriptionState::Loading,
+            events: 0,
+            deadline: None,
+        });
+        Ok(slot)
+    }
+
+    /// Allocates a subscription and writes the `REQ` for `query` into `out`, returning the slot.
+    /// Errors with `QueueFull` if all slots are taken or `ContentOverflow` if `out` runs out
+    /// of room, in which case the slot is released again. After an error, whatever follows the
+    /// length `out` had before the call is the start of an unfinished `REQ` and must not be sent.
+    pub fn subscribe<B: OutBuf>(&mut self, query: &Query, out: &mut B) -> Result<usize, Error> {
+        let slot = self.allocate()?;
+        let id = &self.slots[slot].as_ref().ok_or(Error::InternalError)?.id;
+        if let Err(e) = query.serialize_to_buf(id, out) {
+            self.release(slot);
+            return Err(e);
+        }
+        Ok(slot)
+    }
+
+    /// Writes the `CLOSE` for the subscription in `slot` into `out` and releases the slot.
+    /// Nothing is written if the relay already closed the subscription or the slot is empty.
+    pub fn close<B: OutBuf>(&mut self, slot: usize, out: &mut B) -> Result<(), Error> {
+        let Some(sub) = self.get(slot) else {
+            return Ok(());
+        };
+        if !matches!(sub.state, SubscriptionState::Closed(_)) {
+            close_subscription_to_buf(&sub.id, out)?;
+        }
+        self.release(slot);
+        Ok(())
+    }
+
+    /// Has the subscription in `slot` expire at unix time `deadline`, or never with `None`
+    pub fn set_deadline(&mut self, slot: usize, deadline: Option<u64>) {
+        if let Some(Some(sub)) = self.slots.get_mut(slot) {
+            sub.deadline = deadline;
+        }
+    }
+
+    /// Finds a subscription past its deadline at unix time `now`, calls
+    /// [`SubscriptionHooks::on_expired`] for it and returns its slot, or `None` if no
+    /// subscription has expired. Each expires once: its deadline is cleared, and it keeps its
+    /// slot until [`SubscriptionManager::close`] sends its `CLOSE`. Subscriptions already closed
+    /// by the relay don't expire.
+    pub fn poll<H: SubscriptionHooks>(&mut self, now: u64, hooks: &mut H) -> Option<usize> {
+        let slot = self.slots.iter().position(|sub| {
+            sub.as_ref().is_some_and(|sub| {
+                !matches!(sub.state, SubscriptionState::Closed(_))
+                    && sub.deadline.is_some_and(|deadline| deadline <= now)
+            })
+        })?;
+        let sub = self.slots[slot].as_mut()?;
+        sub.deadline = None;
+        hooks.on_expired(&sub.id);
+        Some(slot)
+    }
+
+    /// Frees `slot` without telling the relay
+    pub fn release(&mut self, slot: usize) {
+        if let Some(sub) = self.slots.get_mut(slot) {
+            *sub = None;
+        }
+    }
+
+    /// Frees every slot, such as after the connection to the relay is lost
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|sub| *sub = None);
+    }
+
+    /// Records `msg` against the subscription it names, returning that subscription's slot.
+    /// Returns `None` for messages that don't name a subscription or name an unknown one.
+    pub fn handle(&mut self, msg: &RelayMessage) -> Option<usize> {
+        self.handle_with_hooks(msg, &mut NoHooks)
+    }
+
+    /// Same as [`SubscriptionManager::handle`], calling [`SubscriptionHooks::on_eose`] and
+    /// [`SubscriptionHooks::on_closed`] for the subscriptions it knows
+    pub fn handle_with_hooks<H: SubscriptionHooks>(
+        &mut self,
+        msg: &RelayMessage,
+        hooks: &mut H,
+    ) -> Option<usize> {
+        let id = match msg {
+            RelayMessage::Event(event) => &event.subscription_id,
+            RelayMessage::Eose(eose) => &eose.subscription_id,
+            RelayMessage::Closed(closed) => &closed.subscription_id,
+            RelayMessage::Count(count) => &count.subscription_id,
+            _ => return None,
+        };
+        let slot = self.find(id)?;
+        let sub = self.slots[slot].as_mut()?;
+        match msg {
+            RelayMessage::Event(_) => sub.events = sub.events.saturating_add(1),
+            RelayMessage::Eose(_) => {
+                sub.state = SubscriptionState::Live;
+                hooks.on_eose(&sub.id);
+            }
+            RelayMessage::Closed(closed) => {
+                sub.state = SubscriptionState::Closed(closed.message.clone());
+                hooks.on_closed(&sub.id, &closed.message);
+            }
+            _ => {}
+        }
+        Some(slot)
+    }
+
+    /// Parses `frame` and records it like [`SubscriptionManager::handle`], but an `EVENT` for a
+    /// known subscription whose id is in `seen`, such as one already delivered by another relay,
+    /// is reported as [`Received::Duplicate`] without copying the note out, checking its
+    /// signature or counting it. Ids of events for a known subscription that pass the checks are
+    /// added to `seen`.
+    /// Errors if the frame isn't a relay message this crate can parse.
+    pub fn handle_unseen<const S: usize>(
+        &mut self,
+        frame: &[u8],
+        seen: &mut SeenCache<S>,
+    ) -> Result<Received, Error> {
+        if ResponseTypes::try_from(frame)? == ResponseTypes::Event {
+            let event = EventMessageRef::try_from(frame)?;
+            if let Some(slot) = self.find(event.subscription_id) {
+                let id = from_hex(event.note.id().as_bytes()).map_err(|_| Error::EventNotValid)?;
+                if seen.contains(&id) {
+                    return Ok(Received::Duplicate { slot });
+                }
+            }
+            let msg = RelayMessage::Event(event.try_into()?);
+            let slot = self.handle(&msg);
+            if let (Some(_), RelayMessage::Event(event)) = (slot, &msg) {
+                seen.insert(&event.note.id);
+            }
+            return Ok(Received::Message { slot, msg });
+        }
+        let msg = RelayMessage::try_from(frame)?;
+        let slot = self.handle(&msg);
+        Ok(Received::Message { slot, msg })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let mut subs: SubscriptionManager<2> = SubscriptionManager::new();
+        let mut out: Vec<u8, 128> = Vec::new();
+        let first = subs.subscribe(&Query::new(), &mut out).unwrap();
+        let second = subs.allocate().unwrap();
+        assert_eq!(subs.allocate(), Err(Error::QueueFull));
+        assert_eq!(subs.get(second).unwrap().id(), "sub_1");
+        assert_eq!(subs.find("sub_0"), Some(first));
+
+        let event = RelayMessage::try_from(r#"["EVENT","sub_0",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#).unwrap();
+        assert_eq!(subs.handle(&event), Some(first));
+        assert_eq!(subs.get(first).unwrap().events(), 1);
+        assert_eq!(
+            subs.get(first).unwrap().state(),
+            &SubscriptionState::Loading
+        );
+        let eose = RelayMessage::try_from(r#"["EOSE","sub_0"]"#).unwrap();
+        subs.handle(&eose);
+        assert!(subs.get(first).unwrap().is_live());
+
+        let closed =
+            RelayMessage::try_from(r#"["CLOSED","sub_1","auth-required: sign in"]"#).unwrap();
+        assert_eq!(subs.handle(&closed), Some(second));
+        assert_eq!(
+            subs.get(second).unwrap().state(),
+            &SubscriptionState::Closed("auth-required: sign in".into())
+        );
+        let unknown = RelayMessage::try_from(r#"["EOSE","sub_9"]"#).unwrap();
+        assert_eq!(subs.handle(&unknown), None);
+
+        out.clear();
+        subs.close(first, &mut out).unwrap();
+        assert_eq!(out, br#"["CLOSE","sub_0"]"#);
+        out.clear();
+        subs.close(second, &mut out).unwrap();
+        assert!(out.is_empty());
+        assert!(subs.is_empty());
+        // ids are not reused
+        subs.allocate().unwrap();
+        assert_eq!(subs.find("sub_2"), Some(0));
+    }
+
+    /// Remembers the last hook called
+    #[derive(Default)]
+    struct Recorder {
+        last: Option<(&'static str, String<SUB_ID_SIZE>, String<180>)>,
+    }
+
+    impl SubscriptionHooks for Recorder {
+        fn on_eose(&mut self, subscription_id: &str) {
+            self.last = Some(("eose", subscription_id.into(), String::new()));
+        }
+
+        fn on_closed(&mut self, subscription_id: &str, reason: &str) {
+            self.last = Some(("closed", subscription_id.into(), reason.into()));
+        }
+
+        fn on_expired(&mut self, subscription_id: &str) {
+            self.last = Some(("expired", subscription_id.into(), String::new()));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut subs: SubscriptionManager<2> = SubscriptionManager::new();
+        let mut hooks = Recorder::default();
+        let first = subs.allocate().unwrap();
+        subs.allocate().unwrap();
+
+        let eose = RelayMessage::try_from(r#"["EOSE","sub_0"]"#).unwrap();
+        assert_eq!(subs.handle_with_hooks(&eose, &mut hooks), Some(first));
+        assert_eq!(hooks.last, Some(("eose", "sub_0".into(), String::new())));
+        let closed =
+            RelayMessage::try_from(r#"["CLOSED","sub_1","rate-limited: slow down"]"#).unwrap();
+        subs.handle_with_hooks(&closed, &mut hooks);
+        assert_eq!(
+            hooks.last,
+            Some(("closed", "sub_1".into(), "rate-limited: slow down".into()))
+        );
+        let unknown = RelayMessage::try_from(r#"["CLOSED","sub_9","error: gone"]"#).unwrap();
+        hooks.last = None;
+        assert_eq!(subs.handle_with_hooks(&unknown, &mut hooks), None);
+        assert_eq!(hooks.last, None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut subs: SubscriptionManager<2> = SubscriptionManager::new();
+        let mut hooks = Recorder::default();
+        let first = subs.allocate().unwrap();
+        let second = subs.allocate().unwrap();
+        subs.set_deadline(first, Some(100));
+        subs.set_deadline(second, Some(100));
+        // a subscription the relay closed doesn't expire
+        let closed = RelayMessage::try_from(r#"["CLOSED","sub_1","error: gone"]"#).unwrap();
+        subs.handle(&closed);
+
+        assert_eq!(subs.poll(99, &mut hooks), None);
+        assert_eq!(hooks.last, None);
+        assert_eq!(subs.poll(100, &mut hooks), Some(first));
+        assert_eq!(hooks.last, Some(("expired", "sub_0".into(), String::new())));
+        assert_eq!(subs.get(first).unwrap().deadline(), None);
+        // only once, and it still needs closing
+        assert_eq!(subs.poll(200, &mut hooks), None);
+        let mut out: Vec<u8, 32> = Vec::new();
+        subs.close(first, &mut out).unwrap();
+        assert_eq!(out, br#"["CLOSE","sub_0"]"#);
+    }
+
+    #[test]
+    fn test_handle_unseen() {
+        const EVENT: &[u8] = br#"["EVENT","sub_0",{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+        let mut subs: SubscriptionManager<1> = SubscriptionManager::new();
+        let slot = subs.allocate().unwrap();
+        let mut seen: SeenCache<4> = SeenCache::new();
+        assert!(matches!(
+            subs.handle_unseen(EVENT, &mut seen),
+            Ok(Received::Message {
+                slot: Some(0),
+                msg: RelayMessage::Event(_)
+            })
+        ));
+        assert_eq!(
+            subs.handle_unseen(EVENT, &mut seen),
+            Ok(Received::Duplicate { slot })
+        );
+        assert_eq!(subs.get(slot).unwrap().events(), 1);
+        assert_eq!(seen.len(), 1);
+
+        // a copy is dropped before its signature is checked
+        let forged = core::str::from_utf8(EVENT).unwrap().replace("89a4", "0000");
+        assert_eq!(
+            subs.handle_unseen(forged.as_bytes(), &mut seen),
+            Ok(Received::Duplicate { slot })
+        );
+        seen.clear();
+        assert_eq!(
+            subs.handle_unseen(forged.as_bytes(), &mut seen),
+            Err(Error::InvalidSignature)
+        );
+        assert!(seen.is_empty());
+
+        // events for unknown subscriptions aren't remembered
+        subs.release(slot);
+        assert!(matches!(
+            subs.handle_unseen(EVENT, &mut seen),
+            Ok(Received::Message { slot: None, .. })
+        ));
+        assert!(seen.is_empty());
+        assert_eq!(
+            subs.handle_unseen(br#"["EOSE","sub_0"]"#, &mut seen),
+            Ok(Received::Message {
+                slot: None,
+                msg: RelayMessage::try_from(r#"["EOSE","sub_0"]"#).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_subscribe_overflow() {
+        let mut subs: SubscriptionManager<1> = SubscriptionManager::new();
+        let mut out: Vec<u8, 4> = Vec::new();
+        assert_eq!(
+            subs.subscribe(&Query::new(), &mut out),
+            Err(Error::ContentOverflow)
+        );
+        assert!(subs.is_empty());
+        // what was written before is kept, followed by the start of the REQ
+        let mut out: Vec<u8, 18> = Vec::new();
+        out.extend_from_slice(b"[1]").unwrap();
+        assert_eq!(
+            subs.subscribe(&Query::new(), &mut out),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(out, br#"[1]["REQ","sub_1","#);
+    }
+}
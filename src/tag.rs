@@ -0,0 +1,320 @@
+//! Structured note tags
+//!
+//! A [`Tag`] keeps its name and each value as separate strings, so values can contain any
+//! character, including the `,` that earlier versions of this crate used as a delimiter.
+//!
+//! Common tags have typed constructors such as [`Tag::event`] and [`Tag::hashtag`], anything
+//! else can be built with [`Tag::custom`].
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, tag::Tag};
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .add_tags([
+//!         Tag::custom("content-warning", &["spoilers, season 2"]).unwrap(),
+//!         Tag::hashtag("esp32").unwrap(),
+//!     ])
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! assert_eq!(note.get_tag("content-warning").unwrap()[0][0], "spoilers, season 2");
+//! ```
+
+use heapless::{String, Vec};
+
+use crate::{
+    buffer::OutBuf,
+    errors::Error,
+    hex::{HexId, HexPubkey},
+    utils::write_escaped,
+};
+
+/// Longest tag name
+pub const TAG_NAME_SIZE: usize = 16;
+/// Longest value a tag holds by default
+pub const TAG_VALUE_SIZE: usize = 100;
+/// Most values a tag holds by default, not counting its name
+pub const MAX_TAG_VALUES: usize = 4;
+
+/// A single note tag, such as `["p", "<pubkey>", "wss://relay.example.com"]`.
+/// `VALUE` is the longest each value can be and `VALUES` the most values it holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tag<const VALUE: usize = TAG_VALUE_SIZE, const VALUES: usize = MAX_TAG_VALUES> {
+    /// `None` for the empty tag `[]`
+    name: Option<String<TAG_NAME_SIZE>>,
+    values: Vec<String<VALUE>, VALUES>,
+}
+
+impl<const VALUE: usize, const VALUES: usize> Tag<VALUE, VALUES> {
+    /// Creates a tag with no values.
+    /// Errors with `TagNameTooLong` if `name` is longer than [`TAG_NAME_SIZE`].
+    pub fn new(name: &str) -> Result<Self, Error> {
+        let mut tag_name = String::new();
+        tag_name.push_str(name).map_err(|_| Error::TagNameTooLong)?;
+        Ok(Tag {
+            name: Some(tag_name),
+            values: Vec::new(),
+        })
+    }
+
+    /// The empty tag `[]`, with no name or values. Some events in the wild carry one, and it is
+    /// part of what their id covers.
+    pub fn empty() -> Self {
+        Tag {
+            name: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// True for the empty tag `[]`
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+    }
+
+    /// `["e", <id>, <relay_hint>, <marker>]` referencing the note with hex id `id`, as in
+    /// [NIP-10](https://github.com/nostr-protocol/nips/blob/master/10.md). `marker` is
+    /// `root`, `reply` or `mention`. An empty relay hint is written when only the marker is given.
+    /// The id is written lowercase.
+    /// Errors with `EventNotValid` if `id` isn't 64 hex characters and `ContentOverflow` if a
+    /// value is longer than `VALUE`.
+    pub fn event(id: &str, relay_hint: Option<&str>, marker: Option<&str>) -> Result<Self, Error> {
+        let id = HexId::try_from(id)?.to_hex();
+        let mut tag = Tag::try_from(["e", hex_str(&id)])?;
+        if relay_hint.is_some() || marker.is_some() {
+            tag.push_value(relay_hint.unwrap_or(""))?;
+        }
+        if let Some(marker) = marker {
+            tag.push_value(marker)?;
+        }
+        Ok(tag)
+    }
+
+    /// `["p", <pubkey>, <relay_hint>]` mentioning the user with hex pubkey `pubkey`, written
+    /// lowercase.
+    /// Errors with `InvalidPubkey` if `pubkey` isn't 64 hex characters and `ContentOverflow` if
+    /// a value is longer than `VALUE`.
+    pub fn pubkey(pubkey: &str, relay_hint: Option<&str>) -> Result<Self, Error> {
+        let pubkey = HexPubkey::try_from(pubkey)?.to_hex();
+        let mut tag = Tag::try_from(["p", hex_str(&pubkey)])?;
+        if let Some(relay_hint) = relay_hint {
+            tag.push_value(relay_hint)?;
+        }
+        Ok(tag)
+    }
+
+    /// `["t", <hashtag>]`, with `hashtag` given without the `#`.
+    /// Errors with `ContentOverflow` if `hashtag` is longer than `VALUE`.
+    pub fn hashtag(hashtag: &str) -> Result<Self, Error> {
+        Tag::try_from(["t", hashtag])
+    }
+
+    /// A tag named `name` holding `values`.
+    /// Errors with `TagNameTooLong` if `name` is longer than [`TAG_NAME_SIZE`] and
+    /// `ContentOverflow` if there are more than `VALUES` values or one is longer than `VALUE`.
+    pub fn custom(name: &str, values: &[&str]) -> Result<Self, Error> {
+        let mut tag = Tag::new(name)?;
+        for value in values {
+            tag.push_value(value)?;
+        }
+        Ok(tag)
+    }
+
+    /// Appends a value.
+    /// Errors with `ContentOverflow` if `value` is longer than `VALUE`
+    /// or the tag already holds `VALUES` values, and with `MalformedContent` for the
+    /// empty tag, which has no name for the value to follow.
+    pub fn push_value(&mut self, value: &str) -> Result<(), Error> {
+        if self.is_empty() {
+            return Err(Error::MalformedContent);
+        }
+        let mut stored = String::new();
+        stored.push_str(value).map_err(|_| Error::ContentOverflow)?;
+        self.values.push(stored).map_err(|_| Error::ContentOverflow)
+    }
+
+    /// Same as [`Tag::push_value`], returning the tag for chaining
+    pub fn with_value(mut self, value: &str) -> Result<Self, Error> {
+        self.push_value(value)?;
+        Ok(self)
+    }
+
+    /// Name of the tag, its first element, empty for the empty tag
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_default()
+    }
+
+    /// Values following the name
+    pub fn values(&self) -> impl Iterator<Item = &str> {
+        self.values.iter().map(|value| value.as_str())
+    }
+
+    /// The value at `index`, not counting the name
+    pub fn value(&self, index: usize) -> Option<&str> {
+        self.values.get(index).map(|value| value.as_str())
+    }
+
+    /// The id an `e` tag references, `None` for other tags or if the id isn't valid hex
+    pub fn referenced_event(&self) -> Option<HexId> {
+        if self.name() != "e" {
+            return None;
+        }
+        HexId::try_from(self.value(0)?).ok()
+    }
+
+    /// The pubkey a `p` tag mentions, `None` for other tags or if the pubkey isn't valid hex
+    pub fn referenced_pubkey(&self) -> Option<HexPubkey> {
+        if self.name() != "p" {
+            return None;
+        }
+        HexPubkey::try_from(self.value(0)?).ok()
+    }
+
+    /// Writes the tag as a JSON array
+    pub(crate) fn write_json<B: OutBuf>(&self, out: &mut B) -> Result<(), Error> {
+        let Some(name) = &self.name else {
+            return out.push_slice(b"[]");
+        };
+        out.push_slice(br#"[""#)?;
+        write_escaped(out, name)?;
+        out.push_byte(b'"')?;
+        for value in self.values.iter() {
+            out.push_slice(br#",""#)?;
+            write_escaped(out, value)?;
+            out.push_byte(b'"')?;
+        }
+        out.push_byte(b']')
+    }
+}
+
+/// Builds a tag from its name followed by its values
+impl<const N: usize, const VALUE: usize, const VALUES: usize> TryFrom<[&str; N]>
+    for Tag<VALUE, VALUES>
+{
+    type Error = Error;
+
+    fn try_from(parts: [&str; N]) -> Result<Self, Self::Error> {
+        Tag::try_from(parts.as_slice())
+    }
+}
+
+/// Builds a tag from its name followed by its values, errors with `MalformedContent` if `parts` is empty
+impl<const VALUE: usize, const VALUES: usize> TryFrom<&[&str]> for Tag<VALUE, VALUES> {
+    type Error = Error;
+
+    fn try_from(parts: &[&str]) -> Result<Self, Self::Error> {
+        let (name, values) = parts.split_first().ok_or(Error::MalformedContent)?;
+        Tag::custom(name, values)
+    }
+}
+
+/// `hex` as a string, it is always ASCII
+fn hex_str(hex: &[u8; 64]) -> &str {
+    core::str::from_utf8(hex).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let tag: Tag = Tag::new("r")
+            .unwrap()
+            .with_value("wss://relay.example.com/?a=1,b=2")
+            .unwrap()
+            .with_value("read")
+            .unwrap();
+        assert_eq!(tag.name(), "r");
+        assert_eq!(tag.value(0), Some("wss://relay.example.com/?a=1,b=2"));
+        assert_eq!(tag.values().count(), 2);
+        let mut json: Vec<u8, 64> = Vec::new();
+        tag.write_json(&mut json).unwrap();
+        assert_eq!(json, br#"["r","wss://relay.example.com/?a=1,b=2","read"]"#);
+    }
+
+    #[test]
+    fn test_constructors() {
+        const ID: &str = "b515da91ac5df638fa0c3a7ef9bd1d4e8cbbe8c8c1c60a27e05ae6ac5cee9a5b";
+        const PUBKEY: &str = "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+        let relay = "wss://relay.example.com";
+        assert_eq!(Tag::event(ID, None, None), Tag::<100>::try_from(["e", ID]));
+        assert_eq!(
+            Tag::event(ID, Some(relay), Some("root")),
+            Tag::<100>::try_from(["e", ID, relay, "root"])
+        );
+        assert_eq!(
+            Tag::event(ID, None, Some("reply")),
+            Tag::<100>::try_from(["e", ID, "", "reply"])
+        );
+        assert_eq!(
+            Tag::<100>::event("not an id", None, None),
+            Err(Error::EventNotValid)
+        );
+        assert_eq!(
+            Tag::pubkey(PUBKEY, Some(relay)),
+            Tag::<100>::try_from(["p", PUBKEY, relay])
+        );
+        assert_eq!(Tag::<100>::pubkey(ID, None).unwrap().value(1), None);
+        assert_eq!(Tag::<100>::pubkey("0a", None), Err(Error::InvalidPubkey));
+        let upper = Tag::<100>::pubkey(
+            "098EF66BCE60DD4CF10B4AE5949D1EC6DD777DDEB4BC49B47F97275A127A63CF",
+            None,
+        )
+        .unwrap();
+        assert_eq!(upper.value(0), Some(PUBKEY));
+        assert_eq!(upper.referenced_pubkey(), HexPubkey::try_from(PUBKEY).ok());
+        assert_eq!(upper.referenced_event(), None);
+        assert_eq!(
+            Tag::<100>::event(ID, None, None)
+                .unwrap()
+                .referenced_event(),
+            HexId::try_from(ID).ok()
+        );
+        assert_eq!(
+            Tag::<100>::try_from(["p", "npub1..."])
+                .unwrap()
+                .referenced_pubkey(),
+            None
+        );
+        assert_eq!(Tag::hashtag("esp32"), Tag::<100>::try_from(["t", "esp32"]));
+        assert_eq!(
+            Tag::custom("l", &["kitchen", "room"]),
+            Tag::<100>::try_from(["l", "kitchen", "room"])
+        );
+        assert_eq!(
+            Tag::<8>::hashtag("longer than 8"),
+            Err(Error::ContentOverflow)
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(
+            Tag::<8>::new("a-very-long-tag-name"),
+            Err(Error::TagNameTooLong)
+        );
+        assert_eq!(
+            Tag::<8>::try_from(["t", "1", "2", "3", "4", "5"]),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(
+            Tag::<8>::try_from(["t", "longer than 8"]),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(Tag::<8>::try_from([]), Err(Error::MalformedContent));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut tag: Tag = Tag::empty();
+        assert!(tag.is_empty());
+        assert!(!Tag::<8>::new("").unwrap().is_empty());
+        assert_eq!(tag.name(), "");
+        assert_eq!(tag.push_value("x"), Err(Error::MalformedContent));
+        let mut json: Vec<u8, 8> = Vec::new();
+        tag.write_json(&mut json).unwrap();
+        assert_eq!(json, b"[]");
+    }
+}
@@ -0,0 +1,185 @@
+//! Pre-serialized notes for devices that publish the same event over and over
+//!
+//! A [`NoteTemplate`] fixes the pubkey, kind and tags of a note when it is created. The part of
+//! the id hash that covers the pubkey is computed once up front and the kind and tags are kept
+//! already serialized, so each [`NoteTemplate::sign`] only hashes the timestamp, the content and
+//! the pre-serialized bytes, then signs. No scratch buffer is needed.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, NoteKinds, tag::Tag};
+//! let template = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .set_kind(NoteKinds::IOT)
+//!     .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+//!     .unwrap()
+//!     .into_template()
+//!     .unwrap();
+//! // aux_rnd should come from a random number generator
+//! let reading = template.sign(1686880020, "21.5", [0; 32]).unwrap();
+//! assert!(reading.validate_signature().is_ok());
+//! ```
+
+use heapless::{String, Vec};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    clock::check_window,
+    curve::KeyPair,
+    errors::Error,
+    tag::Tag,
+    tag::{MAX_TAG_VALUES, TAG_VALUE_SIZE},
+    utils::{escape_json, to_decimal_str},
+    Note, NoteBuilder, NoteKinds, MAX_TAGS, MAX_TAG_JSON_SIZE, NOTE_SIZE,
+};
+
+/// Longest the serialized kind and tags of a default sized note can be, `,kind,[tags],"`
+const FIXED_PART_SIZE: usize = 1 + 10 + 1 + 2 + MAX_TAGS * MAX_TAG_JSON_SIZE + (MAX_TAGS - 1) + 2;
+
+/// A note with everything but `created_at` and `content` fixed, created by [`NoteBuilder::into_template`]
+pub struct NoteTemplate<
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    keypair: KeyPair,
+    /// Earliest and latest `created_at` accepted, from the builder
+    created_at_window: Option<(u64, u64)>,
+    pubkey: [u8; 32],
+    kind: NoteKinds,
+    tags: Vec<Tag<TAG, VALUES>, NTAGS>,
+    /// Hash state after `[0,"pubkey",`
+    head: Sha256,
+    /// `,kind,[tags],"`, hashed after the timestamp
+    fixed: Vec<u8, FIXED_PART_SIZE>,
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the builder into a [`NoteTemplate`] keeping its kind and tags.
+    /// Any content set on the builder is ignored, it is given to each [`NoteTemplate::sign`] instead.
+    /// Errors with `ContentOverflow` if the tags are larger than a default sized note's can be,
+    /// and `MissingPrivkey` if the builder was created from a [`Signer`](crate::signer::Signer).
+    pub fn into_template(self) -> Result<NoteTemplate<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let mut head = Sha256::new();
+        head.update(br#"[0,""#);
+        head.update(self.note.pubkey());
+        head.update(br#"","#);
+
+        let mut fixed = Vec::new();
+        fixed
+            .extend_from_slice(b",")
+            .map_err(|_| Error::ContentOverflow)?;
+        fixed
+            .extend_from_slice(self.note.kind.serialize().as_bytes())
+            .map_err(|_| Error::ContentOverflow)?;
+        fixed
+            .extend_from_slice(b",")
+            .map_err(|_| Error::ContentOverflow)?;
+        self.note.write_tags(&mut fixed)?;
+        fixed
+            .extend_from_slice(br#",""#)
+            .map_err(|_| Error::ContentOverflow)?;
+
+        Ok(NoteTemplate {
+            keypair: self.keypair.ok_or(Error::MissingPrivkey)?,
+            created_at_window: self.created_at_window,
+            pubkey: self.note.pubkey,
+            kind: self.note.kind,
+            tags: self.note.tags,
+            head,
+            fixed,
+        })
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteTemplate<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Creates a signed note from the template.
+    /// Errors with `ContentOverflow` if `content` is longer than a note's content can be, and
+    /// `CreatedAtOutOfRange` if `created_at` is outside the builder's
+    /// [window](NoteBuilder::created_at_window).
+    pub fn sign(
+        &self,
+        created_at: u64,
+        content: &str,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        check_window(created_at, self.created_at_window)?;
+        let mut stored: String<CONTENT> = String::new();
+        stored
+            .push_str(content)
+            .map_err(|_| Error::ContentOverflow)?;
+
+        let mut hasher = self.head.clone();
+        hasher.update(to_decimal_str(created_at).as_bytes());
+        hasher.update(&self.fixed);
+        escape_json(content, |bytes| {
+            hasher.update(bytes);
+            Ok(())
+        })?;
+        hasher.update(br#""]"#);
+
+        let mut note = Note {
+            id: [0; 32],
+            pubkey: self.pubkey,
+            created_at,
+            kind: self.kind,
+            tags: self.tags.clone(),
+            content: if content.is_empty() {
+                None
+            } else {
+                Some(stored)
+            },
+            sig: [0; 64],
+        };
+        note.id = hasher.finalize().into();
+        note.set_sig(&self.keypair, &aux_rnd)?;
+        Ok(note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::PRIVKEY;
+
+    #[test]
+    fn test_matches_builder() {
+        let template = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::IOT)
+            .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["l", "room", "kitchen"]).unwrap())
+            .unwrap()
+            .into_template()
+            .unwrap();
+        for (created_at, content) in [(1686880020, "21.5"), (1686880080, "21.75")] {
+            let expected = Note::new_builder(PRIVKEY)
+                .unwrap()
+                .set_kind(NoteKinds::IOT)
+                .add_tag(Tag::try_from(["t", "temperature"]).unwrap())
+                .unwrap()
+                .add_tag(Tag::try_from(["l", "room", "kitchen"]).unwrap())
+                .unwrap()
+                .content(content.into())
+                .build(created_at, [0; 32])
+                .unwrap();
+            assert_eq!(template.sign(created_at, content, [0; 32]), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn test_empty_content() {
+        let template = Note::new_builder(PRIVKEY).unwrap().into_template().unwrap();
+        let expected = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(template.sign(1686880020, "", [0; 32]), Ok(expected));
+    }
+}
@@ -0,0 +1,201 @@
+//! Reply threading ([NIP-10](https://github.com/nostr-protocol/nips/blob/master/10.md))
+//!
+//! A reply names the first note of its thread and the note it answers with `e` tags marked
+//! `root` and `reply`, and mentions their authors with `p` tags so they get notified.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::Note;
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let root = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("gm".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let reply = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("gm to you too".into())
+//!     .create_reply(&root, None)
+//!     .unwrap()
+//!     .build(1686880021, [0; 32])
+//!     .unwrap();
+//! let thread = reply.thread_info().unwrap();
+//! assert_eq!(&thread.root, root.id_bytes());
+//! assert_eq!(thread.reply, thread.root);
+//! ```
+
+use heapless::Vec;
+
+use crate::{errors::Error, tag::Tag, utils::from_hex, Note, NoteBuilder};
+
+/// Where a note sits in a thread, see [`Note::thread_info`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThreadInfo {
+    /// Id of the first note of the thread
+    pub root: [u8; 32],
+    /// Id of the note being answered, the same as `root` for a direct reply to it
+    pub reply: [u8; 32],
+}
+
+/// The `["e", <id>, "", <marker>, <pubkey>]` tag pointing at `note`
+fn marked_tag<
+    const TAG: usize,
+    const VALUES: usize,
+    const C: usize,
+    const T: usize,
+    const N: usize,
+    const V: usize,
+>(
+    note: &Note<C, T, N, V>,
+    marker: &str,
+) -> Result<Tag<TAG, VALUES>, Error> {
+    let (id, pubkey) = (note.id(), note.pubkey());
+    let id = core::str::from_utf8(&id).map_err(|_| Error::EncodeError)?;
+    let pubkey = core::str::from_utf8(&pubkey).map_err(|_| Error::EncodeError)?;
+    Tag::try_from(["e", id, "", marker, pubkey])
+}
+
+/// The `["p", <pubkey>]` tag mentioning the author of `note`
+fn author_tag<
+    const TAG: usize,
+    const VALUES: usize,
+    const C: usize,
+    const T: usize,
+    const N: usize,
+    const V: usize,
+>(
+    note: &Note<C, T, N, V>,
+) -> Result<Tag<TAG, VALUES>, Error> {
+    let pubkey = note.pubkey();
+    Tag::try_from([
+        "p",
+        core::str::from_utf8(&pubkey).map_err(|_| Error::EncodeError)?,
+    ])
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Makes the note a reply to `parent`, in the thread started by `root`.
+    /// Pass `None` for `root` when `parent` is the first note of the thread.
+    /// Adds the marked `e` tags and a `p` tag for each author, up to four tags,
+    /// replacing any added before.
+    /// Errors with `ContentOverflow` if `TAG` is too short to hold the ids and pubkeys.
+    pub fn create_reply<const C: usize, const T: usize, const N: usize, const V: usize>(
+        mut self,
+        parent: &Note<C, T, N, V>,
+        root: Option<&Note<C, T, N, V>>,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        const { assert!(4 <= NTAGS, "a reply needs up to four tags") };
+        let mut tags: Vec<Tag<TAG, VALUES>, NTAGS> = Vec::new();
+        // the assert above leaves room for all of them
+        match root {
+            Some(root) => {
+                let _ = tags.push(marked_tag(root, "root")?);
+                let _ = tags.push(marked_tag(parent, "reply")?);
+                let _ = tags.push(author_tag(parent)?);
+                if root.pubkey != parent.pubkey {
+                    let _ = tags.push(author_tag(root)?);
+                }
+            }
+            None => {
+                let _ = tags.push(marked_tag(parent, "root")?);
+                let _ = tags.push(author_tag(parent)?);
+            }
+        }
+        self.note.tags = tags;
+        Ok(self)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// The thread this note replies in, `None` if it has no `e` tags.
+    /// Understands marked `e` tags as well as the older positional form, where the first
+    /// `e` tag is the root and the last the note being answered.
+    /// `e` tags that don't hold a hex id are skipped.
+    pub fn thread_info(&self) -> Option<ThreadInfo> {
+        let events = || {
+            self.tags
+                .iter()
+                .filter(|tag| tag.name() == "e")
+                .filter_map(|tag| Some((from_hex(tag.value(0)?.as_bytes()).ok()?, tag.value(2))))
+        };
+        let marked = |marker| {
+            events()
+                .find(|(_, found)| *found == Some(marker))
+                .map(|(id, _)| id)
+        };
+        if let Some(root) = marked("root") {
+            return Some(ThreadInfo {
+                root,
+                reply: marked("reply").unwrap_or(root),
+            });
+        }
+        let (root, _) = events().next()?;
+        let (reply, _) = events().next_back()?;
+        Some(ThreadInfo { root, reply })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{text_note, OTHER_PRIVKEY};
+    const ID_A: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+    const ID_B: &str = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+
+    #[test]
+    fn test_reply_in_thread() {
+        let root = text_note();
+        let parent = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_reply(&root, None)
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        assert_eq!(parent.get_tag("e").unwrap()[0][2], "root");
+        assert_eq!(parent.get_tag("p").unwrap().len(), 1);
+
+        let reply = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .create_reply(&parent, Some(&root))
+            .unwrap()
+            .add_tag(Tag::try_from(["t", "thread"]).unwrap())
+            .unwrap()
+            .build(1686880022, [0; 32])
+            .unwrap();
+        let e_tags = reply.get_tag("e").unwrap();
+        assert_eq!(e_tags[0][2], "root");
+        assert_eq!(e_tags[1][2], "reply");
+        // both authors are mentioned
+        assert_eq!(reply.get_tag("p").unwrap().len(), 2);
+        assert!(reply.verify().is_ok());
+        assert_eq!(
+            reply.thread_info(),
+            Some(ThreadInfo {
+                root: *root.id_bytes(),
+                reply: *parent.id_bytes(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_positional_tags() {
+        let note = Note::new_builder(OTHER_PRIVKEY)
+            .unwrap()
+            .add_tag(Tag::try_from(["e", ID_A]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["e", "not an id"]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["e", ID_B]).unwrap())
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap();
+        let thread = note.thread_info().unwrap();
+        assert_eq!(thread.root, from_hex::<32>(ID_A.as_bytes()).unwrap());
+        assert_eq!(thread.reply, from_hex::<32>(ID_B.as_bytes()).unwrap());
+        assert_eq!(text_note().thread_info(), None);
+    }
+}
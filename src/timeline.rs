@@ -0,0 +1,143 @@
+//! Fixed-capacity timeline of the latest notes
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, timeline::Timeline};
+//! let builder = || Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3").unwrap();
+//! let mut timeline: Timeline<2> = Timeline::new();
+//! timeline.insert(builder().content("first".into()).build(1, [0; 32]).unwrap());
+//! timeline.insert(builder().content("second".into()).build(2, [0; 32]).unwrap());
+//! timeline.insert(builder().content("third".into()).build(3, [0; 32]).unwrap());
+//! let newest = timeline.iter().next().unwrap();
+//! assert_eq!(newest.content(), Some("third"));
+//! assert_eq!(timeline.len(), 2);
+//! ```
+
+use heapless::Vec;
+
+use crate::Note;
+
+/// Result of inserting a note into a [`Timeline`]
+#[derive(Debug, PartialEq)]
+pub enum TimelineInsert {
+    /// The note was added
+    Inserted,
+    /// The note was added and the oldest note was evicted to make room
+    Evicted,
+    /// A note with the same id is already in the timeline
+    Duplicate,
+    /// The timeline is full and the note is older than everything in it
+    TooOld,
+}
+
+/// Holds up to `N` notes ordered newest first, ties on `created_at` ordered by id
+pub struct Timeline<const N: usize> {
+    notes: Vec<Note, N>,
+}
+
+/// True if `a` is shown before `b`
+fn is_newer(a: &Note, b: &Note) -> bool {
+    a.created_at > b.created_at || (a.created_at == b.created_at && a.id < b.id)
+}
+
+impl<const N: usize> Default for Timeline<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Timeline<N> {
+    /// Creates an empty timeline
+    pub const fn new() -> Self {
+        Timeline { notes: Vec::new() }
+    }
+
+    /// Number of notes held
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    /// True if no notes are held
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Iterates from newest to oldest
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter()
+    }
+
+    /// The newest note
+    pub fn newest(&self) -> Option<&Note> {
+        self.notes.first()
+    }
+
+    /// The oldest note
+    pub fn oldest(&self) -> Option<&Note> {
+        self.notes.last()
+    }
+
+    /// True if a note with `id` is held
+    pub fn contains(&self, id: &[u8; 64]) -> bool {
+        self.notes.iter().any(|note| note.id() == *id)
+    }
+
+    /// Inserts `note` in order, evicting the oldest note if the timeline is full
+    pub fn insert(&mut self, note: Note) -> TimelineInsert {
+        if self.contains(&note.id()) {
+            return TimelineInsert::Duplicate;
+        }
+        let pos = self
+            .notes
+            .iter()
+            .position(|held| is_newer(&note, held))
+            .unwrap_or(self.notes.len());
+        if pos == N {
+            return TimelineInsert::TooOld;
+        }
+        let evicted = self.notes.is_full();
+        if evicted {
+            self.notes.pop();
+        }
+        // cannot fail, there is room for at least one more note
+        let _ = self.notes.insert(pos, note);
+        if evicted {
+            TimelineInsert::Evicted
+        } else {
+            TimelineInsert::Inserted
+        }
+    }
+
+    /// Removes every note
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::note_at;
+
+    #[test]
+    fn test_sorted_and_deduplicated() {
+        let mut timeline: Timeline<3> = Timeline::new();
+        assert_eq!(timeline.insert(note_at(2)), TimelineInsert::Inserted);
+        assert_eq!(timeline.insert(note_at(3)), TimelineInsert::Inserted);
+        assert_eq!(timeline.insert(note_at(1)), TimelineInsert::Inserted);
+        assert_eq!(timeline.insert(note_at(2)), TimelineInsert::Duplicate);
+        let order: Vec<u64, 3> = timeline.iter().map(|n| n.created_at).collect();
+        assert_eq!(order, [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_evicts_oldest() {
+        let mut timeline: Timeline<2> = Timeline::new();
+        timeline.insert(note_at(2));
+        timeline.insert(note_at(3));
+        assert_eq!(timeline.insert(note_at(1)), TimelineInsert::TooOld);
+        assert_eq!(timeline.insert(note_at(4)), TimelineInsert::Evicted);
+        assert_eq!(timeline.oldest().unwrap().created_at, 3);
+        assert_eq!(timeline.newest().unwrap().created_at, 4);
+    }
+}
@@ -0,0 +1,222 @@
+//! Notes with their id computed but not yet signed
+//!
+//! [`NoteBuilder::build_unsigned`] fixes `created_at` and computes the id, but leaves the signature
+//! for later. The [`UnsignedNote`] can then be signed with [`UnsignedNote::sign`], or the id can be
+//! sent elsewhere, such as a NIP-46 remote signer or another core, and the returned signature
+//! attached with [`UnsignedNote::add_signature`].
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{keys::{AlignedType, Keys, CONTEXT_SIZE}, Note};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let unsigned = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build_unsigned(1686880020)
+//!     .unwrap();
+//! // later, possibly somewhere else
+//! let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+//! let keys = Keys::new(privkey, &mut context).unwrap();
+//! let note = unsigned.sign(&keys, [0; 32]).unwrap();
+//! assert!(note.validate_signature().is_ok());
+//! ```
+
+use crate::{
+    buffer::{OutBuf, SliceBuf},
+    curve::{Curve, CurveBackend},
+    errors::Error,
+    keys::Keys,
+    tag::Tag,
+    tag::{MAX_TAG_VALUES, TAG_VALUE_SIZE},
+    Note, NoteBuilder, NoteKinds, MAX_TAGS, NOTE_SIZE,
+};
+
+/// A note with every field but the signature, created by [`NoteBuilder::build_unsigned`]
+#[derive(Debug, PartialEq)]
+pub struct UnsignedNote<
+    const CONTENT: usize = NOTE_SIZE,
+    const TAG: usize = TAG_VALUE_SIZE,
+    const NTAGS: usize = MAX_TAGS,
+    const VALUES: usize = MAX_TAG_VALUES,
+> {
+    /// Signature is left zeroed
+    note: Note<CONTENT, TAG, NTAGS, VALUES>,
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Set the 'created_at' and compute the id, without signing
+    #[inline]
+    pub fn build_unsigned(
+        mut self,
+        created_at: u64,
+    ) -> Result<UnsignedNote<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.check_content()?;
+        self.set_created_at(created_at)?;
+        self.note.set_id()?;
+        Ok(UnsignedNote { note: self.note })
+    }
+
+    /// Same as [`NoteBuilder::build_unsigned`]. The note is now hashed as it is serialized, so
+    /// `scratch` is no longer used.
+    #[deprecated(note = "hashing no longer needs a scratch buffer, use `build_unsigned`")]
+    #[inline]
+    pub fn build_unsigned_in(
+        self,
+        created_at: u64,
+        _scratch: &mut [u8],
+    ) -> Result<UnsignedNote<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        self.build_unsigned(created_at)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    UnsignedNote<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Hex encoded id of the note
+    pub fn id(&self) -> [u8; 64] {
+        self.note.id()
+    }
+
+    /// The id as the 32 byte digest to be signed
+    pub fn digest(&self) -> Result<[u8; 32], Error> {
+        Ok(self.note.id)
+    }
+
+    /// Hex encoded pubkey of the note's author
+    pub fn pubkey(&self) -> [u8; 64] {
+        self.note.pubkey()
+    }
+
+    /// Unix timestamp the note was created at
+    pub fn created_at(&self) -> u64 {
+        self.note.created_at()
+    }
+
+    /// Kind of the note
+    pub fn kind(&self) -> NoteKinds {
+        self.note.kind()
+    }
+
+    /// Tags of the note
+    pub fn tags(&self) -> &[Tag<TAG, VALUES>] {
+        self.note.tags()
+    }
+
+    /// Content of the note, if any
+    pub fn content(&self) -> Option<&str> {
+        self.note.content()
+    }
+
+    /// Writes the note as a JSON object without `sig` into `buf`, returning the number of bytes
+    /// written.
+    /// Errors with `ContentOverflow` if `buf` is too small.
+    pub fn json_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut out = SliceBuf::new(buf);
+        self.note.write_json_fields(&mut out, false)?;
+        Ok(out.len())
+    }
+
+    /// Parses a note written by [`UnsignedNote::json_into`] and checks its id.
+    /// Errors with `EventNotValid` if the id doesn't match.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let note = Note::from_unsigned_json(json)?;
+        Ok(UnsignedNote { note })
+    }
+
+    /// Signs the note.
+    /// Errors with `InvalidPrivkey` if `keys` doesn't belong to the note's pubkey.
+    pub fn sign(
+        mut self,
+        keys: &Keys,
+        aux_rnd: [u8; 32],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        if keys.pubkey() != self.note.pubkey() {
+            return Err(Error::InvalidPrivkey);
+        }
+        keys.sign_note(&mut self.note, &aux_rnd)?;
+        Ok(self.note)
+    }
+
+    /// Attaches a BIP-340 signature of [`UnsignedNote::digest`] made elsewhere.
+    /// Errors with `InvalidSignature` if it isn't a valid signature by the note's pubkey.
+    pub fn add_signature(
+        mut self,
+        sig: &[u8; 64],
+    ) -> Result<Note<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        let pubkey = Curve::parse_pubkey(&self.note.pubkey)?;
+        self.note.sig = *sig;
+        self.note.verify_with(&pubkey)?;
+        Ok(self.note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{AlignedType, CONTEXT_SIZE};
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
+
+    fn unsigned() -> UnsignedNote {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build_unsigned(1686880020)
+            .unwrap()
+    }
+
+    fn expected() -> Note {
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        let pending = unsigned();
+        assert_eq!(pending.id(), expected().id());
+        assert_eq!(pending.content(), Some("esptest"));
+
+        let mut context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let keys = Keys::new(PRIVKEY, &mut context).unwrap();
+        assert_eq!(pending.sign(&keys, [0; 32]), Ok(expected()));
+
+        let mut other_context = [AlignedType::zeroed(); CONTEXT_SIZE];
+        let other = Keys::new(OTHER_PRIVKEY, &mut other_context).unwrap();
+        assert_eq!(unsigned().sign(&other, [0; 32]), Err(Error::InvalidPrivkey));
+    }
+
+    #[test]
+    fn test_add_signature() {
+        let expected = expected();
+        let mut sig = [0_u8; 64];
+        base16ct::lower::decode(expected.sig(), &mut sig).unwrap();
+        assert_eq!(unsigned().add_signature(&sig), Ok(expected));
+
+        sig[0] ^= 1;
+        assert_eq!(unsigned().add_signature(&sig), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut buf = [0_u8; 300];
+        let len = unsigned().json_into(&mut buf).unwrap();
+        let json = core::str::from_utf8(&buf[..len]).unwrap();
+        assert!(!json.contains(r#""sig""#));
+
+        assert_eq!(UnsignedNote::from_json(json), Ok(unsigned()));
+        // a rumor with a wrong id is rejected
+        let mut tampered = [0_u8; 300];
+        tampered[..len].copy_from_slice(&buf[..len]);
+        let content = json.find("esptest").unwrap();
+        tampered[content] = b'E';
+        let tampered = core::str::from_utf8(&tampered[..len]).unwrap();
+        assert_eq!(
+            UnsignedNote::<400>::from_json(tampered),
+            Err(Error::EventNotValid)
+        );
+    }
+}
@@ -1,29 +1,208 @@
 use heapless::String;
 
-const DEC_STRING_SIZE: usize = 10;
+use crate::{buffer::OutBuf, errors::Error};
 
-/// Panics if number is larger than 7 digits, ie > 9,999,999
-pub fn to_decimal_str(num: u32) -> String<DEC_STRING_SIZE> {
-    if num == 0 {
-        return String::from("0");
-    }
-    let mut serialized: String<DEC_STRING_SIZE> = String::new();
+/// Digits in `u64::MAX`
+pub const DEC_STRING_SIZE: usize = 20;
+
+/// Formats `num` in decimal
+pub fn to_decimal_str(num: u64) -> String<DEC_STRING_SIZE> {
+    // fill from the end so the digits come out in order
+    let mut digits = [0_u8; DEC_STRING_SIZE];
+    let mut start = DEC_STRING_SIZE;
     let mut n = num;
-    while n > 0 {
-        let last_dec = n % 10;
-        serialized
-            .push(char::from_digit(last_dec.into(), 10).expect("impossible to fail here"))
-            .expect("impossible to fail here");
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
         n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+
+    let mut output = String::new();
+    digits[start..].iter().for_each(|digit| {
+        // can't overflow, the buffer holds the longest u64
+        let _ = output.push(*digit as char);
+    });
+    output
+}
+
+/// Writes `bytes` as lowercase hex
+pub fn write_hex<B: OutBuf>(out: &mut B, bytes: &[u8]) -> Result<(), Error> {
+    let mut hex = [0_u8; 64];
+    for chunk in bytes.chunks(32) {
+        let hex = base16ct::lower::encode(chunk, &mut hex).map_err(|_| Error::EncodeError)?;
+        out.push_slice(hex)?;
+    }
+    Ok(())
+}
+
+/// Lowercase hex of `bytes`, `M` must be twice `N`
+pub fn to_hex<const N: usize, const M: usize>(bytes: &[u8; N]) -> [u8; M] {
+    const { assert!(M == 2 * N, "hex is twice as long as its bytes") };
+    let mut hex = [0_u8; M];
+    // can't fail, the assert above sizes the output
+    let _ = base16ct::lower::encode(bytes, &mut hex);
+    hex
+}
+
+/// Decodes exactly `N` bytes of lowercase hex.
+/// Errors with `MalformedContent` if `hex` isn't `2 * N` hex characters.
+pub fn from_hex<const N: usize>(hex: &[u8]) -> Result<[u8; N], Error> {
+    let mut bytes = [0_u8; N];
+    if hex.len() != 2 * N {
+        return Err(Error::MalformedContent);
+    }
+    base16ct::lower::decode(hex, &mut bytes).map_err(|_| Error::MalformedContent)?;
+    Ok(bytes)
+}
+
+/// CRC-32 (IEEE 802.3) of `data`, computed bitwise to avoid a lookup table
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    values.fold(1, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x1ff_ffff) << 5) ^ value as u32;
+        GENERATOR
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (top >> i) & 1 == 1)
+            .fold(chk, |chk, (_, gen)| chk ^ gen)
+    })
+}
+
+/// Bech32 (NIP-19) encoding of 32 bytes of `data` with the human readable part `hrp`
+pub fn to_bech32<const N: usize>(hrp: &str, data: &[u8; 32]) -> Result<String<N>, Error> {
+    // regroup the 256 bits into 52 five bit words, zero padding the last one
+    let mut words = [0_u8; 52];
+    words.iter_mut().enumerate().for_each(|(i, word)| {
+        let bit = i * 5;
+        let byte = bit / 8;
+        let pair = ((data[byte] as u16) << 8) | *data.get(byte + 1).unwrap_or(&0) as u16;
+        *word = ((pair >> (11 - bit % 8)) & 0x1f) as u8;
+    });
+
+    let hrp_expanded = hrp
+        .bytes()
+        .map(|b| b >> 5)
+        .chain(core::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f));
+    let checksum = bech32_polymod(
+        hrp_expanded
+            .chain(words.iter().copied())
+            .chain([0; 6].iter().copied()),
+    ) ^ 1;
+
+    let mut output = String::new();
+    output.push_str(hrp).map_err(|_| Error::EncodeError)?;
+    output.push('1').map_err(|_| Error::EncodeError)?;
+    let checksum_words = (0..6).map(|i| ((checksum >> (5 * (5 - i))) & 0x1f) as u8);
+    for word in words.iter().copied().chain(checksum_words) {
+        output
+            .push(BECH32_CHARSET[word as usize] as char)
+            .map_err(|_| Error::EncodeError)?;
     }
+    Ok(output)
+}
 
-    let mut output_str: String<DEC_STRING_SIZE> = String::new();
-    // there's probably a better way to do this...
-    while let Some(digit) = serialized.pop() {
-        output_str.push(digit).expect("impossible to fail here");
+/// Escapes `value` for use inside a JSON string per NIP-01, handing the output to `emit` in pieces
+pub fn escape_json(
+    value: &str,
+    mut emit: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let bytes = value.as_bytes();
+    // copy runs of plain bytes in one go
+    let mut start = 0;
+    for (i, b) in bytes.iter().enumerate() {
+        let escaped: &[u8] = match b {
+            b'\n' => br"\n",
+            b'"' => br#"\""#,
+            b'\\' => br"\\",
+            b'\r' => br"\r",
+            b'\t' => br"\t",
+            0x08 => br"\b",
+            0x0C => br"\f",
+            _ => continue,
+        };
+        emit(&bytes[start..i])?;
+        emit(escaped)?;
+        start = i + 1;
     }
+    emit(&bytes[start..])
+}
 
-    output_str
+/// Writes `value` escaped for use inside a JSON string
+pub fn write_escaped<B: OutBuf>(out: &mut B, value: &str) -> Result<(), Error> {
+    escape_json(value, |bytes| out.push_slice(bytes))
+}
+
+/// Reads the 4 hex digits of a `\u` escape
+fn hex4(chars: &mut core::str::Chars) -> Result<u32, Error> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or(Error::MalformedContent)?;
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+/// Decodes the escapes in the body of a JSON string.
+/// Errors with `ContentOverflow` if the result doesn't fit, and `MalformedContent` for invalid escapes.
+pub fn unescape_json<const N: usize>(value: &str) -> Result<String<N>, Error> {
+    let mut output = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next().ok_or(Error::MalformedContent)? {
+                'n' => '\n',
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'r' => '\r',
+                't' => '\t',
+                'b' => '\u{08}',
+                'f' => '\u{0C}',
+                'u' => {
+                    let mut code = hex4(&mut chars)?;
+                    // characters outside the basic plane come as a surrogate pair
+                    if (0xD800..0xDC00).contains(&code) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err(Error::MalformedContent);
+                        }
+                        let low = hex4(&mut chars)?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err(Error::MalformedContent);
+                        }
+                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                    char::from_u32(code).ok_or(Error::MalformedContent)?
+                }
+                _ => return Err(Error::MalformedContent),
+            }
+        } else {
+            c
+        };
+        output.push(c).map_err(|_| Error::ContentOverflow)?;
+    }
+    Ok(output)
 }
 
 #[cfg(test)]
@@ -35,5 +214,47 @@ mod tests {
         let num = 1234;
         let to_str = to_decimal_str(num);
         assert_eq!(to_str.as_str(), "1234");
+        assert_eq!(to_decimal_str(0).as_str(), "0");
+        assert_eq!(to_decimal_str(1686880020).as_str(), "1686880020");
+        assert_eq!(to_decimal_str(u64::MAX).as_str(), "18446744073709551615");
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_bech32() {
+        let mut pubkey = [0_u8; 32];
+        base16ct::lower::decode(
+            "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d",
+            &mut pubkey,
+        )
+        .unwrap();
+        let npub: String<63> = to_bech32("npub", &pubkey).unwrap();
+        assert_eq!(
+            npub,
+            "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6"
+        );
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let raw = "say \"hi\"\\\n\ttab\r\u{08}\u{0C} done";
+        let mut escaped: heapless::Vec<u8, 64> = heapless::Vec::new();
+        write_escaped(&mut escaped, raw).unwrap();
+        assert_eq!(escaped, br#"say \"hi\"\\\n\ttab\r\b\f done"#.as_slice());
+        let unescaped: String<64> = unescape_json(core::str::from_utf8(&escaped).unwrap()).unwrap();
+        assert_eq!(unescaped, raw);
+    }
+
+    #[test]
+    fn test_unescape_unicode() {
+        let unescaped: String<16> = unescape_json(r"\u00e9\/\ud83d\ude00").unwrap();
+        assert_eq!(unescaped, "é/😀");
+        assert_eq!(unescape_json::<16>(r"\x"), Err(Error::MalformedContent));
+        assert_eq!(unescape_json::<16>(r"\ud83d"), Err(Error::MalformedContent));
     }
 }
@@ -0,0 +1,112 @@
+//! Per-kind content rules
+//!
+//! Some kinds put structured data in `content`. These checks catch content that other clients
+//! would fail to read, either before signing with [`NoteBuilder::validate_content`] or after
+//! parsing with [`Note::validate_content`]. They are optional, nothing else in the crate runs them.
+//!
+//! | kind  | rule                                      |
+//! |-------|-------------------------------------------|
+//! | 0     | a JSON object                             |
+//! | 3     | empty, or a JSON object of relays         |
+//! | 4     | `<base64>?iv=<base64>`                    |
+//! | 10002 | empty                                     |
+//!
+//! Only the outer shape is checked, the JSON inside an object is not parsed.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{Note, NoteKinds, errors::{ContentRule, Error}};
+//! let builder = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .set_kind(NoteKinds::Metadata)
+//!     .content("my name".into());
+//! assert_eq!(
+//!     builder.validate_content(),
+//!     Err(Error::InvalidContent(ContentRule::MetadataNotObject))
+//! );
+//! ```
+
+use crate::{
+    errors::{ContentRule, Error},
+    Note, NoteBuilder, NoteKinds,
+};
+
+fn is_json_object(content: &str) -> bool {
+    let content = content.trim();
+    content.starts_with('{') && content.ends_with('}')
+}
+
+fn is_base64(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'=')
+}
+
+/// Checks `content` against the rules for `kind`.
+/// Kinds without rules always pass.
+pub fn validate_content(kind: NoteKinds, content: Option<&str>) -> Result<(), Error> {
+    let content = content.unwrap_or("");
+    let rule = match kind.as_u32() {
+        0 if !is_json_object(content) => ContentRule::MetadataNotObject,
+        3 if !content.is_empty() && !is_json_object(content) => ContentRule::ContactListNotObject,
+        4 => match content.split_once("?iv=") {
+            Some((text, iv)) if is_base64(text) && is_base64(iv) => return Ok(()),
+            _ => ContentRule::DmMissingIv,
+        },
+        10002 if !content.is_empty() => ContentRule::RelayListNotEmpty,
+        _ => return Ok(()),
+    };
+    Err(Error::InvalidContent(rule))
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Checks the note's content against the rules for its kind
+    pub fn validate_content(&self) -> Result<(), Error> {
+        validate_content(self.kind, self.content())
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Checks the content set so far against the rules for the kind set so far
+    pub fn validate_content(&self) -> Result<(), Error> {
+        self.note.validate_content()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let metadata = NoteKinds::Metadata;
+        assert!(validate_content(metadata, Some(r#" {"name":"esp32"} "#)).is_ok());
+        assert_eq!(
+            validate_content(metadata, None),
+            Err(Error::InvalidContent(ContentRule::MetadataNotObject))
+        );
+        assert!(validate_content(NoteKinds::ContactList, None).is_ok());
+        assert!(validate_content(NoteKinds::DM, Some("sZhES/uu+V1=?iv=eM0mGFqF==")).is_ok());
+        assert_eq!(
+            validate_content(NoteKinds::DM, Some("hello")),
+            Err(Error::InvalidContent(ContentRule::DmMissingIv))
+        );
+        assert_eq!(
+            validate_content(NoteKinds::RelayList, Some("wss://a.example")),
+            Err(Error::InvalidContent(ContentRule::RelayListNotEmpty))
+        );
+        assert!(validate_content(NoteKinds::ShortNote, Some("anything")).is_ok());
+    }
+
+    #[test]
+    fn test_parsed_dm() {
+        let json = r#"{"content":"lPQ9iBd6abUrDBJbHWaL3qqhqsuAxK0aU80IgsZ2aqE=?iv=O1zZfD9HPiig1yuZEWX7uQ==","created_at":1691117390,"id":"c0be8c32d95f7599ccfe324711ad50890ee08985710997fcda1a1a3840a23d51","kind":4,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"8ee1e83ab037c9e9ff1ac97db88aa045b2f1d9204daa7fee25e5f42274ee8d5f4365b87677c4f27827ca043becc65c1f38f646d05adf3d2c570b66fea57e5918","tags":[["p","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"]]}"#;
+        let note = Note::try_from(json).unwrap();
+        assert!(note.validate_content().is_ok());
+    }
+}
@@ -0,0 +1,158 @@
+//! Known-good events, filters and relay messages for downstream test suites
+//!
+//! Only compiled with the `test-vectors` feature. Every event here is signed and verifies,
+//! and each has a constructor that parses it into the crate's own types so firmware tests
+//! can start from a valid value without carrying their own fixtures.
+//!
+//! The two keys used throughout are [`PRIVKEY`] (pubkey [`PUBKEY`]) and [`OTHER_PRIVKEY`]
+//! (pubkey [`OTHER_PUBKEY`]). They are published test keys and must never hold real funds or identities.
+//!
+//! The NIP-44 vectors are taken from the official NIP-44 test vectors.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "test-vectors")]
+//! # {
+//! use nostr_nostd::vectors;
+//! let note = vectors::text_note();
+//! assert_eq!(note.content(), Some(vectors::TEXT_NOTE_CONTENT));
+//! # }
+//! ```
+
+use crate::{query::Query, Note};
+
+/// Private key that signed [`TEXT_NOTE_JSON`] and [`DM_SENT_JSON`]
+pub const PRIVKEY: &str = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+/// Public key belonging to [`PRIVKEY`]
+pub const PUBKEY: &str = "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf";
+/// Private key that signed [`DM_RECEIVED_JSON`]
+pub const OTHER_PRIVKEY: &str = "aecb67d55da9b658cd419013d7026f30ee23c5c5b032948e84e8ae523b559f92";
+/// Public key belonging to [`OTHER_PRIVKEY`]
+pub const OTHER_PUBKEY: &str = "ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0";
+
+/// Content of [`TEXT_NOTE_JSON`]
+pub const TEXT_NOTE_CONTENT: &str = "esptest";
+/// Kind 1 note from [`PRIVKEY`], built with `created_at` 1686880020 and zeroed aux randomness
+pub const TEXT_NOTE_JSON: &str = r#"{"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}"#;
+
+/// Plaintext of both DM vectors
+pub const DM_PLAINTEXT: &str = "hello from the internet";
+/// NIP-04 DM from [`PRIVKEY`] to [`OTHER_PUBKEY`]
+pub const DM_SENT_JSON: &str = r#"{"content":"lPQ9iBd6abUrDBJbHWaL3qqhqsuAxK0aU80IgsZ2aqE=?iv=O1zZfD9HPiig1yuZEWX7uQ==","created_at":1691117390,"id":"c0be8c32d95f7599ccfe324711ad50890ee08985710997fcda1a1a3840a23d51","kind":4,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"8ee1e83ab037c9e9ff1ac97db88aa045b2f1d9204daa7fee25e5f42274ee8d5f4365b87677c4f27827ca043becc65c1f38f646d05adf3d2c570b66fea57e5918","tags":[["p","ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0"]]}"#;
+/// NIP-04 DM from [`OTHER_PRIVKEY`] to [`PUBKEY`]
+pub const DM_RECEIVED_JSON: &str = r#"{"content":"sZhES/uuV1uMmt9neb6OQw6mykdLYerAnTN+LodleSI=?iv=eM0mGFqFhxmmMwE4YPsQMQ==","created_at":1691110186,"id":"517a5f0f29f5037d763bbd5fbe96c9082c1d39eca917aa22b514c5effc36bab9","kind":4,"pubkey":"ed984a5438492bdc75860aad15a59f8e2f858792824d615401fb49d79c2087b0","sig":"3097de7d5070b892b81b245a5b276eccd7cb283a29a934a71af4960188e55e87d639b774cc331eb9f94ea7c46373c52b8ab39bfee75fe4bb11a1dd4c187e1f3e","tags":[["p","098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"]]}"#;
+
+/// First private key of the NIP-44 vector
+pub const NIP44_SEC1: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+/// Second private key of the NIP-44 vector
+pub const NIP44_SEC2: &str = "0000000000000000000000000000000000000000000000000000000000000002";
+/// Conversation key shared by [`NIP44_SEC1`] and [`NIP44_SEC2`]
+pub const NIP44_CONVERSATION_KEY: &str =
+    "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d";
+/// Nonce used to encrypt [`NIP44_PAYLOAD`]
+pub const NIP44_NONCE: [u8; 32] = {
+    let mut nonce = [0; 32];
+    nonce[31] = 1;
+    nonce
+};
+/// Plaintext of [`NIP44_PAYLOAD`]
+pub const NIP44_PLAINTEXT: &str = "a";
+/// [`NIP44_PLAINTEXT`] encrypted under [`NIP44_CONVERSATION_KEY`] with [`NIP44_NONCE`]
+pub const NIP44_PAYLOAD: &str = "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb";
+
+/// Subscription id used by [`DMS_REQ`]
+pub const DMS_SUBSCRIPTION_ID: &str = "my_dms";
+/// `REQ` produced by [`dms_query`] on [`DMS_SUBSCRIPTION_ID`]
+pub const DMS_REQ: &str = r##"["REQ","my_dms",{"#p":["098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf"],"kinds":[4]}]"##;
+
+/// Relay `EVENT` message carrying [`TEXT_NOTE_JSON`]
+pub const EVENT_MSG: &str = r#"["EVENT","sub_1", {"content":"esptest","created_at":1686880020,"id":"b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8","kind":1,"pubkey":"098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf","sig":"89a4f1ad4b65371e6c3167ea8cb13e73cf64dd5ee71224b1edd8c32ad817af2312202cadb2f22f35d599793e8b1c66b3979d4030f1e7a252098da4a4e0c48fab","tags":[]}]"#;
+/// Relay `OK` rejecting [`TEXT_NOTE_JSON`] as a duplicate
+pub const OK_MSG: &str = r#"["OK", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8", false, "duplicate event"]"#;
+/// Relay `EOSE`
+pub const EOSE_MSG: &str =
+    r#"["EOSE", "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8"]"#;
+/// Relay `NOTICE`
+pub const NOTICE_MSG: &str = r#"["NOTICE", "restricted: we can't serve DMs to unauthenticated users, does your client implement NIP-42?"]"#;
+/// Relay `AUTH` challenge
+pub const AUTH_MSG: &str = r#"["AUTH", "encrypt me"]"#;
+
+/// Parses a vector that is known to be valid
+#[allow(clippy::expect_used)]
+fn parse(json: &str) -> Note {
+    Note::try_from(json).expect("test vectors are valid")
+}
+
+/// [`TEXT_NOTE_JSON`] as a [`Note`]
+pub fn text_note() -> Note {
+    parse(TEXT_NOTE_JSON)
+}
+
+/// [`DM_SENT_JSON`] as a [`Note`]
+pub fn dm_sent() -> Note {
+    parse(DM_SENT_JSON)
+}
+
+/// [`DM_RECEIVED_JSON`] as a [`Note`]
+pub fn dm_received() -> Note {
+    parse(DM_RECEIVED_JSON)
+}
+
+/// Kind 1 note from [`PRIVKEY`] without content, for tests that need notes of different ages
+#[cfg(test)]
+pub(crate) fn note_at(created_at: u64) -> Note {
+    Note::new_builder(PRIVKEY)
+        .unwrap()
+        .build(created_at, [0; 32])
+        .unwrap()
+}
+
+/// Query for DMs sent to [`PUBKEY`], serializes to [`DMS_REQ`]
+#[allow(clippy::expect_used)]
+pub fn dms_query() -> Query {
+    let mut query = Query::new();
+    query.get_my_dms(PRIVKEY).expect("test vectors are valid");
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay_responses::ResponseTypes;
+
+    #[test]
+    fn test_notes_match_builder() {
+        let built = Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content(TEXT_NOTE_CONTENT.into())
+            .build(1686880020, [0; 32])
+            .unwrap();
+        assert_eq!(text_note(), built);
+        assert_eq!(dm_sent().read_dm(PRIVKEY).unwrap(), DM_PLAINTEXT);
+        assert!(dm_received().validate_signature().is_ok());
+    }
+
+    #[test]
+    fn test_nip44() {
+        let mut key = [0_u8; 32];
+        base16ct::lower::decode(NIP44_CONVERSATION_KEY, &mut key).unwrap();
+        assert_eq!(
+            crate::nip44::encrypt(&key, NIP44_PLAINTEXT, NIP44_NONCE).unwrap(),
+            NIP44_PAYLOAD
+        );
+    }
+
+    #[test]
+    fn test_query_and_messages() {
+        let req = dms_query().serialize_to_relay(DMS_SUBSCRIPTION_ID).unwrap();
+        assert_eq!(req, DMS_REQ.as_bytes());
+        assert_eq!(ResponseTypes::try_from(EVENT_MSG), Ok(ResponseTypes::Event));
+        assert_eq!(ResponseTypes::try_from(OK_MSG), Ok(ResponseTypes::Ok));
+        assert_eq!(ResponseTypes::try_from(EOSE_MSG), Ok(ResponseTypes::Eose));
+        assert_eq!(
+            ResponseTypes::try_from(NOTICE_MSG),
+            Ok(ResponseTypes::Notice)
+        );
+        assert_eq!(ResponseTypes::try_from(AUTH_MSG), Ok(ResponseTypes::Auth));
+    }
+}
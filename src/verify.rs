@@ -0,0 +1,342 @@
+//! Signature verification helpers
+//!
+//! Verifying a note needs its author's pubkey parsed into a curve point, which costs about as
+//! much as the signature check itself. Streams of notes usually come from a handful of authors,
+//! so [`PubkeyCache`] keeps the last few parsed pubkeys around to skip that work.
+//!
+//! [`Note::verify`] also sets up a new curve context for every note. A [`Verifier`] keeps a
+//! verification-only context, a quarter the size of the signing one, in a buffer the caller owns.
+//! A burst of notes, such as the stored events sent before `EOSE`, can be checked with
+//! [`verify_batch`], which shares one context and pubkey cache across the whole batch.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{
+//!     keys::AlignedType,
+//!     verify::{PubkeyCache, Verifier, VERIFY_CONTEXT_SIZE},
+//!     Note,
+//! };
+//! let note = Note::new_builder("a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3")
+//!     .unwrap()
+//!     .content("esptest".into())
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut cache: PubkeyCache<4> = PubkeyCache::new();
+//! assert!(note.validate_signature_cached(&mut cache).is_ok());
+//! assert_eq!(cache.len(), 1);
+//!
+//! let mut context = [AlignedType::zeroed(); VERIFY_CONTEXT_SIZE];
+//! let verifier = Verifier::new(&mut context).unwrap();
+//! assert!(verifier.verify(&note).is_ok());
+//! assert!(verifier.verify_cached(&note, &mut cache).is_ok());
+//! ```
+
+use heapless::Vec;
+
+use crate::{
+    curve::{self, AlignedType, Curve, CurveBackend, PublicKey, VerifyContext},
+    errors::Error,
+    Note,
+};
+
+/// Number of [`AlignedType`] the context buffer given to [`Verifier::new`] should hold
+pub const VERIFY_CONTEXT_SIZE: usize = curve::VERIFY_CONTEXT_ITEMS;
+
+/// Most distinct authors [`verify_batch`] keeps parsed at once
+pub const BATCH_PUBKEYS: usize = 8;
+
+/// Parsed pubkeys of the `N` most recently seen authors
+pub struct PubkeyCache<const N: usize> {
+    entries: Vec<([u8; 32], PublicKey), N>,
+    // slot replaced on the next miss once the cache is full
+    next: usize,
+}
+
+impl<const N: usize> Default for PubkeyCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PubkeyCache<N> {
+    /// Creates an empty cache
+    pub const fn new() -> Self {
+        PubkeyCache {
+            entries: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Number of cached pubkeys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if nothing has been cached yet
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the parsed form of the x-only `pubkey`, parsing and caching it if needed.
+    /// Once full, the oldest entry is replaced.
+    pub(crate) fn get(&mut self, pubkey: &[u8; 32]) -> Result<PublicKey, Error> {
+        if let Some((_, parsed)) = self.entries.iter().find(|(raw, _)| raw == pubkey) {
+            return Ok(*parsed);
+        }
+        let parsed = Curve::parse_pubkey(pubkey)?;
+        if N == 0 {
+            return Ok(parsed);
+        }
+        if self.entries.push((*pubkey, parsed)).is_err() {
+            self.entries[self.next] = (*pubkey, parsed);
+            self.next = (self.next + 1) % N;
+        }
+        Ok(parsed)
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    Note<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Validates the event's signature, looking the author's pubkey up in `cache`
+    pub fn validate_signature_cached<const N: usize>(
+        &self,
+        cache: &mut PubkeyCache<N>,
+    ) -> Result<(), Error> {
+        let pubkey = cache.get(&self.pubkey)?;
+        self.verify_with(&pubkey)
+    }
+
+    /// Checks the signature against an already parsed pubkey
+    pub(crate) fn verify_with(&self, pubkey: &PublicKey) -> Result<(), Error> {
+        curve::verify(pubkey, &self.id, &self.sig)
+    }
+
+    /// Checks the signature against an already parsed pubkey, using an existing context
+    pub(crate) fn verify_in(
+        &self,
+        ctx: &VerifyContext<'_>,
+        pubkey: &PublicKey,
+    ) -> Result<(), Error> {
+        Curve::verify(ctx, pubkey, &self.id, &self.sig)
+    }
+}
+
+/// A verification-only context, set up once and reused for every note
+pub struct Verifier<'buf> {
+    ctx: VerifyContext<'buf>,
+}
+
+impl<'buf> Verifier<'buf> {
+    /// Sets up a context in `buf`, which should hold [`VERIFY_CONTEXT_SIZE`] items.
+    /// Errors with `Secp256k1Error` if `buf` is too small.
+    pub fn new(buf: &'buf mut [AlignedType]) -> Result<Self, Error> {
+        Ok(Verifier {
+            ctx: Curve::verify_context(buf)?,
+        })
+    }
+
+    /// Checks both the id and the signature, as [`Note::verify`]
+    pub fn verify<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> Result<(), Error> {
+        self.verify_id(note)?;
+        self.verify_signature(note)
+    }
+
+    /// Checks the id is the hash of the note's fields, as [`Note::verify_id`]
+    pub fn verify_id<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> Result<(), Error> {
+        note.verify_id()
+    }
+
+    /// Checks the signature, as [`Note::validate_signature`]
+    pub fn verify_signature<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+    ) -> Result<(), Error> {
+        note.verify_in(&self.ctx, &Curve::parse_pubkey(&note.pubkey)?)
+    }
+
+    /// Same as [`Verifier::verify`], looking the author's pubkey up in `cache`
+    pub fn verify_cached<
+        const CONTENT: usize,
+        const TAG: usize,
+        const NTAGS: usize,
+        const VALUES: usize,
+        const N: usize,
+    >(
+        &self,
+        note: &Note<CONTENT, TAG, NTAGS, VALUES>,
+        cache: &mut PubkeyCache<N>,
+    ) -> Result<(), Error> {
+        self.verify_id(note)?;
+        note.verify_in(&self.ctx, &cache.get(&note.pubkey)?)
+    }
+}
+
+/// Checks the id and signature of every note, as [`Note::verify`], writing the result for
+/// `notes[i]` to `results[i]` and returning how many notes passed.
+/// One context is shared by the whole batch, and the pubkeys of up to [`BATCH_PUBKEYS`] authors
+/// are parsed only once. Each signature is still checked on its own, neither curve backend has
+/// batch verification.
+/// Errors with `ContentOverflow`, before checking anything, if `results` is shorter than `notes`.
+pub fn verify_batch<
+    const CONTENT: usize,
+    const TAG: usize,
+    const NTAGS: usize,
+    const VALUES: usize,
+>(
+    notes: &[Note<CONTENT, TAG, NTAGS, VALUES>],
+    results: &mut [Result<(), Error>],
+) -> Result<usize, Error> {
+    if results.len() < notes.len() {
+        return Err(Error::ContentOverflow);
+    }
+    let mut buf = [AlignedType::zeroed(); VERIFY_CONTEXT_SIZE];
+    let verifier = Verifier::new(&mut buf)?;
+    let mut cache: PubkeyCache<BATCH_PUBKEYS> = PubkeyCache::new();
+    let mut passed = 0;
+    for (note, result) in notes.iter().zip(results.iter_mut()) {
+        *result = verifier.verify_cached(note, &mut cache);
+        if result.is_ok() {
+            passed += 1;
+        }
+    }
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
+
+    fn note_from(privkey: &str) -> Note {
+        Note::new_builder(privkey)
+            .unwrap()
+            .content("esptest".into())
+            .build(1686880020, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg(not(feature = "k256"))]
+    fn test_context_size() {
+        assert!(
+            VERIFY_CONTEXT_SIZE * core::mem::size_of::<AlignedType>()
+                >= secp256k1::Secp256k1::preallocate_verification_size()
+        );
+    }
+
+    #[test]
+    fn test_verifier() {
+        let mut context = [AlignedType::zeroed(); VERIFY_CONTEXT_SIZE];
+        let verifier = Verifier::new(&mut context).unwrap();
+        let note = note_from(PRIVKEY);
+        assert_eq!(verifier.verify(&note), Ok(()));
+
+        let mut changed = note.clone();
+        changed.created_at += 1;
+        assert_eq!(verifier.verify_id(&changed), Err(Error::EventNotValid));
+        assert_eq!(verifier.verify_signature(&changed), Ok(()));
+        assert_eq!(verifier.verify(&changed), Err(Error::EventNotValid));
+
+        let mut forged = note.clone();
+        forged.sig[0] ^= 1;
+        assert_eq!(verifier.verify_id(&forged), Ok(()));
+        assert_eq!(verifier.verify(&forged), Err(Error::InvalidSignature));
+        let mut cache: PubkeyCache<1> = PubkeyCache::new();
+        assert_eq!(
+            verifier.verify_cached(&forged, &mut cache),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(verifier.verify_cached(&note, &mut cache), Ok(()));
+
+        #[cfg(not(feature = "k256"))]
+        {
+            let mut small = [AlignedType::zeroed(); 1];
+            assert!(matches!(
+                Verifier::new(&mut small),
+                Err(Error::Secp256k1Error)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut forged = note_from(PRIVKEY);
+        forged.sig[0] ^= 1;
+        let mut changed = note_from(OTHER_PRIVKEY);
+        changed.created_at += 1;
+        let notes = [
+            note_from(PRIVKEY),
+            forged,
+            note_from(OTHER_PRIVKEY),
+            changed,
+            note_from(PRIVKEY),
+        ];
+        let mut results: [Result<(), Error>; 5] = core::array::from_fn(|_| Ok(()));
+        assert_eq!(verify_batch(&notes, &mut results), Ok(3));
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(Error::InvalidSignature),
+                Ok(()),
+                Err(Error::EventNotValid),
+                Ok(())
+            ]
+        );
+        assert_eq!(
+            verify_batch(&notes, &mut results[..4]),
+            Err(Error::ContentOverflow)
+        );
+        assert_eq!(verify_batch::<400, 100, 5, 4>(&[], &mut []), Ok(0));
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut cache: PubkeyCache<1> = PubkeyCache::new();
+        let mine = note_from(PRIVKEY);
+        let theirs = note_from(OTHER_PRIVKEY);
+        assert!(mine.validate_signature_cached(&mut cache).is_ok());
+        assert!(mine.validate_signature_cached(&mut cache).is_ok());
+        assert!(theirs.validate_signature_cached(&mut cache).is_ok());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.entries[0].0, theirs.pubkey);
+    }
+
+    #[test]
+    fn test_cached_rejects_bad_sig() {
+        let mut cache: PubkeyCache<2> = PubkeyCache::new();
+        let mut note = note_from(PRIVKEY);
+        note.sig[0] ^= 1;
+        assert_eq!(
+            note.validate_signature_cached(&mut cache),
+            Err(Error::InvalidSignature)
+        );
+        note.pubkey = [0xff; 32];
+        assert_eq!(
+            note.validate_signature_cached(&mut cache),
+            Err(Error::InvalidPubkey)
+        );
+    }
+}
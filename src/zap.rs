@@ -0,0 +1,307 @@
+//! Lightning zaps ([NIP-57](https://github.com/nostr-protocol/nips/blob/master/57.md))
+//!
+//! A zap request, kind 9734, is signed by the sender and handed to the recipient's lnurl
+//! server instead of being published. Once the invoice is paid the server publishes a zap
+//! receipt, kind 9735, which carries the request as JSON in its `description` tag.
+//!
+//! The description alone is usually longer than a default tag value, parse receipts into a
+//! note with a larger `TAG`.
+//!
+//! # Example
+//! ```
+//! use nostr_nostd::{zap::ZapRequest, Note};
+//! let privkey = "a5084b35a58e3e1a26f5efb46cb9dbada73191526aa6d11bccb590cbeb2d8fa3";
+//! let request = ZapRequest {
+//!     recipient: "098ef66bce60dd4cf10b4ae5949d1ec6dd777ddeb4bc49b47f97275a127a63cf",
+//!     event: None,
+//!     amount_msats: 21_000,
+//!     lnurl: "lnurl1dp68gurn8ghj7um9",
+//!     relays: &["wss://relay.damus.io"],
+//! };
+//! let note = Note::new_builder(privkey)
+//!     .unwrap()
+//!     .create_zap_request(&request)
+//!     .unwrap()
+//!     .build(1686880020, [0; 32])
+//!     .unwrap();
+//! let mut json = [0_u8; 1000];
+//! let len = note.json_into(&mut json).unwrap();
+//! // send json[..len] to the lnurl callback as the `nostr` parameter
+//! ```
+
+use crate::{
+    errors::{Error, Field},
+    note_ref::NoteRef,
+    tag::Tag,
+    utils::{from_hex, to_decimal_str},
+    Note, NoteBuilder, NoteKinds,
+};
+
+/// Kind of a zap request
+pub const ZAP_REQUEST_KIND: u32 = 9734;
+/// Kind of a zap receipt
+pub const ZAP_RECEIPT_KIND: u32 = 9735;
+
+/// What a zap request asks for, see [`NoteBuilder::create_zap_request`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZapRequest<'a> {
+    /// Hex pubkey of the user being zapped
+    pub recipient: &'a str,
+    /// Hex id of the note being zapped, if any
+    pub event: Option<&'a str>,
+    pub amount_msats: u64,
+    /// Bech32 lnurl of the recipient
+    pub lnurl: &'a str,
+    /// Relays the receipt should be published to, at most [`MAX_TAG_VALUES`](crate::tag::MAX_TAG_VALUES)
+    pub relays: &'a [&'a str],
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    NoteBuilder<CONTENT, TAG, NTAGS, VALUES>
+{
+    /// Turns the note into a kind 9734 zap request with `relays`, `amount`, `lnurl`, `p` and
+    /// `e` tags. Any content is sent along as the zap's message.
+    /// Replaces any tags added before.
+    /// Errors with `InvalidPubkey` or `EventNotValid` if the recipient or event isn't hex and
+    /// with `ContentOverflow` if there are too many relays or a value is longer than `TAG`.
+    pub fn create_zap_request(
+        mut self,
+        request: &ZapRequest,
+    ) -> Result<NoteBuilder<CONTENT, TAG, NTAGS, VALUES>, Error> {
+        const { assert!(5 <= NTAGS, "a zap request needs up to five tags") };
+        from_hex::<32>(request.recipient.as_bytes()).map_err(|_| Error::InvalidPubkey)?;
+        let mut relays = Tag::new("relays")?;
+        for relay in request.relays {
+            relays.push_value(relay)?;
+        }
+        let amount = to_decimal_str(request.amount_msats);
+        self.note.tags.clear();
+        // the assert above leaves room for all of them
+        let _ = self.note.tags.push(relays);
+        let _ = self.note.tags.push(Tag::try_from(["amount", &amount])?);
+        let _ = self
+            .note
+            .tags
+            .push(Tag::try_from(["lnurl", request.lnurl])?);
+        let _ = self
+            .note
+            .tags
+            .push(Tag::try_from(["p", request.recipient])?);
+        if let Some(event) = request.event {
+            from_hex::<32>(event.as_bytes()).map_err(|_| Error::EventNotValid)?;
+            let _ = self.note.tags.push(Tag::try_from(["e", event])?);
+        }
+        self.note.kind = NoteKinds::ZapRequest;
+        Ok(self)
+    }
+}
+
+/// Amount of a BOLT11 invoice in millisats, from the human readable part in front of the
+/// last `1`, such as `lnbc2500u`. `None` for invoices without an amount.
+/// Errors with `MalformedContent` if the amount can't be read.
+pub fn bolt11_amount_msats(invoice: &str) -> Result<Option<u64>, Error> {
+    let hrp = invoice
+        .rfind('1')
+        .and_then(|end| invoice.get(..end))
+        .and_then(|hrp| hrp.strip_prefix("ln"))
+        .ok_or(Error::MalformedContent)?;
+    // the currency, such as bc or tbs, runs up to the amount
+    let amount = hrp.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        return Ok(None);
+    }
+    let (digits, multiplier) = match amount.as_bytes().last() {
+        Some(c) if c.is_ascii_digit() => (amount, None),
+        Some(c) => (&amount[..amount.len() - 1], Some(*c)),
+        None => return Ok(None),
+    };
+    let value: u64 = digits.parse().map_err(|_| Error::MalformedContent)?;
+    let msats = match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some(b'm') => value.checked_mul(100_000_000),
+        Some(b'u') => value.checked_mul(100_000),
+        Some(b'n') => value.checked_mul(100),
+        // a tenth of a millisat, only whole millisats are valid
+        Some(b'p') if value.is_multiple_of(10) => Some(value / 10),
+        _ => None,
+    };
+    msats.map(Some).ok_or(Error::MalformedContent)
+}
+
+/// What a zap receipt says was paid, parsed from a kind 9735 note
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZapReceipt {
+    /// X-only pubkey of whoever signed the zap request
+    pub sender: [u8; 32],
+    /// X-only pubkey of the user zapped
+    pub recipient: [u8; 32],
+    /// Id of the note zapped, if any
+    pub event: Option<[u8; 32]>,
+    /// Amount of the paid invoice, `None` if it has no amount
+    pub amount_msats: Option<u64>,
+    /// Amount the zap request asked for, if it said
+    pub requested_msats: Option<u64>,
+}
+
+impl ZapReceipt {
+    /// True if the invoice paid the amount the sender asked for, or the request didn't say
+    pub fn amount_matches(&self) -> bool {
+        self.requested_msats.is_none() || self.requested_msats == self.amount_msats
+    }
+}
+
+impl<const CONTENT: usize, const TAG: usize, const NTAGS: usize, const VALUES: usize>
+    TryFrom<&Note<CONTENT, TAG, NTAGS, VALUES>> for ZapReceipt
+{
+    type Error = Error;
+
+    /// Reads the `bolt11` and `description` tags of a kind 9735 note.
+    /// Doesn't check the signature of the embedded zap request, or that the receipt comes
+    /// from the recipient's lnurl server.
+    /// Errors with `InvalidType` for other kinds, `EventMissingField` if a tag is missing and
+    /// `MalformedContent` if the invoice or description can't be read.
+    fn try_from(note: &Note<CONTENT, TAG, NTAGS, VALUES>) -> Result<Self, Self::Error> {
+        if note.kind.as_u32() != ZAP_RECEIPT_KIND {
+            return Err(Error::InvalidType);
+        }
+        let value = |name: &str| {
+            note.tags
+                .iter()
+                .find(|tag| tag.name() == name)
+                .and_then(|tag| tag.value(0))
+        };
+        let invoice = value("bolt11").ok_or(Error::EventMissingField(Field::Tag))?;
+        let request =
+            NoteRef::try_from(value("description").ok_or(Error::EventMissingField(Field::Tag))?)
+                .map_err(|_| Error::MalformedContent)?;
+        if request.kind().as_u32() != ZAP_REQUEST_KIND {
+            return Err(Error::MalformedContent);
+        }
+        let request_value = |name: &str| request.find_tag(name).and_then(|tag| tag.value(0));
+
+        let sender = from_hex(request.pubkey().as_bytes()).map_err(|_| Error::InvalidPubkey)?;
+        let recipient = value("p")
+            .or_else(|| request_value("p"))
+            .ok_or(Error::EventMissingField(Field::Tag))?;
+        let recipient = from_hex(recipient.as_bytes()).map_err(|_| Error::InvalidPubkey)?;
+        let event = value("e")
+            .or_else(|| request_value("e"))
+            .map(|event| from_hex(event.as_bytes()).map_err(|_| Error::EventNotValid))
+            .transpose()?;
+        let requested_msats = request_value("amount")
+            .map(|amount| amount.parse().map_err(|_| Error::MalformedContent))
+            .transpose()?;
+        Ok(ZapReceipt {
+            sender,
+            recipient,
+            event,
+            amount_msats: bolt11_amount_msats(invoice)?,
+            requested_msats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::{OTHER_PRIVKEY, PRIVKEY};
+    const RECIPIENT: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+    const EVENT: &str = "b515da91ac5df638fae0a6e658e03acc1dda6152dd2107d02d5702ccfcf927e8";
+
+    fn request(amount_msats: u64) -> Note {
+        let request = ZapRequest {
+            recipient: RECIPIENT,
+            event: Some(EVENT),
+            amount_msats,
+            lnurl: "lnurl1dp68gurn8ghj7um9",
+            relays: &["wss://a.example", "wss://b.example"],
+        };
+        Note::new_builder(PRIVKEY)
+            .unwrap()
+            .content("great post".into())
+            .create_zap_request(&request)
+            .unwrap()
+            .build(1686880020, [0; 32])
+            .unwrap()
+    }
+
+    fn receipt_note(request: &Note, invoice: &str) -> Note<0, 1024, 4> {
+        let mut json = [0_u8; 1024];
+        let len = request.json_into(&mut json).unwrap();
+        let description = core::str::from_utf8(&json[..len]).unwrap();
+        NoteBuilder::<0, 1024, 4>::new(OTHER_PRIVKEY)
+            .unwrap()
+            .set_kind(NoteKinds::ZapReceipt)
+            .add_tag(Tag::try_from(["p", RECIPIENT]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["bolt11", invoice]).unwrap())
+            .unwrap()
+            .add_tag(Tag::try_from(["description", description]).unwrap())
+            .unwrap()
+            .build(1686880021, [0; 32])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_request() {
+        let note = request(21_000);
+        assert_eq!(note.kind().as_u32(), ZAP_REQUEST_KIND);
+        assert_eq!(
+            note.get_tag("relays").unwrap()[0].as_slice(),
+            ["wss://a.example", "wss://b.example"]
+        );
+        assert_eq!(note.get_tag("amount").unwrap()[0][0], "21000");
+        assert_eq!(note.get_tag("e").unwrap()[0][0], EVENT);
+        assert!(note.verify().is_ok());
+
+        let bad = ZapRequest {
+            recipient: "npub",
+            event: None,
+            amount_msats: 1,
+            lnurl: "",
+            relays: &[],
+        };
+        assert!(matches!(
+            Note::new_builder(PRIVKEY).unwrap().create_zap_request(&bad),
+            Err(Error::InvalidPubkey)
+        ));
+    }
+
+    #[test]
+    fn test_receipt() {
+        let request = request(21_000);
+        let receipt = ZapReceipt::try_from(&receipt_note(&request, "lnbc210n1pjtest")).unwrap();
+        assert_eq!(&receipt.sender, request.pubkey_bytes());
+        assert_eq!(receipt.recipient, from_hex(RECIPIENT.as_bytes()).unwrap());
+        assert_eq!(receipt.event, Some(from_hex(EVENT.as_bytes()).unwrap()));
+        assert_eq!(receipt.amount_msats, Some(21_000));
+        assert!(receipt.amount_matches());
+
+        let underpaid = ZapReceipt::try_from(&receipt_note(&request, "lnbc20n1pjtest")).unwrap();
+        assert!(!underpaid.amount_matches());
+        assert_eq!(ZapReceipt::try_from(&request), Err(Error::InvalidType));
+    }
+
+    #[test]
+    fn test_bolt11_amount() {
+        assert_eq!(
+            bolt11_amount_msats("lnbc2500u1pvjluez"),
+            Ok(Some(250_000_000))
+        );
+        assert_eq!(bolt11_amount_msats("lnbc1m1pvjluez"), Ok(Some(100_000_000)));
+        assert_eq!(
+            bolt11_amount_msats("lntb20m1pvjluez"),
+            Ok(Some(2_000_000_000))
+        );
+        assert_eq!(bolt11_amount_msats("lnbcrt10p1pvjluez"), Ok(Some(1)));
+        assert_eq!(bolt11_amount_msats("lnbc1pvjluez"), Ok(None));
+        assert_eq!(
+            bolt11_amount_msats("lnbc15p1pvjluez"),
+            Err(Error::MalformedContent)
+        );
+        assert_eq!(
+            bolt11_amount_msats("bc1qar0sr"),
+            Err(Error::MalformedContent)
+        );
+    }
+}